@@ -19,17 +19,22 @@
 
 use super::{package_manager::PackageManager, DataType, ExportData, RepoGroup, RepoGroupFormat};
 use crate::{
-    commands::{CliCommand, GlobalOptions},
+    commands::{upload::UploadOptions, CliCommand, GlobalOptions},
     error::Result,
+    passphrase,
     serializer::Format,
 };
 use std::{
     fs::File,
-    io::{self},
+    io::{self, Write},
     path::PathBuf,
 };
+use twackup::crypto::Encryptor;
 
 #[derive(clap::Parser)]
+#[clap(after_help = "
+Passphrase for --encrypt is asked in terminal or taken from TWACKUP_PASSPHRASE variable.
+")]
 pub(crate) struct Export {
     #[clap(flatten)]
     global_options: GlobalOptions,
@@ -47,6 +52,13 @@ pub(crate) struct Export {
     /// Output file, stdout if not present
     #[arg(short, long)]
     output: Option<PathBuf>,
+
+    /// Encrypts output with passphrase. Import command decrypts such files.
+    #[arg(short, long, default_value_t = false)]
+    encrypt: bool,
+
+    #[clap(flatten)]
+    upload_options: UploadOptions,
 }
 
 #[async_trait::async_trait]
@@ -56,11 +68,18 @@ impl CliCommand for Export {
 
         let data = self.construct_data().await?;
         if let Some(path) = &self.output {
-            let file = File::create(path)?;
-            self.format.ser_to_writer(file, &data)?;
+            self.write(File::create(path)?, &data)?;
+            self.upload_options
+                .upload_if_needed(std::slice::from_ref(path))
+                .await?;
         } else {
-            self.format.ser_to_writer(io::stdout(), &data)?;
-            println!();
+            if self.upload_options.upload {
+                log::warn!("Nothing to upload, export must be written to file with --output");
+            }
+            self.write(io::stdout(), &data)?;
+            if !self.encrypt {
+                println!();
+            }
         }
 
         log::info!("Successfully exported {:?} data!", self.data);
@@ -70,6 +89,18 @@ impl CliCommand for Export {
 }
 
 impl Export {
+    fn write<W: Write>(&self, writer: W, data: &ExportData) -> Result<()> {
+        if !self.encrypt {
+            return Ok(self.format.ser_to_writer(writer, data)?);
+        }
+
+        let mut encryptor = Encryptor::new(writer, &passphrase::ask(true)?)?;
+        self.format.ser_to_writer(&mut encryptor, data)?;
+        encryptor.finish()?;
+
+        Ok(())
+    }
+
     async fn construct_data(&self) -> Result<ExportData> {
         let (packages, repositories) = match self.data {
             DataType::Packages => (Some(self.get_packages().await?), None),
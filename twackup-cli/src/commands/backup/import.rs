@@ -18,18 +18,22 @@
  */
 
 use super::{ExportData, RepoGroup, RepoGroupFormat};
-use crate::{commands::CliCommand, error::Result, process, serializer::Format};
+use crate::{commands::CliCommand, error::Result, passphrase, process, serializer::Format};
 use libproc::libproc::proc_pid::am_root;
 use std::{
     fs::File as StdFile,
-    io,
+    io::{self, BufRead, BufReader},
     process::{Command, Stdio},
 };
 use tokio::fs::File;
 use tokio::io::{AsyncWriteExt, BufWriter};
-use twackup::GenericError;
+use twackup::{crypto, GenericError};
 
 #[derive(clap::Parser)]
+#[clap(after_help = "
+Encrypted files are detected automatically. Passphrase is asked in terminal
+or taken from TWACKUP_PASSPHRASE variable.
+")]
 pub(crate) struct Import {
     /// Use another input format
     /// (e.g. when it was processed with third-party parser like jq)
@@ -59,10 +63,19 @@ impl CliCommand for Import {
 impl Import {
     #[inline]
     fn deserialize_input(&self) -> Result<ExportData> {
-        Ok(match self.input.as_str() {
-            "-" => self.format.de_from_reader(io::stdin())?,
-            _ => self.format.de_from_reader(StdFile::open(&self.input)?)?,
-        })
+        match self.input.as_str() {
+            "-" => self.deserialize(BufReader::new(io::stdin())),
+            _ => self.deserialize(BufReader::new(StdFile::open(&self.input)?)),
+        }
+    }
+
+    fn deserialize<R: BufRead>(&self, mut reader: R) -> Result<ExportData> {
+        if !crypto::is_encrypted(reader.fill_buf()?) {
+            return Ok(self.format.de_from_reader(reader)?);
+        }
+
+        let decryptor = crypto::Decryptor::new(reader, &passphrase::ask(false)?)?;
+        Ok(self.format.de_from_reader(decryptor)?)
     }
 
     async fn import_repositories(data: &ExportData) -> Result<()> {
@@ -102,7 +115,7 @@ impl Import {
                     writer.write_all(source.to_deb822().as_bytes()).await?;
                     writer.write_all(b"\n").await?;
                 }
-            };
+            }
             writer.write_all(b"\n").await?;
         }
         writer.flush().await?;
@@ -17,23 +17,33 @@
  * along with Twackup. If not, see <http://www.gnu.org/licenses/>.
  */
 
-use super::{CliCommand, GlobalOptions};
-use crate::{error::Result, paths, progress_bar::ProgressBar};
+use super::{prune::Prune, upload::UploadOptions, CliCommand, GlobalOptions};
+use crate::{
+    error::{CLIError, Result},
+    passphrase, paths,
+    progress_bar::ProgressBar,
+};
 use chrono::Local;
 use console::style;
 use gethostname::gethostname;
 use libproc::libproc::proc_pid::am_root;
-use std::{collections::LinkedList, fs, io, iter::Iterator, path::PathBuf, sync::Arc};
-use tokio::sync::Mutex;
+use std::{
+    collections::LinkedList,
+    fs, io,
+    iter::Iterator,
+    path::{Path, PathBuf},
+};
 use twackup::{
-    archiver::Level as CompressionLevel,
-    builder::{AllPackagesArchive, Preferences, Worker},
+    archiver::{Compression, Level as CompressionLevel},
+    builder::{AllPackagesArchive, BuildReport, BuildSession, Preferences},
+    manifest::{self, Manifest},
     package::Package,
-    progress::Progress,
-    Dpkg, GenericError, PackagesSort,
+    prune::{KeepRule, Pruner},
+    GenericError, PackagesSort,
 };
 
-const DEFAULT_ARCHIVE_NAME: &str = "%host%_%date%.tar.gz";
+const DEFAULT_ARCHIVE_NAME: &str = "%host%_%date%.tar.<compression>";
+const STDOUT_ARCHIVE_NAME: &str = "-";
 
 #[derive(clap::Parser, clap::ValueEnum, Debug, Copy, Clone)]
 enum CompressionType {
@@ -70,8 +80,10 @@ pub(crate) fn should_follow_symlinks() -> bool {
     after_help = "
 Beware, this command doesn't guarantee to copy all files to the final DEB!
 Some files can be skipped because of being renamed or removed in the installation process.
-If you see warnings, it means the final deb misses some contents
-and may not work properly anymore.
+Packages with missing or unreadable files are listed in the summary after the build,
+such debs may not work properly anymore. Use --strict to fail them instead.
+
+Passphrase for --encrypt is asked in terminal or taken from TWACKUP_PASSPHRASE variable.
 "
 )]
 pub(crate) struct Build {
@@ -91,10 +103,16 @@ pub(crate) struct Build {
     #[arg(short = 'A', long, default_value_t = false)]
     archive: bool,
 
-    /// Name of archive if --archive is set. Supports only .tar.gz archives for now.
+    /// Name of archive if --archive is set. Archive is compressed with the same
+    /// type and level as DEB's. Use '-' to write it to stdout.
     #[arg(long, default_value = DEFAULT_ARCHIVE_NAME)]
     archive_name: String,
 
+    /// Encrypts archive with passphrase. Makes sense only if --archive is set.
+    /// Restore command decrypts such archives.
+    #[arg(long, default_value_t = false)]
+    encrypt: bool,
+
     /// Removes all DEB's after adding to archive. Makes sense only if --archive is set.
     #[arg(long, short = 'R', default_value_t = false)]
     remove_after: bool,
@@ -108,6 +126,34 @@ pub(crate) struct Build {
     #[arg(long, short = 'c', default_value = "gzip")]
     compression_type: CompressionType,
 
+    /// Reads every rebuilt DEB back and compares its contents with the installed files.
+    /// Differences are printed as warnings.
+    #[arg(long, default_value_t = false)]
+    verify: bool,
+
+    /// Fails building of package if any of its files couldn't be archived
+    /// instead of producing incomplete DEB.
+    #[arg(long, default_value_t = false)]
+    strict: bool,
+
+    /// Skips packages whose installed files haven't changed since their DEB's
+    /// in destination directory were built.
+    #[arg(long, short = 'i', default_value_t = false)]
+    incremental: bool,
+
+    /// Retention rule applied to destination directory after building,
+    /// see prune command for the format. Just built DEB's and archive are never deleted.
+    #[arg(long)]
+    keep: Vec<KeepRule>,
+
+    #[clap(flatten)]
+    upload_options: UploadOptions,
+
+    /// Maximum count of packages built at the same time.
+    /// Defaults to count of available CPUs.
+    #[arg(long, short = 'j')]
+    jobs: Option<usize>,
+
     /// Will add files to deb by following symlinks if flag is set.
     /// Enabled by default only for rootless jailbreaks.
     #[arg(long, short = 'f', default_value_t = should_follow_symlinks())]
@@ -177,56 +223,163 @@ impl Build {
             log::warn!("{}", GenericError::NotRunningAsRoot);
         }
 
-        let archive = self.create_archive_if_needed().await?;
+        let archive_path = self.archive.then(|| self.archive_path());
+        let (archive, passphrase) = self.create_archive_if_needed(archive_path.clone())?;
 
-        let mut preferences =
-            Preferences::new(&self.global_options.admin_dir, &self.destination_dir);
+        let dpkg = self.global_options.dpkg(false).await?;
+        let mut preferences = Preferences::new(&dpkg.paths, &self.destination_dir);
         preferences.remove_deb = self.remove_after;
         preferences.follow_symlinks = self.follow_symlinks;
-        preferences.compression.level = CompressionLevel::Custom(self.compression_level);
-        preferences.compression.r#type = self.compression_type.into();
+        preferences.compression = self.compression();
+        preferences.strict = self.strict;
+        preferences.verify = self.verify;
+        preferences.incremental = self.incremental;
+
+        let mut session =
+            BuildSession::new(&dpkg, preferences, progress.clone())?.archive(archive.clone());
+        if let Some(jobs) = self.jobs {
+            session = session.jobs(jobs);
+        }
+
+        let cancellation = session.cancellation_token().clone();
+        let interrupt = tokio::spawn(async move {
+            if tokio::signal::ctrl_c().await.is_ok() {
+                log::warn!("Interrupted, waiting for running packages to stop...");
+                cancellation.cancel();
+            }
+        });
+
+        let summary = session.run(packages).await?;
+        interrupt.abort();
+
+        let reports: Vec<_> = summary.reports().collect();
+        if let Some(archive) = archive {
+            let mut manifest = Manifest::new(&dpkg);
+            for (package, report) in &reports {
+                manifest.push(package, report);
+            }
+
+            archive
+                .append_data(manifest::FILE_NAME, manifest.to_vec()?)
+                .await?;
+            archive.finish().await?;
+
+            if let Some(path) = archive_path.as_ref().filter(|path| !Self::is_stdout(path)) {
+                manifest.write_sidecar(path, passphrase.as_deref())?;
+            }
+        }
 
-        let contents = Dpkg::new(&self.global_options.admin_dir, false).info_dir_contents()?;
-        let contents = Arc::new(contents);
+        Self::print_summary(&reports);
 
-        futures::future::join_all(packages.into_iter().map(|package| {
-            let progress = progress.clone();
-            let archive = archive.clone();
-            let preferences = preferences.clone();
-            let contents = contents.clone();
+        if self.upload_options.upload {
+            match &archive_path {
+                Some(path) if !Self::is_stdout(path) => {
+                    let files = [path.clone(), manifest::sidecar_path(path)];
+                    self.upload_options.upload_if_needed(&files).await?;
+                }
+                _ => {
+                    log::warn!("Nothing to upload, archive must be written to file with --archive");
+                }
+            }
+        }
 
-            tokio::spawn(async move {
-                let builder = Worker::new(&package, progress, archive, preferences, contents);
-                builder.run().await
-            })
-        }))
-        .await;
+        if !self.keep.is_empty() {
+            let built = reports.iter().map(|(_, report)| report.deb_path.clone());
+            let plan = Pruner::new(&self.destination_dir, self.keep.iter().copied())
+                .protect(built)
+                .protect(archive_path)
+                .plan()?;
+            Prune::apply(&plan, false)?;
+        }
 
-        progress.finished_all();
-        log::info!("Processed {} packages", all_count);
+        let stats = &summary.stats;
+        log::info!(
+            "Processed {} packages: {} built, {} up to date, {} failed, {} incomplete, {} cancelled in {:.1?}",
+            stats.total,
+            stats.succeeded - stats.up_to_date,
+            stats.up_to_date,
+            stats.failed,
+            stats.incomplete,
+            stats.cancelled,
+            stats.elapsed
+        );
+
+        if stats.failed > 0 || stats.cancelled > 0 {
+            return Err(CLIError::BuildFailed {
+                failed: stats.failed,
+                cancelled: stats.cancelled,
+            });
+        }
 
         Ok(())
     }
 
-    async fn create_archive_if_needed(&self) -> Result<Option<AllPackagesArchive>> {
-        if !self.archive {
-            return Ok(None);
+    fn print_summary(reports: &[(&Package, &BuildReport)]) {
+        for (package, report) in reports {
+            if report.is_incomplete() {
+                log::warn!(
+                    target: &package.id,
+                    "{} missing and {} unreadable file(s), deb may not work properly",
+                    report.missing_files.len(),
+                    report.unreadable_files.len()
+                );
+            }
+
+            let Some(verification) = &report.verification else {
+                continue;
+            };
+            for mismatch in &verification.mismatches {
+                log::warn!(target: &package.id, "{:?}: {:?}", mismatch.path, mismatch.kind);
+            }
+            for member in &verification.missing_control_members {
+                log::warn!(target: &package.id, "control member {member} is missing");
+            }
         }
+    }
 
-        let filename = match &*self.archive_name {
-            DEFAULT_ARCHIVE_NAME => format!(
-                "{}_{}.tar.gz",
-                gethostname().to_str().unwrap_or_default(),
-                Local::now().format("%v_%T")
-            ),
-            _ => self.archive_name.clone(),
+    /// Creates archive if needed. Returns passphrase archive is encrypted with
+    fn create_archive_if_needed(
+        &self,
+        path: Option<PathBuf>,
+    ) -> Result<(Option<AllPackagesArchive>, Option<String>)> {
+        let Some(path) = path else {
+            return Ok((None, None));
         };
 
-        let filepath = self.destination_dir.join(&filename);
-        let file = tokio::fs::File::create(filepath).await?;
+        if self.encrypt {
+            let passphrase = passphrase::ask(true)?;
+            let archive =
+                AllPackagesArchive::create_encrypted(path, self.compression(), &passphrase)?;
+            Ok((Some(archive), Some(passphrase)))
+        } else {
+            let archive = AllPackagesArchive::create(path, self.compression())?;
+            Ok((Some(archive), None))
+        }
+    }
+
+    fn is_stdout(path: &Path) -> bool {
+        path == Path::new(STDOUT_ARCHIVE_NAME)
+    }
+
+    fn archive_path(&self) -> PathBuf {
+        match &*self.archive_name {
+            STDOUT_ARCHIVE_NAME => PathBuf::from(STDOUT_ARCHIVE_NAME),
+            DEFAULT_ARCHIVE_NAME => self.destination_dir.join(format!(
+                "{}_{}.tar.{}{}",
+                gethostname().to_str().unwrap_or_default(),
+                Local::now().format("%v_%T"),
+                self.compression().r#type.as_str(),
+                if self.encrypt { ".enc" } else { "" }
+            )),
+            name => self.destination_dir.join(name),
+        }
+    }
 
-        let archive = tokio_tar::Builder::new(file);
-        Ok(Some(Arc::new(Mutex::new(archive))))
+    fn compression(&self) -> Compression {
+        let mut compression = Compression::default();
+        compression.level = CompressionLevel::Custom(self.compression_level);
+        compression.r#type = self.compression_type.into();
+        compression
     }
 
     fn create_dir_if_needed(&self) -> Result<()> {
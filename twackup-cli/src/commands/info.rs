@@ -0,0 +1,115 @@
+/*
+ * Copyright 2020 DanP
+ *
+ * This file is part of Twackup
+ *
+ * Twackup is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Twackup is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Twackup. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use super::{CliCommand, GlobalOptions};
+use crate::error::Result;
+use console::style;
+use serde::Serialize;
+use std::{io, path::Path};
+use twackup::overrides::{Diversion, StatOverride};
+
+#[derive(clap::Parser)]
+#[clap(version)]
+pub(crate) struct Info {
+    #[clap(flatten)]
+    global_options: GlobalOptions,
+
+    /// Prints information in JSON format
+    #[arg(long, default_value_t = false)]
+    json: bool,
+}
+
+#[derive(Serialize)]
+struct Summary<'a> {
+    admin_dir: &'a Path,
+    packages: usize,
+    pending_updates: bool,
+    diversions: Vec<&'a Diversion>,
+    stat_overrides: Vec<&'a StatOverride>,
+}
+
+#[async_trait::async_trait]
+impl CliCommand for Info {
+    async fn run(&self) -> Result<()> {
+        let dpkg = self.global_options.dpkg(true).await?;
+        let packages = dpkg.unsorted_packages(false).await?;
+        let diversions = dpkg.diversions()?;
+        let stat_overrides = dpkg.stat_overrides()?;
+
+        let summary = Summary {
+            admin_dir: &self.global_options.admin_dir,
+            packages: packages.len(),
+            pending_updates: dpkg.has_pending_updates()?,
+            diversions: diversions.iter().collect(),
+            stat_overrides: stat_overrides.iter().collect(),
+        };
+
+        if self.json {
+            serde_json::to_writer_pretty(io::stdout(), &summary).map_err(io::Error::from)?;
+            println!();
+        } else {
+            Self::print(&summary);
+        }
+
+        Ok(())
+    }
+}
+
+impl Info {
+    fn print(summary: &Summary<'_>) {
+        let pending = if summary.pending_updates {
+            style("yes, run `dpkg --configure -a`").red()
+        } else {
+            style("no").green()
+        };
+
+        println!("Admin directory: {}", summary.admin_dir.display());
+        println!("Packages: {}", summary.packages);
+        println!("Unmerged updates: {pending}");
+
+        println!(
+            "\n{} ({})",
+            style("Diversions").bold(),
+            summary.diversions.len()
+        );
+        for diversion in &summary.diversions {
+            let package = diversion.package.as_deref().unwrap_or("local");
+            println!(
+                "  {} -> {} ({package})",
+                diversion.from.display(),
+                diversion.to.display()
+            );
+        }
+
+        println!(
+            "\n{} ({})",
+            style("Stat overrides").bold(),
+            summary.stat_overrides.len()
+        );
+        for stat in &summary.stat_overrides {
+            println!(
+                "  {}:{} {:04o} {}",
+                stat.user,
+                stat.group,
+                stat.mode,
+                stat.path.display()
+            );
+        }
+    }
+}
@@ -18,8 +18,17 @@
  */
 
 mod build;
+mod info;
 mod leaves;
 mod list;
+mod orphans;
+mod owner;
+mod prune;
+mod repo;
+mod restore;
+mod serve;
+mod upload;
+mod verify;
 
 #[cfg(feature = "ios")]
 mod backup;
@@ -28,8 +37,9 @@ use crate::{error::Result, paths};
 use std::{
     collections::{BTreeMap, LinkedList},
     path::PathBuf,
+    time::Duration,
 };
-use twackup::{package::Package, Dpkg, PackagesSort};
+use twackup::{package::Package, vfs::TarFs, Dpkg, LockWait, PackagesSort};
 
 #[async_trait::async_trait]
 pub(crate) trait CliCommand {
@@ -43,20 +53,58 @@ struct GlobalOptions {
     #[arg(long, default_value = paths::dpkg_admin_dir(), value_parser)]
     #[arg(help_heading = "GLOBAL OPTIONS", global = true)]
     admin_dir: PathBuf,
+
+    /// Analyse system installed in this directory, e.g. mounted device image or chroot.
+    /// Admin dir and package files are resolved under it
+    #[arg(long, value_name = "DIR")]
+    #[arg(help_heading = "GLOBAL OPTIONS", global = true)]
+    root: Option<PathBuf>,
+
+    /// Read dpkg database and package files from tar archive instead of this system,
+    /// e.g. from backup of the whole device. Archive can be compressed, then it is
+    /// decompressed to temp directory first and needs as much free space there
+    #[arg(long, value_name = "TAR")]
+    #[arg(help_heading = "GLOBAL OPTIONS", global = true)]
+    snapshot: Option<PathBuf>,
+
+    /// Seconds to wait for dpkg database locked by dpkg or apt.
+    /// Waits until it is unlocked if not set, fails immediately if 0
+    #[arg(long, value_name = "SECONDS")]
+    #[arg(help_heading = "GLOBAL OPTIONS", global = true)]
+    lock_timeout: Option<u64>,
 }
 
 impl GlobalOptions {
+    pub(crate) async fn dpkg(&self, should_lock: bool) -> Result<Dpkg> {
+        let wait = match self.lock_timeout {
+            None => LockWait::Forever,
+            Some(0) => LockWait::NonBlocking,
+            Some(seconds) => LockWait::Timeout(Duration::from_secs(seconds)),
+        };
+
+        let dpkg = match &self.root {
+            Some(root) => Dpkg::with_root(root, &self.admin_dir, should_lock),
+            None => Dpkg::new(&self.admin_dir, should_lock),
+        };
+        let dpkg = dpkg.lock_wait(wait);
+
+        match &self.snapshot {
+            Some(snapshot) => Ok(dpkg.filesystem(TarFs::open(snapshot).await?)),
+            None => Ok(dpkg),
+        }
+    }
+
     pub(crate) async fn packages(
         &self,
         leaves_only: bool,
         sort: PackagesSort,
     ) -> Result<BTreeMap<String, Package>> {
-        let dpkg = Dpkg::new(&self.admin_dir, true);
+        let dpkg = self.dpkg(true).await?;
         Ok(dpkg.packages(leaves_only, sort).await?)
     }
 
     pub(crate) async fn unsorted_packages(&self, leaves_only: bool) -> Result<LinkedList<Package>> {
-        let dpkg = Dpkg::new(&self.admin_dir, true);
+        let dpkg = self.dpkg(true).await?;
         Ok(dpkg.unsorted_packages(leaves_only).await?)
     }
 }
@@ -102,6 +150,48 @@ pub(crate) enum Command {
     #[clap(disable_version_flag = true)]
     Import(backup::import::Import),
 
+    /// Installs packages from archive created by build command with --archive flag.
+    ///
+    /// Reads archive manifest, verifies checksums and installs debs
+    /// with dpkg in dependency order.
+    #[clap(disable_version_flag = true)]
+    Restore(restore::Restore),
+
+    /// Deletes old DEB versions and archives from destination directory
+    /// according to retention rules.
+    #[clap(disable_version_flag = true)]
+    Prune(prune::Prune),
+
+    /// Manages flat APT repository made of rebuilt debs
+    #[clap(subcommand)]
+    Repo(repo::Repo),
+
+    /// Shares repository directory over HTTP, so other devices can install debs from it
+    #[clap(disable_version_flag = true)]
+    Serve(serve::Serve),
+
+    /// Uploads files to remote storages described in config file
+    #[clap(disable_version_flag = true)]
+    Upload(upload::Upload),
+
+    /// Checks installed files of packages against hashes recorded by dpkg
+    /// and reports modified, missing and permission-changed files
+    #[clap(disable_version_flag = true)]
+    Verify(verify::Verify),
+
+    /// Shows state of dpkg database: unmerged updates, diversions and stat overrides
+    #[clap(disable_version_flag = true)]
+    Info(info::Info),
+
+    /// Searches for packages that installed given files, like `dpkg -S` does
+    #[clap(disable_version_flag = true)]
+    Owner(owner::Owner),
+
+    /// Searches tweak directories for files left by uninstalled packages
+    /// and for files of installed packages that were removed
+    #[clap(disable_version_flag = true)]
+    Orphans(orphans::Orphans),
+
     /// Shows license under Twackup is being distributed
     #[clap(aliases = &["w", "c"])]
     ShowLicense,
@@ -0,0 +1,108 @@
+/*
+ * Copyright 2020 DanP
+ *
+ * This file is part of Twackup
+ *
+ * Twackup is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Twackup is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Twackup. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use super::{CliCommand, GlobalOptions};
+use crate::{error::Result, paths};
+use console::style;
+use std::{io, path::PathBuf};
+use twackup::{orphans::Scanner, ownership::Index};
+
+#[derive(clap::Parser)]
+#[clap(
+    version,
+    after_help = "
+On iOS tweak directories are scanned by default: /Library/MobileSubstrate/DynamicLibraries
+and their rootless counterparts under /var/jb. Symlinks inside roots are not followed.
+"
+)]
+pub(crate) struct Orphans {
+    #[clap(flatten)]
+    global_options: GlobalOptions,
+
+    /// Directories to scan for files no package owns.
+    /// This argument can have multiple values separated by space ' '.
+    roots: Vec<PathBuf>,
+
+    /// Shell pattern of paths to skip, e.g. '*.disabled'.
+    /// Can be specified multiple times.
+    #[arg(long, short)]
+    ignore: Vec<String>,
+
+    /// Doesn't report owned files that are missing on disk
+    #[arg(long, default_value_t = false)]
+    no_missing: bool,
+
+    /// Prints report in JSON format
+    #[arg(long, default_value_t = false)]
+    json: bool,
+}
+
+#[async_trait::async_trait]
+impl CliCommand for Orphans {
+    async fn run(&self) -> Result<()> {
+        let dpkg = self.global_options.dpkg(true).await?;
+        let index = Index::build(&dpkg).await?;
+
+        let roots = if self.roots.is_empty() {
+            paths::orphans_roots()
+        } else {
+            self.roots.clone()
+        };
+
+        let mut scanner = Scanner::new(&index).within(&dpkg);
+        for root in &roots {
+            scanner = scanner.root(root);
+        }
+        for pattern in &self.ignore {
+            scanner = scanner.ignore(pattern);
+        }
+
+        let mut report = scanner.scan()?;
+        if self.no_missing {
+            report.missing.clear();
+        }
+
+        if self.json {
+            serde_json::to_writer_pretty(io::stdout(), &report).map_err(io::Error::from)?;
+            println!();
+        } else {
+            for orphan in &report.unowned {
+                let kind = if orphan.is_dir { "directory" } else { "file" };
+                println!("{:<10} {}", style(kind).yellow(), orphan.path.display());
+            }
+            for missing in &report.missing {
+                println!(
+                    "{:<10} {} ({})",
+                    style("missing").red(),
+                    missing.path.display(),
+                    missing.packages.join(", ")
+                );
+            }
+        }
+
+        log::info!(
+            "Scanned {} roots: {} unowned, {} missing",
+            roots.len(),
+            report.unowned.len(),
+            report.missing.len()
+        );
+
+        Ok(())
+    }
+}
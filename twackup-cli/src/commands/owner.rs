@@ -0,0 +1,95 @@
+/*
+ * Copyright 2020 DanP
+ *
+ * This file is part of Twackup
+ *
+ * Twackup is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Twackup is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Twackup. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use super::{CliCommand, GlobalOptions};
+use crate::error::Result;
+use console::style;
+use std::io;
+use twackup::ownership::{Index, Query};
+
+#[derive(clap::Parser)]
+#[clap(
+    version,
+    after_help = "
+Queries containing *, ? or [ are treated as shell patterns matched against full paths,
+e.g. '*/bin/bash'. Others must be equal to the file path unless --prefix is set.
+"
+)]
+pub(crate) struct Owner {
+    #[clap(flatten)]
+    global_options: GlobalOptions,
+
+    /// Paths or patterns to search for.
+    /// This argument can have multiple values separated by space ' '.
+    #[arg(required = true)]
+    queries: Vec<String>,
+
+    /// Searches for all files which paths start with given queries
+    #[arg(long, short, default_value_t = false)]
+    prefix: bool,
+
+    /// Prints found files in JSON format
+    #[arg(long, default_value_t = false)]
+    json: bool,
+}
+
+#[async_trait::async_trait]
+impl CliCommand for Owner {
+    async fn run(&self) -> Result<()> {
+        let dpkg = self.global_options.dpkg(true).await?;
+        let index = Index::build(&dpkg).await?;
+
+        let mut found = vec![];
+        for query in &self.queries {
+            let matches = if self.prefix {
+                index.lookup(&Query::Prefix(query.clone()))
+            } else {
+                index.lookup(&Query::detect(query))
+            };
+
+            if matches.is_empty() {
+                log::warn!("No package owns {}", query);
+            }
+            found.extend(matches);
+        }
+
+        if self.json {
+            serde_json::to_writer_pretty(io::stdout(), &found).map_err(io::Error::from)?;
+            println!();
+        } else {
+            for found in found {
+                if let Some(diversion) = found.diversion {
+                    let by = match &diversion.package {
+                        Some(package) => format!("diversion by {package}"),
+                        None => "local diversion".to_string(),
+                    };
+                    println!("{by} from: {}", diversion.from.display());
+                    println!("{by} to: {}", diversion.to.display());
+                }
+                println!(
+                    "{}: {}",
+                    style(found.packages.join(", ")).bold(),
+                    found.path
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
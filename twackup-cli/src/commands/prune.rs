@@ -0,0 +1,86 @@
+/*
+ * Copyright 2020 DanP
+ *
+ * This file is part of Twackup
+ *
+ * Twackup is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Twackup is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Twackup. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use super::CliCommand;
+use crate::{error::Result, paths};
+use indicatif::HumanBytes;
+use std::path::PathBuf;
+use twackup::prune::{KeepRule, Plan, Pruner};
+
+#[derive(clap::Parser)]
+#[clap(
+    version,
+    after_help = "
+Rules can be combined, e.g. `--keep versions=2 --keep days=30 --keep size=2G`.
+Files that are neither DEB's named as id_version_arch.deb nor tar archives are never deleted.
+Manifests stored next to archives are deleted together with them.
+"
+)]
+pub(crate) struct Prune {
+    /// Directory with DEB's and archives
+    #[arg(default_value = paths::debs_target_dir(), value_parser)]
+    directory: PathBuf,
+
+    /// Retention rule: versions=N keeps N latest versions of every package,
+    /// days=N keeps archives created during last N days,
+    /// size=N[K|M|G|T] deletes oldest files until directory fits into size.
+    #[arg(long, short, required = true)]
+    keep: Vec<KeepRule>,
+
+    /// Only prints files that would be deleted
+    #[arg(long, default_value_t = false)]
+    dry_run: bool,
+}
+
+impl Prune {
+    /// Prints or deletes files selected by plan
+    pub(crate) fn apply(plan: &Plan, dry_run: bool) -> Result<()> {
+        for candidate in &plan.delete {
+            let path = candidate.path.display();
+            let size = HumanBytes(candidate.size);
+            if dry_run {
+                println!("{path} ({size}, {:?})", candidate.reason);
+            } else {
+                log::debug!("Deleting {path} ({size}, {:?})", candidate.reason);
+            }
+        }
+
+        if !dry_run {
+            plan.apply()?;
+        }
+
+        log::info!(
+            "{} {} file(s), {} freed, {} left",
+            if dry_run { "Would delete" } else { "Deleted" },
+            plan.delete.len(),
+            HumanBytes(plan.deleted_size()),
+            HumanBytes(plan.kept_size)
+        );
+
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl CliCommand for Prune {
+    async fn run(&self) -> Result<()> {
+        let plan = Pruner::new(&self.directory, self.keep.iter().copied()).plan()?;
+        Self::apply(&plan, self.dry_run)
+    }
+}
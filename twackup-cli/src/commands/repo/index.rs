@@ -0,0 +1,95 @@
+/*
+ * Copyright 2020 DanP
+ *
+ * This file is part of Twackup
+ *
+ * Twackup is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Twackup is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Twackup. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use crate::{commands::CliCommand, error::Result, paths};
+use std::{fs, path::PathBuf};
+use twackup::repo::{self, openpgp::SigningKey, ReleaseInfo};
+
+#[derive(clap::Parser)]
+#[clap(version)]
+pub(crate) struct Index {
+    /// Directory with debs. Subdirectories are scanned too.
+    #[arg(default_value = paths::debs_target_dir(), value_parser)]
+    directory: PathBuf,
+
+    /// Origin field of Release file
+    #[arg(long)]
+    origin: Option<String>,
+
+    /// Label field of Release file
+    #[arg(long)]
+    label: Option<String>,
+
+    /// Suite field of Release file
+    #[arg(long)]
+    suite: Option<String>,
+
+    /// Description field of Release file
+    #[arg(long)]
+    description: Option<String>,
+
+    /// Signs Release file with this unprotected Ed25519 secret key,
+    /// e.g. exported with `gpg --export-secret-keys`. Writes Release.gpg and inline-signed release.
+    #[arg(long, short = 'k')]
+    sign_key: Option<PathBuf>,
+}
+
+impl Index {
+    fn release_info(&self) -> ReleaseInfo {
+        let mut info = ReleaseInfo::default();
+        if let Some(origin) = &self.origin {
+            info.origin.clone_from(origin);
+        }
+        if let Some(label) = &self.label {
+            info.label.clone_from(label);
+        }
+        if let Some(suite) = &self.suite {
+            info.suite.clone_from(suite);
+        }
+        if let Some(description) = &self.description {
+            info.description.clone_from(description);
+        }
+        info
+    }
+}
+
+#[async_trait::async_trait]
+impl CliCommand for Index {
+    async fn run(&self) -> Result<()> {
+        let index = repo::Index::scan(&self.directory).await?;
+        let mut files = index.write(&self.release_info()).await?;
+
+        if let Some(path) = &self.sign_key {
+            let key = SigningKey::parse(&fs::read(path)?)?;
+            files.extend(index.sign(&key)?);
+            log::info!("Signed with key {}", key.public_key().fingerprint());
+        }
+
+        log::info!(
+            "Indexed {} debs in {}",
+            index.entries.len(),
+            self.directory.display()
+        );
+        for file in files {
+            log::debug!("Written {}", file.display());
+        }
+
+        Ok(())
+    }
+}
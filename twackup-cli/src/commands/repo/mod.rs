@@ -0,0 +1,47 @@
+/*
+ * Copyright 2020 DanP
+ *
+ * This file is part of Twackup
+ *
+ * Twackup is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Twackup is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Twackup. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+pub(crate) mod index;
+pub(crate) mod verify;
+
+use super::CliCommand;
+use crate::error::Result;
+
+#[derive(clap::Parser)]
+#[clap(version)]
+pub(crate) enum Repo {
+    /// Writes Packages and Release files for directory with debs,
+    /// so it can be added to package manager as flat repository.
+    #[clap(disable_version_flag = true)]
+    Index(index::Index),
+
+    /// Checks signature of repository release file against keyring
+    #[clap(disable_version_flag = true)]
+    Verify(verify::Verify),
+}
+
+#[async_trait::async_trait]
+impl CliCommand for Repo {
+    async fn run(&self) -> Result<()> {
+        match self {
+            Self::Index(cmd) => cmd.run().await,
+            Self::Verify(cmd) => cmd.run().await,
+        }
+    }
+}
@@ -0,0 +1,60 @@
+/*
+ * Copyright 2020 DanP
+ *
+ * This file is part of Twackup
+ *
+ * Twackup is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Twackup is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Twackup. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use crate::{commands::CliCommand, error::Result};
+use std::{fs, path::PathBuf};
+use twackup::repo::openpgp::{self, PublicKey};
+
+#[derive(clap::Parser)]
+#[clap(version)]
+pub(crate) struct Verify {
+    /// Inline-signed release file, or plain Release file if --signature is set
+    file: PathBuf,
+
+    /// Armored or binary file with trusted public keys
+    #[arg(long, short = 'k')]
+    keyring: PathBuf,
+
+    /// Detached signature of the file, e.g. Release.gpg
+    #[arg(long, short = 's')]
+    signature: Option<PathBuf>,
+}
+
+#[async_trait::async_trait]
+impl CliCommand for Verify {
+    async fn run(&self) -> Result<()> {
+        let keyring = PublicKey::parse_keyring(&fs::read(&self.keyring)?)?;
+        let data = fs::read(&self.file)?;
+
+        let signer = if let Some(signature) = &self.signature {
+            openpgp::verify_detached(&data, &fs::read(signature)?, &keyring)?.clone()
+        } else {
+            let message = String::from_utf8_lossy(&data);
+            openpgp::verify_cleartext(&message, &keyring)?.signer
+        };
+
+        log::info!(
+            "Good signature of {} by key {}",
+            self.file.display(),
+            signer.fingerprint()
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,243 @@
+/*
+ * Copyright 2020 DanP
+ *
+ * This file is part of Twackup
+ *
+ * Twackup is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Twackup is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Twackup. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use super::CliCommand;
+use crate::{
+    error::{CLIError, Result},
+    passphrase,
+    progress_bar::ProgressBar,
+};
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+use twackup::{
+    deb::Deb,
+    manifest::{self, Manifest, ManifestPackage},
+    package::Package,
+    restore,
+};
+
+#[derive(clap::Parser)]
+#[clap(
+    version,
+    after_help = "
+Packages are installed with dependencies going first. For testing on a regular Linux host use
+--root and --admindir pointing to a scratch directory together with --dry-run.
+
+Encrypted archives are detected automatically. Passphrase is asked in terminal
+or taken from TWACKUP_PASSPHRASE variable.
+"
+)]
+pub(crate) struct Restore {
+    /// Archive created by build command with --archive flag
+    archive: PathBuf,
+
+    /// Package identifiers to restore. All packages from archive are restored if not set.
+    /// This argument can have multiple values separated by space ' '.
+    packages: Vec<String>,
+
+    /// Only prints dpkg invocations instead of running them
+    #[arg(long, default_value_t = false)]
+    dry_run: bool,
+
+    /// Installs debs archive contains but its manifest doesn't list.
+    /// Checksums of such debs can't be verified, so they are skipped by default
+    #[arg(long, default_value_t = false)]
+    allow_unlisted: bool,
+
+    /// Dpkg executable used for installing packages
+    #[arg(long, default_value = "dpkg")]
+    dpkg: PathBuf,
+
+    /// Install packages relative to this directory. Is passed to dpkg as is.
+    #[arg(long)]
+    root: Option<PathBuf>,
+
+    /// Dpkg database directory. Is passed to dpkg as is.
+    #[arg(long)]
+    admindir: Option<PathBuf>,
+
+    /// Additional option passed to dpkg, e.g. --dpkg-option=--force-script-chrootless.
+    /// Can be specified multiple times.
+    #[arg(long, allow_hyphen_values = true)]
+    dpkg_option: Vec<String>,
+
+    /// Maximum count of debs passed to single dpkg invocation
+    #[arg(long, default_value_t = 16)]
+    #[arg(value_parser = clap::value_parser!(u32).range(1..))]
+    batch_size: u32,
+}
+
+#[async_trait::async_trait]
+impl CliCommand for Restore {
+    async fn run(&self) -> Result<()> {
+        // Private directory, so no one can replace debs before dpkg installs them
+        let unpack_dir = tempfile::Builder::new()
+            .prefix("twackup-restore-")
+            .tempdir()?;
+        self.restore(unpack_dir.path()).await
+    }
+}
+
+impl Restore {
+    async fn restore(&self, unpack_dir: &Path) -> Result<()> {
+        let passphrase = passphrase::ask_if_encrypted(&self.archive)?;
+        let passphrase = passphrase.as_deref();
+
+        // Manifest is placed at the end of archive, so it is unpacked together with debs
+        let files = restore::unpack(
+            &self.archive,
+            unpack_dir,
+            passphrase,
+            &[manifest::FILE_NAME],
+        )
+        .await?;
+        let (manifests, debs): (Vec<_>, Vec<_>) = files
+            .into_iter()
+            .partition(|path| path.ends_with(manifest::FILE_NAME));
+
+        let manifest = manifests.first().map(fs::read).transpose()?;
+        let manifest = manifest.as_deref().map(Manifest::from_slice).transpose()?;
+        if manifest.is_none() {
+            log::warn!("Archive has no manifest, checksums won't be verified");
+        }
+
+        let manifest_packages: HashMap<_, _> = manifest
+            .iter()
+            .flat_map(|manifest| &manifest.packages)
+            .map(|package| (package.deb.as_str(), package))
+            .collect();
+
+        let mut packages = Vec::with_capacity(debs.len());
+        for deb_path in debs {
+            let deb = Deb::open(&deb_path)?;
+            let package = deb.package().await?;
+            if !self.is_selected(&package) {
+                continue;
+            }
+
+            let file_name = deb_path.file_name().unwrap_or_default().to_string_lossy();
+            match manifest_packages.get(&*file_name) {
+                Some(entry) if !Self::check_entry(entry, &deb)? => continue,
+                Some(_) => {}
+                None if manifest.is_none() => {}
+                None if self.allow_unlisted => {
+                    log::warn!(target: &package.id, "isn't listed in manifest, not verified");
+                }
+                None => {
+                    log::error!(target: &package.id, "isn't listed in manifest, skipping");
+                    continue;
+                }
+            }
+
+            packages.push((package, deb_path));
+        }
+
+        for id in &self.packages {
+            if !packages.iter().any(|(package, _)| &package.id == id) {
+                log::warn!("Archive doesn't contain package with identifier {}", id);
+            }
+        }
+
+        let packages = restore::install_order(packages);
+        self.install(&packages)
+    }
+
+    fn is_selected(&self, package: &Package) -> bool {
+        self.packages.is_empty() || self.packages.contains(&package.id)
+    }
+
+    fn check_entry(entry: &ManifestPackage, deb: &Deb) -> Result<bool> {
+        for warning in &entry.warnings {
+            log::warn!(target: &entry.id, "was built with warning: {}", warning);
+        }
+
+        let checksum = deb.sha256()?;
+        if checksum != entry.sha256 {
+            log::error!(target: &entry.id, "checksum mismatch, skipping");
+            return Ok(false);
+        }
+
+        Ok(true)
+    }
+
+    fn install(&self, packages: &[(Package, PathBuf)]) -> Result<()> {
+        let batches: Vec<_> = packages.chunks(self.batch_size as usize).collect();
+        let progress = ProgressBar::default(packages.len() as u64);
+
+        for (index, batch) in batches.iter().enumerate() {
+            let ids: Vec<_> = batch
+                .iter()
+                .map(|(package, _)| package.id.as_str())
+                .collect();
+            progress.0.set_message(format!(
+                "Batch {}/{}: {}",
+                index + 1,
+                batches.len(),
+                ids.join(", ")
+            ));
+
+            let mut command = self.dpkg_command();
+            command.args(batch.iter().map(|(_, path)| path));
+
+            if self.dry_run {
+                progress.0.suspend(|| println!("{command:?}"));
+            } else {
+                let status = progress.0.suspend(|| command.status())?;
+                if !status.success() {
+                    progress.finish();
+                    return Err(CLIError::Dpkg(status));
+                }
+            }
+
+            progress.0.inc(batch.len() as u64);
+        }
+
+        progress.finish();
+        if self.dry_run {
+            log::info!("Would restore {} packages", packages.len());
+        } else {
+            log::info!("Restored {} packages", packages.len());
+        }
+
+        Ok(())
+    }
+
+    fn dpkg_command(&self) -> Command {
+        let mut command = Command::new(&self.dpkg);
+        command
+            .stdin(Stdio::inherit())
+            .stdout(Stdio::inherit())
+            .stderr(Stdio::inherit());
+
+        if let Some(root) = &self.root {
+            command.arg(format!("--root={}", root.display()));
+        }
+        if let Some(admindir) = &self.admindir {
+            command.arg(format!("--admindir={}", admindir.display()));
+        }
+
+        command.args(&self.dpkg_option);
+        command.arg("-i");
+        command
+    }
+}
@@ -0,0 +1,94 @@
+/*
+ * Copyright 2020 DanP
+ *
+ * This file is part of Twackup
+ *
+ * Twackup is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Twackup is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Twackup. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use super::CliCommand;
+use crate::{error::Result, paths};
+use std::{
+    net::{IpAddr, Ipv4Addr, UdpSocket},
+    path::PathBuf,
+};
+use twackup::{builder::CancellationToken, repo::Server};
+
+#[derive(clap::Parser)]
+#[clap(
+    version,
+    after_help = "
+Run `twackup repo index` on the directory first, so package managers can find debs there.
+"
+)]
+pub(crate) struct Serve {
+    /// Repository directory
+    #[arg(default_value = paths::debs_target_dir(), value_parser)]
+    directory: PathBuf,
+
+    /// Port to listen on
+    #[arg(long, short, default_value_t = 8080)]
+    port: u16,
+
+    /// Address to listen on. Repository is shared with this device only by default,
+    /// use 0.0.0.0 to share it with other devices in the network.
+    #[arg(long, default_value_t = IpAddr::V4(Ipv4Addr::LOCALHOST))]
+    bind: IpAddr,
+}
+
+impl Serve {
+    /// Detects address of this device in local network.
+    /// Connecting UDP socket doesn't send anything, it only selects outgoing interface
+    fn lan_address() -> Option<IpAddr> {
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).ok()?;
+        socket.connect((Ipv4Addr::new(192, 168, 0, 1), 80)).ok()?;
+        Some(socket.local_addr().ok()?.ip())
+    }
+}
+
+#[async_trait::async_trait]
+impl CliCommand for Serve {
+    async fn run(&self) -> Result<()> {
+        if !self.directory.join("Packages").exists() {
+            log::warn!(
+                "{} has no Packages file, run `twackup repo index` first",
+                self.directory.display()
+            );
+        }
+
+        let server = Server::bind(&self.directory, (self.bind, self.port)).await?;
+        let address = server.local_addr()?;
+
+        let host = match address.ip() {
+            ip if ip.is_unspecified() => Self::lan_address().unwrap_or(ip),
+            ip => ip,
+        };
+        let url = match host {
+            IpAddr::V4(ip) => format!("http://{ip}:{}/", address.port()),
+            IpAddr::V6(ip) => format!("http://[{ip}]:{}/", address.port()),
+        };
+        log::info!("Serving {} at {url}", self.directory.display());
+        log::info!("Add {url} as a source in Sileo or Zebra. Press Ctrl-C to stop.");
+
+        let cancellation = CancellationToken::new();
+        let interrupt = cancellation.clone();
+        tokio::spawn(async move {
+            if tokio::signal::ctrl_c().await.is_ok() {
+                interrupt.cancel();
+            }
+        });
+
+        Ok(server.run(cancellation).await?)
+    }
+}
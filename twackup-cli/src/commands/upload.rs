@@ -0,0 +1,141 @@
+/*
+ * Copyright 2020 DanP
+ *
+ * This file is part of Twackup
+ *
+ * Twackup is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Twackup is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Twackup. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use super::CliCommand;
+use crate::{
+    error::{CLIError, Result},
+    paths,
+    serializer::SerdeError,
+};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+use twackup::upload::Config;
+
+#[derive(clap::Parser)]
+#[clap(
+    version,
+    after_help = "
+Targets are described in TOML config file:
+
+  [[target]]
+  type = \"s3\"
+  endpoint = \"http://127.0.0.1:9000\"
+  path_style = true
+  bucket = \"backups\"
+  access_key = \"...\"
+  secret_key = \"...\"
+
+  [[target]]
+  type = \"webdav\"
+  url = \"https://dav.example.com/backups/\"
+  username = \"...\"
+  password = \"...\"
+  chunk_size = 8388608
+
+Large files are uploaded to S3 in parts of part_size bytes and to WebDAV in chunks
+of chunk_size bytes, so running the same upload again continues it.
+"
+)]
+pub(crate) struct Upload {
+    /// Files to upload
+    #[arg(required = true)]
+    files: Vec<PathBuf>,
+
+    /// Config file describing upload targets
+    #[arg(long, short, default_value = paths::upload_config(), value_parser)]
+    config: PathBuf,
+}
+
+/// Upload options shared by commands producing backups
+#[derive(clap::Args)]
+pub(crate) struct UploadOptions {
+    /// Uploads result to every target from upload config, see upload command for the format
+    #[arg(long, default_value_t = false)]
+    pub(crate) upload: bool,
+
+    /// Config file describing upload targets
+    #[arg(long, default_value = paths::upload_config(), value_parser)]
+    pub(crate) upload_config: PathBuf,
+}
+
+#[async_trait::async_trait]
+impl CliCommand for Upload {
+    async fn run(&self) -> Result<()> {
+        upload(&self.config, &self.files).await
+    }
+}
+
+impl UploadOptions {
+    /// Uploads `files` if upload was requested
+    pub(crate) async fn upload_if_needed(&self, files: &[PathBuf]) -> Result<()> {
+        if self.upload {
+            upload(&self.upload_config, files).await?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Uploads every file to every target from config.
+/// Failed uploads don't stop others and are reported at the end
+pub(crate) async fn upload(config: &Path, files: &[PathBuf]) -> Result<()> {
+    let contents = fs::read_to_string(config)?;
+    let targets = toml::from_str::<Config>(&contents)
+        .map_err(SerdeError::from)?
+        .targets()?;
+    if targets.is_empty() {
+        log::warn!("No upload targets configured in {}", config.display());
+        return Ok(());
+    }
+
+    let mut failed = 0;
+    for file in files {
+        let Some(name) = file.file_name().and_then(|name| name.to_str()) else {
+            log::error!("{} has no valid file name", file.display());
+            failed += targets.len();
+            continue;
+        };
+
+        for target in &targets {
+            log::info!(target: target.name(), "uploading {}...", file.display());
+            match target.upload(file, name).await {
+                Ok(receipt) => log::info!(
+                    target: target.name(),
+                    "uploaded {} bytes to {} (sha256 {}{})",
+                    receipt.size,
+                    receipt.url,
+                    receipt.sha256,
+                    if receipt.resumed { ", resumed" } else { "" }
+                ),
+                Err(error) => {
+                    log::error!(target: target.name(), "failed to upload {}: {}", file.display(), error);
+                    failed += 1;
+                }
+            }
+        }
+    }
+
+    if failed > 0 {
+        return Err(CLIError::UploadFailed(failed));
+    }
+
+    Ok(())
+}
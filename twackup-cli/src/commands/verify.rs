@@ -0,0 +1,138 @@
+/*
+ * Copyright 2020 DanP
+ *
+ * This file is part of Twackup
+ *
+ * Twackup is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Twackup is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Twackup. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use super::{CliCommand, GlobalOptions};
+use crate::error::{CLIError, Result};
+use console::style;
+use std::{io, path::PathBuf};
+use twackup::integrity::{Checker, Problem, Report};
+
+#[derive(clap::Parser)]
+#[clap(
+    version,
+    after_help = "
+Dpkg records only hashes of installed files. Permissions and owners are compared
+only if --reference-dir contains debs of the same package versions, e.g. built earlier.
+"
+)]
+pub(crate) struct Verify {
+    #[clap(flatten)]
+    global_options: GlobalOptions,
+
+    /// Package identifiers to verify. All packages are verified if not set.
+    /// This argument can have multiple values separated by space ' '.
+    packages: Vec<String>,
+
+    /// Directory with debs used as a source of expected permissions and owners
+    #[arg(long, short)]
+    reference_dir: Option<PathBuf>,
+
+    /// Maximum count of files hashed at the same time.
+    /// Defaults to count of available CPUs.
+    #[arg(long, short = 'j')]
+    jobs: Option<usize>,
+
+    /// Prints reports of all packages in JSON format
+    #[arg(long, default_value_t = false)]
+    json: bool,
+}
+
+#[async_trait::async_trait]
+impl CliCommand for Verify {
+    async fn run(&self) -> Result<()> {
+        let packages = self.global_options.unsorted_packages(false).await?;
+        let packages: Vec<_> = packages
+            .into_iter()
+            .filter(|package| self.packages.is_empty() || self.packages.contains(&package.id))
+            .collect();
+
+        for id in &self.packages {
+            if !packages.iter().any(|package| &package.id == id) {
+                log::warn!("Package with identifier {} is not installed", id);
+            }
+        }
+
+        let dpkg = self.global_options.dpkg(true).await?;
+        let mut checker = Checker::new(&dpkg)?;
+        if let Some(reference_dir) = &self.reference_dir {
+            checker = checker.reference_dir(reference_dir);
+        }
+        if let Some(jobs) = self.jobs {
+            checker = checker.jobs(jobs);
+        }
+
+        let reports = checker.check(&packages).await?;
+        if self.json {
+            serde_json::to_writer_pretty(io::stdout(), &reports).map_err(io::Error::from)?;
+            println!();
+        } else {
+            reports
+                .iter()
+                .filter(|report| !report.is_ok())
+                .for_each(Self::print_report);
+        }
+
+        let files: usize = reports.iter().map(|report| report.checked).sum();
+        let failed = reports.iter().filter(|report| !report.is_ok()).count();
+        log::info!(
+            "Checked {} files of {} packages, {} packages have issues",
+            files,
+            reports.len(),
+            failed
+        );
+
+        if failed > 0 {
+            return Err(CLIError::VerificationFailed(failed));
+        }
+
+        Ok(())
+    }
+}
+
+impl Verify {
+    fn print_report(report: &Report) {
+        println!("{} {}", style("▶︎").yellow(), style(&report.id).bold());
+
+        for issue in &report.issues {
+            let (kind, details) = match &issue.problem {
+                Problem::Missing => ("missing", String::new()),
+                Problem::Modified { .. } => ("modified", String::new()),
+                Problem::Mode { expected, actual } => {
+                    ("mode", format!(" ({expected:04o} -> {actual:04o})"))
+                }
+                Problem::Owner { expected, actual } => (
+                    "owner",
+                    format!(
+                        " ({}:{} -> {}:{})",
+                        expected.0, expected.1, actual.0, actual.1
+                    ),
+                ),
+                Problem::Unreadable { error } => ("unreadable", format!(" ({error})")),
+                _ => ("unknown", String::new()),
+            };
+
+            let conffile = if issue.conffile { " [conffile]" } else { "" };
+            println!(
+                "  {:<10} {}{details}{conffile}",
+                style(kind).red(),
+                issue.path.display()
+            );
+        }
+    }
+}
@@ -32,4 +32,25 @@ pub(crate) enum CLIError {
 
     #[error("Plist: {0}")]
     Plist(#[from] plist::Error),
+
+    #[error("OpenPGP: {0}")]
+    OpenPgp(#[from] twackup::repo::openpgp::Error),
+
+    #[error("Passphrase can't be empty")]
+    EmptyPassphrase,
+
+    #[error("Passphrases don't match")]
+    PassphraseMismatch,
+
+    #[error("{failed} package(s) failed to build, {cancelled} cancelled")]
+    BuildFailed { failed: usize, cancelled: usize },
+
+    #[error("{0} upload(s) failed")]
+    UploadFailed(usize),
+
+    #[error("{0} package(s) failed integrity verification")]
+    VerificationFailed(usize),
+
+    #[error("Dpkg exited with {0}. See stderr for more info.")]
+    Dpkg(std::process::ExitStatus),
 }
@@ -19,6 +19,7 @@
 
 use super::progress_bar::PROGRESS_BAR;
 use log::{LevelFilter, Log, Metadata, Record};
+use std::ptr::addr_of;
 use stderrlog::LogLevelNum;
 
 pub(crate) struct Logger(stderrlog::StdErrLog);
@@ -42,7 +43,7 @@ impl Log for Logger {
     }
 
     fn log(&self, record: &Record<'_>) {
-        match unsafe { PROGRESS_BAR.as_ref() } {
+        match unsafe { (*addr_of!(PROGRESS_BAR)).as_ref() } {
             Some(progress_bar) => progress_bar.0.suspend(|| self.0.log(record)),
             None => self.0.log(record),
         }
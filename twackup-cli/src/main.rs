@@ -33,6 +33,7 @@
 mod commands;
 mod error;
 mod logger;
+mod passphrase;
 mod paths;
 mod process;
 mod progress_bar;
@@ -42,6 +43,7 @@ use clap::Parser;
 use commands::{CliCommand, Command};
 use error::Result;
 use std::fs;
+use std::process::ExitCode;
 use std::time::Instant;
 
 const fn long_version_message() -> &'static str {
@@ -65,26 +67,41 @@ pub(crate) struct Options {
 }
 
 #[tokio::main]
-async fn main() {
+async fn main() -> ExitCode {
     logger::Logger::init();
 
     let start_time = Instant::now();
-    match _run().await {
-        Ok(()) => log::info!(
-            "command performed in {}",
-            indicatif::HumanDuration(start_time.elapsed())
-        ),
-        Err(error) => log::error!("{}", error),
+    match run().await {
+        Ok(()) => {
+            log::info!(
+                "command performed in {}",
+                indicatif::HumanDuration(start_time.elapsed())
+            );
+            ExitCode::SUCCESS
+        }
+        Err(error) => {
+            log::error!("{}", error);
+            ExitCode::FAILURE
+        }
     }
 }
 
 /// Starts parsing CLI arguments and runs actions for them
-async fn _run() -> Result<()> {
+async fn run() -> Result<()> {
     let options = Options::parse();
     match options.sub_cmd {
         Command::List(cmd) => cmd.run().await,
         Command::Leaves(cmd) => cmd.run().await,
         Command::Build(cmd) => cmd.run().await,
+        Command::Restore(cmd) => cmd.run().await,
+        Command::Prune(cmd) => cmd.run().await,
+        Command::Repo(cmd) => cmd.run().await,
+        Command::Serve(cmd) => cmd.run().await,
+        Command::Upload(cmd) => cmd.run().await,
+        Command::Verify(cmd) => cmd.run().await,
+        Command::Info(cmd) => cmd.run().await,
+        Command::Owner(cmd) => cmd.run().await,
+        Command::Orphans(cmd) => cmd.run().await,
 
         #[cfg(feature = "ios")]
         Command::Export(cmd) => cmd.run().await,
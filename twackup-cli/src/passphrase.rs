@@ -0,0 +1,59 @@
+/*
+ * Copyright 2020 DanP
+ *
+ * This file is part of Twackup
+ *
+ * Twackup is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Twackup is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Twackup. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use crate::error::{CLIError, Result};
+use std::{env, fs::File, io::Read, path::Path};
+use twackup::crypto;
+
+/// Environment variable passphrase is taken from instead of asking user
+const ENV_VARIABLE: &str = "TWACKUP_PASSPHRASE";
+
+/// Returns passphrase from environment or asks user for it in terminal.
+/// If `confirm` is set, passphrase has to be typed twice. Empty passphrase is rejected.
+pub(crate) fn ask(confirm: bool) -> Result<String> {
+    if let Ok(passphrase) = env::var(ENV_VARIABLE) {
+        if passphrase.is_empty() {
+            return Err(CLIError::EmptyPassphrase);
+        }
+        return Ok(passphrase);
+    }
+
+    let passphrase = rpassword::prompt_password("Passphrase: ")?;
+    if passphrase.is_empty() {
+        return Err(CLIError::EmptyPassphrase);
+    }
+
+    if confirm && rpassword::prompt_password("Repeat passphrase: ")? != passphrase {
+        return Err(CLIError::PassphraseMismatch);
+    }
+
+    Ok(passphrase)
+}
+
+/// Asks for passphrase only if file at `path` is encrypted
+pub(crate) fn ask_if_encrypted(path: &Path) -> Result<Option<String>> {
+    let mut magic = [0; crypto::MAGIC.len()];
+    let read = File::open(path)?.read(&mut magic)?;
+
+    if crypto::is_encrypted(&magic[..read]) {
+        ask(false).map(Some)
+    } else {
+        Ok(None)
+    }
+}
@@ -36,3 +36,43 @@ pub(crate) fn debs_target_dir() -> OsString {
         Err(_) => "./twackup".into(),
     }
 }
+
+#[cfg(target_os = "ios")]
+pub(crate) fn upload_config() -> OsString {
+    "/var/mobile/Library/Preferences/ru.danpashin.twackup.upload.toml".into()
+}
+
+#[cfg(not(target_os = "ios"))]
+pub(crate) fn upload_config() -> OsString {
+    let config_dir = std::env::var_os("XDG_CONFIG_HOME")
+        .map(std::path::PathBuf::from)
+        .or_else(|| {
+            std::env::var_os("HOME").map(|home| std::path::Path::new(&home).join(".config"))
+        });
+
+    match config_dir {
+        Some(config_dir) => config_dir
+            .join("twackup")
+            .join("upload.toml")
+            .into_os_string(),
+        None => "upload.toml".into(),
+    }
+}
+
+#[cfg(target_os = "ios")]
+pub(crate) fn orphans_roots() -> Vec<std::path::PathBuf> {
+    [
+        "/Library/MobileSubstrate/DynamicLibraries",
+        "/var/jb/Library/MobileSubstrate/DynamicLibraries",
+        "/var/jb/usr/lib",
+    ]
+    .into_iter()
+    .map(std::path::PathBuf::from)
+    .filter(|path| path.exists())
+    .collect()
+}
+
+#[cfg(not(target_os = "ios"))]
+pub(crate) fn orphans_roots() -> Vec<std::path::PathBuf> {
+    vec!["/usr/local/lib".into()]
+}
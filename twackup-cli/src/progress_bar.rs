@@ -17,9 +17,9 @@
  * along with Twackup. If not, see <http://www.gnu.org/licenses/>.
  */
 
-use indicatif::ProgressBar as ProgressBarImpl;
-use std::path::Path;
-use twackup::{package::Package, progress::Progress};
+use indicatif::{HumanBytes, ProgressBar as ProgressBarImpl};
+use std::ptr::addr_of_mut;
+use twackup::progress::{Event, Progress};
 
 pub(crate) static mut PROGRESS_BAR: Option<ProgressBar> = None;
 
@@ -41,26 +41,63 @@ impl ProgressBar {
     }
 
     pub(crate) fn make_static(self) -> &'static Self {
-        unsafe { PROGRESS_BAR.insert(self) }
+        unsafe { (*addr_of_mut!(PROGRESS_BAR)).insert(self) }
     }
-}
 
-impl Progress for ProgressBar {
-    fn started_processing(&self, package: &Package) {
-        let message = format!("Processing {}", package.human_name());
-        self.0.set_message(message);
-    }
-
-    fn finished_processing<P: AsRef<Path>>(&self, package: &Package, _deb_path: P) {
-        self.0.inc(1);
+    pub(crate) fn finish(&self) {
+        self.0.finish_and_clear();
 
-        let message = format!("Done {}", package.human_name());
-        self.0.set_message(message);
+        unsafe { (*addr_of_mut!(PROGRESS_BAR)).take() };
     }
+}
 
-    fn finished_all(&self) {
-        self.0.finish_and_clear();
+impl Progress for ProgressBar {
+    fn on_event(&self, event: Event<'_>) {
+        match event {
+            Event::Started { package } => {
+                let message = format!("Processing {}", package.human_name());
+                self.0.set_message(message);
+            }
+            Event::FileArchived {
+                package,
+                path,
+                bytes,
+            } => {
+                let size = HumanBytes(bytes);
+                log::debug!(target: &package.id, "Archived {} ({size})", path.display());
+            }
+            Event::Compression {
+                package,
+                processed,
+                compressed,
+            } => {
+                let message = format!(
+                    "Processing {} ({} -> {})",
+                    package.human_name(),
+                    HumanBytes(processed),
+                    HumanBytes(compressed)
+                );
+                self.0.set_message(message);
+            }
+            Event::Failed { package, .. } => {
+                self.0.inc(1);
+
+                let message = format!("Failed {}", package.human_name());
+                self.0.set_message(message);
+            }
+            Event::Finished { package, report } => {
+                self.0.inc(1);
 
-        unsafe { PROGRESS_BAR.take() };
+                let status = if report.up_to_date {
+                    "Up to date"
+                } else {
+                    "Done"
+                };
+                let message = format!("{status} {}", package.human_name());
+                self.0.set_message(message);
+            }
+            Event::AllFinished => self.finish(),
+            _ => {}
+        }
     }
 }
@@ -61,6 +61,7 @@ fn with_error() {
 fn with_default_convert_all() {
     #[derive(StrEnumWithError)]
     #[twackup(convert_all = "lower")]
+    #[allow(clippy::enum_variant_names)]
     enum TestEnum {
         LowerCase,
         #[twackup(convert = "upper")]
@@ -125,6 +125,13 @@ actor Dpkg {
 
         return results
     }
+
+    /// Stops currently running rebuild.
+    /// Packages that were not rebuilt yet are returned as failures.
+    /// Not isolated to actor as it is blocked by running rebuild
+    nonisolated func cancelRebuild() {
+        tw_cancel_rebuild(innerDpkg)
+    }
 }
 
 #if swift(>=6.0)
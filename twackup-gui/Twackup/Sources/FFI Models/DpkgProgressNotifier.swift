@@ -11,10 +11,31 @@ protocol DpkgProgressSubscriber: Hashable, Sendable {
     /// Being called when package just finished it's rebuilding operation
     func finishedProcessing(package: FFIPackage, debURL: URL) async
 
+    /// Being called when package rebuilding failed or was cancelled
+    func failedProcessing(package: FFIPackage, error: String) async
+
+    /// Being called when file was placed to package deb
+    func archivedFile(package: FFIPackage, path: String, bytes: UInt64) async
+
+    /// Being called after every archived file with total size of archived files
+    /// and size of compressed data
+    func compressionProgress(package: FFIPackage, processed: UInt64, compressed: UInt64) async
+
     /// Being called when all packages are processed
     func finishedAll() async
 }
 
+extension DpkgProgressSubscriber {
+    func failedProcessing(package: FFIPackage, error: String) async {
+    }
+
+    func archivedFile(package: FFIPackage, path: String, bytes: UInt64) async {
+    }
+
+    func compressionProgress(package: FFIPackage, processed: UInt64, compressed: UInt64) async {
+    }
+}
+
 final class DpkgProgressNotifier: @unchecked Sendable {
     private(set) var ffiFunctions = TwProgressFunctions()
 
@@ -64,6 +85,35 @@ final class DpkgProgressNotifier: @unchecked Sendable {
             let debURL = URL(fileURLWithPath: debPath)
             dpkg.finishedProcessing(ffiPackage, debURL: debURL)
         }
+        ffiFunctions.failed_processing = { context, package, error in
+            guard let context, let ffiPackage = FFIPackage(package) else {
+                tw_package_release(package.inner)
+                return
+            }
+
+            let dpkg = Unmanaged<DpkgProgressNotifier>.fromOpaque(context).takeUnretainedValue()
+            dpkg.failedProcessing(ffiPackage, error: String(ffiSlice: error) ?? "")
+        }
+        ffiFunctions.file_archived = { context, package, path, bytes in
+            guard let context, let ffiPackage = FFIPackage(package),
+                  let path = String(ffiSlice: path)
+            else {
+                tw_package_release(package.inner)
+                return
+            }
+
+            let dpkg = Unmanaged<DpkgProgressNotifier>.fromOpaque(context).takeUnretainedValue()
+            dpkg.archivedFile(ffiPackage, path: path, bytes: bytes)
+        }
+        ffiFunctions.compression_progress = { context, package, processed, compressed in
+            guard let context, let ffiPackage = FFIPackage(package) else {
+                tw_package_release(package.inner)
+                return
+            }
+
+            let dpkg = Unmanaged<DpkgProgressNotifier>.fromOpaque(context).takeUnretainedValue()
+            dpkg.compressionProgress(ffiPackage, processed: processed, compressed: compressed)
+        }
         ffiFunctions.finished_all = { context in
             guard let context else { return }
 
@@ -88,6 +138,30 @@ final class DpkgProgressNotifier: @unchecked Sendable {
         }
     }
 
+    private func failedProcessing(_ package: FFIPackage, error: String) {
+        subscribers.forEach { subscriber in
+            Task(priority: .high) {
+                await subscriber.failedProcessing(package: package, error: error)
+            }
+        }
+    }
+
+    private func archivedFile(_ package: FFIPackage, path: String, bytes: UInt64) {
+        subscribers.forEach { subscriber in
+            Task(priority: .high) {
+                await subscriber.archivedFile(package: package, path: path, bytes: bytes)
+            }
+        }
+    }
+
+    private func compressionProgress(_ package: FFIPackage, processed: UInt64, compressed: UInt64) {
+        subscribers.forEach { subscriber in
+            Task(priority: .high) {
+                await subscriber.compressionProgress(package: package, processed: processed, compressed: compressed)
+            }
+        }
+    }
+
     private func finishedAll() {
         subscribers.forEach { subscriber in
             Task(priority: .high) {
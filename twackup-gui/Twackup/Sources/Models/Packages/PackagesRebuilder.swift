@@ -115,6 +115,14 @@ actor PackagesRebuilder: DpkgProgressSubscriber, Equatable, Hashable {
         updateHandler?(progress)
     }
 
+    func compressionProgress(package: FFIPackage, processed: UInt64, compressed: UInt64) {
+        let formatter = ByteCountFormatter()
+        let processed = formatter.string(fromByteCount: Int64(clamping: processed))
+        let compressed = formatter.string(fromByteCount: Int64(clamping: compressed))
+        progress.localizedAdditionalDescription = "\(package.name) (\(processed) → \(compressed))"
+        updateHandler?(progress)
+    }
+
     func finishedAll() {
     }
 
@@ -35,21 +35,46 @@
 //! }
 //! ```
 //!
+//! Decoding works the same way, but compression type can be detected from the data itself
+//!
+//! ```no_run
+//! use twackup::{archiver::{Decoder, Type}, Result};
+//! use std::io::Cursor;
+//!
+//! #[tokio::main]
+//! async fn main() -> Result<()> {
+//!     let data = std::fs::read("data.tar.xz")?;
+//!     let r#type = Type::detect(&data).unwrap_or_default();
+//!     let decoder = Decoder::new(Cursor::new(data), r#type)?;
+//!
+//!     // do something with decoder as it implements AsyncRead
+//!
+//!     Ok(())
+//! }
+//! ```
+//!
 
-use bzip2::write::BzEncoder;
-use flate2::write::GzEncoder;
+use crate::crypto;
+use bzip2::{read::MultiBzDecoder, write::BzEncoder};
+use flate2::{read::MultiGzDecoder, write::GzEncoder};
 use std::{
-    io::{Error, Write},
+    fs::File,
+    io::{BufRead, BufReader, Error, Read, Write},
+    path::Path,
     pin::Pin,
     task::{Context, Poll},
 };
-use tokio::io::AsyncWrite;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
 use twackup_derive::StrEnumWithError;
-use xz2::write::XzEncoder;
-use zstd::Encoder as ZSTDEncoder;
+use xz2::{
+    read::XzDecoder,
+    stream::{LzmaOptions, Stream},
+    write::XzEncoder,
+};
+use zstd::{Decoder as ZSTDDecoder, Encoder as ZSTDEncoder};
 
 /// Defines type of the encoder
-#[derive(Debug, StrEnumWithError, Default, Copy, Clone)]
+#[derive(Debug, StrEnumWithError, Default, Copy, Clone, PartialEq, Eq)]
 #[twackup(convert_all = "lower")]
 #[non_exhaustive]
 pub enum Type {
@@ -62,6 +87,8 @@ pub enum Type {
     Zst,
     /// Another old-style bzip2 type
     Bz2,
+    /// Legacy lzma type. Superseded by xz, but still can be found in old debs
+    Lzma,
 }
 
 /// Defines how much data encoder will compress.
@@ -104,6 +131,48 @@ pub enum Encoder<T: Write> {
     Zstd(ZSTDEncoder<'static, T>),
     /// Another old-style bzip2 type
     Bzip2(BzEncoder<T>),
+    /// Legacy lzma type
+    Lzma(XzEncoder<T>),
+}
+
+/// Wrapper on underlying decoders
+#[non_exhaustive]
+pub enum Decoder<T: Read> {
+    /// Old-style Gzip type
+    Gzip(MultiGzDecoder<T>),
+    /// Modern-based xz type
+    Xz(XzDecoder<T>),
+    /// Super-modern and fast zstd type
+    Zstd(ZSTDDecoder<'static, BufReader<T>>),
+    /// Another old-style bzip2 type
+    Bzip2(MultiBzDecoder<T>),
+    /// Legacy lzma type
+    Lzma(XzDecoder<T>),
+}
+
+impl Type {
+    /// Detects compression type by looking at magic bytes of the data.
+    /// It is enough to pass only first few bytes of the data
+    ///
+    /// Returns `None` if data doesn't look like any of supported types
+    #[must_use]
+    pub fn detect(data: &[u8]) -> Option<Self> {
+        if data.starts_with(&[0x1F, 0x8B]) {
+            Some(Self::Gz)
+        } else if data.starts_with(&[0xFD, b'7', b'z', b'X', b'Z', 0x00]) {
+            Some(Self::Xz)
+        } else if data.starts_with(&[0x28, 0xB5, 0x2F, 0xFD]) {
+            Some(Self::Zst)
+        } else if data.starts_with(b"BZh") {
+            Some(Self::Bz2)
+        } else if data.starts_with(&[0x5D, 0x00, 0x00]) {
+            // lzma-alone has no real magic, but almost every encoder
+            // writes default properties followed by dictionary size
+            Some(Self::Lzma)
+        } else {
+            None
+        }
+    }
 }
 
 impl Level {
@@ -148,6 +217,23 @@ impl<T: Write> Encoder<T> {
                 inner,
                 bzip2::Compression::new(compression.level.raw_value()),
             ))),
+            Type::Lzma => {
+                let options = LzmaOptions::new_preset(compression.level.raw_value())
+                    .map_err(std::io::Error::from)?;
+                let stream = Stream::new_lzma_encoder(&options).map_err(std::io::Error::from)?;
+                Ok(Self::Lzma(XzEncoder::new_stream(inner, stream)))
+            }
+        }
+    }
+
+    /// Returns reference to inner object compressed data is written to
+    #[inline]
+    pub fn get_ref(&self) -> &T {
+        match self {
+            Self::Gzip(inner) => inner.get_ref(),
+            Self::Xz(inner) | Self::Lzma(inner) => inner.get_ref(),
+            Self::Zstd(inner) => inner.get_ref(),
+            Self::Bzip2(inner) => inner.get_ref(),
         }
     }
 
@@ -159,13 +245,48 @@ impl<T: Write> Encoder<T> {
     pub fn into_inner(self) -> std::io::Result<T> {
         match self {
             Self::Gzip(inner) => inner.finish(),
-            Self::Xz(inner) => inner.finish(),
+            Self::Xz(inner) | Self::Lzma(inner) => inner.finish(),
             Self::Zstd(inner) => inner.finish(),
             Self::Bzip2(inner) => inner.finish(),
         }
     }
 }
 
+impl<T: Read> Decoder<T> {
+    /// Creates decoder for specified compression type
+    ///
+    /// - `inner` - Inner object from which decompressor will read compressed data
+    /// - `type` - Type of compression. Can be detected with [`Type::detect`]
+    ///
+    /// # Errors
+    /// Return error if zstd or lzma decoder initialization failed
+    ///
+    #[inline]
+    pub fn new(inner: T, r#type: Type) -> crate::error::Result<Self> {
+        match r#type {
+            Type::Gz => Ok(Self::Gzip(MultiGzDecoder::new(inner))),
+            Type::Xz => Ok(Self::Xz(XzDecoder::new_multi_decoder(inner))),
+            Type::Zst => Ok(Self::Zstd(ZSTDDecoder::new(inner)?)),
+            Type::Bz2 => Ok(Self::Bzip2(MultiBzDecoder::new(inner))),
+            Type::Lzma => {
+                let stream = Stream::new_lzma_decoder(u64::MAX).map_err(std::io::Error::from)?;
+                Ok(Self::Lzma(XzDecoder::new_stream(inner, stream)))
+            }
+        }
+    }
+
+    /// Consumes self and returns inner reader
+    #[inline]
+    pub fn into_inner(self) -> T {
+        match self {
+            Self::Gzip(inner) => inner.into_inner(),
+            Self::Xz(inner) | Self::Lzma(inner) => inner.into_inner(),
+            Self::Zstd(inner) => inner.finish().into_inner(),
+            Self::Bzip2(inner) => inner.into_inner(),
+        }
+    }
+}
+
 unsafe impl<T: Write> Sync for Encoder<T> {}
 
 impl<T: Write + Unpin> AsyncWrite for Encoder<T> {
@@ -177,7 +298,7 @@ impl<T: Write + Unpin> AsyncWrite for Encoder<T> {
         let enum_self = self.get_mut();
         match enum_self {
             Self::Gzip(inner) => Poll::Ready(inner.write(buf)),
-            Self::Xz(inner) => Poll::Ready(inner.write(buf)),
+            Self::Xz(inner) | Self::Lzma(inner) => Poll::Ready(inner.write(buf)),
             Self::Zstd(inner) => Poll::Ready(inner.write(buf)),
             Self::Bzip2(inner) => Poll::Ready(inner.write(buf)),
         }
@@ -187,7 +308,7 @@ impl<T: Write + Unpin> AsyncWrite for Encoder<T> {
         let enum_self = self.get_mut();
         match enum_self {
             Self::Gzip(inner) => Poll::Ready(inner.flush()),
-            Self::Xz(inner) => Poll::Ready(inner.flush()),
+            Self::Xz(inner) | Self::Lzma(inner) => Poll::Ready(inner.flush()),
             Self::Zstd(inner) => Poll::Ready(inner.flush()),
             Self::Bzip2(inner) => Poll::Ready(inner.flush()),
         }
@@ -197,9 +318,137 @@ impl<T: Write + Unpin> AsyncWrite for Encoder<T> {
         let enum_self = self.get_mut();
         match enum_self {
             Self::Gzip(inner) => Poll::Ready(inner.try_finish()),
-            Self::Xz(inner) => Poll::Ready(inner.try_finish()),
+            Self::Xz(inner) | Self::Lzma(inner) => Poll::Ready(inner.try_finish()),
             Self::Zstd(inner) => Poll::Ready(inner.do_finish()),
             Self::Bzip2(inner) => Poll::Ready(inner.try_finish()),
         }
     }
 }
+
+/// Opens file for reading decompressing it if compression type could be detected.
+/// Uncompressed files are read as is.
+///
+/// # Errors
+/// Returns error if file couldn't be opened or decompressor initialization failed
+#[inline]
+pub fn open_detected<P: AsRef<Path>>(
+    path: P,
+) -> crate::error::Result<Box<dyn AsyncRead + Unpin + Send>> {
+    open_detected_with(path, None)
+}
+
+/// Same as [`open_detected`], but also decrypts file if it was encrypted
+/// with [`crate::crypto::Encryptor`]. Passphrase is required only for encrypted files.
+///
+/// # Errors
+/// Returns error if file couldn't be opened, is encrypted and passphrase is missing or wrong
+/// or decompressor initialization failed
+#[inline]
+pub fn open_detected_with<P: AsRef<Path>>(
+    path: P,
+    passphrase: Option<&str>,
+) -> crate::error::Result<Box<dyn AsyncRead + Unpin + Send>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    if !crypto::is_encrypted(reader.fill_buf()?) {
+        return decode_detected(reader);
+    }
+
+    let passphrase = passphrase.ok_or(crypto::Error::PassphraseRequired)?;
+    decode_detected(BufReader::new(crypto::Decryptor::new(reader, passphrase)?))
+}
+
+fn decode_detected<T: BufRead + Unpin + Send + 'static>(
+    mut reader: T,
+) -> crate::error::Result<Box<dyn AsyncRead + Unpin + Send>> {
+    Ok(match Type::detect(reader.fill_buf()?) {
+        Some(r#type) => Box::new(Decoder::new(reader, r#type)?),
+        None => Box::new(Plain(reader)),
+    })
+}
+
+/// Adapter for reading uncompressed data with the same interface as [`Decoder`]
+pub(crate) struct Plain<T>(pub(crate) T);
+
+impl<T: Read + Unpin> AsyncRead for Plain<T> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<(), Error>> {
+        let read = self.get_mut().0.read(buf.initialize_unfilled())?;
+        buf.advance(read);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<T: Read + Unpin> AsyncRead for Decoder<T> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<(), Error>> {
+        let enum_self = self.get_mut();
+        let unfilled = buf.initialize_unfilled();
+        let result = match enum_self {
+            Self::Gzip(inner) => inner.read(unfilled),
+            Self::Xz(inner) | Self::Lzma(inner) => inner.read(unfilled),
+            Self::Zstd(inner) => inner.read(unfilled),
+            Self::Bzip2(inner) => inner.read(unfilled),
+        };
+
+        Poll::Ready(result.map(|read| buf.advance(read)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Compression, Decoder, Encoder, Level, Type};
+    use crate::Result;
+    use std::io::Cursor;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    const TYPES: [Type; 5] = [Type::Gz, Type::Xz, Type::Zst, Type::Bz2, Type::Lzma];
+
+    async fn compress(r#type: Type, contents: &[u8]) -> Result<Vec<u8>> {
+        let compression = Compression {
+            r#type,
+            level: Level::Fast,
+        };
+
+        let mut encoder = Encoder::new(vec![], compression)?;
+        encoder.write_all(contents).await?;
+        encoder.shutdown().await?;
+
+        Ok(encoder.into_inner()?)
+    }
+
+    #[tokio::test]
+    async fn round_trip() -> Result<()> {
+        let contents = b"Package: twackup\nVersion: 1.0.0\n".repeat(64);
+
+        for r#type in TYPES {
+            let compressed = compress(r#type, &contents).await?;
+
+            let mut decoder = Decoder::new(Cursor::new(compressed), r#type)?;
+            let mut decompressed = vec![];
+            decoder.read_to_end(&mut decompressed).await?;
+
+            assert_eq!(decompressed, contents, "{type:?} round trip failed");
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn detect() -> Result<()> {
+        for r#type in TYPES {
+            let compressed = compress(r#type, b"twackup").await?;
+            assert_eq!(Type::detect(&compressed), Some(r#type));
+        }
+
+        assert_eq!(Type::detect(b"!<arch>\n"), None);
+        assert_eq!(Type::detect(&[]), None);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,373 @@
+/*
+ * Copyright 2020 DanP
+ *
+ * This file is part of Twackup
+ *
+ * Twackup is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Twackup is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Twackup. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use crate::{
+    archiver::{Compression, Encoder},
+    crypto::Encryptor,
+    error::{Generic, Result},
+};
+use std::{
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+use tokio::{
+    io::AsyncWriteExt,
+    sync::{mpsc, oneshot},
+};
+
+type Output = Encoder<Sink>;
+
+/// Final destination of compressed archive data
+enum Sink {
+    Plain(Box<dyn Write + Send>),
+    Encrypted(Encryptor<Box<dyn Write + Send>>),
+}
+
+/// Single archive containing all built debs.
+///
+/// Archive is written by a background task, so cloned handles can be
+/// passed to concurrent workers while entries are appended one by one.
+#[derive(Clone)]
+pub struct AllPackagesArchive {
+    sender: mpsc::Sender<Command>,
+}
+
+enum Command {
+    Append {
+        path: PathBuf,
+        name: PathBuf,
+        reply: oneshot::Sender<io::Result<()>>,
+    },
+    AppendData {
+        name: PathBuf,
+        data: Vec<u8>,
+        reply: oneshot::Sender<io::Result<()>>,
+    },
+    Finish {
+        reply: oneshot::Sender<io::Result<()>>,
+    },
+}
+
+impl AllPackagesArchive {
+    /// Creates compressed tar archive at `path`. If path equals to `-`, archive is written to stdout.
+    /// Must be called within tokio runtime.
+    ///
+    /// # Errors
+    /// Returns error if file couldn't be created or compressor initialization failed
+    #[inline]
+    pub fn create<P: AsRef<Path>>(path: P, compression: Compression) -> Result<Self> {
+        let path = path.as_ref();
+        if path == Path::new("-") {
+            Self::new(io::stdout(), compression)
+        } else {
+            Self::new(std::fs::File::create(path)?, compression)
+        }
+    }
+
+    /// Creates compressed tar archive writing to `writer`.
+    /// Must be called within tokio runtime.
+    ///
+    /// # Errors
+    /// Returns error if compressor initialization failed
+    #[inline]
+    pub fn new<W: Write + Send + 'static>(writer: W, compression: Compression) -> Result<Self> {
+        Self::with_sink(Sink::Plain(Box::new(writer)), compression)
+    }
+
+    /// Same as [`AllPackagesArchive::create`], but compressed archive is also encrypted
+    /// with key derived from `passphrase`. Must be called within tokio runtime.
+    ///
+    /// # Errors
+    /// Returns error if file couldn't be created, key derivation or compressor initialization failed
+    #[inline]
+    pub fn create_encrypted<P: AsRef<Path>>(
+        path: P,
+        compression: Compression,
+        passphrase: &str,
+    ) -> Result<Self> {
+        let path = path.as_ref();
+        if path == Path::new("-") {
+            Self::new_encrypted(io::stdout(), compression, passphrase)
+        } else {
+            Self::new_encrypted(std::fs::File::create(path)?, compression, passphrase)
+        }
+    }
+
+    /// Same as [`AllPackagesArchive::new`], but compressed archive is also encrypted
+    /// with key derived from `passphrase`. Must be called within tokio runtime.
+    ///
+    /// # Errors
+    /// Returns error if key derivation or compressor initialization failed
+    #[inline]
+    pub fn new_encrypted<W: Write + Send + 'static>(
+        writer: W,
+        compression: Compression,
+        passphrase: &str,
+    ) -> Result<Self> {
+        let writer: Box<dyn Write + Send> = Box::new(writer);
+        let encryptor = Encryptor::new(writer, passphrase)?;
+        Self::with_sink(Sink::Encrypted(encryptor), compression)
+    }
+
+    fn with_sink(sink: Sink, compression: Compression) -> Result<Self> {
+        let encoder = Encoder::new(sink, compression)?;
+
+        // Workers wait for their deb to be appended, so small buffer is enough
+        let (sender, receiver) = mpsc::channel(8);
+        tokio::spawn(Self::write_entries(
+            tokio_tar::Builder::new(encoder),
+            receiver,
+        ));
+
+        Ok(Self { sender })
+    }
+
+    /// Appends file at `path` to archive with `name` and waits until it is written
+    ///
+    /// # Errors
+    /// Returns error if file couldn't be appended or archive is already finished
+    #[inline]
+    pub async fn append<P: AsRef<Path>, N: AsRef<Path>>(&self, path: P, name: N) -> Result<()> {
+        let (reply, response) = oneshot::channel();
+        let command = Command::Append {
+            path: path.as_ref().to_path_buf(),
+            name: name.as_ref().to_path_buf(),
+            reply,
+        };
+
+        self.send(command, response).await
+    }
+
+    /// Appends in-memory file with `name` to archive and waits until it is written
+    ///
+    /// # Errors
+    /// Returns error if data couldn't be appended or archive is already finished
+    #[inline]
+    pub async fn append_data<N: AsRef<Path>>(&self, name: N, data: Vec<u8>) -> Result<()> {
+        let (reply, response) = oneshot::channel();
+        let command = Command::AppendData {
+            name: name.as_ref().to_path_buf(),
+            data,
+            reply,
+        };
+
+        self.send(command, response).await
+    }
+
+    /// Writes tar end blocks, finishes compression and flushes output.
+    /// Entries can't be appended after this call.
+    ///
+    /// # Errors
+    /// Returns error if archive couldn't be finalised or is already finished
+    #[inline]
+    pub async fn finish(&self) -> Result<()> {
+        let (reply, response) = oneshot::channel();
+        self.send(Command::Finish { reply }, response).await
+    }
+
+    async fn send(
+        &self,
+        command: Command,
+        response: oneshot::Receiver<io::Result<()>>,
+    ) -> Result<()> {
+        self.sender
+            .send(command)
+            .await
+            .map_err(|_| Generic::ArchiveFinished)?;
+
+        Ok(response.await.map_err(|_| Generic::ArchiveFinished)??)
+    }
+
+    async fn write_entries(
+        mut builder: tokio_tar::Builder<Output>,
+        mut receiver: mpsc::Receiver<Command>,
+    ) {
+        let mut finish_reply = None;
+        while let Some(command) = receiver.recv().await {
+            match command {
+                Command::Append { path, name, reply } => {
+                    let result = builder.append_path_with_name(path, name).await;
+                    reply.send(result).ok();
+                }
+                Command::AppendData { name, data, reply } => {
+                    let result = Self::write_data(&mut builder, &name, &data).await;
+                    reply.send(result).ok();
+                }
+                Command::Finish { reply } => {
+                    finish_reply = Some(reply);
+                    break;
+                }
+            }
+        }
+
+        // Close channel so no more entries can be sent after finishing
+        drop(receiver);
+
+        let result = Self::finalise(builder).await;
+        match finish_reply {
+            Some(reply) => {
+                reply.send(result).ok();
+            }
+            None => {
+                if let Err(error) = result {
+                    log::error!("Failed to finalise archive: {}", error);
+                }
+            }
+        }
+    }
+
+    async fn write_data(
+        builder: &mut tokio_tar::Builder<Output>,
+        name: &Path,
+        data: &[u8],
+    ) -> io::Result<()> {
+        let mtime = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_secs());
+
+        let mut header = tokio_tar::Header::new_gnu();
+        header.set_mode(0o100_644); // o=rw,g=r,o=r
+        header.set_size(data.len() as u64);
+        header.set_mtime(mtime);
+
+        builder.append_data(&mut header, name, data).await
+    }
+
+    async fn finalise(builder: tokio_tar::Builder<Output>) -> io::Result<()> {
+        // into_inner writes tar end blocks
+        let mut encoder = builder.into_inner().await?;
+        encoder.shutdown().await?;
+
+        encoder.into_inner()?.finish()
+    }
+}
+
+impl Sink {
+    /// Writes the last encrypted chunk if needed and flushes output
+    fn finish(self) -> io::Result<()> {
+        match self {
+            Self::Plain(mut writer) => writer.flush(),
+            Self::Encrypted(encryptor) => encryptor.finish()?.flush(),
+        }
+    }
+}
+
+impl Write for Sink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Plain(writer) => writer.write(buf),
+            Self::Encrypted(encryptor) => encryptor.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Plain(writer) => writer.flush(),
+            Self::Encrypted(encryptor) => encryptor.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AllPackagesArchive;
+    use crate::{
+        archiver::{self, Compression, Decoder, Type},
+        crypto,
+        testing::temp_dir,
+        Result,
+    };
+    use std::{fs, path::Path};
+    use tokio::io::AsyncReadExt;
+    use tokio_stream::StreamExt;
+
+    #[tokio::test]
+    async fn concurrent_appends() -> Result<()> {
+        let directory = temp_dir("twackup-all-packages-archive")?;
+
+        let archive_path = directory.join("archive.tar.xz");
+        let compression = Compression {
+            r#type: Type::Xz,
+            ..Compression::default()
+        };
+        let archive = AllPackagesArchive::create(&archive_path, compression)?;
+
+        let tasks: Vec<_> = (0..8)
+            .map(|index| {
+                let path = directory.join(format!("{index}.deb"));
+                fs::write(&path, format!("package {index}")).unwrap();
+
+                let archive = archive.clone();
+                tokio::spawn(async move { archive.append(&path, format!("{index}.deb")).await })
+            })
+            .collect();
+
+        for task in tasks {
+            task.await??;
+        }
+        archive.finish().await?;
+        assert!(archive.finish().await.is_err());
+
+        let data = fs::read(&archive_path)?;
+        assert_eq!(Type::detect(&data), Some(Type::Xz));
+
+        let decoder = Decoder::new(data.as_slice(), Type::Xz)?;
+        let mut tar = tokio_tar::Archive::new(decoder);
+        let mut entries = tar.entries()?;
+        let mut count = 0;
+        while let Some(entry) = entries.next().await {
+            entry?;
+            count += 1;
+        }
+        assert_eq!(count, 8);
+
+        fs::remove_dir_all(directory)?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn encrypted() -> Result<()> {
+        let directory = temp_dir("twackup-all-packages-archive-encrypted")?;
+
+        let archive_path = directory.join("archive.tar.gz.enc");
+        let archive =
+            AllPackagesArchive::create_encrypted(&archive_path, Compression::default(), "secret")?;
+        archive.append_data("test.deb", b"package".to_vec()).await?;
+        archive.finish().await?;
+
+        assert!(crypto::is_encrypted(&fs::read(&archive_path)?));
+        assert!(archiver::open_detected(&archive_path).is_err());
+
+        let reader = archiver::open_detected_with(&archive_path, Some("secret"))?;
+        let mut tar = tokio_tar::Archive::new(reader);
+        let mut entries = tar.entries()?;
+        let mut entry = entries.next().await.unwrap()?;
+        assert_eq!(entry.path()?.as_ref(), Path::new("test.deb"));
+
+        let mut data = vec![];
+        entry.read_to_end(&mut data).await?;
+        assert_eq!(data, b"package");
+
+        fs::remove_dir_all(directory)?;
+
+        Ok(())
+    }
+}
@@ -20,14 +20,34 @@
 use crate::{
     archiver::{Compression, Encoder},
     error::Result,
+    overrides::{Accounts, StatOverride},
+    vfs::{FileKind, FileSystem, Metadata},
 };
+use md5::{Digest, Md5};
 use std::{
     borrow::BorrowMut,
+    ffi::OsStr,
+    fs,
+    future::Future,
+    io::{self, Read},
+    os::unix::ffi::OsStrExt,
     path::{Path, PathBuf},
+    pin::Pin,
+    task::{ready, Context, Poll},
     time::SystemTime,
 };
-use tokio::io::AsyncWriteExt;
-use tokio_tar::Builder as Tar;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWriteExt, DuplexStream, ReadBuf},
+    task::JoinHandle,
+};
+use tokio_tar::{Builder as Tar, EntryType, Header};
+use tokio_util::io::SyncIoBridge;
+
+/// Length of link name field in tar header, longer names are stored in separate entry
+const LINK_NAME_LENGTH: usize = 100;
+
+/// Size of buffer between blocking task reading file and archive
+const STREAM_BUFFER_SIZE: usize = 64 * 1024;
 
 pub(crate) type DebianInnerTar = TarArchive<Encoder<Vec<u8>>>;
 
@@ -42,6 +62,19 @@ pub(crate) struct TarArchive<W: tokio::io::AsyncWrite + Unpin + Send + Sync + 's
     builder: Tar<W>,
 }
 
+/// Contents of file outside of the real filesystem read by blocking task.
+/// Fails instead of ending early if file couldn't be read completely
+struct BlockingReader {
+    inner: DuplexStream,
+    task: Option<JoinHandle<io::Result<()>>>,
+}
+
+/// Calculates md5 of the contents read through it
+struct HashingReader<R> {
+    inner: R,
+    hasher: Md5,
+}
+
 impl Deb {
     /// Constructs debian archive instance
     ///
@@ -74,13 +107,31 @@ impl Deb {
         self.control.borrow_mut()
     }
 
-    /// Construct debian package
+    /// Construct debian package.
+    /// Package is written to temporary file next to output first,
+    /// so output never contains partially written deb
     ///
     /// # Errors
     /// Returns IO error if temp dir is not writable
     #[inline]
     pub(crate) async fn build(self) -> Result<()> {
-        let mut builder = ar::Builder::new(std::fs::File::create(&self.output)?);
+        let output = self.output.clone();
+        let mut partial = output.clone().into_os_string();
+        partial.push(".part");
+        let partial = PathBuf::from(partial);
+
+        let result = self.write(&partial).await;
+        match result {
+            Ok(()) => Ok(fs::rename(&partial, &output)?),
+            Err(error) => {
+                fs::remove_file(&partial).ok();
+                Err(error)
+            }
+        }
+    }
+
+    async fn write(self, path: &Path) -> Result<()> {
+        let mut builder = ar::Builder::new(fs::File::create(path)?);
 
         let mtime = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH)?;
 
@@ -130,6 +181,107 @@ impl<W: tokio::io::AsyncWrite + Unpin + Send + Sync> TarArchive<W> {
         &mut self.builder
     }
 
+    /// Appends entry of `fs` at `path` with `name`. Entries of the real filesystem
+    /// are archived natively, others are built from `metadata` and read through `fs`.
+    /// Owner and permissions are taken from stat override if there's one,
+    /// its owner names are resolved with accounts of the root.
+    ///
+    /// Returns md5 of regular file contents exactly as they were archived
+    ///
+    /// # Errors
+    /// Returns error if entry couldn't be read or added to archive
+    pub(crate) async fn append_entry<N: AsRef<Path>>(
+        &mut self,
+        fs: &dyn FileSystem,
+        path: &Path,
+        name: N,
+        metadata: &Metadata,
+        stat: Option<(&StatOverride, &Accounts)>,
+    ) -> io::Result<Option<String>> {
+        let host_path = fs.host_path(path);
+        if let (Some(host_path), None, false) = (host_path, stat, metadata.is_file()) {
+            self.builder.append_path_with_name(host_path, name).await?;
+            return Ok(None);
+        }
+
+        let mut header = Header::new_gnu();
+        header.set_mode(metadata.permissions());
+        header.set_uid(metadata.uid.into());
+        header.set_gid(metadata.gid.into());
+        header.set_mtime(u64::try_from(metadata.mtime).unwrap_or_default());
+        header.set_size(0);
+        header.set_entry_type(match metadata.kind {
+            FileKind::Directory => EntryType::Directory,
+            FileKind::Symlink => EntryType::Symlink,
+            FileKind::Fifo => EntryType::Fifo,
+            FileKind::BlockDevice => EntryType::Block,
+            FileKind::CharDevice => EntryType::Char,
+            FileKind::File | FileKind::Socket => EntryType::Regular,
+        });
+
+        if let Some((stat, accounts)) = stat {
+            header.set_mode(stat.mode);
+            if let Some(uid) = stat.uid(accounts) {
+                header.set_uid(uid.into());
+            }
+            if let Some(gid) = stat.gid(accounts) {
+                header.set_gid(gid.into());
+            }
+            // dpkg prefers names over identifiers when unpacking
+            header.set_username(stat.user.name().unwrap_or_default())?;
+            header.set_groupname(stat.group.name().unwrap_or_default())?;
+        }
+
+        match metadata.kind {
+            FileKind::File => {
+                let contents = file_contents(fs, path, host_path, metadata, &mut header).await?;
+                let mut reader = HashingReader::new(contents);
+                self.builder
+                    .append_data(&mut header, name, &mut reader)
+                    .await?;
+                Ok(Some(reader.finalize()))
+            }
+            FileKind::Symlink => {
+                self.set_link_name(&mut header, &fs.read_link(path)?)
+                    .await?;
+                self.builder
+                    .append_data(&mut header, name, tokio::io::empty())
+                    .await?;
+                Ok(None)
+            }
+            _ => {
+                self.builder
+                    .append_data(&mut header, name, tokio::io::empty())
+                    .await?;
+                Ok(None)
+            }
+        }
+    }
+
+    /// Stores link target in `header` or in preceding GNU long link entry if it doesn't fit
+    async fn set_link_name(&mut self, header: &mut Header, target: &Path) -> io::Result<()> {
+        let target = target.as_os_str().as_bytes();
+        if target.len() < LINK_NAME_LENGTH {
+            return header.set_link_name(OsStr::from_bytes(target));
+        }
+
+        let mut long_link = Header::new_gnu();
+        if let Some(gnu) = long_link.as_gnu_mut() {
+            gnu.name[..13].copy_from_slice(b"././@LongLink");
+        }
+        long_link.set_mode(0o644);
+        long_link.set_uid(0);
+        long_link.set_gid(0);
+        long_link.set_mtime(0);
+        // Name is NUL-terminated as GNU tar does
+        long_link.set_size(target.len() as u64 + 1);
+        long_link.set_entry_type(EntryType::GNULongLink);
+        long_link.set_cksum();
+
+        let data = [target, &[0]].concat();
+        self.builder.append(&long_link, data.as_slice()).await
+    }
+
     /// Appends non-existing on the filesystem file to archive
     ///
     /// # Errors
@@ -156,3 +308,102 @@ impl<W: tokio::io::AsyncWrite + Unpin + Send + Sync> TarArchive<W> {
         Ok(())
     }
 }
+
+/// Opens regular file for archiving and sets its size in `header`
+async fn file_contents(
+    fs: &dyn FileSystem,
+    path: &Path,
+    host_path: Option<&Path>,
+    metadata: &Metadata,
+    header: &mut Header,
+) -> io::Result<Box<dyn AsyncRead + Unpin + Send>> {
+    let Some(host_path) = host_path else {
+        header.set_size(metadata.len);
+        return Ok(Box::new(BlockingReader::spawn(
+            fs.open(path)?,
+            metadata.len,
+        )));
+    };
+
+    let file = tokio::fs::File::open(host_path).await?;
+    // File could change since metadata was fetched
+    let len = file.metadata().await?.len();
+    header.set_size(len);
+    Ok(Box::new(file.take(len)))
+}
+
+impl BlockingReader {
+    /// Streams exactly `len` bytes of `file` from blocking task
+    fn spawn(file: Box<dyn Read + Send>, len: u64) -> Self {
+        let (writer, inner) = tokio::io::duplex(STREAM_BUFFER_SIZE);
+        let mut writer = SyncIoBridge::new(writer);
+        let task = tokio::task::spawn_blocking(move || {
+            let copied = io::copy(&mut file.take(len), &mut writer)?;
+            if copied < len {
+                let error = format!("file was truncated to {copied} of {len} bytes");
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, error));
+            }
+            Ok(())
+        });
+
+        Self {
+            inner,
+            task: Some(task),
+        }
+    }
+}
+
+impl AsyncRead for BlockingReader {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let filled = buf.filled().len();
+        let this = &mut *self;
+        ready!(Pin::new(&mut this.inner).poll_read(cx, buf))?;
+        if buf.filled().len() > filled || buf.remaining() == 0 {
+            return Poll::Ready(Ok(()));
+        }
+
+        // Writer is dropped when task finishes, so result of the task decides
+        // whether it is the end of file or reading failed
+        let Some(task) = &mut this.task else {
+            return Poll::Ready(Ok(()));
+        };
+        let result = ready!(Pin::new(task).poll(cx));
+        this.task = None;
+        Poll::Ready(result.unwrap_or_else(|error| Err(io::Error::new(io::ErrorKind::Other, error))))
+    }
+}
+
+impl<R> HashingReader<R> {
+    fn new(inner: R) -> Self {
+        Self {
+            inner,
+            hasher: Md5::new(),
+        }
+    }
+
+    /// Lowercase hex hash as in dpkg `md5sums` files
+    fn finalize(self) -> String {
+        format!("{:x}", self.hasher.finalize())
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for HashingReader<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let filled = buf.filled().len();
+        let this = &mut *self;
+        let poll = Pin::new(&mut this.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = poll {
+            this.hasher.update(&buf.filled()[filled..]);
+        }
+
+        poll
+    }
+}
@@ -0,0 +1,201 @@
+/*
+ * Copyright 2020 DanP
+ *
+ * This file is part of Twackup
+ *
+ * Twackup is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Twackup is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Twackup. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use super::{FileOverrides, Preferences};
+use crate::{error::Result, package::Package};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    fs, io,
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+/// Name of the file in destination directory fingerprints are stored in
+pub const FINGERPRINTS_FILE_NAME: &str = ".twackup-fingerprints";
+
+/// Fingerprints of installed package contents debs in destination directory were built from.
+/// Is used for skipping packages that haven't changed since the previous build
+#[derive(Debug)]
+pub struct Fingerprints {
+    path: PathBuf,
+    entries: Mutex<HashMap<String, Fingerprint>>,
+}
+
+/// Recorded state of single built deb
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Fingerprint {
+    /// Hash of package control and metadata of its installed files
+    pub(crate) contents: String,
+    /// Hex-encoded SHA-256 checksum of the deb
+    pub(crate) sha256: String,
+    /// Size of the deb in bytes
+    pub(crate) size: u64,
+}
+
+impl Fingerprints {
+    /// Reads fingerprints stored in destination directory.
+    /// Missing file is treated as empty one
+    ///
+    /// # Errors
+    /// Returns error if file exists but couldn't be read
+    pub fn load<P: AsRef<Path>>(destination_dir: P) -> Result<Self> {
+        let path = destination_dir.as_ref().join(FINGERPRINTS_FILE_NAME);
+
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(error) => return Err(error.into()),
+        };
+
+        let entries = contents
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.split('\t');
+                let deb_name = fields.next()?.to_string();
+                let fingerprint = Fingerprint {
+                    contents: fields.next()?.to_string(),
+                    sha256: fields.next()?.to_string(),
+                    size: fields.next()?.parse().ok()?,
+                };
+                Some((deb_name, fingerprint))
+            })
+            .collect();
+
+        Ok(Self {
+            path,
+            entries: Mutex::new(entries),
+        })
+    }
+
+    /// Writes fingerprints back to destination directory.
+    /// Entries of debs that don't exist anymore are dropped
+    ///
+    /// # Errors
+    /// Returns error if file couldn't be written
+    pub fn save(&self) -> Result<()> {
+        let directory = self.path.parent().unwrap_or(Path::new("."));
+
+        let mut entries: Vec<_> = self
+            .lock()
+            .iter()
+            .filter(|(deb_name, _)| directory.join(deb_name).exists())
+            .map(|(deb_name, fingerprint)| {
+                format!(
+                    "{deb_name}\t{}\t{}\t{}\n",
+                    fingerprint.contents, fingerprint.sha256, fingerprint.size
+                )
+            })
+            .collect();
+        entries.sort();
+
+        let part = self.path.with_extension("part");
+        fs::write(&part, entries.concat())?;
+        fs::rename(part, &self.path)?;
+
+        Ok(())
+    }
+
+    pub(crate) fn get(&self, deb_name: &str) -> Option<Fingerprint> {
+        self.lock().get(deb_name).cloned()
+    }
+
+    pub(crate) fn insert(&self, deb_name: String, fingerprint: Fingerprint) {
+        self.lock().insert(deb_name, fingerprint);
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, Fingerprint>> {
+        // Map stays consistent even if some worker panicked while holding the lock
+        self.entries
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+}
+
+/// Hashes package control and build preferences affecting deb contents together with
+/// path, type, permissions, size and modification time of every installed file and metadata file.
+/// Installed files are pairs of path listed by package and path the file is read from,
+/// diversions and stat overrides of listed paths are hashed as well
+pub(crate) fn contents_fingerprint<'a, I>(
+    preferences: &Preferences,
+    package: &Package,
+    overrides: &FileOverrides,
+    files: &[(PathBuf, PathBuf)],
+    metadata_files: I,
+) -> String
+where
+    I: Iterator<Item = &'a PathBuf>,
+{
+    let fs = preferences.paths.fs();
+    let follow_symlinks = preferences.follow_symlinks;
+
+    let mut hasher = Sha256::new();
+    hasher.update(package.to_control());
+    hasher.update(preferences.compression.r#type.as_str());
+    hasher.update(preferences.compression.level.raw_value().to_be_bytes());
+    hasher.update([u8::from(follow_symlinks)]);
+
+    for (listed, _) in files {
+        if let Some(diversion) = overrides.diversions.get(listed) {
+            hasher.update(diversion.to.as_os_str().as_bytes());
+            hasher.update(diversion.package.as_deref().unwrap_or_default());
+            hasher.update([0]);
+        }
+        if let Some(stat) = overrides.stat.get(listed) {
+            let (uid, gid) = (stat.uid(&overrides.accounts), stat.gid(&overrides.accounts));
+            let stat = format!(
+                "{} {} {uid:?} {gid:?} {:o}",
+                stat.user, stat.group, stat.mode
+            );
+            hasher.update(stat);
+            hasher.update([0]);
+        }
+    }
+
+    let mut metadata_files: Vec<_> = metadata_files.collect();
+    metadata_files.sort();
+
+    let files = (files.iter().map(|(_, source)| source.as_path()))
+        .chain(metadata_files.into_iter().map(PathBuf::as_path));
+    for file in files {
+        hasher.update(file.as_os_str().as_bytes());
+
+        let metadata = if follow_symlinks {
+            fs.metadata(file)
+        } else {
+            fs.symlink_metadata(file)
+        };
+
+        match metadata {
+            Ok(metadata) => {
+                hasher.update(metadata.mode.to_be_bytes());
+                hasher.update(metadata.uid.to_be_bytes());
+                hasher.update(metadata.gid.to_be_bytes());
+                hasher.update(metadata.len.to_be_bytes());
+                hasher.update(metadata.mtime.to_be_bytes());
+                hasher.update(metadata.mtime_nsec.to_be_bytes());
+            }
+            Err(error) => hasher.update(error.kind().to_string()),
+        }
+        hasher.update([0]);
+    }
+
+    format!("{:x}", hasher.finalize())
+}
@@ -24,16 +24,18 @@
 //!
 //! ```no_run
 //! use twackup::builder::{Worker, Preferences};
-//! use twackup::{Result, Dpkg, progress::Progress, package::Package};
+//! use twackup::{Result, Dpkg, progress::{Event, Progress}, package::Package};
 //! use std::{collections::HashSet, sync::Arc, path::Path};
 //!
 //! // some progress struct btw
 //! struct ProgressImpl;
 //!
 //! impl Progress for ProgressImpl {
-//!     fn started_processing(&self, _package: &Package) {}
-//!     fn finished_processing<P: AsRef<Path>>(&self, _package: &Package, _deb_path: P) {}
-//!     fn finished_all(&self) {}
+//!     fn on_event(&self, event: Event<'_>) {
+//!         if let Event::Failed { package, error } = event {
+//!             eprintln!("{} failed: {error}", package.id);
+//!         }
+//!     }
 //! }
 //!
 //! #[tokio::main]
@@ -47,8 +49,11 @@
 //!     let dpkg_contents = Arc::new(HashSet::new());
 //!
 //!     let worker = Worker::new(&package, progress, None, preferences, dpkg_contents);
-//!     let deb_path = worker.run().await?;
-//!     println!("Deb is located at {:?}", deb_path);
+//!     let report = worker.run().await?;
+//!     println!("Deb is located at {:?}", report.deb_path);
+//!     if report.is_incomplete() {
+//!         println!("Some files are missing: {:?}", report.missing_files);
+//!     }
 //!
 //!     Ok(())
 //! }
@@ -62,30 +67,56 @@
 //! ```
 //!
 
+mod archive;
 mod deb;
+mod incremental;
+mod report;
+mod session;
+mod verify;
 
 use crate::{
     archiver::Compression,
     dpkg::Paths,
     error::{Generic, Result},
+    overrides::{Accounts, Diversions, StatOverrides},
     package::Package,
-    progress::Progress,
+    progress::{Event, Progress},
+    vfs::FileKind,
 };
+pub use archive::AllPackagesArchive;
 use deb::{Deb, DebianInnerTar};
+use incremental::Fingerprint;
+pub use incremental::{Fingerprints, FINGERPRINTS_FILE_NAME};
+pub use report::{BuildReport, UnreadableFile};
+pub use session::{BuildSession, BuildStats, BuildSummary, PackageResult};
+use sha2::{Digest, Sha256};
 use std::{
-    collections::HashSet,
+    borrow::Cow,
+    collections::{HashMap, HashSet},
     fs, io,
     path::{Path, PathBuf},
     sync::Arc,
 };
-use tokio::{fs::File, sync::Mutex};
-
-/// Alias for creating archive for all packaged files. Probably should be removed
-pub type AllPackagesArchive = Arc<Mutex<tokio_tar::Builder<File>>>;
+pub use tokio_util::sync::CancellationToken;
+pub use verify::{Mismatch, MismatchKind, Verification};
+
+/// Package metadata files from dpkg info directory that are placed in control archive
+const CONTROL_MEMBERS: [&str; 9] = [
+    "md5sums",
+    "preinst",
+    "postinst",
+    "prerm",
+    "postrm",
+    "extrainst_",
+    "conffiles",
+    "config",
+    "shlibs",
+];
 
 /// Builder preferences
 #[derive(Clone, Debug)]
 #[non_exhaustive]
+#[allow(clippy::struct_excessive_bools)]
 pub struct Preferences {
     /// Should remove deb after packaging. Probably should be removed
     /// false by default
@@ -96,6 +127,19 @@ pub struct Preferences {
     /// Should follow symlinks while creating deb or not.
     /// Disabling can produce broken debs.
     pub follow_symlinks: bool,
+    /// Should fail the build if some of package files couldn't be archived
+    /// instead of producing incomplete deb.
+    /// false by default
+    pub strict: bool,
+    /// Should read deb back after building and compare it with installed files.
+    /// Result is placed in [`BuildReport::verification`].
+    /// false by default
+    pub verify: bool,
+    /// Should skip packages whose installed files haven't changed since the deb
+    /// in destination directory was built. Only [`BuildSession`] loads and saves fingerprints,
+    /// standalone workers need them to be set with [`Worker::fingerprints`].
+    /// false by default
+    pub incremental: bool,
     /// Dpkg dir paths
     paths: Paths,
     /// Directory to which final deb should be moved
@@ -110,6 +154,31 @@ pub struct Worker<'a, T: Progress> {
     archive: Option<AllPackagesArchive>,
     preferences: Preferences,
     dpkg_contents: Arc<HashSet<PathBuf>>,
+    cancellation: CancellationToken,
+    fingerprints: Option<Arc<Fingerprints>>,
+    overrides: Option<Arc<FileOverrides>>,
+}
+
+/// Diversions and stat overrides from dpkg database applied to package files
+#[derive(Debug)]
+pub(crate) struct FileOverrides {
+    diversions: Diversions,
+    stat: StatOverrides,
+    /// Accounts of the root owner names of stat overrides are resolved with
+    accounts: Accounts,
+}
+
+impl FileOverrides {
+    /// Reads both databases from dpkg directory and accounts from root
+    pub(crate) fn load(paths: &Paths) -> Result<Self> {
+        let diversions = paths.read_optional(&paths.diversions_file())?;
+        let stat = paths.read_optional(&paths.statoverride_file())?;
+        Ok(Self {
+            diversions: Diversions::parse(&diversions)?,
+            stat: StatOverrides::parse(&stat)?,
+            accounts: Accounts::load(paths)?,
+        })
+    }
 }
 
 impl Preferences {
@@ -124,6 +193,9 @@ impl Preferences {
             remove_deb: false,
             compression: Compression::default(),
             follow_symlinks: false,
+            strict: false,
+            verify: false,
+            incremental: false,
             paths: admin_dir.into(),
             destination_dir: destination_dir.as_ref().to_path_buf(),
         }
@@ -153,54 +225,226 @@ impl<'a, T: Progress> Worker<'a, T> {
             archive,
             preferences,
             dpkg_contents,
+            cancellation: CancellationToken::new(),
+            fingerprints: None,
+            overrides: None,
         }
     }
 
+    /// Sets token which stops worker when cancelled.
+    /// Worker removes its unfinished deb and returns [`Generic::Cancelled`]
+    #[inline]
+    #[must_use]
+    pub fn cancellation(mut self, token: CancellationToken) -> Self {
+        self.cancellation = token;
+        self
+    }
+
+    /// Sets fingerprints of previously built debs. Worker skips package if its deb
+    /// is up to date and records new fingerprint after building otherwise.
+    /// Has effect only if [`Preferences::incremental`] is set
+    #[inline]
+    #[must_use]
+    pub fn fingerprints(mut self, fingerprints: Arc<Fingerprints>) -> Self {
+        self.fingerprints = Some(fingerprints);
+        self
+    }
+
+    /// Sets already loaded diversions and stat overrides,
+    /// so every worker doesn't read them from dpkg database again
+    #[inline]
+    #[must_use]
+    pub(crate) fn overrides(mut self, overrides: Arc<FileOverrides>) -> Self {
+        self.overrides = Some(overrides);
+        self
+    }
+
     /// Runs worker
     ///
     /// # Errors
-    /// Returns error if temp dir creation or any of underlying package operation failed
+    /// Returns error if temp dir creation or any of underlying package operation failed.
+    /// Also returns error if [`Preferences::strict`] is set and some files couldn't be archived
+    /// or if worker was cancelled
     #[inline]
-    pub async fn run(&self) -> Result<PathBuf> {
-        self.progress.started_processing(self.package);
+    pub async fn run(&self) -> Result<BuildReport> {
+        let package = self.package;
+        let result = self.build().await;
+        match &result {
+            Ok(report) => self.progress.on_event(Event::Finished { package, report }),
+            Err(error) => self.progress.on_event(Event::Failed { package, error }),
+        }
+
+        result
+    }
+
+    async fn build(&self) -> Result<BuildReport> {
+        self.check_cancelled()?;
+        self.progress.on_event(Event::Started {
+            package: self.package,
+        });
 
         let deb_name = format!("{}.deb", self.package.canonical_name());
-        let deb_path = self.preferences.destination_dir.join(deb_name);
+        let deb_path = self.preferences.destination_dir.join(&deb_name);
+
+        let mut report = BuildReport {
+            deb_path: deb_path.clone(),
+            ..BuildReport::default()
+        };
+
+        let overrides = self.file_overrides()?;
+        let fingerprint = self.contents_fingerprint(&overrides)?;
+        let stored = (fingerprint.as_deref())
+            .and_then(|fingerprint| self.up_to_date_fingerprint(&deb_name, fingerprint));
+        if let Some(stored) = stored {
+            report.up_to_date = true;
+            report.deb_size = stored.size;
+            report.deb_sha256 = stored.sha256;
+            self.add_to_archive(&deb_path).await?;
+
+            return Ok(report);
+        }
 
         let mut deb = Deb::new(
             &deb_path,
             self.preferences.compression,
             self.preferences.follow_symlinks,
         )?;
-        self.archive_files(deb.data_mut_ref()).await?;
-        self.archive_metadata(deb.control_mut_ref()).await?;
+        let md5sums = self
+            .archive_files(deb.data_mut_ref(), &overrides, &mut report)
+            .await?;
+        self.archive_metadata(deb.control_mut_ref(), &mut report)
+            .await?;
+
+        if self.preferences.strict && report.is_incomplete() {
+            return Err(Generic::IncompletePackage {
+                package: self.package.id.clone(),
+                missing: report.missing_files.len(),
+                unreadable: report.unreadable_files.len(),
+            });
+        }
+
+        self.check_cancelled()?;
         deb.build().await?;
+        report.deb_size = fs::metadata(&deb_path)?.len();
+        report.deb_sha256 = sha256_file(&deb_path)?;
+
+        if let (Some(fingerprints), Some(contents)) = (&self.fingerprints, fingerprint) {
+            let fingerprint = Fingerprint {
+                contents,
+                sha256: report.deb_sha256.clone(),
+                size: report.deb_size,
+            };
+            fingerprints.insert(deb_name, fingerprint);
+        }
+
+        if self.preferences.verify {
+            report.verification = Some(self.verify(&deb_path, &md5sums).await?);
+        }
 
         self.add_to_archive(&deb_path).await?;
 
-        self.progress.finished_processing(self.package, &deb_path);
-        Ok(deb_path)
+        Ok(report)
     }
 
-    /// Archives package files and compresses in a single archive
+    /// Archives package files and compresses in a single archive.
+    /// Diverted files are read from their new location, but keep original names.
+    /// Returns md5 hashes of archived regular files keyed by their names in archive
     ///
     /// # Errors
     /// Returns error if dpkg directory couldn't be read or any of underlying operation failed
-    async fn archive_files(&self, archiver: &mut DebianInnerTar) -> io::Result<()> {
-        let files = self
-            .package
-            .get_installed_files(self.preferences.paths.as_ref())?;
+    async fn archive_files(
+        &self,
+        archiver: &mut DebianInnerTar,
+        overrides: &FileOverrides,
+        report: &mut BuildReport,
+    ) -> Result<HashMap<PathBuf, String>> {
+        let fs = self.preferences.paths.fs();
+        let files = self.package.get_installed_files(&self.preferences.paths)?;
+        let mut md5sums = HashMap::new();
 
         for file in files {
+            self.check_cancelled()?;
+
             // Remove root slash because tars don't contain absolute paths
             let name = file.trim_start_matches('/');
-            let res = archiver.get_mut().append_path_with_name(&file, name).await;
-            if let Err(error) = res {
-                log::warn!(target: &self.package.id, "{}", error);
+            if name.is_empty() {
+                report.skipped_files.push(PathBuf::from(file));
+                continue;
+            }
+
+            let metadata = self
+                .source_path(overrides, Path::new(&file))
+                .and_then(|source| {
+                    let metadata = if self.preferences.follow_symlinks {
+                        fs.metadata(&source)
+                    } else {
+                        fs.symlink_metadata(&source)
+                    };
+                    Ok((source, metadata?))
+                });
+
+            let (source, metadata) = match metadata {
+                Ok(metadata) => metadata,
+                Err(error) if error.kind() == io::ErrorKind::NotFound => {
+                    log::warn!(target: &self.package.id, "{} is missing", file);
+                    report.missing_files.push(PathBuf::from(file));
+                    continue;
+                }
+                Err(error) => {
+                    log::warn!(target: &self.package.id, "{}", error);
+                    report.unreadable_files.push(UnreadableFile {
+                        path: PathBuf::from(file),
+                        error: error.to_string(),
+                    });
+                    continue;
+                }
+            };
+
+            // Sockets are created by running processes and can't be archived
+            if metadata.kind == FileKind::Socket {
+                report.skipped_files.push(PathBuf::from(file));
+                continue;
+            }
+
+            let stat =
+                (overrides.stat.get(&file)).filter(|_| metadata.is_file() || metadata.is_dir());
+            let stat = stat.map(|stat| (stat, &overrides.accounts));
+            let res = archiver
+                .append_entry(fs, &source, name, &metadata, stat)
+                .await;
+            match res {
+                Ok(md5) => {
+                    if let Some(md5) = md5 {
+                        md5sums.insert(PathBuf::from(name), md5);
+                    }
+
+                    let bytes = if metadata.is_dir() { 0 } else { metadata.len };
+                    report.archived_size += bytes;
+
+                    let package = self.package;
+                    let path = Path::new(&file);
+                    self.progress.on_event(Event::FileArchived {
+                        package,
+                        path,
+                        bytes,
+                    });
+                    self.progress.on_event(Event::Compression {
+                        package,
+                        processed: report.archived_size,
+                        compressed: archiver.get_mut().get_ref().get_ref().len() as u64,
+                    });
+                }
+                Err(error) => {
+                    log::warn!(target: &self.package.id, "{}", error);
+                    report.unreadable_files.push(UnreadableFile {
+                        path: PathBuf::from(file),
+                        error: error.to_string(),
+                    });
+                }
             }
         }
 
-        Ok(())
+        Ok(md5sums)
     }
 
     /// Collects package metadata such as install scripts,
@@ -208,36 +452,120 @@ impl<'a, T: Progress> Worker<'a, T> {
     ///
     /// # Errors
     /// Returns error if control file couldn't be appended
-    async fn archive_metadata(&self, archiver: &mut DebianInnerTar) -> Result<()> {
+    async fn archive_metadata(
+        &self,
+        archiver: &mut DebianInnerTar,
+        report: &mut BuildReport,
+    ) -> Result<()> {
         // Order in this archive doesn't matter. So we'll add control at first
         archiver
             .append_new_file("control", self.package.to_control().as_bytes())
             .await?;
 
-        let possible_extensions = [
-            "md5sums",
-            "preinst",
-            "postinst",
-            "prerm",
-            "postrm",
-            "extrainst_",
-            "conffiles",
-            "config",
-            "shlibs",
-        ];
-
-        let contents = self.dpkg_contents.iter().filter_map(|entry| {
+        let fs = self.preferences.paths.fs();
+        for (path, ext) in self.control_members() {
+            let res = match fs.metadata(path) {
+                Ok(metadata) => archiver.append_entry(fs, path, ext, &metadata, None).await,
+                Err(error) => Err(error),
+            };
+            match res {
+                Ok(_) => report.control_members.push(ext.to_string()),
+                Err(error) => {
+                    log::warn!(target: &self.package.id, "{}", error);
+                    report.unreadable_files.push(UnreadableFile {
+                        path: path.clone(),
+                        error: error.to_string(),
+                    });
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Searches package metadata files in dpkg info directory
+    /// which should be placed in control archive
+    fn control_members(&self) -> impl Iterator<Item = (&PathBuf, &str)> {
+        self.dpkg_contents.iter().filter_map(|entry| {
             let file_name = entry.file_name()?.to_str()?;
             let rem = file_name.strip_prefix(&self.package.id)?;
             let rem = rem.strip_prefix('.')?;
-            possible_extensions.contains(&rem).then_some((entry, rem))
-        });
+            CONTROL_MEMBERS.contains(&rem).then_some((entry, rem))
+        })
+    }
 
-        for (path, ext) in contents {
-            let res = archiver.get_mut().append_path_with_name(path, ext).await;
-            if let Err(error) = res {
-                log::warn!(target: &self.package.id, "{}", error);
-            }
+    /// Returns diversions and stat overrides set by session or reads them from dpkg database
+    fn file_overrides(&self) -> Result<Arc<FileOverrides>> {
+        match &self.overrides {
+            Some(overrides) => Ok(overrides.clone()),
+            None => Ok(Arc::new(FileOverrides::load(&self.preferences.paths)?)),
+        }
+    }
+
+    /// Location in the filesystem of the file package lists at `path`.
+    /// Diverted files are stored at other path, files of other root are resolved under it
+    fn source_path<'p>(
+        &self,
+        overrides: &'p FileOverrides,
+        path: &'p Path,
+    ) -> io::Result<Cow<'p, Path>> {
+        let source = overrides.diversions.source_of(path, &self.package.id);
+        self.preferences
+            .paths
+            .resolve(source, self.preferences.follow_symlinks)
+    }
+
+    /// Computes fingerprint of installed package contents if incremental build is enabled
+    fn contents_fingerprint(&self, overrides: &FileOverrides) -> Result<Option<String>> {
+        if !self.preferences.incremental || self.fingerprints.is_none() {
+            return Ok(None);
+        }
+
+        let files: Vec<_> = self
+            .package
+            .get_installed_files(&self.preferences.paths)?
+            .iter()
+            .map(|file| {
+                let listed = PathBuf::from(file);
+                let source = match self.source_path(overrides, &listed) {
+                    Ok(source) => source.into_owned(),
+                    Err(_) => listed.clone(),
+                };
+                (listed, source)
+            })
+            .collect();
+        let metadata_files = self.control_members().map(|(path, _)| path);
+
+        Ok(Some(incremental::contents_fingerprint(
+            &self.preferences,
+            self.package,
+            overrides,
+            &files,
+            metadata_files,
+        )))
+    }
+
+    /// Returns stored fingerprint if deb exists, was built from the same contents
+    /// and wasn't modified since then
+    fn up_to_date_fingerprint(&self, deb_name: &str, contents: &str) -> Option<Fingerprint> {
+        let stored = self.fingerprints.as_ref()?.get(deb_name)?;
+        if stored.contents != contents {
+            return None;
+        }
+
+        let deb_path = self.preferences.destination_dir.join(deb_name);
+        let size = fs::metadata(&deb_path).ok()?.len();
+        // Size check avoids hashing debs that were obviously replaced
+        if size != stored.size || sha256_file(&deb_path).ok()? != stored.sha256 {
+            return None;
+        }
+
+        Some(stored)
+    }
+
+    fn check_cancelled(&self) -> Result<()> {
+        if self.cancellation.is_cancelled() {
+            return Err(Generic::Cancelled);
         }
 
         Ok(())
@@ -249,12 +577,10 @@ impl<'a, T: Progress> Worker<'a, T> {
     /// Returns error if any of underlying operations failed
     async fn add_to_archive(&self, file: &PathBuf) -> Result<()> {
         if let Some(ref archive) = self.archive {
-            let mut archive = archive.try_lock().map_err(|_| Generic::Lock)?;
-
             let file_name = file.file_name().ok_or(Generic::PathMustHaveFileEnding)?;
             let abs_file = Path::new(".").join(file_name);
 
-            archive.append_path_with_name(&file, &abs_file).await?;
+            archive.append(file, abs_file).await?;
 
             if self.preferences.remove_deb {
                 fs::remove_file(file).ok();
@@ -265,56 +591,381 @@ impl<'a, T: Progress> Worker<'a, T> {
     }
 }
 
+/// Computes hex-encoded SHA-256 checksum of the file
+pub(crate) fn sha256_file<P: AsRef<Path>>(path: P) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut fs::File::open(path)?, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
 #[cfg(test)]
 mod tests {
     use crate::{
-        builder::{Preferences, Worker},
-        package::Package,
-        progress::Progress,
+        builder::{MismatchKind, Preferences, Worker},
+        deb::Deb,
+        progress::{Event, Progress},
+        testing::{temp_dir, NoProgress, DPKG_DIR},
+        vfs::MemoryFs,
         Dpkg, Result,
     };
-    use std::{fs, path::Path, process::Command, sync::Arc};
-
-    struct ProgressImpl;
+    use md5::{Digest, Md5};
+    use std::{
+        fs,
+        os::unix::fs::PermissionsExt,
+        path::Path,
+        sync::{Arc, Mutex},
+    };
 
-    impl Progress for ProgressImpl {
-        fn started_processing(&self, _package: &Package) {}
-        fn finished_processing<P: AsRef<Path>>(&self, _package: &Package, _deb_path: P) {}
-        fn finished_all(&self) {}
+    #[derive(Default)]
+    struct RecordingProgress(Mutex<Vec<String>>);
+
+    impl Progress for &RecordingProgress {
+        fn on_event(&self, event: Event<'_>) {
+            let name = match event {
+                Event::Started { .. } => "started".to_string(),
+                Event::FileArchived { path, .. } => format!("archived {}", path.display()),
+                Event::Compression { .. } => return,
+                Event::Failed { .. } => "failed".to_string(),
+                Event::Finished { .. } => "finished".to_string(),
+                Event::AllFinished => "all finished".to_string(),
+            };
+            self.0.lock().unwrap().push(name);
+        }
     }
 
     #[tokio::test]
     async fn package_build_correctness() -> Result<()> {
-        let dpkg_dir = concat!(env!("CARGO_MANIFEST_DIR"), "/assets/dpkg_database_dir");
-        let dpkg = Dpkg::new(dpkg_dir, false);
+        let dpkg = Dpkg::new(DPKG_DIR, false);
 
         let mut packages = dpkg.unsorted_packages(false).await?;
         let package = packages.pop_back().unwrap();
 
         let dpkg_contents = Arc::new(dpkg.info_dir_contents()?);
-        let preferences = Preferences::new(dpkg_dir, "/tmp");
+        let preferences = Preferences::new(DPKG_DIR, "/tmp");
 
-        let worker = Worker::new(&package, ProgressImpl, None, preferences, dpkg_contents);
-        let deb_path = worker.run().await?;
+        let worker = Worker::new(&package, NoProgress, None, preferences, dpkg_contents);
+        let deb_path = worker.run().await?.deb_path;
 
-        let dpkg = Command::new("dpkg")
-            .args(["-I", deb_path.to_str().unwrap()])
-            .output()?;
-        let output = String::from_utf8(dpkg.stdout).unwrap();
+        let deb = Deb::open(&deb_path)?;
 
-        assert!(output.contains("Package: hosts"));
-        assert!(output.contains("Version: 1.0.0"));
-        assert!(output.contains("preinst"));
+        let control = deb.package().await?;
+        assert_eq!(control.id, "hosts");
+        assert_eq!(control.version, "1.0.0");
 
-        let dpkg = Command::new("dpkg")
-            .args(["-c", deb_path.to_str().unwrap()])
-            .output()?;
-        let output = String::from_utf8(dpkg.stdout).unwrap();
+        let control_entries = deb.control_entries().await?;
+        assert!(control_entries
+            .iter()
+            .any(|entry| entry.path == Path::new("preinst")));
 
-        assert!(output.contains("etc/hosts"));
+        let data_entries = deb.data_entries().await?;
+        assert!(data_entries
+            .iter()
+            .any(|entry| entry.path == Path::new("etc/hosts")));
 
         fs::remove_file(deb_path)?;
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn rebuilt_package_verification() -> Result<()> {
+        let dpkg = Dpkg::new(DPKG_DIR, false);
+
+        let mut packages = dpkg.unsorted_packages(false).await?;
+        let package = packages.pop_back().unwrap();
+
+        let destination = temp_dir("twackup-verification")?;
+
+        let dpkg_contents = Arc::new(dpkg.info_dir_contents()?);
+        let mut preferences = Preferences::new(DPKG_DIR, &destination);
+        preferences.verify = true;
+
+        let worker = Worker::new(&package, NoProgress, None, preferences, dpkg_contents);
+        let report = worker.run().await?;
+        assert!(!report.is_incomplete(), "{report:?}");
+        assert!(report.control_members.iter().any(|name| name == "preinst"));
+        assert!(report.deb_size > 0);
+
+        let verification = report.verification.unwrap();
+        assert!(verification.is_ok(), "{verification:?}");
+        assert_eq!(verification.checked_entries, 2);
+
+        fs::remove_dir_all(destination)?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn progress_events() -> Result<()> {
+        let dpkg = Dpkg::new(DPKG_DIR, false);
+
+        let mut packages = dpkg.unsorted_packages(false).await?;
+        let package = packages.pop_back().unwrap();
+
+        let destination = temp_dir("twackup-progress-events")?;
+
+        let dpkg_contents = Arc::new(dpkg.info_dir_contents()?);
+        let preferences = Preferences::new(DPKG_DIR, &destination);
+        let progress = RecordingProgress::default();
+
+        let worker = Worker::new(&package, &progress, None, preferences, dpkg_contents);
+        worker.run().await?;
+
+        let events = progress.0.into_inner().unwrap();
+        assert_eq!(
+            events,
+            [
+                "started",
+                "archived /etc",
+                "archived /etc/hosts",
+                "finished"
+            ]
+        );
+
+        fs::remove_dir_all(destination)?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn diversions_and_stat_overrides() -> Result<()> {
+        let directory = temp_dir("twackup-overrides")?;
+        let admin_dir = directory.join("dpkg");
+        let files = directory.join("files");
+        fs::create_dir_all(admin_dir.join("info"))?;
+        fs::create_dir_all(&files)?;
+
+        let tool = files.join("tool").to_string_lossy().into_owned();
+        fs::write(&tool, "diverting")?;
+        fs::write(files.join("tool.real"), "original")?;
+
+        fs::write(
+            admin_dir.join("status"),
+            "Package: tool\nVersion: 1.0\nArchitecture: all\nStatus: install ok installed\nSection: Utilities\n",
+        )?;
+        fs::write(admin_dir.join("info/tool.list"), format!("/.\n{tool}\n"))?;
+        fs::write(
+            admin_dir.join("diversions"),
+            format!("{tool}\n{tool}.real\nfancy-tool\n"),
+        )?;
+        fs::write(
+            admin_dir.join("statoverride"),
+            format!("root #0 4750 {tool}\n"),
+        )?;
+
+        let dpkg = Dpkg::new(&admin_dir, false);
+        let package = dpkg.unsorted_packages(false).await?.pop_back().unwrap();
+
+        let dpkg_contents = Arc::new(dpkg.info_dir_contents()?);
+        let preferences = Preferences::new(&admin_dir, &directory);
+        let worker = Worker::new(&package, NoProgress, None, preferences, dpkg_contents);
+        let report = worker.run().await?;
+        assert!(!report.is_incomplete(), "{report:?}");
+
+        let entries = Deb::open(&report.deb_path)?.data_entries().await?;
+        let entry = entries
+            .iter()
+            .find(|entry| Path::new("/").join(&entry.path) == Path::new(&tool))
+            .unwrap();
+        assert_eq!(entry.size, "original".len() as u64);
+        assert_eq!(entry.mode & 0o7777, 0o4750);
+        assert_eq!((entry.uid, entry.gid), (0, 0));
+
+        fs::remove_dir_all(directory)?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn package_from_other_root() -> Result<()> {
+        let root = temp_dir("twackup-other-root")?;
+        let admin_dir = root.join("private/jb/var/lib/dpkg");
+        fs::create_dir_all(admin_dir.join("info"))?;
+        fs::create_dir_all(root.join("private/jb/usr/bin"))?;
+        fs::create_dir_all(root.join("var"))?;
+        std::os::unix::fs::symlink("/private/jb", root.join("var/jb"))?;
+        fs::write(root.join("private/jb/usr/bin/tool"), "tool")?;
+        let permissions = fs::Permissions::from_mode(0o755);
+        fs::set_permissions(root.join("private/jb/usr/bin/tool"), permissions)?;
+
+        fs::write(
+            admin_dir.join("status"),
+            "Package: tool\nVersion: 1.0\nArchitecture: iphoneos-arm64\nStatus: install ok installed\nSection: Utilities\n",
+        )?;
+        fs::write(
+            admin_dir.join("info/tool.list"),
+            "/.\n/var/jb\n/var/jb/usr\n/var/jb/usr/bin\n/var/jb/usr/bin/tool\n",
+        )?;
+
+        // Owner names are resolved with accounts of the root, not of the host
+        fs::create_dir_all(root.join("etc"))?;
+        fs::write(
+            root.join("etc/passwd"),
+            "mobile:*:501:501::/var/mobile:/bin/sh\n",
+        )?;
+        fs::write(root.join("etc/group"), "mobile:*:501:mobile\n")?;
+        fs::write(
+            admin_dir.join("statoverride"),
+            "mobile mobile 0755 /var/jb/usr/bin/tool\n",
+        )?;
+
+        let dpkg = Dpkg::with_root(&root, "/var/jb/var/lib/dpkg", false);
+        let package = dpkg.unsorted_packages(false).await?.pop_back().unwrap();
+
+        let dpkg_contents = Arc::new(dpkg.info_dir_contents()?);
+        let mut preferences = Preferences::new(&dpkg.paths, &root);
+        preferences.verify = true;
+        let worker = Worker::new(&package, NoProgress, None, preferences, dpkg_contents);
+        let report = worker.run().await?;
+        assert!(!report.is_incomplete(), "{report:?}");
+        assert!(report.verification.unwrap().is_ok());
+
+        let entries = Deb::open(&report.deb_path)?.data_entries().await?;
+        let tool = entries
+            .iter()
+            .find(|entry| entry.path == Path::new("var/jb/usr/bin/tool"))
+            .unwrap();
+        assert_eq!(tool.size, 4);
+        assert_eq!((tool.uid, tool.gid), (501, 501));
+
+        // Symlink itself is archived, not the directory it points to
+        let jb = entries
+            .iter()
+            .find(|entry| entry.path == Path::new("var/jb"))
+            .unwrap();
+        assert_eq!(jb.link_name.as_deref(), Some(Path::new("/private/jb")));
+
+        fs::remove_dir_all(root)?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn package_from_memory_fs() -> Result<()> {
+        let destination = temp_dir("twackup-memory-fs")?;
+
+        let long_target = format!("/usr/share/{}/tool", "nested".repeat(20));
+        let mut snapshot = MemoryFs::new();
+        snapshot.insert_file(
+            "/var/lib/dpkg/status",
+            "Package: tool\nVersion: 1.0\nArchitecture: all\nStatus: install ok installed\nSection: Utilities\n",
+            0o644,
+        );
+        snapshot.insert_file(
+            "/var/lib/dpkg/info/tool.list",
+            "/.\n/usr\n/usr/bin\n/usr/bin/tool\n/usr/bin/alias\n",
+            0o644,
+        );
+        snapshot.insert_file("/var/lib/dpkg/info/tool.postinst", "#!/bin/sh\n", 0o755);
+        snapshot
+            .insert_file("/usr/bin/tool", "#!/bin/sh\necho tool\n", 0o4755)
+            .uid = 501;
+        snapshot.insert_symlink("/usr/bin/alias", &long_target);
+
+        // Lock is skipped as database isn't located in the real filesystem
+        let dpkg = Dpkg::new("/var/lib/dpkg", true).filesystem(snapshot);
+        let package = dpkg.unsorted_packages(false).await?.pop_back().unwrap();
+        assert_eq!(package.get_installed_files(&dpkg.paths)?.len(), 5);
+
+        let dpkg_contents = Arc::new(dpkg.info_dir_contents()?);
+        let mut preferences = Preferences::new(&dpkg.paths, &destination);
+        preferences.verify = true;
+        let worker = Worker::new(&package, NoProgress, None, preferences, dpkg_contents);
+        let report = worker.run().await?;
+        assert!(!report.is_incomplete(), "{report:?}");
+        assert!(report.verification.unwrap().is_ok());
+        assert_eq!(report.control_members, ["postinst"]);
+
+        let entries = Deb::open(&report.deb_path)?.data_entries().await?;
+        let find = |path: &str| entries.iter().find(|entry| entry.path == Path::new(path));
+        let tool = find("usr/bin/tool").unwrap();
+        assert_eq!((tool.size, tool.mode, tool.uid), (20, 0o4755, 501));
+        let alias = find("usr/bin/alias").unwrap();
+        assert_eq!(alias.link_name, Some(long_target.into()));
+
+        fs::remove_dir_all(destination)?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn file_truncated_while_archiving() -> Result<()> {
+        let destination = temp_dir("twackup-truncated-file")?;
+
+        let mut snapshot = MemoryFs::new();
+        snapshot.insert_file(
+            "/var/lib/dpkg/status",
+            "Package: tool\nVersion: 1.0\nArchitecture: all\nStatus: install ok installed\nSection: Utilities\n",
+            0o644,
+        );
+        snapshot.insert_file("/var/lib/dpkg/info/tool.list", "/usr/bin/tool\n", 0o644);
+        // Metadata is fetched before file is read, so file could shrink in between
+        snapshot.insert_file("/usr/bin/tool", "tool", 0o755).len = 100;
+
+        let dpkg = Dpkg::new("/var/lib/dpkg", false).filesystem(snapshot);
+        let package = dpkg.unsorted_packages(false).await?.pop_back().unwrap();
+
+        let dpkg_contents = Arc::new(dpkg.info_dir_contents()?);
+        let preferences = Preferences::new(&dpkg.paths, &destination);
+        let worker = Worker::new(&package, NoProgress, None, preferences, dpkg_contents);
+        let report = worker.run().await?;
+
+        assert_eq!(report.unreadable_files.len(), 1, "{report:?}");
+        let unreadable = &report.unreadable_files[0];
+        assert_eq!(unreadable.path, Path::new("/usr/bin/tool"));
+        assert!(unreadable.error.contains("truncated"), "{unreadable:?}");
+
+        fs::remove_dir_all(destination)?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn verification_uses_hashes_of_archived_files() -> Result<()> {
+        let destination = temp_dir("twackup-archived-hashes")?;
+
+        let mut snapshot = MemoryFs::new();
+        snapshot.insert_file(
+            "/var/lib/dpkg/status",
+            "Package: tool\nVersion: 1.0\nArchitecture: all\nStatus: install ok installed\nSection: Utilities\n",
+            0o644,
+        );
+        snapshot.insert_file(
+            "/var/lib/dpkg/info/tool.list",
+            "/usr\n/usr/bin\n/usr/bin/tool\n/usr/bin/helper\n",
+            0o644,
+        );
+        let md5sums = format!(
+            "{:x}  usr/bin/tool\n{:x}  usr/bin/helper\n",
+            Md5::digest("tool"),
+            Md5::digest("original helper"),
+        );
+        snapshot.insert_file("/var/lib/dpkg/info/tool.md5sums", md5sums, 0o644);
+        snapshot.insert_file("/usr/bin/tool", "tool", 0o755);
+        snapshot.insert_file("/usr/bin/helper", "modified helper", 0o755);
+
+        let dpkg = Dpkg::new("/var/lib/dpkg", false).filesystem(snapshot);
+        let package = dpkg.unsorted_packages(false).await?.pop_back().unwrap();
+
+        let dpkg_contents = Arc::new(dpkg.info_dir_contents()?);
+        let mut preferences = Preferences::new(&dpkg.paths, &destination);
+        preferences.verify = true;
+        let worker = Worker::new(&package, NoProgress, None, preferences, dpkg_contents);
+        let verification = worker.run().await?.verification.unwrap();
+
+        assert_eq!(verification.mismatches.len(), 1, "{verification:?}");
+        let mismatch = &verification.mismatches[0];
+        assert_eq!(mismatch.path, Path::new("/usr/bin/helper"));
+        assert_eq!(
+            mismatch.kind,
+            MismatchKind::Md5 {
+                recorded: format!("{:x}", Md5::digest("original helper")),
+                archived: format!("{:x}", Md5::digest("modified helper")),
+            }
+        );
+
+        fs::remove_dir_all(destination)?;
+
+        Ok(())
+    }
 }
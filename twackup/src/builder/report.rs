@@ -0,0 +1,103 @@
+/*
+ * Copyright 2020 DanP
+ *
+ * This file is part of Twackup
+ *
+ * Twackup is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Twackup is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Twackup. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use super::Verification;
+use std::path::PathBuf;
+
+/// File that is listed in the package but couldn't be archived
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct UnreadableFile {
+    /// Absolute path of the file
+    pub path: PathBuf,
+    /// Description of the error occurred while archiving
+    pub error: String,
+}
+
+/// Describes what was actually placed in the rebuilt deb
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct BuildReport {
+    /// Path of the built deb
+    pub deb_path: PathBuf,
+    /// Files listed by dpkg that don't exist anymore
+    pub missing_files: Vec<PathBuf>,
+    /// Files that exist but couldn't be read or archived
+    pub unreadable_files: Vec<UnreadableFile>,
+    /// Entries that were intentionally not archived, like filesystem root or sockets
+    pub skipped_files: Vec<PathBuf>,
+    /// Maintainer scripts and other metadata files placed in control archive
+    pub control_members: Vec<String>,
+    /// Total size of archived files before compression in bytes
+    pub archived_size: u64,
+    /// Size of the final deb in bytes
+    pub deb_size: u64,
+    /// Hex-encoded SHA-256 checksum of the final deb
+    pub deb_sha256: String,
+    /// Deb wasn't rebuilt because installed files haven't changed since previous build.
+    /// See [`super::Preferences::incremental`]
+    pub up_to_date: bool,
+    /// Result of comparing deb with the installed files.
+    /// Present only if verification was enabled in preferences
+    pub verification: Option<Verification>,
+}
+
+impl BuildReport {
+    /// Returns true if some listed files were not placed in the deb
+    #[inline]
+    #[must_use]
+    pub fn is_incomplete(&self) -> bool {
+        !self.missing_files.is_empty() || !self.unreadable_files.is_empty()
+    }
+
+    /// Human-readable description of every problem occurred while building
+    #[inline]
+    #[must_use]
+    pub fn warnings(&self) -> Vec<String> {
+        let missing =
+            (self.missing_files.iter()).map(|path| format!("{} is missing", path.display()));
+        let unreadable = (self.unreadable_files.iter())
+            .map(|file| format!("{} is unreadable: {}", file.path.display(), file.error));
+
+        let mut warnings: Vec<_> = missing.chain(unreadable).collect();
+
+        if let Some(verification) = &self.verification {
+            let mismatches = (verification.mismatches.iter())
+                .map(|mismatch| format!("{}: {:?}", mismatch.path.display(), mismatch.kind));
+            warnings.extend(mismatches);
+
+            let members = (verification.missing_control_members.iter())
+                .map(|member| format!("control member {member} is missing"));
+            warnings.extend(members);
+        }
+
+        warnings
+    }
+
+    /// Returns ratio of deb size to archived files size.
+    /// Less is better. Returns `None` if nothing was archived
+    #[inline]
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn compression_ratio(&self) -> Option<f64> {
+        (self.archived_size != 0).then(|| self.deb_size as f64 / self.archived_size as f64)
+    }
+}
@@ -0,0 +1,434 @@
+/*
+ * Copyright 2020 DanP
+ *
+ * This file is part of Twackup
+ *
+ * Twackup is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Twackup is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Twackup. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use super::{
+    AllPackagesArchive, BuildReport, CancellationToken, FileOverrides, Fingerprints, Preferences,
+    Worker,
+};
+use crate::{
+    error::{Generic, Result},
+    package::Package,
+    progress::{Event, Progress},
+    Dpkg,
+};
+use std::{
+    borrow::Borrow,
+    collections::HashSet,
+    num::NonZeroUsize,
+    path::PathBuf,
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+use tokio::sync::Semaphore;
+
+/// Builds multiple packages concurrently sharing single dpkg info directory listing
+pub struct BuildSession<T> {
+    progress: T,
+    preferences: Preferences,
+    archive: Option<AllPackagesArchive>,
+    dpkg_contents: Arc<HashSet<PathBuf>>,
+    jobs: usize,
+    cancellation: CancellationToken,
+}
+
+/// Result of building single package in session
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct PackageResult<P> {
+    /// Package that was built
+    pub package: P,
+    /// Build report or error occurred while building
+    pub result: Result<BuildReport>,
+}
+
+/// Aggregate statistics of the whole session
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct BuildStats {
+    /// Count of packages passed to session
+    pub total: usize,
+    /// Count of packages built successfully
+    pub succeeded: usize,
+    /// Count of packages failed to build
+    pub failed: usize,
+    /// Count of packages not built because session was cancelled
+    pub cancelled: usize,
+    /// Count of built packages with missing or unreadable files
+    pub incomplete: usize,
+    /// Count of succeeded packages that weren't rebuilt because their debs are up to date
+    pub up_to_date: usize,
+    /// Sum of uncompressed sizes of all archived files in bytes
+    pub archived_size: u64,
+    /// Sum of sizes of all built debs in bytes
+    pub deb_size: u64,
+    /// Time spent on building
+    pub elapsed: Duration,
+}
+
+/// Per-package results in the order packages were passed and aggregate statistics
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct BuildSummary<P> {
+    /// Results for each package
+    pub results: Vec<PackageResult<P>>,
+    /// Aggregate statistics
+    pub stats: BuildStats,
+}
+
+impl<T: Progress + Clone + Send + Sync + 'static> BuildSession<T> {
+    /// Creates new session reading dpkg info directory contents once for all packages.
+    /// Job limit defaults to count of available CPUs.
+    ///
+    /// # Errors
+    /// Returns error if dpkg info directory couldn't be read
+    #[inline]
+    pub fn new(dpkg: &Dpkg, preferences: Preferences, progress: T) -> Result<Self> {
+        let dpkg_contents = Arc::new(dpkg.info_dir_contents()?);
+        Ok(Self::with_contents(preferences, progress, dpkg_contents))
+    }
+
+    /// Creates new session with already fetched dpkg info directory contents
+    #[inline]
+    pub fn with_contents(
+        preferences: Preferences,
+        progress: T,
+        dpkg_contents: Arc<HashSet<PathBuf>>,
+    ) -> Self {
+        let jobs = thread::available_parallelism().map_or(1, NonZeroUsize::get);
+
+        Self {
+            progress,
+            preferences,
+            archive: None,
+            dpkg_contents,
+            jobs,
+            cancellation: CancellationToken::new(),
+        }
+    }
+
+    /// Sets maximum count of packages built at the same time. Zero is treated as one
+    #[inline]
+    #[must_use]
+    pub fn jobs(mut self, jobs: usize) -> Self {
+        self.jobs = jobs.max(1);
+        self
+    }
+
+    /// Sets archive every built deb will be appended to
+    #[inline]
+    #[must_use]
+    pub fn archive(mut self, archive: Option<AllPackagesArchive>) -> Self {
+        self.archive = archive;
+        self
+    }
+
+    /// Sets token which stops the session when cancelled.
+    /// Packages not built yet are reported with [`Generic::Cancelled`] error
+    #[inline]
+    #[must_use]
+    pub fn cancellation(mut self, token: CancellationToken) -> Self {
+        self.cancellation = token;
+        self
+    }
+
+    /// Token for cancelling the session
+    #[inline]
+    pub fn cancellation_token(&self) -> &CancellationToken {
+        &self.cancellation
+    }
+
+    /// Shared dpkg info directory contents
+    #[inline]
+    pub fn dpkg_contents(&self) -> &Arc<HashSet<PathBuf>> {
+        &self.dpkg_contents
+    }
+
+    /// Builds all packages respecting job limit.
+    /// Failure of one package doesn't stop others.
+    ///
+    /// If [`Preferences::incremental`] is set, fingerprints are loaded from destination
+    /// directory before building and saved back after it
+    ///
+    /// # Errors
+    /// Returns error if diversions or stat overrides of dpkg database couldn't be read.
+    /// No package is built in this case
+    #[inline]
+    pub async fn run<P, I>(&self, packages: I) -> Result<BuildSummary<P>>
+    where
+        P: Borrow<Package> + Clone + Send + 'static,
+        I: IntoIterator<Item = P>,
+    {
+        let started = Instant::now();
+        let semaphore = Arc::new(Semaphore::new(self.jobs));
+        // Read once, so workers don't parse the same databases for every package
+        let overrides = Arc::new(FileOverrides::load(&self.preferences.paths)?);
+        let fingerprints = self.load_fingerprints();
+
+        let handles: Vec<_> = packages
+            .into_iter()
+            .map(|package| {
+                let semaphore = semaphore.clone();
+                let progress = self.progress.clone();
+                let archive = self.archive.clone();
+                let preferences = self.preferences.clone();
+                let contents = self.dpkg_contents.clone();
+                let cancellation = self.cancellation.clone();
+                let fingerprints = fingerprints.clone();
+                let overrides = overrides.clone();
+                let task_package = package.clone();
+
+                let handle = tokio::spawn(async move {
+                    // Semaphore is never closed so acquire can't fail
+                    let package = task_package.borrow();
+                    let _permit = tokio::select! {
+                        permit = semaphore.acquire_owned() => permit,
+                        () = cancellation.cancelled() => {
+                            let error = Generic::Cancelled;
+                            progress.on_event(Event::Failed { package, error: &error });
+                            return Err(error);
+                        }
+                    };
+
+                    let mut worker = Worker::new(package, progress, archive, preferences, contents)
+                        .cancellation(cancellation)
+                        .overrides(overrides);
+                    if let Some(fingerprints) = fingerprints {
+                        worker = worker.fingerprints(fingerprints);
+                    }
+                    worker.run().await
+                });
+
+                (package, handle)
+            })
+            .collect();
+
+        let mut results = Vec::with_capacity(handles.len());
+        for (package, handle) in handles {
+            let result = handle.await.unwrap_or_else(|error| Err(error.into()));
+            match &result {
+                Err(Generic::Cancelled) | Ok(_) => {}
+                Err(error) => log::error!(target: &package.borrow().id, "{}", error),
+            }
+            results.push(PackageResult { package, result });
+        }
+
+        if let Some(fingerprints) = fingerprints {
+            if let Err(error) = fingerprints.save() {
+                log::warn!("Failed to save fingerprints: {}", error);
+            }
+        }
+
+        self.progress.on_event(Event::AllFinished);
+
+        let stats = BuildStats::collect(&results, started.elapsed());
+        Ok(BuildSummary { results, stats })
+    }
+}
+
+impl<T> BuildSession<T> {
+    fn load_fingerprints(&self) -> Option<Arc<Fingerprints>> {
+        if !self.preferences.incremental {
+            return None;
+        }
+
+        match Fingerprints::load(&self.preferences.destination_dir) {
+            Ok(fingerprints) => Some(Arc::new(fingerprints)),
+            Err(error) => {
+                log::warn!(
+                    "Failed to load fingerprints, rebuilding everything: {}",
+                    error
+                );
+                None
+            }
+        }
+    }
+}
+
+impl BuildStats {
+    fn collect<P>(results: &[PackageResult<P>], elapsed: Duration) -> Self {
+        let mut stats = Self {
+            total: results.len(),
+            elapsed,
+            ..Self::default()
+        };
+
+        for report in results.iter().map(|result| &result.result) {
+            match report {
+                Ok(report) => {
+                    stats.succeeded += 1;
+                    stats.archived_size += report.archived_size;
+                    stats.deb_size += report.deb_size;
+                    if report.is_incomplete() {
+                        stats.incomplete += 1;
+                    }
+                    if report.up_to_date {
+                        stats.up_to_date += 1;
+                    }
+                }
+                Err(Generic::Cancelled) => stats.cancelled += 1,
+                Err(_) => stats.failed += 1,
+            }
+        }
+
+        stats
+    }
+}
+
+impl<P> BuildSummary<P> {
+    /// Iterates over successfully built packages with their reports
+    #[inline]
+    pub fn reports(&self) -> impl Iterator<Item = (&P, &BuildReport)> {
+        self.results
+            .iter()
+            .filter_map(|result| Some((&result.package, result.result.as_ref().ok()?)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BuildSession;
+    use crate::{
+        archiver::Level,
+        builder::Preferences,
+        error::Result,
+        testing::{temp_dir, NoProgress, DPKG_DIR},
+        Dpkg,
+    };
+    use std::fs;
+
+    #[tokio::test]
+    async fn session_stats() -> Result<()> {
+        let dpkg = Dpkg::new(DPKG_DIR, false);
+        let packages = dpkg.unsorted_packages(false).await?;
+
+        let destination = temp_dir("twackup-session")?;
+
+        let preferences = Preferences::new(DPKG_DIR, &destination);
+        let session = BuildSession::new(&dpkg, preferences, NoProgress)?.jobs(1);
+        let summary = session.run(packages).await?;
+
+        assert_eq!(summary.stats.total, 1);
+        assert_eq!(summary.stats.succeeded, 1);
+        assert_eq!(summary.stats.failed, 0);
+        assert!(summary.stats.deb_size > 0);
+        assert_eq!(summary.reports().count(), 1);
+
+        fs::remove_dir_all(destination)?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn incremental_session() -> Result<()> {
+        let dpkg = Dpkg::new(DPKG_DIR, false);
+        let packages = dpkg.unsorted_packages(false).await?;
+
+        let destination = temp_dir("twackup-session-incremental")?;
+
+        let mut preferences = Preferences::new(DPKG_DIR, &destination);
+        preferences.incremental = true;
+        let session = BuildSession::new(&dpkg, preferences, NoProgress)?;
+
+        let first = session.run(packages.clone()).await?;
+        assert_eq!(first.stats.succeeded, 1);
+        assert_eq!(first.stats.up_to_date, 0);
+
+        let second = session.run(packages.clone()).await?;
+        assert_eq!(second.stats.succeeded, 1);
+        assert_eq!(second.stats.up_to_date, 1);
+
+        let (_, first_report) = first.reports().next().unwrap();
+        let (_, second_report) = second.reports().next().unwrap();
+        assert_eq!(first_report.deb_sha256, second_report.deb_sha256);
+
+        // Deb is rebuilt after it was removed
+        fs::remove_file(&first_report.deb_path)?;
+        let third = session.run(packages.clone()).await?;
+        assert_eq!(third.stats.up_to_date, 0);
+
+        // and after it was modified without changing its size
+        let mut deb = fs::read(&first_report.deb_path)?;
+        let last = deb.len() - 1;
+        deb[last] ^= 0xff;
+        fs::write(&first_report.deb_path, deb)?;
+        let fourth = session.run(packages.clone()).await?;
+        assert_eq!(fourth.stats.up_to_date, 0);
+
+        // Changed compression produces different deb
+        let mut preferences = Preferences::new(DPKG_DIR, &destination);
+        preferences.incremental = true;
+        preferences.compression.level = Level::Best;
+        let session = BuildSession::new(&dpkg, preferences, NoProgress)?;
+        let fifth = session.run(packages).await?;
+        assert_eq!(fifth.stats.up_to_date, 0);
+
+        fs::remove_dir_all(destination)?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn cancelled_session() -> Result<()> {
+        let dpkg = Dpkg::new(DPKG_DIR, false);
+        let packages = dpkg.unsorted_packages(false).await?;
+
+        let destination = temp_dir("twackup-session-cancelled")?;
+
+        let preferences = Preferences::new(DPKG_DIR, &destination);
+        let session = BuildSession::new(&dpkg, preferences, NoProgress)?;
+        session.cancellation_token().cancel();
+        let summary = session.run(packages).await?;
+
+        assert_eq!(summary.stats.cancelled, 1);
+        assert_eq!(summary.stats.succeeded, 0);
+        assert_eq!(fs::read_dir(&destination)?.count(), 0);
+
+        fs::remove_dir_all(destination)?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn unreadable_overrides() -> Result<()> {
+        let directory = temp_dir("twackup-session-overrides")?;
+        let admin_dir = directory.join("dpkg");
+        fs::create_dir_all(admin_dir.join("info"))?;
+        fs::write(
+            admin_dir.join("status"),
+            "Package: tool\nVersion: 1.0\nArchitecture: all\nStatus: install ok installed\n",
+        )?;
+        // Reading directory fails with other error than missing file
+        fs::create_dir_all(admin_dir.join("diversions"))?;
+
+        let dpkg = Dpkg::new(&admin_dir, false);
+        let packages = dpkg.unsorted_packages(false).await?;
+
+        let destination = directory.join("debs");
+        let preferences = Preferences::new(&admin_dir, &destination);
+        let session = BuildSession::new(&dpkg, preferences, NoProgress)?;
+        assert!(session.run(packages).await.is_err());
+        assert!(!destination.exists());
+
+        fs::remove_dir_all(directory)?;
+
+        Ok(())
+    }
+}
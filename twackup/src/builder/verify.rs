@@ -0,0 +1,253 @@
+/*
+ * Copyright 2020 DanP
+ *
+ * This file is part of Twackup
+ *
+ * Twackup is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Twackup is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Twackup. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use super::Worker;
+use crate::{
+    deb::{Deb, Entry, EntryKind},
+    error::Result,
+    integrity,
+    progress::Progress,
+    vfs::{FileKind, FileSystem, Metadata},
+};
+use std::{
+    collections::{HashMap, HashSet},
+    io,
+    path::{Path, PathBuf},
+};
+
+/// Describes how archived entry differs from the installed one
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum MismatchKind {
+    /// Entry is archived but doesn't exist in the filesystem anymore
+    Missing,
+    /// Entry is a file in archive, but a directory in the filesystem or vice versa
+    Type,
+    /// Archived file size differs from the installed one
+    Size {
+        /// Size of the installed file
+        installed: u64,
+        /// Size of the archived file
+        archived: u64,
+    },
+    /// Permission bits differ
+    Mode {
+        /// Permission bits of the installed entry
+        installed: u32,
+        /// Permission bits of the archived entry
+        archived: u32,
+    },
+    /// Symbolic link points to another location
+    SymlinkTarget {
+        /// Target of the installed link
+        installed: PathBuf,
+        /// Target of the archived link
+        archived: Option<PathBuf>,
+    },
+    /// Archived file hash differs from the one recorded by dpkg in `md5sums`
+    Md5 {
+        /// Hash recorded by dpkg
+        recorded: String,
+        /// Hash of the archived file
+        archived: String,
+    },
+}
+
+/// Single verification failure
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct Mismatch {
+    /// Absolute path of the installed entry
+    pub path: PathBuf,
+    /// What exactly differs
+    pub kind: MismatchKind,
+}
+
+/// Result of comparing rebuilt deb with the installed package
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct Verification {
+    /// Number of data entries that were checked
+    pub checked_entries: usize,
+    /// Data entries that differ from the installed ones
+    pub mismatches: Vec<Mismatch>,
+    /// Control members that exist in dpkg database but are missing in the deb
+    pub missing_control_members: Vec<String>,
+}
+
+impl Verification {
+    /// Returns true if deb fully matches the installed package
+    #[inline]
+    #[must_use]
+    pub fn is_ok(&self) -> bool {
+        self.mismatches.is_empty() && self.missing_control_members.is_empty()
+    }
+}
+
+impl<T: Progress> Worker<'_, T> {
+    /// Reads deb built by [`Worker::run`] back and compares it with the installed package.
+    ///
+    /// Every data entry is checked against the live file (size, mode, symlink target).
+    /// Hashes of files calculated while they were archived are compared with dpkg `md5sums`.
+    /// Diverted entries are compared with the file at diverted location. Also checks
+    /// that all package metadata files from dpkg database were placed in control archive.
+    ///
+    /// # Errors
+    /// Returns error if deb couldn't be read
+    pub(super) async fn verify<P: AsRef<Path>>(
+        &self,
+        deb_path: P,
+        archived_md5sums: &HashMap<PathBuf, String>,
+    ) -> Result<Verification> {
+        let deb = Deb::open(deb_path)?;
+        let mut report = Verification::default();
+
+        let control_entries: HashSet<_> = deb
+            .control_entries()
+            .await?
+            .into_iter()
+            .map(|entry| entry.path)
+            .collect();
+
+        report.missing_control_members = self
+            .control_members()
+            .filter(|(_, name)| !control_entries.contains(Path::new(name)))
+            .map(|(_, name)| name.to_string())
+            .collect();
+
+        let overrides = self.file_overrides()?;
+        let recorded_md5sums = self.recorded_md5sums()?;
+
+        for entry in deb.data_entries().await? {
+            report.checked_entries += 1;
+
+            let path = Path::new("/").join(&entry.path);
+            let kinds = match self
+                .source_path(&overrides, &path)
+                .and_then(|source| Ok((self.live_metadata(&source)?, source)))
+            {
+                Ok((metadata, source)) => {
+                    compare(self.preferences.paths.fs(), &source, &entry, &metadata)?
+                }
+                Err(error) if error.kind() == io::ErrorKind::NotFound => {
+                    vec![MismatchKind::Missing]
+                }
+                Err(error) => return Err(error.into()),
+            };
+
+            let recorded = recorded_md5sums.get(&entry.path);
+            let archived = archived_md5sums.get(&entry.path);
+            let md5 = recorded
+                .zip(archived)
+                .filter(|(recorded, archived)| recorded != archived);
+            let md5 = md5.map(|(recorded, archived)| MismatchKind::Md5 {
+                recorded: recorded.clone(),
+                archived: archived.clone(),
+            });
+
+            report
+                .mismatches
+                .extend(kinds.into_iter().chain(md5).map(|kind| Mismatch {
+                    path: path.clone(),
+                    kind,
+                }));
+        }
+
+        Ok(report)
+    }
+
+    /// Fetches metadata of the installed entry
+    /// respecting symlinks following preference
+    fn live_metadata(&self, path: &Path) -> io::Result<Metadata> {
+        let fs = self.preferences.paths.fs();
+        if self.preferences.follow_symlinks {
+            fs.metadata(path)
+        } else {
+            fs.symlink_metadata(path)
+        }
+    }
+
+    /// Reads hashes recorded by dpkg while installing package.
+    /// Returns empty map if package has no such file
+    fn recorded_md5sums(&self) -> Result<HashMap<PathBuf, String>> {
+        let md5sums = integrity::read_md5sums(&self.preferences.paths, &self.package.id)?;
+        Ok(md5sums.unwrap_or_default())
+    }
+}
+
+/// Compares archived entry with metadata of the installed one
+fn compare(
+    fs: &dyn FileSystem,
+    path: &Path,
+    entry: &Entry,
+    metadata: &Metadata,
+) -> Result<Vec<MismatchKind>> {
+    let live_kind = match metadata.kind {
+        FileKind::File => EntryKind::File,
+        FileKind::Directory => EntryKind::Directory,
+        FileKind::Symlink => EntryKind::Symlink,
+        _ => EntryKind::Other,
+    };
+
+    // Hard links are archived as references to already added entries
+    if entry.kind == EntryKind::HardLink && live_kind == EntryKind::File {
+        return Ok(vec![]);
+    }
+
+    if entry.kind != live_kind {
+        return Ok(vec![MismatchKind::Type]);
+    }
+
+    let mut mismatches = vec![];
+    match entry.kind {
+        EntryKind::File if entry.size != metadata.len => {
+            mismatches.push(MismatchKind::Size {
+                installed: metadata.len,
+                archived: entry.size,
+            });
+        }
+        EntryKind::Symlink => {
+            let installed = fs.read_link(path)?;
+            if entry.link_name.as_ref() != Some(&installed) {
+                mismatches.push(MismatchKind::SymlinkTarget {
+                    installed,
+                    archived: entry.link_name.clone(),
+                });
+            }
+
+            // Symlinks permissions don't make sense
+            return Ok(mismatches);
+        }
+        _ => {}
+    }
+
+    let installed_mode = metadata.permissions();
+    let archived_mode = entry.mode & 0o7777;
+    if installed_mode != archived_mode {
+        mismatches.push(MismatchKind::Mode {
+            installed: installed_mode,
+            archived: archived_mode,
+        });
+    }
+
+    Ok(mismatches)
+}
@@ -0,0 +1,476 @@
+/*
+ * Copyright 2020 DanP
+ *
+ * This file is part of Twackup
+ *
+ * Twackup is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Twackup is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Twackup. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Passphrase-based authenticated encryption for backups.
+//!
+//! Key is derived with Argon2id from passphrase and random salt,
+//! data is split into 64 KiB chunks sealed with ChaCha20-Poly1305.
+//! Every nonce contains chunk counter and a flag marking the last chunk,
+//! so reordered, truncated or extended streams are detected.
+//!
+//! ### Example usage
+//!
+//! ```no_run
+//! use twackup::{crypto::{Decryptor, Encryptor}, Result};
+//! use std::io::{Read, Write};
+//!
+//! fn main() -> Result<()> {
+//!     let mut encryptor = Encryptor::new(vec![], "passphrase")?;
+//!     encryptor.write_all(b"secret")?;
+//!     let encrypted = encryptor.finish()?;
+//!
+//!     let mut decryptor = Decryptor::new(encrypted.as_slice(), "passphrase")?;
+//!     let mut decrypted = vec![];
+//!     decryptor.read_to_end(&mut decrypted)?;
+//!
+//!     Ok(())
+//! }
+//! ```
+
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::{
+    aead::{Aead, KeyInit, Payload},
+    ChaCha20Poly1305, Nonce,
+};
+use std::io::{self, Read, Write};
+
+/// First bytes of every encrypted stream
+pub const MAGIC: &[u8; 8] = b"TWACKENC";
+
+const FORMAT_VERSION: u8 = 1;
+const SALT_LENGTH: usize = 16;
+const HEADER_LENGTH: usize = MAGIC.len() + 1 + 3 * 4 + SALT_LENGTH;
+const CHUNK_SIZE: usize = 64 * 1024;
+const TAG_LENGTH: usize = 16;
+const SEGMENT_SIZE: usize = CHUNK_SIZE + TAG_LENGTH;
+
+/// Streams requiring more memory for key derivation are rejected
+const MAX_MEMORY_KIB: u32 = 1024 * 1024;
+
+/// Streams requiring more passes of key derivation are rejected
+const MAX_ITERATIONS: u32 = 64;
+
+/// Streams requiring more lanes of key derivation are rejected
+const MAX_PARALLELISM: u32 = 16;
+
+/// Different encryption errors
+#[derive(thiserror::Error, Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// Data is encrypted but passphrase wasn't provided
+    #[error("Data is encrypted, passphrase is required")]
+    PassphraseRequired,
+
+    /// Stream doesn't start with valid header
+    #[error("Invalid encryption header")]
+    InvalidHeader,
+
+    /// Stream was created by newer version of twackup
+    #[error("Unsupported encryption format version {0}")]
+    UnsupportedVersion(u8),
+
+    /// Key derivation failed or its parameters are invalid
+    #[error("Key derivation failed: {0}")]
+    KeyDerivation(String),
+
+    /// Chunk authentication failed
+    #[error("Decryption failed: wrong passphrase or corrupted data")]
+    Decryption,
+}
+
+/// Argon2id cost parameters stored in stream header
+#[derive(Clone, Copy, Debug)]
+struct KdfParams {
+    memory_kib: u32,
+    iterations: u32,
+    parallelism: u32,
+}
+
+impl Default for KdfParams {
+    fn default() -> Self {
+        Self {
+            memory_kib: 64 * 1024,
+            iterations: 3,
+            parallelism: 1,
+        }
+    }
+}
+
+/// Encrypts everything written to it and writes to inner writer.
+/// [`Encryptor::finish`] must be called after the last write
+pub struct Encryptor<W: Write> {
+    inner: W,
+    cipher: ChaCha20Poly1305,
+    header: Vec<u8>,
+    counter: u64,
+    buffer: Vec<u8>,
+}
+
+/// Decrypts data read from inner reader
+pub struct Decryptor<R: Read> {
+    inner: R,
+    cipher: ChaCha20Poly1305,
+    header: Vec<u8>,
+    counter: u64,
+    /// Bytes of the next segment read while searching for the end of current one
+    lookahead: Vec<u8>,
+    plain: Vec<u8>,
+    position: usize,
+    finished: bool,
+}
+
+/// Returns true if data starts with encrypted stream header.
+/// It is enough to pass only first few bytes of the data
+#[must_use]
+pub fn is_encrypted(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+impl<W: Write> Encryptor<W> {
+    /// Derives key from passphrase and writes stream header
+    ///
+    /// # Errors
+    /// Returns error if random salt couldn't be generated or header couldn't be written
+    pub fn new(inner: W, passphrase: &str) -> crate::Result<Self> {
+        Self::with_params(inner, passphrase, KdfParams::default())
+    }
+
+    fn with_params(mut inner: W, passphrase: &str, params: KdfParams) -> crate::Result<Self> {
+        let mut salt = [0; SALT_LENGTH];
+        getrandom::getrandom(&mut salt).map_err(|error| Error::KeyDerivation(error.to_string()))?;
+
+        let mut header = Vec::with_capacity(HEADER_LENGTH);
+        header.extend_from_slice(MAGIC);
+        header.push(FORMAT_VERSION);
+        header.extend_from_slice(&params.memory_kib.to_le_bytes());
+        header.extend_from_slice(&params.iterations.to_le_bytes());
+        header.extend_from_slice(&params.parallelism.to_le_bytes());
+        header.extend_from_slice(&salt);
+
+        let cipher = derive_cipher(passphrase, &salt, params)?;
+        inner.write_all(&header)?;
+
+        Ok(Self {
+            inner,
+            cipher,
+            header,
+            counter: 0,
+            buffer: Vec::with_capacity(CHUNK_SIZE),
+        })
+    }
+
+    /// Seals the last chunk, flushes and returns inner writer
+    ///
+    /// # Errors
+    /// Returns error if data couldn't be written
+    pub fn finish(mut self) -> io::Result<W> {
+        self.seal(true)?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+
+    /// Returns reference to inner writer
+    #[inline]
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    fn seal(&mut self, last: bool) -> io::Result<()> {
+        let payload = Payload {
+            msg: &self.buffer,
+            aad: &self.header,
+        };
+        let nonce = nonce(self.counter, last);
+        let sealed = (self.cipher.encrypt(Nonce::from_slice(&nonce), payload))
+            .map_err(|_| io::Error::new(io::ErrorKind::Other, "chunk encryption failed"))?;
+
+        self.inner.write_all(&sealed)?;
+        self.buffer.clear();
+        self.counter += 1;
+
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for Encryptor<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut written = 0;
+        while written < buf.len() {
+            // Full chunk is sealed only when more data comes,
+            // because the last one must be marked as such
+            if self.buffer.len() == CHUNK_SIZE {
+                self.seal(false)?;
+            }
+
+            let length = (CHUNK_SIZE - self.buffer.len()).min(buf.len() - written);
+            self.buffer
+                .extend_from_slice(&buf[written..written + length]);
+            written += length;
+        }
+
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<R: Read> Decryptor<R> {
+    /// Reads stream header and derives key from passphrase
+    ///
+    /// # Errors
+    /// Returns error if header is invalid or couldn't be read
+    pub fn new(mut inner: R, passphrase: &str) -> crate::Result<Self> {
+        let mut header = vec![0; HEADER_LENGTH];
+        inner
+            .read_exact(&mut header)
+            .map_err(|error| match error.kind() {
+                io::ErrorKind::UnexpectedEof => Error::InvalidHeader.into(),
+                _ => crate::error::Generic::from(error),
+            })?;
+
+        let (params, salt) = parse_header(&header)?;
+        let cipher = derive_cipher(passphrase, salt, params)?;
+
+        Ok(Self {
+            inner,
+            cipher,
+            header,
+            counter: 0,
+            lookahead: vec![],
+            plain: vec![],
+            position: 0,
+            finished: false,
+        })
+    }
+
+    fn open_segment(&mut self) -> io::Result<()> {
+        // One byte more than segment is read to know whether current segment is the last
+        let mut segment = std::mem::take(&mut self.lookahead);
+        let mut filled = segment.len();
+        segment.resize(SEGMENT_SIZE + 1, 0);
+
+        while filled < segment.len() {
+            match self.inner.read(&mut segment[filled..]) {
+                Ok(0) => break,
+                Ok(read) => filled += read,
+                Err(error) if error.kind() == io::ErrorKind::Interrupted => {}
+                Err(error) => return Err(error),
+            }
+        }
+        segment.truncate(filled);
+
+        let last = segment.len() <= SEGMENT_SIZE;
+        if !last {
+            self.lookahead = segment.split_off(SEGMENT_SIZE);
+        }
+
+        let payload = Payload {
+            msg: &segment,
+            aad: &self.header,
+        };
+        let nonce = nonce(self.counter, last);
+        self.plain = (self.cipher.decrypt(Nonce::from_slice(&nonce), payload))
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, Error::Decryption))?;
+        self.position = 0;
+        self.counter += 1;
+        self.finished = last;
+
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for Decryptor<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.plain.len() {
+            if self.finished {
+                return Ok(0);
+            }
+            self.open_segment()?;
+        }
+
+        let length = buf.len().min(self.plain.len() - self.position);
+        buf[..length].copy_from_slice(&self.plain[self.position..self.position + length]);
+        self.position += length;
+
+        Ok(length)
+    }
+}
+
+fn parse_header(header: &[u8]) -> Result<(KdfParams, &[u8]), Error> {
+    let Some(rest) = header.strip_prefix(MAGIC) else {
+        return Err(Error::InvalidHeader);
+    };
+    let [version, rest @ ..] = rest else {
+        return Err(Error::InvalidHeader);
+    };
+    if *version != FORMAT_VERSION {
+        return Err(Error::UnsupportedVersion(*version));
+    }
+
+    let number = |index: usize| {
+        let bytes = &rest[index * 4..index * 4 + 4];
+        u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+    };
+    let params = KdfParams {
+        memory_kib: number(0),
+        iterations: number(1),
+        parallelism: number(2),
+    };
+    if params.memory_kib > MAX_MEMORY_KIB {
+        return Err(Error::KeyDerivation("memory cost is too high".to_string()));
+    }
+    if params.iterations > MAX_ITERATIONS {
+        return Err(Error::KeyDerivation("time cost is too high".to_string()));
+    }
+    if params.parallelism > MAX_PARALLELISM {
+        return Err(Error::KeyDerivation("parallelism is too high".to_string()));
+    }
+
+    Ok((params, &rest[12..]))
+}
+
+fn derive_cipher(
+    passphrase: &str,
+    salt: &[u8],
+    params: KdfParams,
+) -> Result<ChaCha20Poly1305, Error> {
+    let kdf_error = |error: argon2::Error| Error::KeyDerivation(error.to_string());
+
+    let params = Params::new(
+        params.memory_kib,
+        params.iterations,
+        params.parallelism,
+        Some(32),
+    )
+    .map_err(kdf_error)?;
+
+    let mut key = [0; 32];
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(kdf_error)?;
+
+    ChaCha20Poly1305::new_from_slice(&key).map_err(|error| Error::KeyDerivation(error.to_string()))
+}
+
+/// Nonce is big-endian chunk counter followed by the last chunk flag
+fn nonce(counter: u64, last: bool) -> [u8; 12] {
+    let mut nonce = [0; 12];
+    nonce[3..11].copy_from_slice(&counter.to_be_bytes());
+    nonce[11] = u8::from(last);
+    nonce
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Decryptor, Encryptor, KdfParams, CHUNK_SIZE, HEADER_LENGTH, MAGIC, SEGMENT_SIZE};
+    use crate::Result;
+    use std::io::{Read, Write};
+
+    const PARAMS: KdfParams = KdfParams {
+        memory_kib: 64,
+        iterations: 1,
+        parallelism: 1,
+    };
+
+    fn encrypt(data: &[u8], passphrase: &str) -> Result<Vec<u8>> {
+        let mut encryptor = Encryptor::with_params(vec![], passphrase, PARAMS)?;
+        // Odd sized writes to cross chunk boundaries
+        for part in data.chunks(1000) {
+            encryptor.write_all(part)?;
+        }
+        Ok(encryptor.finish()?)
+    }
+
+    fn decrypt(data: &[u8], passphrase: &str) -> Result<Vec<u8>> {
+        let mut decryptor = Decryptor::new(data, passphrase)?;
+        let mut decrypted = vec![];
+        decryptor.read_to_end(&mut decrypted)?;
+        Ok(decrypted)
+    }
+
+    #[test]
+    fn round_trip() -> Result<()> {
+        for size in [0, 1, CHUNK_SIZE, CHUNK_SIZE + 1, 3 * CHUNK_SIZE + 7] {
+            let data: Vec<_> = (0..=u8::MAX).cycle().take(size).collect();
+            let encrypted = encrypt(&data, "twackup")?;
+
+            assert!(super::is_encrypted(&encrypted));
+            let segments = size.saturating_sub(1) / CHUNK_SIZE + 1;
+            assert_eq!(
+                encrypted.len(),
+                HEADER_LENGTH + size + segments * (SEGMENT_SIZE - CHUNK_SIZE)
+            );
+            assert_eq!(decrypt(&encrypted, "twackup")?, data, "size {size}");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn tampering() -> Result<()> {
+        let data = vec![42; 2 * CHUNK_SIZE + 100];
+        let encrypted = encrypt(&data, "twackup")?;
+
+        assert!(decrypt(&encrypted, "wrong").is_err());
+
+        // Stream cut exactly at chunk boundary
+        assert!(decrypt(&encrypted[..HEADER_LENGTH + SEGMENT_SIZE], "twackup").is_err());
+        assert!(decrypt(&encrypted[..encrypted.len() - 1], "twackup").is_err());
+
+        let mut modified = encrypted.clone();
+        modified[HEADER_LENGTH + 10] ^= 1;
+        assert!(decrypt(&modified, "twackup").is_err());
+
+        // Salt is authenticated as part of header
+        let mut modified = encrypted.clone();
+        modified[HEADER_LENGTH - 1] ^= 1;
+        assert!(decrypt(&modified, "twackup").is_err());
+
+        let mut extended = encrypted;
+        extended.push(0);
+        assert!(decrypt(&extended, "twackup").is_err());
+
+        assert!(decrypt(b"TWACK", "twackup").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn excessive_costs() -> Result<()> {
+        let encrypted = encrypt(b"twackup", "twackup")?;
+
+        // Memory, iterations and parallelism follow magic and version
+        for index in 0..3 {
+            let offset = MAGIC.len() + 1 + index * 4;
+            let mut modified = encrypted.clone();
+            modified[offset..offset + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+            assert!(
+                matches!(
+                    Decryptor::new(modified.as_slice(), "twackup"),
+                    Err(crate::GenericError::Crypto(super::Error::KeyDerivation(_)))
+                ),
+                "parameter {index}"
+            );
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,404 @@
+/*
+ * Copyright 2020 DanP
+ *
+ * This file is part of Twackup
+ *
+ * Twackup is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Twackup is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Twackup. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Deb is a Twackup module that reads debian archives
+//! created by [builder](crate::builder) or any other tool like dpkg-deb
+//!
+//! ### Example usage
+//!
+//! ```no_run
+//! use twackup::{deb::Deb, Result};
+//!
+//! #[tokio::main]
+//! async fn main() -> Result<()> {
+//!     let deb = Deb::open("/tmp/package.deb")?;
+//!
+//!     let package = deb.package().await?;
+//!     println!("Found package {} {}", package.id, package.version);
+//!
+//!     for entry in deb.data_entries().await? {
+//!         println!("{:?} - {} bytes", entry.path, entry.size);
+//!     }
+//!
+//!     deb.extract("/tmp/package").await?;
+//!
+//!     Ok(())
+//! }
+//! ```
+//!
+
+use crate::{
+    archiver::{Decoder, Plain, Type},
+    error::Result,
+    package::{Field, Package},
+    parser::{self, Parsable},
+};
+use std::{
+    fs::File,
+    io::{BufReader, Read, Seek, SeekFrom},
+    path::{Component, Path, PathBuf},
+};
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio_stream::StreamExt;
+use tokio_tar::{Archive, EntryType};
+
+type MemberArchive = Archive<Box<dyn AsyncRead + Unpin + Send>>;
+
+/// Different errors for debian archives
+#[derive(thiserror::Error, Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// Archive has no required member. For example, `control.tar.gz`
+    #[error("Missing archive member: `{0}`")]
+    MissingMember(&'static str),
+
+    /// Archive format version is not supported
+    #[error("Unsupported deb format version: `{0}`")]
+    UnsupportedVersion(String),
+
+    /// Member is compressed with unknown compression
+    #[error("Unknown member compression: `{0}`")]
+    UnknownCompression(String),
+}
+
+/// Type of data archive entry
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum EntryKind {
+    /// Regular file
+    File,
+    /// Directory
+    Directory,
+    /// Symbolic link
+    Symlink,
+    /// Hard link to other entry of the same archive
+    HardLink,
+    /// Other types like fifos or devices
+    Other,
+}
+
+/// Describes single entry of control or data archive
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct Entry {
+    /// Entry path relative to the archive root. Doesn't contain leading `./`
+    pub path: PathBuf,
+    /// Type of the entry
+    pub kind: EntryKind,
+    /// Size of entry contents in bytes
+    pub size: u64,
+    /// Permission bits of the entry
+    pub mode: u32,
+    /// Owner user identifier
+    pub uid: u64,
+    /// Owner group identifier
+    pub gid: u64,
+    /// Modification time in seconds since UNIX epoch
+    pub mtime: u64,
+    /// Link target for symbolic and hard links
+    pub link_name: Option<PathBuf>,
+}
+
+/// Member of ar archive that contains tarball
+#[derive(Clone, Debug)]
+struct Member {
+    index: usize,
+    compression: Option<Type>,
+}
+
+/// Debian archive opened for reading
+#[derive(Clone, Debug)]
+pub struct Deb {
+    path: PathBuf,
+    control: Member,
+    data: Member,
+}
+
+impl Deb {
+    /// Opens debian archive and searches for control and data members
+    ///
+    /// # Errors
+    /// Returns error if file couldn't be read or it is not a valid debian archive
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut archive = ar::Archive::new(File::open(&path)?);
+
+        let mut version = None;
+        let mut control = None;
+        let mut data = None;
+
+        let mut index = 0;
+        while let Some(entry) = archive.next_entry() {
+            let mut entry = entry?;
+
+            // GNU ar can terminate names with slash
+            let name = String::from_utf8_lossy(entry.header().identifier());
+            let name = name.trim_end_matches('/').to_string();
+
+            if name == "debian-binary" {
+                let mut contents = String::new();
+                entry.read_to_string(&mut contents)?;
+                version = Some(contents.trim().to_string());
+            } else if let Some(extension) = name.strip_prefix("control.tar") {
+                control = Some(Member::new(index, extension)?);
+            } else if let Some(extension) = name.strip_prefix("data.tar") {
+                data = Some(Member::new(index, extension)?);
+            }
+
+            index += 1;
+        }
+
+        let version = version.ok_or(Error::MissingMember("debian-binary"))?;
+        if !version.starts_with("2.") {
+            return Err(Error::UnsupportedVersion(version).into());
+        }
+
+        Ok(Self {
+            path,
+            control: control.ok_or(Error::MissingMember("control.tar"))?,
+            data: data.ok_or(Error::MissingMember("data.tar"))?,
+        })
+    }
+
+    /// Returns path of this archive
+    #[inline]
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Computes hex-encoded SHA-256 checksum of the whole archive
+    ///
+    /// # Errors
+    /// Returns error if archive couldn't be read
+    pub fn sha256(&self) -> Result<String> {
+        Ok(crate::builder::sha256_file(&self.path)?)
+    }
+
+    /// Parses control file of the archive to package model.
+    /// As debs don't contain any status, it will be set to *not-installed*
+    ///
+    /// # Errors
+    /// Returns error if archive couldn't be read or control file is invalid
+    pub async fn package(&self) -> Result<Package> {
+        let control = self
+            .control_file("control")
+            .await?
+            .ok_or(Error::MissingMember("control"))?;
+
+        let mut fields = parser::parse_fields(&control);
+        fields
+            .entry(Field::Status.as_str().to_string())
+            .or_insert_with(|| "install ok not-installed".to_string());
+        fields
+            .entry(Field::Section.as_str().to_string())
+            .or_default();
+
+        Ok(Package::new(fields)?)
+    }
+
+    /// Searches file in control archive and reads it contents.
+    /// Name must not contain any path components, e.g. `md5sums` or `postinst`
+    ///
+    /// # Errors
+    /// Returns error if control archive couldn't be read
+    pub async fn control_file(&self, name: &str) -> Result<Option<Vec<u8>>> {
+        let mut archive = self.member_archive(&self.control)?;
+        let mut entries = archive.entries()?;
+
+        while let Some(entry) = entries.next().await {
+            let mut entry = entry?;
+            if normalize(&entry.path()?) == Path::new(name) {
+                let mut contents = vec![];
+                entry.read_to_end(&mut contents).await?;
+                return Ok(Some(contents));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Lists entries of control archive, such as control file or maintainer scripts
+    ///
+    /// # Errors
+    /// Returns error if control archive couldn't be read
+    pub async fn control_entries(&self) -> Result<Vec<Entry>> {
+        self.entries(&self.control).await
+    }
+
+    /// Lists entries of data archive. Paths are relative to the filesystem root
+    ///
+    /// # Errors
+    /// Returns error if data archive couldn't be read
+    pub async fn data_entries(&self) -> Result<Vec<Entry>> {
+        self.entries(&self.data).await
+    }
+
+    /// Extracts data archive contents to destination directory
+    ///
+    /// # Errors
+    /// Returns error if data archive couldn't be read or destination isn't writable
+    pub async fn extract<P: AsRef<Path>>(&self, destination: P) -> Result<()> {
+        let mut archive = self.member_archive(&self.data)?;
+        archive.unpack(destination).await?;
+
+        Ok(())
+    }
+
+    async fn entries(&self, member: &Member) -> Result<Vec<Entry>> {
+        let mut archive = self.member_archive(member)?;
+        let mut entries = archive.entries()?;
+
+        let mut result = vec![];
+        while let Some(entry) = entries.next().await {
+            let entry = entry?;
+            let header = entry.header();
+
+            let path = normalize(&entry.path()?);
+            if path.as_os_str().is_empty() {
+                continue;
+            }
+
+            let kind = match header.entry_type() {
+                EntryType::Regular | EntryType::Continuous => EntryKind::File,
+                EntryType::Directory => EntryKind::Directory,
+                EntryType::Symlink => EntryKind::Symlink,
+                EntryType::Link => EntryKind::HardLink,
+                _ => EntryKind::Other,
+            };
+
+            result.push(Entry {
+                path,
+                kind,
+                size: header.size()?,
+                mode: header.mode()?,
+                uid: header.uid()?,
+                gid: header.gid()?,
+                mtime: header.mtime()?,
+                link_name: entry.link_name()?.map(|link| link.to_path_buf()),
+            });
+        }
+
+        Ok(result)
+    }
+
+    /// Wraps ar member into tar archive. Member is streamed from the file
+    /// and decompressed on the fly, so it is never read to memory as a whole
+    fn member_archive(&self, member: &Member) -> Result<MemberArchive> {
+        let mut archive = ar::Archive::new(File::open(&self.path)?);
+        let size = {
+            // Entry reads the rest of its data when dropped, so it is skipped instead
+            let mut entry = archive.jump_to_entry(member.index)?;
+            entry.seek(SeekFrom::End(0))?
+        };
+
+        let mut file = archive.into_inner()?;
+        let offset = file.stream_position()? - size;
+        file.seek(SeekFrom::Start(offset))?;
+
+        let contents = BufReader::new(file.take(size));
+        let reader: Box<dyn AsyncRead + Unpin + Send> = match member.compression {
+            Some(r#type) => Box::new(Decoder::new(contents, r#type)?),
+            None => Box::new(Plain(contents)),
+        };
+
+        Ok(Archive::new(reader))
+    }
+}
+
+impl Member {
+    /// Creates member from its name extension, e.g. `.xz`.
+    /// Empty extension means no compression
+    fn new(index: usize, extension: &str) -> Result<Self> {
+        let compression = match extension.strip_prefix('.') {
+            Some(extension) => Some(
+                Type::try_from(extension)
+                    .map_err(|error| Error::UnknownCompression(error.to_string()))?,
+            ),
+            None if extension.is_empty() => None,
+            None => return Err(Error::UnknownCompression(extension.to_string()).into()),
+        };
+
+        Ok(Self { index, compression })
+    }
+}
+
+/// Removes leading `./` and `/` from tar entry path
+fn normalize(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|component| !matches!(component, Component::CurDir | Component::RootDir))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Deb, EntryKind};
+    use crate::{
+        archiver::{Compression, Type},
+        builder::{Preferences, Worker},
+        testing::{temp_dir, NoProgress, DPKG_DIR},
+        Dpkg, Result,
+    };
+    use std::{fs, path::Path, sync::Arc};
+
+    #[tokio::test]
+    async fn extract_rebuilt_package() -> Result<()> {
+        let dpkg = Dpkg::new(DPKG_DIR, false);
+
+        let mut packages = dpkg.unsorted_packages(false).await?;
+        let package = packages.pop_back().unwrap();
+
+        let destination = temp_dir("twackup-deb-extract")?;
+
+        let mut preferences = Preferences::new(DPKG_DIR, &destination);
+        preferences.compression = Compression {
+            r#type: Type::Xz,
+            ..Compression::default()
+        };
+
+        let dpkg_contents = Arc::new(dpkg.info_dir_contents()?);
+        let worker = Worker::new(&package, NoProgress, None, preferences, dpkg_contents);
+        let deb = Deb::open(worker.run().await?.deb_path)?;
+
+        let entries = deb.data_entries().await?;
+        let hosts = entries
+            .iter()
+            .find(|entry| entry.path == Path::new("etc/hosts"))
+            .unwrap();
+        assert_eq!(hosts.kind, EntryKind::File);
+
+        let unpacked = destination.join("unpacked");
+        deb.extract(&unpacked).await?;
+
+        let metadata = fs::metadata(unpacked.join("etc/hosts"))?;
+        assert_eq!(metadata.len(), hosts.size);
+
+        fs::remove_dir_all(&destination)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn not_a_deb() {
+        let database = concat!(env!("CARGO_MANIFEST_DIR"), "/assets/databases/valid");
+        assert!(Deb::open(database).is_err());
+    }
+}
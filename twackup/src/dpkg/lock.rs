@@ -19,39 +19,331 @@
 
 use super::paths::Paths;
 use std::{
-    fs::{self, File},
+    collections::BTreeMap,
+    fs::{self, File, OpenOptions},
     io,
-    os::unix::io::AsRawFd,
-    path::PathBuf,
+    os::unix::{fs::OpenOptionsExt, io::AsRawFd},
+    path::{Path, PathBuf},
+    sync::{Mutex, MutexGuard},
+    time::Duration,
 };
+use tokio::time::Instant;
 
+/// Interval between attempts to take busy lock
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Lock files held by this process with count of their holders.
+/// File stays open while it has holders, as closing any descriptor releases the lock
+static HELD: Mutex<BTreeMap<PathBuf, (File, usize)>> = Mutex::new(BTreeMap::new());
+
+/// Describes how to wait for dpkg database locked by another process, e.g. apt
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum LockWait {
+    /// Waits until database is unlocked
+    #[default]
+    Forever,
+    /// Fails immediately if database is locked
+    NonBlocking,
+    /// Fails if database is still locked after this duration
+    Timeout(Duration),
+}
+
+/// Dpkg database locking error
+#[derive(thiserror::Error, Debug)]
+#[non_exhaustive]
+pub enum LockError {
+    /// Lock is held by another process and [`LockWait::NonBlocking`] was requested
+    #[error("{} is locked by {}", path.display(), holder(*pid))]
+    Busy {
+        /// Path of the lock file
+        path: PathBuf,
+        /// Process holding the lock if it could be detected
+        pid: Option<i32>,
+    },
+
+    /// Lock is still held by another process after waiting
+    #[error("Timed out waiting for {} locked by {}", path.display(), holder(*pid))]
+    Timeout {
+        /// Path of the lock file
+        path: PathBuf,
+        /// Process holding the lock if it could be detected
+        pid: Option<i32>,
+    },
+
+    /// Lock file couldn't be opened or locked
+    #[error("Couldn't lock {}: {source}", path.display())]
+    Io {
+        /// Path of the lock file
+        path: PathBuf,
+        /// Underlying error
+        source: io::Error,
+    },
+}
+
+/// Holds dpkg database locked the same way dpkg and apt do.
+///
+/// `lock-frontend` is locked first, then `lock`. Both are `fcntl` record locks,
+/// so they are released by the kernel when files are closed and lock files
+/// are never deleted. Twackup only reads the database, so read locks are taken:
+/// they conflict with write locks of dpkg and apt, but not with each other.
+///
+/// `fcntl` locks belong to process and all of them are released when any descriptor
+/// of the file is closed, so each lock file is opened once per process and
+/// is shared by all holders. Busy locks are polled without blocking the runtime.
 pub(crate) struct Lock {
-    path: PathBuf,
-    file: File,
+    _frontend: Held,
+    _database: Held,
 }
 
 impl Lock {
-    pub(crate) fn new(paths: &Paths) -> io::Result<Self> {
-        let path = paths.lock_file();
-        let file = File::create(&path)?;
-        flock(&file, libc::LOCK_EX)?;
+    pub(crate) async fn new(paths: &Paths, wait: LockWait) -> Result<Self, LockError> {
+        let deadline = match wait {
+            LockWait::Timeout(timeout) => Some(Instant::now() + timeout),
+            _ => None,
+        };
+
+        let frontend = Held::acquire(&paths.frontend_lock_file(), wait, deadline).await?;
+        let database = Held::acquire(&paths.lock_file(), wait, deadline).await?;
 
-        Ok(Self { path, file })
+        Ok(Self {
+            _frontend: frontend,
+            _database: database,
+        })
     }
 }
 
-impl Drop for Lock {
+/// Reference to lock file held by this process
+struct Held(PathBuf);
+
+impl Held {
+    async fn acquire(
+        path: &Path,
+        wait: LockWait,
+        deadline: Option<Instant>,
+    ) -> Result<Self, LockError> {
+        // The same file can be reached by different paths
+        let key = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        loop {
+            let pid = match Self::try_acquire(path, &key) {
+                Err(LockError::Busy { pid, .. }) => pid,
+                result => return result,
+            };
+
+            let path = path.to_path_buf();
+            match (wait, deadline) {
+                (LockWait::NonBlocking, _) => return Err(LockError::Busy { path, pid }),
+                (_, Some(deadline)) if Instant::now() >= deadline => {
+                    return Err(LockError::Timeout { path, pid })
+                }
+                _ => tokio::time::sleep(POLL_INTERVAL).await,
+            }
+        }
+    }
+
+    /// Takes lock without waiting or joins holders of the lock already taken by this process
+    fn try_acquire(path: &Path, key: &Path) -> Result<Self, LockError> {
+        let io_error = |source| LockError::Io {
+            path: path.to_path_buf(),
+            source,
+        };
+
+        let mut held = Self::held();
+        if let Some((_, holders)) = held.get_mut(key) {
+            *holders += 1;
+            return Ok(Self(key.to_path_buf()));
+        }
+
+        let file = open(path).map_err(io_error)?;
+        loop {
+            let mut record = record(false);
+            if unsafe { libc::fcntl(file.as_raw_fd(), libc::F_SETLK, &mut record) } == 0 {
+                held.insert(key.to_path_buf(), (file, 1));
+                return Ok(Self(key.to_path_buf()));
+            }
+
+            let error = io::Error::last_os_error();
+            match error.raw_os_error() {
+                Some(libc::EINTR) => {}
+                Some(libc::EACCES | libc::EAGAIN) => {
+                    // Lock isn't held by this process, so descriptor can be closed
+                    let pid = holder_pid(&file);
+                    let path = path.to_path_buf();
+                    return Err(LockError::Busy { path, pid });
+                }
+                _ => return Err(io_error(error)),
+            }
+        }
+    }
+
+    fn held() -> MutexGuard<'static, BTreeMap<PathBuf, (File, usize)>> {
+        // Map is consistent even if some holder panicked
+        HELD.lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+}
+
+impl Drop for Held {
     fn drop(&mut self) {
-        flock(&self.file, libc::LOCK_UN).ok();
-        fs::remove_file(&self.path).ok();
+        let mut held = Self::held();
+        if let Some((_, holders)) = held.get_mut(&self.0) {
+            *holders -= 1;
+            if *holders == 0 {
+                // Closing the file releases the lock
+                held.remove(&self.0);
+            }
+        }
+    }
+}
+
+/// Opens lock file without truncating it. Falls back to read-only mode
+/// as read lock doesn't need write access
+fn open(path: &Path) -> io::Result<File> {
+    let result = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .mode(0o640) // u=rw,g=r,o=
+        .open(path);
+
+    match result {
+        Err(error) if error.kind() == io::ErrorKind::PermissionDenied => File::open(path),
+        result => result,
     }
 }
 
-fn flock(file: &File, flag: libc::c_int) -> io::Result<()> {
-    let ret = unsafe { libc::flock(file.as_raw_fd(), flag) };
-    if ret < 0 {
-        Err(io::Error::last_os_error())
-    } else {
-        Ok(())
+/// Asks kernel which process holds lock conflicting with ours
+fn holder_pid(file: &File) -> Option<i32> {
+    let mut record = record(false);
+    let result = unsafe { libc::fcntl(file.as_raw_fd(), libc::F_GETLK, &mut record) };
+    // Kernel changes only lock type if there's no conflicting lock anymore
+    (result == 0 && record.l_pid > 0).then_some(record.l_pid)
+}
+
+/// Describes read or write lock of the whole file
+// Constants are c_int on some platforms and c_short on others
+#[allow(clippy::cast_possible_truncation, clippy::unnecessary_cast)]
+fn record(write: bool) -> libc::flock {
+    let r#type = if write { libc::F_WRLCK } else { libc::F_RDLCK };
+
+    // SAFETY: flock is a plain C struct, zeroes are valid for all its fields
+    let mut record: libc::flock = unsafe { std::mem::zeroed() };
+    record.l_type = r#type as libc::c_short;
+    record.l_whence = libc::SEEK_SET as libc::c_short;
+    record
+}
+
+fn holder(pid: Option<i32>) -> String {
+    pid.map_or_else(
+        || "another process".to_string(),
+        |pid| format!("process {pid}"),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{record, Lock, LockError, LockWait};
+    use crate::{dpkg::Paths, testing::temp_dir};
+    use std::{
+        ffi::CString,
+        fs::OpenOptions,
+        os::unix::{ffi::OsStrExt, io::AsRawFd},
+        path::Path,
+        time::Duration,
+    };
+
+    /// Checks in child process whether write lock of `path` would conflict.
+    /// File is opened only by child, as closing it in this process would release locks
+    fn locked_for_others(path: &Path) -> bool {
+        let path = CString::new(path.as_os_str().as_bytes()).unwrap();
+        let child = unsafe { libc::fork() };
+        if child == 0 {
+            unsafe {
+                let fd = libc::open(path.as_ptr(), libc::O_RDONLY);
+                let mut record = record(true);
+                libc::fcntl(fd, libc::F_GETLK, &mut record);
+                let unlocked = i32::from(record.l_type) == libc::F_UNLCK;
+                libc::_exit(i32::from(!unlocked));
+            }
+        }
+
+        let mut status = 0;
+        unsafe { libc::waitpid(child, &mut status, 0) };
+        libc::WEXITSTATUS(status) == 1
+    }
+
+    #[tokio::test]
+    async fn busy_database() {
+        let directory = temp_dir("twackup-dpkg-lock").unwrap();
+        let paths = Paths::new(&directory);
+
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(paths.frontend_lock_file())
+            .unwrap();
+
+        // Locks of the same process don't conflict, so writer lock is taken by child
+        let mut pipe = [0; 2];
+        assert_eq!(unsafe { libc::pipe(pipe.as_mut_ptr()) }, 0);
+        let child = unsafe { libc::fork() };
+        if child == 0 {
+            unsafe {
+                let mut record = record(true);
+                libc::fcntl(file.as_raw_fd(), libc::F_SETLK, &mut record);
+                libc::write(pipe[1], [1_u8].as_ptr().cast(), 1);
+                libc::pause();
+                libc::_exit(0);
+            }
+        }
+        let mut ready = [0_u8];
+        unsafe { libc::read(pipe[0], ready.as_mut_ptr().cast(), 1) };
+
+        let error = Lock::new(&paths, LockWait::NonBlocking).await.err();
+        assert!(matches!(error, Some(LockError::Busy { pid: Some(pid), .. }) if pid == child));
+
+        let timeout = LockWait::Timeout(Duration::from_millis(300));
+        let error = Lock::new(&paths, timeout).await.err();
+        assert!(matches!(error, Some(LockError::Timeout { .. })));
+
+        unsafe {
+            libc::kill(child, libc::SIGKILL);
+            libc::waitpid(child, std::ptr::null_mut(), 0);
+            libc::close(pipe[0]);
+            libc::close(pipe[1]);
+        }
+
+        let lock = Lock::new(&paths, LockWait::NonBlocking).await;
+        assert!(lock.is_ok());
+        drop(lock);
+
+        assert!(paths.frontend_lock_file().exists());
+        assert!(paths.lock_file().exists());
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn overlapping_locks() {
+        let directory = temp_dir("twackup-dpkg-lock-overlapping").unwrap();
+        let paths = Paths::new(&directory);
+
+        let first = Lock::new(&paths, LockWait::NonBlocking).await.unwrap();
+        let second = Lock::new(&paths, LockWait::NonBlocking).await.unwrap();
+        assert!(locked_for_others(&paths.lock_file()));
+
+        // Releasing one holder must not unlock database for the other one
+        drop(first);
+        assert!(locked_for_others(&paths.lock_file()));
+        assert!(locked_for_others(&paths.frontend_lock_file()));
+
+        drop(second);
+        assert!(!locked_for_others(&paths.lock_file()));
+        assert!(!locked_for_others(&paths.frontend_lock_file()));
+
+        std::fs::remove_dir_all(directory).unwrap();
     }
 }
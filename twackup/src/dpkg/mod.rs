@@ -19,18 +19,21 @@
 
 mod lock;
 mod paths;
+mod updates;
 
 use crate::{
     error::Result,
+    overrides::{Accounts, Diversions, StatOverrides},
     package::{Package, Priority, Section},
-    parser::Parser,
+    vfs::FileSystem,
 };
 use lock::Lock;
+pub use lock::{LockError, LockWait};
 pub(crate) use paths::Paths;
 use std::{
     collections::{BTreeMap, HashSet, LinkedList},
-    fs,
     path::{Path, PathBuf},
+    sync::Arc,
 };
 
 /// Defines how packages must be sorted
@@ -49,6 +52,7 @@ pub struct Dpkg {
     /// Represents different paths for dpkg files and directories
     pub paths: Paths,
     should_lock: bool,
+    lock_wait: LockWait,
 }
 
 impl Dpkg {
@@ -57,18 +61,56 @@ impl Dpkg {
     /// # Parameters
     ///
     /// - `dpkg_dir` - directory of dpkg data, on iOS and debian based systems
-    /// it is located at **/var/lib/dpkg**
+    ///   it is located at **/var/lib/dpkg**
     ///
     /// - `should_lock` - if dpkg database should be locked while getting
-    /// packages or performing other operations
+    ///   packages or performing other operations. Locking is compatible with
+    ///   dpkg and apt, so database isn't read in the middle of their transactions
     #[inline]
     pub fn new<P: AsRef<Path>>(dpkg_dir: P, should_lock: bool) -> Self {
         Self {
             paths: Paths::new(dpkg_dir),
             should_lock,
+            lock_wait: LockWait::default(),
         }
     }
 
+    /// Constructs dpkg wrapper for system located at `root`, e.g. mounted device image,
+    /// Debian chroot or copy of rootless `/var/jb` tree placed at `<root>/var/jb`.
+    ///
+    /// `dpkg_dir` is given as seen inside of the root, e.g. **/var/lib/dpkg**.
+    /// Files of packages are resolved under the root too and symlinks never lead outside of it
+    #[inline]
+    pub fn with_root<R: AsRef<Path>, P: AsRef<Path>>(
+        root: R,
+        dpkg_dir: P,
+        should_lock: bool,
+    ) -> Self {
+        Self {
+            paths: Paths::with_root(root, dpkg_dir),
+            ..Self::new("", should_lock)
+        }
+    }
+
+    /// Reads database and installed files from `fs`, e.g. [`TarFs`](crate::vfs::TarFs)
+    /// with snapshot of another system, instead of the real filesystem.
+    /// Such database is never locked as nobody else can modify it
+    #[inline]
+    #[must_use]
+    pub fn filesystem<F: FileSystem + 'static>(mut self, fs: F) -> Self {
+        self.paths = self.paths.filesystem(Arc::new(fs));
+        self
+    }
+
+    /// Sets how to wait for database locked by another process.
+    /// Waits forever by default
+    #[inline]
+    #[must_use]
+    pub fn lock_wait(mut self, wait: LockWait) -> Self {
+        self.lock_wait = wait;
+        self
+    }
+
     /// Fetches packages from dpkg database
     ///
     /// # Parameters
@@ -78,14 +120,24 @@ impl Dpkg {
     /// Returns error if parsing database failed or dpkg directory lock failed
     pub async fn unsorted_packages(&self, leaves_only: bool) -> Result<LinkedList<Package>> {
         // lock database as it can be modified while parsing
-        let lock = if self.should_lock {
-            Some(Lock::new(&self.paths)?)
-        } else {
-            None
-        };
+        let lock = self.lock().await?;
 
-        let parser = Parser::new(self.paths.status_file())?;
-        let packages = parser.parse::<Package>().await;
+        let parser = self.paths.parser(&self.paths.status_file())?;
+        let mut packages = parser.parse::<Package>().await;
+
+        // dpkg merges its journal on the next run, until then status file is outdated
+        let updates = updates::pending(&self.paths)?;
+        if !updates.is_empty() {
+            log::warn!(
+                "Dpkg database has {} unmerged updates, probably dpkg was interrupted. \
+                Run `dpkg --configure -a` to fix it",
+                updates.len()
+            );
+            packages = updates::merge(&self.paths, packages, &updates)
+                .await?
+                .into_iter()
+                .collect();
+        }
 
         // remove database lock as it is not needed
         drop(lock);
@@ -146,17 +198,69 @@ impl Dpkg {
         Ok(sorted)
     }
 
+    /// Reads diversions made by packages and administrator
+    ///
+    /// # Errors
+    /// Returns error if diversions file couldn't be read or is malformed
+    #[inline]
+    pub fn diversions(&self) -> Result<Diversions> {
+        let contents = self.paths.read_optional(&self.paths.diversions_file())?;
+        Ok(Diversions::parse(&contents)?)
+    }
+
+    /// Reads users and groups of the system packages are installed to
+    ///
+    /// # Errors
+    /// Returns error if `/etc/passwd` or `/etc/group` couldn't be read
+    #[inline]
+    pub fn accounts(&self) -> Result<Accounts> {
+        Accounts::load(&self.paths)
+    }
+
+    /// Reads owners and permissions overridden by administrator
+    ///
+    /// # Errors
+    /// Returns error if statoverride file couldn't be read or is malformed
+    #[inline]
+    pub fn stat_overrides(&self) -> Result<StatOverrides> {
+        let contents = self.paths.read_optional(&self.paths.statoverride_file())?;
+        Ok(StatOverrides::parse(&contents)?)
+    }
+
+    /// Checks if dpkg journal has entries that aren't merged into status file yet.
+    /// It happens when dpkg was interrupted. Packages are returned with these entries
+    /// applied anyway, as dpkg does
+    ///
+    /// # Errors
+    /// Returns error if updates directory couldn't be read
+    #[inline]
+    pub fn has_pending_updates(&self) -> Result<bool> {
+        Ok(!updates::pending(&self.paths)?.is_empty())
+    }
+
+    /// Locks dpkg database if wrapper was constructed with `should_lock`
+    /// and database is located in the real filesystem
+    pub(crate) async fn lock(&self) -> Result<Option<Lock>> {
+        let is_host = self.paths.fs().host_path(self.paths.as_ref()).is_some();
+        if self.should_lock && is_host {
+            Ok(Some(Lock::new(&self.paths, self.lock_wait).await?))
+        } else {
+            Ok(None)
+        }
+    }
+
     /// Fetches packages info directory contents
     ///
     /// # Errors
     /// Returns error if dpkg directory read failed
     pub fn info_dir_contents(&self) -> Result<HashSet<PathBuf>> {
-        Ok(fs::read_dir(self.paths.info_dir())?
-            .flatten()
-            .filter_map(|entry| match entry.metadata() {
-                Ok(metadata) if !metadata.is_dir() => Some(entry.path()),
-                _ => None,
-            })
+        Ok(self
+            .paths
+            .fs()
+            .read_dir(&self.paths.info_dir())?
+            .into_iter()
+            .filter(|entry| !entry.metadata.is_dir())
+            .map(|entry| entry.path)
             .collect())
     }
 }
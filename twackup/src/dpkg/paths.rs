@@ -17,39 +17,173 @@
  * along with Twackup. If not, see <http://www.gnu.org/licenses/>.
  */
 
-use std::path::{Path, PathBuf};
+use crate::{
+    parser::Parser,
+    vfs::{self, FileSystem, RealFs},
+};
+use std::{
+    borrow::Cow,
+    io,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
 #[derive(Clone, Debug)]
-pub struct Paths(pub PathBuf);
+pub struct Paths {
+    fs: Arc<dyn FileSystem>,
+    root: Option<PathBuf>,
+    dpkg_dir: PathBuf,
+    admin_dir: PathBuf,
+}
 
 impl Paths {
     #[inline]
     pub fn new<P: AsRef<Path>>(dpkg_dir: P) -> Self {
-        Self(dpkg_dir.as_ref().to_path_buf())
+        Self {
+            fs: Arc::new(RealFs),
+            root: None,
+            dpkg_dir: dpkg_dir.as_ref().to_path_buf(),
+            admin_dir: dpkg_dir.as_ref().to_path_buf(),
+        }
+    }
+
+    /// Creates paths of dpkg database located at `dpkg_dir` inside of `root`,
+    /// e.g. mounted device image or chroot. Installed files are resolved under it too
+    pub fn with_root<R: AsRef<Path>, P: AsRef<Path>>(root: R, dpkg_dir: P) -> Self {
+        let root = root.as_ref();
+        if root == Path::new("/") {
+            return Self::new(dpkg_dir);
+        }
+
+        let mut paths = Self {
+            root: Some(root.to_path_buf()),
+            ..Self::new(dpkg_dir)
+        };
+        paths.locate_admin_dir();
+        paths
+    }
+
+    /// Reads database and installed files from `fs` instead of the real filesystem
+    #[must_use]
+    pub fn filesystem(mut self, fs: Arc<dyn FileSystem>) -> Self {
+        self.fs = fs;
+        self.locate_admin_dir();
+        self
+    }
+
+    /// Filesystem database and installed files are read from
+    #[inline]
+    pub fn fs(&self) -> &dyn FileSystem {
+        self.fs.as_ref()
+    }
+
+    /// Directory all installed files are resolved under. `None` for the live system
+    #[inline]
+    pub fn root(&self) -> Option<&Path> {
+        self.root.as_deref()
+    }
+
+    /// Converts absolute `path` as seen inside of root to path in the filesystem.
+    /// Symlinks are resolved like in chroot: absolute targets and `..` never leave the root.
+    /// The last component is resolved only if `follow` is set.
+    ///
+    /// # Errors
+    /// Returns error if symlinks are nested too deeply or some of them couldn't be read
+    pub fn resolve<'a>(&self, path: &'a Path, follow: bool) -> io::Result<Cow<'a, Path>> {
+        let Some(root) = &self.root else {
+            return Ok(Cow::Borrowed(path));
+        };
+
+        let resolved = vfs::resolve(root, path, follow, |path| {
+            match self.fs.symlink_metadata(path) {
+                Ok(metadata) if metadata.is_symlink() => self.fs.read_link(path).map(Some),
+                _ => Ok(None),
+            }
+        })?;
+
+        Ok(Cow::Owned(resolved))
+    }
+
+    /// Prepares parser of database file. Files of the real filesystem are mapped to memory
+    ///
+    /// # Errors
+    /// Returns error if file couldn't be read
+    pub(crate) fn parser(&self, path: &Path) -> io::Result<Parser> {
+        match self.fs.host_path(path) {
+            Some(path) => Parser::new(path),
+            None => Ok(Parser::from_bytes(self.fs.read(path)?)),
+        }
+    }
+
+    /// Reads database file which is created by dpkg only when needed.
+    /// Returns empty string if file doesn't exist
+    ///
+    /// # Errors
+    /// Returns error if file exists but couldn't be read
+    pub(crate) fn read_optional(&self, path: &Path) -> io::Result<String> {
+        match self.fs.read_to_string(path) {
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(String::new()),
+            result => result,
+        }
+    }
+
+    fn locate_admin_dir(&mut self) {
+        // Missing directory will be reported when database is read
+        self.admin_dir = match (&self.root, self.resolve(&self.dpkg_dir, true)) {
+            (_, Ok(admin_dir)) => admin_dir.into_owned(),
+            (Some(root), Err(_)) => {
+                root.join(self.dpkg_dir.strip_prefix("/").unwrap_or(&self.dpkg_dir))
+            }
+            (None, Err(_)) => self.dpkg_dir.clone(),
+        };
     }
 
     #[must_use]
     #[inline]
     pub fn status_file(&self) -> PathBuf {
-        self.0.join("status")
+        self.admin_dir.join("status")
     }
 
     #[must_use]
     #[inline]
     pub fn info_dir(&self) -> PathBuf {
-        self.0.join("info")
+        self.admin_dir.join("info")
+    }
+
+    #[must_use]
+    #[inline]
+    pub fn diversions_file(&self) -> PathBuf {
+        self.admin_dir.join("diversions")
+    }
+
+    #[must_use]
+    #[inline]
+    pub fn statoverride_file(&self) -> PathBuf {
+        self.admin_dir.join("statoverride")
+    }
+
+    #[must_use]
+    #[inline]
+    pub fn updates_dir(&self) -> PathBuf {
+        self.admin_dir.join("updates")
     }
 
     #[must_use]
     #[inline]
     pub fn lock_file(&self) -> PathBuf {
-        self.0.join("lock")
+        self.admin_dir.join("lock")
+    }
+
+    #[must_use]
+    #[inline]
+    pub fn frontend_lock_file(&self) -> PathBuf {
+        self.admin_dir.join("lock-frontend")
     }
 }
 
 impl AsRef<PathBuf> for Paths {
     fn as_ref(&self) -> &PathBuf {
-        &self.0
+        &self.admin_dir
     }
 }
 
@@ -76,3 +210,49 @@ impl From<&str> for Paths {
         Self::new(value)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Paths;
+    use crate::testing::temp_dir;
+    use std::{fs, os::unix::fs::symlink, path::Path};
+
+    #[test]
+    fn resolve_under_root() {
+        let root = temp_dir("twackup-root-paths").unwrap();
+        fs::create_dir_all(root.join("private/preboot/jb/usr/lib"))
+            .and_then(|()| fs::create_dir_all(root.join("var")))
+            .unwrap();
+        symlink("/private/preboot/jb", root.join("var/jb")).unwrap();
+        symlink("../../../../../../etc", root.join("private/escape")).unwrap();
+        symlink("loop", root.join("loop")).unwrap();
+
+        let paths = Paths::with_root(&root, "/var/jb/var/lib/dpkg");
+        assert_eq!(
+            paths.as_ref(),
+            &root.join("private/preboot/jb/var/lib/dpkg")
+        );
+
+        let resolve =
+            |path: &str, follow| paths.resolve(Path::new(path), follow).unwrap().into_owned();
+        assert_eq!(
+            resolve("/var/jb/usr/lib", true),
+            root.join("private/preboot/jb/usr/lib")
+        );
+        assert_eq!(resolve("/var/jb", false), root.join("var/jb"));
+        assert_eq!(
+            resolve("/private/escape/passwd", true),
+            root.join("etc/passwd")
+        );
+        assert!(paths.resolve(Path::new("/loop"), true).is_err());
+
+        let live = Paths::with_root("/", "/var/lib/dpkg");
+        assert!(live.root().is_none());
+        assert_eq!(
+            live.resolve(Path::new("/var/jb"), true).unwrap(),
+            Path::new("/var/jb")
+        );
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,178 @@
+/*
+ * Copyright 2020 DanP
+ *
+ * This file is part of Twackup
+ *
+ * Twackup is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Twackup is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Twackup. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use super::paths::Paths;
+use crate::{
+    package::{Field, Package},
+    parser::Parsable,
+};
+use std::{collections::HashMap, convert::Infallible, io, path::PathBuf};
+
+/// Longest name of journal entry dpkg accepts
+const MAX_NAME_LENGTH: usize = 10;
+
+/// Raw stanza of journal entry. Unlike [`Package`] it can't fail parsing,
+/// so records of removed packages aren't lost
+struct Record(HashMap<String, String>);
+
+impl Parsable for Record {
+    type Error = Infallible;
+
+    fn new(fields: HashMap<String, String>) -> Result<Self, Self::Error> {
+        Ok(Self(fields))
+    }
+}
+
+/// Lists journal entries dpkg hasn't merged into status file yet in the order it applies them.
+/// Entry names consist of digits only, other files like `tmp.i` are being written right now.
+pub(crate) fn pending(paths: &Paths) -> io::Result<Vec<PathBuf>> {
+    let entries = match paths.fs().read_dir(&paths.updates_dir()) {
+        Ok(entries) => entries,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(error) => return Err(error),
+    };
+
+    let mut updates = vec![];
+    for entry in entries {
+        let name = entry.path.file_name().and_then(|name| name.to_str());
+        let is_journal = name.is_some_and(|name| {
+            !name.is_empty()
+                && name.len() <= MAX_NAME_LENGTH
+                && name.bytes().all(|byte| byte.is_ascii_digit())
+        });
+
+        if is_journal {
+            updates.push(entry.path);
+        }
+    }
+
+    // dpkg sorts entries alphabetically, names have the same width so it's a numeric order too
+    updates.sort();
+
+    Ok(updates)
+}
+
+/// Applies journal entries on top of packages parsed from status file.
+/// Every record replaces the package with the same name and architecture,
+/// records that aren't valid packages anymore (e.g. purged ones) remove it.
+pub(crate) async fn merge(
+    paths: &Paths,
+    packages: impl IntoIterator<Item = Package>,
+    updates: &[PathBuf],
+) -> io::Result<Vec<Package>> {
+    let mut merged: Vec<_> = packages.into_iter().map(Some).collect();
+    let mut positions: HashMap<_, _> = merged
+        .iter()
+        .flatten()
+        .enumerate()
+        .map(|(index, package)| (key_of(package), index))
+        .collect();
+
+    for path in updates {
+        // Empty files can't be mapped and contain nothing anyway
+        if paths.fs().metadata(path)?.len == 0 {
+            continue;
+        }
+
+        for Record(fields) in paths.parser(path)?.parse::<Record>().await {
+            let Some(id) = fields.get(Field::Package.as_str()) else {
+                continue;
+            };
+            let architecture = fields.get(Field::Architecture.as_str());
+            let key = (id.clone(), architecture.cloned().unwrap_or_default());
+
+            let package = Package::new(fields).ok();
+            if let Some(&index) = positions.get(&key) {
+                merged[index] = package;
+            } else if package.is_some() {
+                positions.insert(key, merged.len());
+                merged.push(package);
+            }
+        }
+    }
+
+    Ok(merged.into_iter().flatten().collect())
+}
+
+fn key_of(package: &Package) -> (String, String) {
+    let architecture = package.get(Field::Architecture).unwrap_or_default();
+    (package.id.clone(), architecture.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{testing::temp_dir, Dpkg, Result};
+    use std::fs;
+
+    const STATUS: &str = "Package: tweak
+Version: 1.0
+Architecture: iphoneos-arm
+Status: install ok installed
+Section: Tweaks
+
+Package: removed
+Version: 1.0
+Architecture: iphoneos-arm
+Status: install ok installed
+Section: Tweaks
+";
+
+    #[tokio::test]
+    async fn pending_updates() -> Result<()> {
+        let directory = temp_dir("twackup-dpkg-updates")?;
+        fs::create_dir_all(directory.join("updates"))?;
+        fs::write(directory.join("status"), STATUS)?;
+
+        let dpkg = Dpkg::new(&directory, false);
+        assert!(!dpkg.has_pending_updates()?);
+
+        let update =
+            |name: &str, contents: &str| fs::write(directory.join("updates").join(name), contents);
+        update(
+            "0000",
+            "Package: tweak\nVersion: 2.0\nArchitecture: iphoneos-arm\nStatus: install ok unpacked\nSection: Tweaks\n",
+        )?;
+        update(
+            "0001",
+            "Package: tweak\nVersion: 2.0\nArchitecture: iphoneos-arm\nStatus: install ok installed\nSection: Tweaks\n",
+        )?;
+        update(
+            "0002",
+            "Package: removed\nArchitecture: iphoneos-arm\nStatus: purge ok not-installed\n",
+        )?;
+        update(
+            "0003",
+            "Package: new\nVersion: 0.1\nArchitecture: all\nStatus: install ok installed\nSection: Utilities\n",
+        )?;
+        // Entry being written must be skipped
+        update("tmp.i", "Package: tweak\nVersion: 3.0\n")?;
+        assert!(dpkg.has_pending_updates()?);
+
+        let packages: Vec<_> = dpkg.unsorted_packages(false).await?.into_iter().collect();
+        let versions: Vec<_> = packages
+            .iter()
+            .map(|package| (package.id.as_str(), package.version.as_str()))
+            .collect();
+        assert_eq!(versions, [("tweak", "2.0"), ("new", "0.1")]);
+
+        fs::remove_dir_all(directory)?;
+
+        Ok(())
+    }
+}
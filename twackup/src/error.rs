@@ -37,18 +37,73 @@ pub enum Generic {
     #[error("PackageError: {0}")]
     Package(#[from] crate::package::Error),
 
+    /// Some debian archive reading error
+    #[error("DebError: {0}")]
+    Deb(#[from] crate::deb::Error),
+
+    /// Backup manifest reading or writing error
+    #[error("ManifestError: {0}")]
+    #[cfg(feature = "serde")]
+    Manifest(#[from] crate::manifest::Error),
+
     /// Some package parsing error
     #[error("RepoError: {0}")]
     Repo(#[from] crate::repository::Error),
 
+    /// Backup encryption or decryption error
+    #[error("CryptoError: {0}")]
+    Crypto(#[from] crate::crypto::Error),
+
+    /// Backup upload error
+    #[error("UploadError: {0}")]
+    #[cfg(feature = "upload")]
+    Upload(#[from] crate::upload::Error),
+
+    /// Diversions or statoverride database parsing error
+    #[error("OverridesError: {0}")]
+    Overrides(#[from] crate::overrides::Error),
+
+    /// Retention rule parsing error
+    #[error("PruneError: {0}")]
+    Prune(#[from] crate::prune::Error),
+
+    /// Repository metadata signing or verification error
+    #[error("OpenPGPError: {0}")]
+    OpenPgp(#[from] crate::repo::openpgp::Error),
+
     /// Is used when dpkg database lock failed
-    #[error("Lock")]
-    Lock,
+    #[error("LockError: {0}")]
+    Lock(#[from] crate::dpkg::LockError),
+
+    /// Is used when entry is appended to already finished archive
+    #[error("Archive is already finished")]
+    ArchiveFinished,
 
     /// Another IO error
     #[error("Path must have file ending")]
     PathMustHaveFileEnding,
 
+    /// Is used when package files couldn't be fully archived in strict mode
+    #[error(
+        "Package `{package}` is incomplete: {missing} file(s) missing, {unreadable} unreadable"
+    )]
+    IncompletePackage {
+        /// Identifier of the package
+        package: String,
+        /// Count of files that don't exist anymore
+        missing: usize,
+        /// Count of files that couldn't be read
+        unreadable: usize,
+    },
+
+    /// Is used when operation was stopped by user
+    #[error("Cancelled")]
+    Cancelled,
+
+    /// Is used when build task panicked or was aborted
+    #[error("TaskError: {0}")]
+    Task(#[from] tokio::task::JoinError),
+
     /// Is used when time is backwards
     #[error("SystemTimeError")]
     SystemTime(#[from] std::time::SystemTimeError),
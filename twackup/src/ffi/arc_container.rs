@@ -18,7 +18,7 @@
  */
 
 use safer_ffi::layout::{OpaqueKind, ReprC};
-use std::{mem, ops::Deref, ptr::NonNull, sync::Arc};
+use std::{borrow::Borrow, mem, ops::Deref, ptr::NonNull, sync::Arc};
 
 pub trait ArcContainerFFIType {
     fn name() -> String;
@@ -91,6 +91,12 @@ impl<Type> AsRef<Type> for ArcContainer<Type> {
     }
 }
 
+impl<Type> Borrow<Type> for ArcContainer<Type> {
+    fn borrow(&self) -> &Type {
+        self
+    }
+}
+
 unsafe impl<Type> Send for ArcContainer<Type> {}
 
 unsafe impl<Type> ReprC for ArcContainer<Type>
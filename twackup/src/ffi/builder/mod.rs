@@ -22,10 +22,10 @@ pub(crate) mod progress;
 use self::progress::{TwProgressFunctions, TwProgressImpl};
 use super::ArcContainer;
 use crate::{
-    builder::{Preferences, Worker},
+    builder::{BuildSession, PackageResult, Preferences},
+    error::Generic,
     ffi::{c_dpkg::TwDpkg, package::TwPackage},
     package::Package,
-    progress::Progress,
     Result,
 };
 use safer_ffi::{
@@ -36,7 +36,7 @@ use safer_ffi::{
     },
     ptr::NonNullMut,
 };
-use std::{collections::LinkedList, os::unix::ffi::OsStringExt, sync::Arc};
+use std::os::unix::ffi::OsStringExt;
 
 #[derive_ReprC]
 #[repr(C)]
@@ -45,6 +45,7 @@ pub struct TwPackagesRebuildResult {
     package: ArcContainer<Package>,
     deb_path: Option<Box<u8>>,
     error: Option<Box<u8>>,
+    cancelled: bool,
 }
 
 #[derive_ReprC]
@@ -94,6 +95,8 @@ pub struct TwBuildPreferences {
     compression_type: TwCompressionType,
     compression_level: TwCompressionLevel,
     follow_symlinks: bool,
+    strict: bool,
+    incremental: bool,
 }
 
 #[derive_ReprC]
@@ -119,34 +122,26 @@ pub(crate) fn rebuild_packages(dpkg: &TwDpkg, parameters: TwBuildParameters<'_>)
     preferences.compression.level = parameters.preferences.compression_level.into();
     preferences.compression.r#type = parameters.preferences.compression_type.into();
     preferences.follow_symlinks = parameters.preferences.follow_symlinks;
-
-    let dpkg_contents = Arc::new(dpkg.inner_dpkg().info_dir_contents()?);
-
-    let mut workers = LinkedList::new();
-    for package in parameters.packages.iter() {
-        let package = package.clone();
-        let dpkg_contents = dpkg_contents.clone();
-        let preferences = preferences.clone();
-
-        workers.push_back(tokio_rt.spawn(async move {
-            let worker = Worker::new(&*package, progress, None, preferences, dpkg_contents);
-            let result = worker.run().await;
-            (package, result)
-        }));
-    }
-
-    let mut results = vec![];
-    tokio_rt.block_on(async {
-        for worker in workers {
-            let Ok((package, result)) = worker.await else {
-                continue;
-            };
-
+    preferences.strict = parameters.preferences.strict;
+    preferences.incremental = parameters.preferences.incremental;
+
+    let session = BuildSession::new(dpkg.inner_dpkg(), preferences, progress)?
+        .cancellation(dpkg.new_rebuild_cancellation());
+    let packages = parameters.packages.iter().cloned();
+    let summary = tokio_rt.block_on(session.run(packages));
+    dpkg.finish_rebuild();
+    let summary = summary?;
+
+    let results: Vec<_> = summary
+        .results
+        .into_iter()
+        .map(|PackageResult { package, result }| {
             log::debug!("rebuild result = {result:?}");
 
+            let cancelled = matches!(result, Err(Generic::Cancelled));
             let result = result
-                .map(|path| {
-                    let path = OsStringExt::into_vec(path.into_os_string());
+                .map(|report| {
+                    let path = OsStringExt::into_vec(report.deb_path.into_os_string());
                     Box::from(path.into_boxed_slice())
                 })
                 .map_err(|error| {
@@ -159,16 +154,15 @@ pub(crate) fn rebuild_packages(dpkg: &TwDpkg, parameters: TwBuildParameters<'_>)
                 Err(error) => (None, Some(error)),
             };
 
-            results.push(TwPackagesRebuildResult {
+            TwPackagesRebuildResult {
                 success: deb_path.is_some(),
                 package,
                 deb_path,
                 error,
-            });
-        }
-    });
-
-    progress.finished_all();
+                cancelled,
+            }
+        })
+        .collect();
 
     if let Some(results_out) = parameters.results {
         let boxed = Box::from(results.into_boxed_slice());
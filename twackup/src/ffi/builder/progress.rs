@@ -17,9 +17,12 @@
  * along with Twackup. If not, see <http://www.gnu.org/licenses/>.
  */
 
-use crate::{ffi::package::TwPackage, package::Package, progress::Progress};
+use crate::{
+    ffi::package::TwPackage,
+    progress::{Event, Progress},
+};
 use safer_ffi::{derive_ReprC, prelude::c_slice::Raw, slice::Ref};
-use std::{ffi::c_void, os::unix::ffi::OsStrExt, path::Path, ptr::NonNull};
+use std::{ffi::c_void, os::unix::ffi::OsStrExt, ptr::NonNull};
 
 type Context = Option<NonNull<c_void>>;
 
@@ -32,6 +35,14 @@ pub struct TwProgressFunctions {
     finished_processing:
         Option<unsafe extern "C" fn(context: Context, package: TwPackage, deb_path: Raw<u8>)>,
     finished_all: Option<unsafe extern "C" fn(context: Context)>,
+    failed_processing:
+        Option<unsafe extern "C" fn(context: Context, package: TwPackage, error: Raw<u8>)>,
+    file_archived: Option<
+        unsafe extern "C" fn(context: Context, package: TwPackage, path: Raw<u8>, bytes: u64),
+    >,
+    compression_progress: Option<
+        unsafe extern "C" fn(context: Context, package: TwPackage, processed: u64, compressed: u64),
+    >,
 }
 
 #[derive(Copy, Clone)]
@@ -40,26 +51,53 @@ pub(crate) struct TwProgressImpl {
 }
 
 impl Progress for TwProgressImpl {
-    fn started_processing(&self, package: &Package) {
-        if let Some(func) = self.functions.started_processing {
-            let package = TwPackage::from(package);
-            unsafe { func(self.functions.context, package) };
-        }
-    }
-
-    fn finished_processing<P: AsRef<Path>>(&self, package: &Package, deb_path: P) {
-        if let Some(func) = self.functions.finished_processing {
-            let package = TwPackage::from(package);
-            let deb_path = deb_path.as_ref().as_os_str().as_bytes();
-            let deb_path = Raw::from(Ref::from(deb_path));
-
-            unsafe { func(self.functions.context, package, deb_path) };
-        }
-    }
+    fn on_event(&self, event: Event<'_>) {
+        let context = self.functions.context;
 
-    fn finished_all(&self) {
-        if let Some(func) = self.functions.finished_all {
-            unsafe { func(self.functions.context) };
+        match event {
+            Event::Started { package } => {
+                if let Some(func) = self.functions.started_processing {
+                    unsafe { func(context, TwPackage::from(package)) };
+                }
+            }
+            Event::FileArchived {
+                package,
+                path,
+                bytes,
+            } => {
+                if let Some(func) = self.functions.file_archived {
+                    let path = Raw::from(Ref::from(path.as_os_str().as_bytes()));
+                    unsafe { func(context, TwPackage::from(package), path, bytes) };
+                }
+            }
+            Event::Compression {
+                package,
+                processed,
+                compressed,
+            } => {
+                if let Some(func) = self.functions.compression_progress {
+                    unsafe { func(context, TwPackage::from(package), processed, compressed) };
+                }
+            }
+            Event::Failed { package, error } => {
+                if let Some(func) = self.functions.failed_processing {
+                    let error = error.to_string();
+                    let error = Raw::from(Ref::from(error.as_bytes()));
+                    unsafe { func(context, TwPackage::from(package), error) };
+                }
+            }
+            Event::Finished { package, report } => {
+                if let Some(func) = self.functions.finished_processing {
+                    let deb_path = report.deb_path.as_os_str().as_bytes();
+                    let deb_path = Raw::from(Ref::from(deb_path));
+                    unsafe { func(context, TwPackage::from(package), deb_path) };
+                }
+            }
+            Event::AllFinished => {
+                if let Some(func) = self.functions.finished_all {
+                    unsafe { func(context) };
+                }
+            }
         }
     }
 }
@@ -18,9 +18,9 @@
  */
 
 use super::package::TwPackage;
-use crate::{dpkg::PackagesSort, Dpkg};
+use crate::{builder::CancellationToken, dpkg::PackagesSort, Dpkg};
 use safer_ffi::{derive_ReprC, prelude::c_slice};
-use std::{ffi::c_void, ptr::NonNull};
+use std::{ffi::c_void, ptr::NonNull, sync::Mutex};
 use tokio::runtime::{Builder, Runtime};
 
 #[derive_ReprC]
@@ -43,11 +43,21 @@ impl From<TwPackagesSort> for PackagesSort {
     }
 }
 
+/// Token of the current rebuild session
+#[derive(Default)]
+struct RebuildCancellation {
+    token: CancellationToken,
+    /// Cancel was requested, but session hasn't finished yet or hasn't started at all.
+    /// Next session checks it, so cancel arriving before the session starts isn't lost
+    requested: bool,
+}
+
 #[derive_ReprC]
 #[repr(C)]
 pub struct TwDpkg {
     dpkg_ptr: NonNull<c_void>,
     runtime_ptr: NonNull<c_void>,
+    cancellation_ptr: NonNull<c_void>,
 }
 
 impl TwDpkg {
@@ -60,10 +70,14 @@ impl TwDpkg {
             .expect("Cannot start tokio runtime");
         let runtime_ptr = Box::into_raw(Box::new(tokio_rt));
 
+        let cancellation = Mutex::new(RebuildCancellation::default());
+        let cancellation_ptr = Box::into_raw(Box::new(cancellation));
+
         unsafe {
             Self {
                 dpkg_ptr: NonNull::new_unchecked(dpkg_ptr.cast()),
                 runtime_ptr: NonNull::new_unchecked(runtime_ptr.cast()),
+                cancellation_ptr: NonNull::new_unchecked(cancellation_ptr.cast()),
             }
         }
     }
@@ -80,6 +94,38 @@ impl TwDpkg {
         unsafe { self.runtime_ptr.cast().as_ref() }
     }
 
+    #[inline]
+    fn inner_cancellation(&self) -> &Mutex<RebuildCancellation> {
+        unsafe { self.cancellation_ptr.cast().as_ref() }
+    }
+
+    /// Replaces rebuild cancellation token with fresh one and returns it.
+    /// Token is already cancelled if cancel was requested before session started
+    pub(crate) fn new_rebuild_cancellation(&self) -> CancellationToken {
+        let token = CancellationToken::new();
+        if let Ok(mut current) = self.inner_cancellation().lock() {
+            if std::mem::take(&mut current.requested) {
+                token.cancel();
+            }
+            current.token = token.clone();
+        }
+        token
+    }
+
+    /// Clears cancel request of the finished session
+    pub(crate) fn finish_rebuild(&self) {
+        if let Ok(mut current) = self.inner_cancellation().lock() {
+            current.requested = false;
+        }
+    }
+
+    pub(crate) fn cancel_rebuild(&self) {
+        if let Ok(mut current) = self.inner_cancellation().lock() {
+            current.requested = true;
+            current.token.cancel();
+        }
+    }
+
     pub(crate) fn get_packages(
         &self,
         leaves_only: bool,
@@ -107,6 +153,11 @@ impl Drop for TwDpkg {
         unsafe {
             drop(Box::from_raw(self.dpkg_ptr.cast::<Dpkg>().as_ptr()));
             drop(Box::from_raw(self.runtime_ptr.cast::<Runtime>().as_ptr()));
+            drop(Box::from_raw(
+                self.cancellation_ptr
+                    .cast::<Mutex<RebuildCancellation>>()
+                    .as_ptr(),
+            ));
         }
     }
 }
@@ -203,6 +203,18 @@ fn tw_rebuild_packages(dpkg: &TwDpkg, parameters: TwBuildParameters<'_>) -> TwRe
     }
 }
 
+/// Cancels rebuild running in *tw_rebuild_packages* for this dpkg instance.
+/// Packages which are not built yet will be returned with `cancelled` flag set.
+/// Can be called from any thread. If rebuild hasn't started yet,
+/// the next *tw_rebuild_packages* call is cancelled right away.
+///
+/// \param[in] dpkg dpkg instance which runs rebuild
+///
+#[ffi_export]
+fn tw_cancel_rebuild(dpkg: &TwDpkg) {
+    dpkg.cancel_rebuild();
+}
+
 /// Deallocates memory allocated from *tw_rebuild_packages*
 ///
 /// \param[in] results *tw_rebuild_packages* result
@@ -0,0 +1,577 @@
+/*
+ * Copyright 2020 DanP
+ *
+ * This file is part of Twackup
+ *
+ * Twackup is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Twackup is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Twackup. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Checks installed files of packages against hashes dpkg recorded in `info/<package>.md5sums`.
+//!
+//! Permission bits and owners are not recorded by dpkg, so they are checked only
+//! when reference debs are provided, e.g. ones built by twackup earlier.
+//! Diverted files are checked at the paths they were moved to.
+//!
+//! ### Example usage
+//!
+//! ```no_run
+//! use twackup::{integrity::Checker, Dpkg, Result};
+//!
+//! #[tokio::main]
+//! async fn main() -> Result<()> {
+//!     let dpkg = Dpkg::new("/var/lib/dpkg", true);
+//!     let packages = dpkg.unsorted_packages(false).await?;
+//!
+//!     let checker = Checker::new(&dpkg)?.reference_dir("/var/mobile/Documents/twackup");
+//!     for report in checker.check(packages).await? {
+//!         for issue in &report.issues {
+//!             println!("{}: {:?} {:?}", report.id, issue.path, issue.problem);
+//!         }
+//!     }
+//!
+//!     Ok(())
+//! }
+//! ```
+
+use crate::{
+    deb::{Deb, Entry},
+    dpkg::Paths,
+    error::Result,
+    overrides::Diversions,
+    package::{Field, Package},
+    vfs::FileSystem,
+    Dpkg,
+};
+use md5::{Digest, Md5};
+use std::{
+    borrow::Borrow,
+    collections::{BTreeMap, HashMap},
+    io,
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    sync::Arc,
+    thread,
+};
+use tokio::sync::Semaphore;
+
+/// Describes how installed file differs from the recorded one
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "kind", rename_all = "snake_case"))]
+#[non_exhaustive]
+pub enum Problem {
+    /// File doesn't exist anymore
+    Missing,
+    /// File contents differ from the ones dpkg installed
+    Modified {
+        /// Hash recorded by dpkg
+        recorded: String,
+        /// Hash of the installed file
+        actual: String,
+    },
+    /// Permission bits differ from the reference deb
+    Mode {
+        /// Permission bits in the reference deb
+        expected: u32,
+        /// Permission bits of the installed file
+        actual: u32,
+    },
+    /// Owner differs from the reference deb
+    Owner {
+        /// User and group identifiers in the reference deb
+        expected: (u64, u64),
+        /// User and group identifiers of the installed file
+        actual: (u64, u64),
+    },
+    /// File couldn't be read
+    Unreadable {
+        /// Description of the error
+        error: String,
+    },
+}
+
+/// Single file that failed verification
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct Issue {
+    /// Absolute path of the installed file
+    pub path: PathBuf,
+    /// Whether file is a configuration file. Users are expected to modify such files
+    pub conffile: bool,
+    /// What exactly differs
+    pub problem: Problem,
+}
+
+/// Verification result of a single package
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct Report {
+    /// Package identifier
+    pub id: String,
+    /// Count of checked files
+    pub checked: usize,
+    /// Whether dpkg recorded hashes for this package. Nothing but
+    /// reference deb entries can be checked if it didn't
+    pub has_md5sums: bool,
+    /// Deb used as a source of expected permissions and owners
+    pub reference: Option<PathBuf>,
+    /// Files that failed verification sorted by path
+    pub issues: Vec<Issue>,
+}
+
+/// Verifies installed files of packages
+#[derive(Clone, Debug)]
+pub struct Checker {
+    paths: Paths,
+    diversions: Diversions,
+    reference_dir: Option<PathBuf>,
+    jobs: usize,
+}
+
+/// What is known about single installed file
+#[derive(Default)]
+struct Expected {
+    md5: Option<String>,
+    conffile: bool,
+    entry: Option<Entry>,
+}
+
+impl Report {
+    /// Returns true if all files match the recorded ones
+    #[inline]
+    #[must_use]
+    pub fn is_ok(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+impl Checker {
+    /// Creates checker for packages from dpkg database
+    ///
+    /// # Errors
+    /// Returns error if diversions of dpkg database couldn't be read
+    pub fn new(dpkg: &Dpkg) -> Result<Self> {
+        Ok(Self {
+            paths: dpkg.paths.clone(),
+            diversions: dpkg.diversions()?,
+            reference_dir: None,
+            jobs: thread::available_parallelism().map_or(1, NonZeroUsize::get),
+        })
+    }
+
+    /// Sets directory with reference debs named `id_version_arch.deb`.
+    /// Permission bits and owners of installed files are compared with ones from these debs
+    #[must_use]
+    pub fn reference_dir<P: AsRef<Path>>(mut self, directory: P) -> Self {
+        self.reference_dir = Some(directory.as_ref().to_path_buf());
+        self
+    }
+
+    /// Sets maximum count of files hashed at the same time.
+    /// Defaults to count of available CPUs
+    #[must_use]
+    pub fn jobs(mut self, jobs: usize) -> Self {
+        self.jobs = jobs.max(1);
+        self
+    }
+
+    /// Verifies installed files of every package. Reports are returned in the same order
+    ///
+    /// # Errors
+    /// Returns error if dpkg database or reference deb couldn't be read
+    pub async fn check<P, I>(&self, packages: I) -> Result<Vec<Report>>
+    where
+        P: Borrow<Package>,
+        I: IntoIterator<Item = P>,
+    {
+        let semaphore = Arc::new(Semaphore::new(self.jobs));
+
+        let mut pending = vec![];
+        for package in packages {
+            let package = package.borrow();
+            let (report, expected) = self.expected_files(package).await?;
+
+            let handles: Vec<_> = expected
+                .into_iter()
+                .map(|(path, expected)| {
+                    let semaphore = semaphore.clone();
+                    let paths = self.paths.clone();
+                    let source = (self.diversions)
+                        .source_of(&path, &package.id)
+                        .to_path_buf();
+                    tokio::spawn(async move {
+                        // Semaphore is never closed so acquire can't fail
+                        let _permit = semaphore.acquire_owned().await;
+                        tokio::task::spawn_blocking(move || {
+                            check_file(&paths, &path, &source, &expected)
+                        })
+                        .await
+                    })
+                })
+                .collect();
+
+            pending.push((report, handles));
+        }
+
+        let mut reports = Vec::with_capacity(pending.len());
+        for (mut report, handles) in pending {
+            report.checked = handles.len();
+            for handle in handles {
+                report.issues.extend(handle.await??);
+            }
+            reports.push(report);
+        }
+
+        Ok(reports)
+    }
+
+    /// Collects hashes, conffiles and reference entries of package files
+    async fn expected_files(
+        &self,
+        package: &Package,
+    ) -> Result<(Report, BTreeMap<PathBuf, Expected>)> {
+        let mut expected: BTreeMap<PathBuf, Expected> = BTreeMap::new();
+
+        let md5sums = read_md5sums(&self.paths, &package.id)?;
+        let has_md5sums = md5sums.is_some();
+        for (path, md5) in md5sums.unwrap_or_default() {
+            expected.entry(Path::new("/").join(path)).or_default().md5 = Some(md5);
+        }
+
+        let conffiles = package.get(Field::Custom("Conffiles".to_string()));
+        for line in conffiles.unwrap_or_default().lines() {
+            let mut parts = line.split_whitespace();
+            let (Some(path), Some(md5)) = (parts.next(), parts.next()) else {
+                continue;
+            };
+
+            // Obsolete conffiles are not expected to exist
+            if parts.next() == Some("obsolete") {
+                continue;
+            }
+
+            let file = expected.entry(PathBuf::from(path)).or_default();
+            file.md5 = Some(md5.to_string());
+            file.conffile = true;
+        }
+
+        let reference = self.reference_deb(package);
+        if let Some(deb) = &reference {
+            for entry in Deb::open(deb)?.data_entries().await? {
+                if entry.path.as_os_str().is_empty() {
+                    continue;
+                }
+                let path = Path::new("/").join(&entry.path);
+                expected.entry(path).or_default().entry = Some(entry);
+            }
+        }
+
+        let report = Report {
+            id: package.id.clone(),
+            checked: 0,
+            has_md5sums,
+            reference,
+            issues: vec![],
+        };
+
+        Ok((report, expected))
+    }
+
+    fn reference_deb(&self, package: &Package) -> Option<PathBuf> {
+        let directory = self.reference_dir.as_ref()?;
+        let path = directory.join(format!("{}.deb", package.canonical_name()));
+        path.exists().then_some(path)
+    }
+}
+
+/// Reads hashes recorded by dpkg while installing package.
+/// Paths are relative to the root. Returns `None` if package has no such file
+pub(crate) fn read_md5sums(paths: &Paths, id: &str) -> Result<Option<HashMap<PathBuf, String>>> {
+    let path = paths.info_dir().join(format!("{id}.md5sums"));
+    let contents = match paths.fs().read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(error) => return Err(error.into()),
+    };
+
+    Ok(Some(
+        contents
+            .lines()
+            .filter_map(|line| line.split_once(' '))
+            .map(|(hash, path)| {
+                let path = path.trim_start().trim_start_matches('/');
+                (PathBuf::from(path), hash.to_string())
+            })
+            .collect(),
+    ))
+}
+
+/// Compares file listed at `path` and stored at `source` with expected state.
+/// File is resolved under root of `paths` if there's one
+fn check_file(paths: &Paths, path: &Path, source: &Path, expected: &Expected) -> Vec<Issue> {
+    let issue = |problem| Issue {
+        path: path.to_path_buf(),
+        conffile: expected.conffile,
+        problem,
+    };
+
+    let metadata = paths.resolve(source, false).and_then(|resolved| {
+        let metadata = paths.fs().symlink_metadata(&resolved)?;
+        Ok((resolved, metadata))
+    });
+    let (resolved, metadata) = match metadata {
+        Ok(metadata) => metadata,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => {
+            return vec![issue(Problem::Missing)]
+        }
+        Err(error) => {
+            let error = error.to_string();
+            return vec![issue(Problem::Unreadable { error })];
+        }
+    };
+
+    let mut issues = vec![];
+    if let Some(entry) = &expected.entry {
+        // Permissions of symbolic links are meaningless
+        let actual = metadata.permissions();
+        if !metadata.is_symlink() && actual != entry.mode & 0o7777 {
+            let expected = entry.mode & 0o7777;
+            issues.push(issue(Problem::Mode { expected, actual }));
+        }
+
+        let actual = (u64::from(metadata.uid), u64::from(metadata.gid));
+        if actual != (entry.uid, entry.gid) {
+            let expected = (entry.uid, entry.gid);
+            issues.push(issue(Problem::Owner { expected, actual }));
+        }
+    }
+
+    if let Some(recorded) = &expected.md5 {
+        // Contents are read through symlinks, but they must be resolved under root too
+        let contents = if metadata.is_symlink() {
+            paths.resolve(source, true)
+        } else {
+            Ok(resolved)
+        };
+        match contents.and_then(|contents| file_md5(paths.fs(), &contents)) {
+            Ok(actual) if &actual != recorded => {
+                let recorded = recorded.clone();
+                issues.push(issue(Problem::Modified { recorded, actual }));
+            }
+            Ok(_) => {}
+            Err(error) => {
+                let error = error.to_string();
+                issues.push(issue(Problem::Unreadable { error }));
+            }
+        }
+    }
+
+    issues
+}
+
+fn file_md5(fs: &dyn FileSystem, path: &Path) -> io::Result<String> {
+    let mut file = fs.open(path)?;
+    let mut hasher = Md5::new();
+    io::copy(&mut file, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Checker, Problem};
+    use crate::{
+        builder::{Preferences, Worker},
+        package::Package,
+        parser::parse_fields,
+        testing::{temp_dir, NoProgress},
+        Dpkg, Parsable, Result,
+    };
+    use md5::{Digest, Md5};
+    use std::{
+        fs,
+        os::unix::fs::{MetadataExt, PermissionsExt},
+        sync::Arc,
+    };
+
+    #[tokio::test]
+    async fn modified_and_missing() -> Result<()> {
+        let directory = temp_dir("twackup-integrity")?;
+        let admin_dir = directory.join("dpkg");
+        fs::create_dir_all(admin_dir.join("info"))?;
+
+        let intact = directory.join("intact");
+        let modified = directory.join("modified");
+        let missing = directory.join("missing");
+        fs::write(&intact, "intact")?;
+        fs::write(&modified, "modified")?;
+
+        let md5sums = format!(
+            "{:x}  {}\n{:x}  {}\n{:x}  {}\n",
+            Md5::digest("intact"),
+            intact.display(),
+            Md5::digest("original"),
+            modified.display(),
+            Md5::digest("missing"),
+            missing.display()
+        );
+        fs::write(admin_dir.join("info/test.md5sums"), md5sums)?;
+
+        let control = "Package: test\nVersion: 1.0\nSection: misc\nStatus: install ok installed\n";
+        let package = Package::new(parse_fields(control.as_bytes()))?;
+
+        let dpkg = Dpkg::new(&admin_dir, false);
+        let reports = Checker::new(&dpkg)?.jobs(2).check([&package]).await?;
+        assert_eq!(reports.len(), 1);
+
+        let report = &reports[0];
+        assert!(report.has_md5sums);
+        assert_eq!(report.checked, 3);
+        assert_eq!(report.issues.len(), 2);
+
+        assert_eq!(report.issues[0].path, missing);
+        assert_eq!(report.issues[0].problem, Problem::Missing);
+        assert_eq!(report.issues[1].path, modified);
+        assert_eq!(
+            report.issues[1].problem,
+            Problem::Modified {
+                recorded: format!("{:x}", Md5::digest("original")),
+                actual: format!("{:x}", Md5::digest("modified")),
+            }
+        );
+
+        fs::remove_dir_all(directory)?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn diverted_file() -> Result<()> {
+        let directory = temp_dir("twackup-integrity-diversion")?;
+        let admin_dir = directory.join("dpkg");
+        fs::create_dir_all(admin_dir.join("info"))?;
+
+        let tool = directory.join("tool");
+        fs::write(&tool, "diverting")?;
+        fs::write(directory.join("tool.real"), "original")?;
+
+        let tool = tool.display();
+        fs::write(
+            admin_dir.join("info/tool.md5sums"),
+            format!("{:x}  {tool}\n", Md5::digest("original")),
+        )?;
+        fs::write(
+            admin_dir.join("info/fancy-tool.md5sums"),
+            format!("{:x}  {tool}\n", Md5::digest("diverting")),
+        )?;
+        fs::write(
+            admin_dir.join("diversions"),
+            format!("{tool}\n{tool}.real\nfancy-tool\n"),
+        )?;
+
+        let packages = ["tool", "fancy-tool"].map(|id| {
+            let control = format!(
+                "Package: {id}\nVersion: 1.0\nSection: misc\nStatus: install ok installed\n"
+            );
+            Package::new(parse_fields(control.as_bytes()))
+        });
+        let [tool, fancy_tool] = packages;
+
+        // Original file is checked where diversion moved it to,
+        // diverting package owns file at the original path
+        let dpkg = Dpkg::new(&admin_dir, false);
+        let reports = Checker::new(&dpkg)?.check([&tool?, &fancy_tool?]).await?;
+        assert_eq!(reports.len(), 2);
+        for report in &reports {
+            assert_eq!(report.checked, 1);
+            assert!(report.issues.is_empty(), "{report:?}");
+        }
+
+        fs::remove_dir_all(directory)?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn reference_mode_and_owner() -> Result<()> {
+        let directory = temp_dir("twackup-integrity-reference")?;
+        let admin_dir = directory.join("dpkg");
+        fs::create_dir_all(admin_dir.join("info"))?;
+
+        let tool = directory.join("tool");
+        fs::write(&tool, "tool")?;
+        fs::set_permissions(&tool, fs::Permissions::from_mode(0o644))?;
+
+        fs::write(
+            admin_dir.join("status"),
+            "Package: tool\nVersion: 1.0\nArchitecture: all\nStatus: install ok installed\nSection: Utilities\n",
+        )?;
+        fs::write(
+            admin_dir.join("info/tool.list"),
+            format!("{}\n", tool.display()),
+        )?;
+        fs::write(
+            admin_dir.join("info/tool.md5sums"),
+            format!("{:x}  {}\n", Md5::digest("tool"), tool.display()),
+        )?;
+
+        // Reference deb records owner and permissions of the stat override
+        let statoverride = admin_dir.join("statoverride");
+        fs::write(
+            &statoverride,
+            format!("#4242 #4343 0600 {}\n", tool.display()),
+        )?;
+
+        let dpkg = Dpkg::new(&admin_dir, false);
+        let package = dpkg.unsorted_packages(false).await?.pop_back().unwrap();
+
+        let dpkg_contents = Arc::new(dpkg.info_dir_contents()?);
+        let preferences = Preferences::new(&admin_dir, &directory);
+        let worker = Worker::new(&package, NoProgress, None, preferences, dpkg_contents);
+        let built = worker.run().await?;
+        assert!(!built.is_incomplete(), "{built:?}");
+        fs::remove_file(statoverride)?;
+
+        let metadata = fs::metadata(&tool)?;
+        let actual = (u64::from(metadata.uid()), u64::from(metadata.gid()));
+
+        let checker = Checker::new(&dpkg)?.reference_dir(&directory);
+        let reports = checker.check([&package]).await?;
+        let report = &reports[0];
+        assert_eq!(report.reference.as_ref(), Some(&built.deb_path));
+        assert_eq!(report.checked, 1);
+        assert_eq!(
+            report
+                .issues
+                .iter()
+                .map(|issue| issue.problem.clone())
+                .collect::<Vec<_>>(),
+            [
+                Problem::Mode {
+                    expected: 0o600,
+                    actual: 0o644
+                },
+                Problem::Owner {
+                    expected: (4242, 4343),
+                    actual
+                },
+            ]
+        );
+
+        fs::remove_dir_all(directory)?;
+
+        Ok(())
+    }
+}
@@ -64,16 +64,32 @@
 
 pub mod archiver;
 pub mod builder;
+pub mod crypto;
+pub mod deb;
 pub(crate) mod dpkg;
 mod error;
+pub mod integrity;
+#[cfg(feature = "serde")]
+pub mod manifest;
+pub mod orphans;
+pub mod overrides;
+pub mod ownership;
 pub mod package;
 mod parser;
 pub mod progress;
+pub mod prune;
+pub mod repo;
 pub mod repository;
+pub mod restore;
+#[cfg(test)]
+mod testing;
+#[cfg(feature = "upload")]
+pub mod upload;
+pub mod vfs;
 
 #[cfg(feature = "ffi")]
 pub mod ffi;
 
-pub use dpkg::{Dpkg, PackagesSort};
+pub use dpkg::{Dpkg, LockError, LockWait, PackagesSort};
 pub use error::{Generic as GenericError, Result};
 pub use parser::{Parsable, Parser};
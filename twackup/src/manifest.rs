@@ -0,0 +1,355 @@
+/*
+ * Copyright 2020 DanP
+ *
+ * This file is part of Twackup
+ *
+ * Twackup is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Twackup is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Twackup. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Backup manifest describing contents of the all-packages archive.
+//!
+//! Manifest is stored as `manifest.json` at the end of archive, because it is written
+//! after all debs are built. The same manifest is also stored next to archive as
+//! `<archive>.manifest.json`, so it can be read without decompressing the whole archive.
+//!
+//! ### Example usage
+//!
+//! ```no_run
+//! use twackup::{manifest::Manifest, Result};
+//!
+//! #[tokio::main]
+//! async fn main() -> Result<()> {
+//!     let manifest = Manifest::from_archive("/var/mobile/Documents/backup.tar.gz", None).await?;
+//!     println!("Backup of {} made at {}", manifest.host, manifest.date);
+//!
+//!     for package in manifest.packages {
+//!         println!("{} {} -> {}", package.id, package.version, package.deb);
+//!     }
+//!
+//!     Ok(())
+//! }
+//! ```
+
+use crate::{
+    archiver,
+    builder::BuildReport,
+    crypto::Encryptor,
+    error::{Generic, Result},
+    package::{Field, Package},
+    Dpkg,
+};
+use std::{
+    fs::File,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio_stream::StreamExt;
+
+/// Name of manifest file inside archive
+pub const FILE_NAME: &str = "manifest.json";
+
+/// Suffix added to archive name to get name of manifest stored next to it
+pub const SIDECAR_SUFFIX: &str = ".manifest.json";
+
+/// Current manifest format version
+const FORMAT_VERSION: u32 = 1;
+
+/// Manifest errors
+#[derive(thiserror::Error, Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// Manifest couldn't be serialized or deserialized
+    #[error("Json: {0}")]
+    Json(#[from] serde_json::Error),
+
+    /// Archive doesn't contain manifest
+    #[error("Archive doesn't contain {FILE_NAME}")]
+    NotFound,
+}
+
+/// Describes device and packages backed up in the archive
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[non_exhaustive]
+pub struct Manifest {
+    /// Version of manifest format
+    pub format_version: u32,
+    /// Version of twackup produced the archive
+    pub twackup_version: String,
+    /// Hostname of the device
+    pub host: String,
+    /// Unix timestamp of archive creation
+    pub date: u64,
+    /// Dpkg directory packages were taken from, as seen inside of `root`
+    pub admin_dir: PathBuf,
+    /// Directory of mounted device image or chroot packages were taken from.
+    /// `None` for the live system
+    #[serde(default)]
+    pub root: Option<PathBuf>,
+    /// Packages placed in the archive
+    pub packages: Vec<ManifestPackage>,
+}
+
+/// Single deb placed in the archive
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[non_exhaustive]
+pub struct ManifestPackage {
+    /// Package identifier
+    pub id: String,
+    /// Package version
+    pub version: String,
+    /// Package architecture if specified
+    pub architecture: Option<String>,
+    /// File name of the deb inside archive
+    pub deb: String,
+    /// Size of the deb in bytes
+    pub size: u64,
+    /// Hex-encoded SHA-256 checksum of the deb
+    pub sha256: String,
+    /// Problems occurred while building the deb
+    pub warnings: Vec<String>,
+}
+
+impl Manifest {
+    /// Creates empty manifest for packages of `dpkg` database, current host and time
+    #[inline]
+    pub fn new(dpkg: &Dpkg) -> Self {
+        let admin_dir: &Path = dpkg.paths.as_ref();
+        let root = dpkg.paths.root();
+        let admin_dir = match root {
+            Some(root) => Path::new("/").join(admin_dir.strip_prefix(root).unwrap_or(admin_dir)),
+            None => admin_dir.to_path_buf(),
+        };
+
+        let date = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_secs());
+
+        Self {
+            format_version: FORMAT_VERSION,
+            twackup_version: env!("CARGO_PKG_VERSION").to_string(),
+            host: gethostname::gethostname().to_string_lossy().into_owned(),
+            date,
+            admin_dir,
+            root: root.map(Path::to_path_buf),
+            packages: vec![],
+        }
+    }
+
+    /// Adds built package to manifest
+    #[inline]
+    pub fn push(&mut self, package: &Package, report: &BuildReport) {
+        let deb = report
+            .deb_path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+
+        self.packages.push(ManifestPackage {
+            id: package.id.clone(),
+            version: package.version.clone(),
+            architecture: package.get(Field::Architecture).ok().map(String::from),
+            deb,
+            size: report.deb_size,
+            sha256: report.deb_sha256.clone(),
+            warnings: report.warnings(),
+        });
+    }
+
+    /// Serializes manifest to pretty-printed JSON
+    ///
+    /// # Errors
+    /// Returns error if serialization failed
+    #[inline]
+    pub fn to_vec(&self) -> Result<Vec<u8>> {
+        Ok(serde_json::to_vec_pretty(self).map_err(Error::from)?)
+    }
+
+    /// Deserializes manifest from JSON
+    ///
+    /// # Errors
+    /// Returns error if data is not a valid manifest
+    #[inline]
+    pub fn from_slice(data: &[u8]) -> Result<Self> {
+        Ok(serde_json::from_slice(data).map_err(Error::from)?)
+    }
+
+    /// Writes manifest next to archive at `archive`.
+    /// Manifest is encrypted with `passphrase` if it is set, as archive itself
+    ///
+    /// # Errors
+    /// Returns error if file couldn't be written or key derivation failed
+    #[inline]
+    pub fn write_sidecar<P: AsRef<Path>>(
+        &self,
+        archive: P,
+        passphrase: Option<&str>,
+    ) -> Result<()> {
+        let data = self.to_vec()?;
+        let file = File::create(sidecar_path(archive))?;
+        match passphrase {
+            Some(passphrase) => {
+                let mut encryptor = Encryptor::new(file, passphrase)?;
+                encryptor.write_all(&data)?;
+                encryptor.finish()?;
+            }
+            None => (&file).write_all(&data)?,
+        }
+
+        Ok(())
+    }
+
+    /// Reads manifest of the all-packages archive. Manifest stored next to archive
+    /// is preferred, otherwise it is searched in the archive itself.
+    /// Compression type is detected automatically, encrypted data is decrypted with `passphrase`
+    ///
+    /// # Errors
+    /// Returns error if archive couldn't be read or decrypted or doesn't contain manifest
+    #[inline]
+    pub async fn from_archive<P: AsRef<Path>>(path: P, passphrase: Option<&str>) -> Result<Self> {
+        let path = path.as_ref();
+        match archiver::open_detected_with(sidecar_path(path), passphrase) {
+            Ok(mut reader) => {
+                let mut data = vec![];
+                reader.read_to_end(&mut data).await?;
+                return Self::from_slice(&data);
+            }
+            Err(Generic::Io(error)) if error.kind() == io::ErrorKind::NotFound => {}
+            Err(error) => return Err(error),
+        }
+
+        let reader = archiver::open_detected_with(path, passphrase)?;
+        Self::from_tar(reader).await
+    }
+
+    async fn from_tar<R: AsyncRead + Unpin + Send>(reader: R) -> Result<Self> {
+        let mut archive = tokio_tar::Archive::new(reader);
+        let mut entries = archive.entries()?;
+
+        while let Some(entry) = entries.next().await {
+            let mut entry = entry?;
+            if entry.path()?.as_ref() != Path::new(FILE_NAME) {
+                continue;
+            }
+
+            let mut data = vec![];
+            entry.read_to_end(&mut data).await?;
+            return Self::from_slice(&data);
+        }
+
+        Err(Error::NotFound.into())
+    }
+}
+
+/// Path of manifest stored next to archive at `archive`
+#[inline]
+#[must_use]
+pub fn sidecar_path<P: AsRef<Path>>(archive: P) -> PathBuf {
+    let mut path = archive.as_ref().as_os_str().to_owned();
+    path.push(SIDECAR_SUFFIX);
+    PathBuf::from(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{sidecar_path, Manifest, FILE_NAME};
+    use crate::{
+        archiver::{Compression, Type},
+        builder::{AllPackagesArchive, BuildReport},
+        crypto,
+        testing::temp_dir,
+        Dpkg, Result,
+    };
+    use std::{fs, path::Path};
+
+    #[tokio::test]
+    async fn archive_round_trip() -> Result<()> {
+        let dpkg_dir = concat!(env!("CARGO_MANIFEST_DIR"), "/assets/dpkg_database_dir");
+        let dpkg = Dpkg::new(dpkg_dir, false);
+        let package = dpkg.unsorted_packages(false).await?.pop_back().unwrap();
+
+        let report = BuildReport {
+            deb_path: "/tmp/hosts_1.0.0_all.deb".into(),
+            deb_size: 42,
+            deb_sha256: "abc".to_string(),
+            missing_files: vec!["/etc/hosts".into()],
+            ..BuildReport::default()
+        };
+
+        let mut manifest = Manifest::new(&dpkg);
+        manifest.push(&package, &report);
+
+        let directory = temp_dir("twackup-manifest")?;
+        let archive_path = directory.join("archive.tar.zst");
+
+        let compression = Compression {
+            r#type: Type::Zst,
+            ..Compression::default()
+        };
+        let archive = AllPackagesArchive::create(&archive_path, compression)?;
+        archive.append_data(FILE_NAME, manifest.to_vec()?).await?;
+        archive.finish().await?;
+
+        let manifest = Manifest::from_archive(&archive_path, None).await?;
+        assert_eq!(manifest.packages.len(), 1);
+
+        let package = &manifest.packages[0];
+        assert_eq!(package.id, "hosts");
+        assert_eq!(package.deb, "hosts_1.0.0_all.deb");
+        assert_eq!(package.size, 42);
+        assert_eq!(package.warnings, ["/etc/hosts is missing"]);
+
+        fs::remove_dir_all(directory)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn admin_dir_inside_root() -> Result<()> {
+        let root = temp_dir("twackup-manifest-root")?;
+        fs::create_dir_all(root.join("var/lib/dpkg"))?;
+
+        let manifest = Manifest::new(&Dpkg::with_root(&root, "/var/lib/dpkg", false));
+        assert_eq!(manifest.admin_dir, Path::new("/var/lib/dpkg"));
+        assert_eq!(manifest.root.as_deref(), Some(root.as_path()));
+
+        fs::remove_dir_all(root)?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn encrypted_sidecar() -> Result<()> {
+        let directory = temp_dir("twackup-manifest-sidecar")?;
+
+        // Archive itself isn't needed when manifest is stored next to it
+        let archive_path = directory.join("archive.tar.gz.enc");
+        let mut manifest = Manifest::new(&Dpkg::new("/var/lib/dpkg", false));
+        manifest.host = "device".to_string();
+        manifest.write_sidecar(&archive_path, Some("secret"))?;
+
+        let sidecar = sidecar_path(&archive_path);
+        assert_eq!(sidecar, directory.join("archive.tar.gz.enc.manifest.json"));
+        assert!(crypto::is_encrypted(&fs::read(&sidecar)?));
+        assert!(Manifest::from_archive(&archive_path, None).await.is_err());
+
+        let manifest = Manifest::from_archive(&archive_path, Some("secret")).await?;
+        assert_eq!(manifest.host, "device");
+
+        fs::remove_dir_all(directory)?;
+
+        Ok(())
+    }
+}
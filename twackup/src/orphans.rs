@@ -0,0 +1,295 @@
+/*
+ * Copyright 2020 DanP
+ *
+ * This file is part of Twackup
+ *
+ * Twackup is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Twackup is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Twackup. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Detects leftovers of uninstalled packages using [`ownership::Index`].
+//!
+//! Files under scanned roots that no package lists are reported as unowned,
+//! list entries under the same roots that don't exist on disk - as missing.
+//!
+//! ### Example usage
+//!
+//! ```no_run
+//! use twackup::{orphans::Scanner, ownership::Index, Dpkg, Result};
+//!
+//! #[tokio::main]
+//! async fn main() -> Result<()> {
+//!     let index = Index::build(&Dpkg::new("/var/lib/dpkg", true)).await?;
+//!     let report = Scanner::new(&index)
+//!         .root("/Library/MobileSubstrate/DynamicLibraries")
+//!         .ignore("*.disabled")
+//!         .scan()?;
+//!
+//!     for orphan in report.unowned {
+//!         println!("{:?}", orphan.path);
+//!     }
+//!
+//!     Ok(())
+//! }
+//! ```
+
+use crate::{
+    dpkg::Paths,
+    error::Result,
+    ownership::{self, Index},
+    Dpkg,
+};
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+/// File or directory no package owns
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct Orphan {
+    /// Absolute path of the file
+    pub path: PathBuf,
+    /// Whether it is a directory. Contents of unowned directories are not reported separately
+    pub is_dir: bool,
+}
+
+/// List entry which file doesn't exist on disk anymore
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct Missing {
+    /// Absolute path of the file
+    pub path: PathBuf,
+    /// Identifiers of packages listing this file
+    pub packages: Vec<String>,
+}
+
+/// Result of scanning roots
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct Report {
+    /// Files no package owns sorted by path
+    pub unowned: Vec<Orphan>,
+    /// Owned files that don't exist sorted by path
+    pub missing: Vec<Missing>,
+}
+
+/// Walks directories and compares their contents with ownership index
+#[derive(Clone, Debug)]
+pub struct Scanner<'a> {
+    index: &'a Index,
+    roots: Vec<PathBuf>,
+    ignored: Vec<Vec<char>>,
+    paths: Paths,
+}
+
+impl<'a> Scanner<'a> {
+    /// Constructs scanner without roots
+    #[inline]
+    #[must_use]
+    pub fn new(index: &'a Index) -> Self {
+        Self {
+            index,
+            roots: vec![],
+            ignored: vec![],
+            paths: Paths::new(""),
+        }
+    }
+
+    /// Scans filesystem of `dpkg` instead of the real one and resolves roots
+    /// and list entries under its root if it was created with [`Dpkg::with_root`].
+    /// Reported paths are still the ones seen inside of it
+    #[inline]
+    #[must_use]
+    pub fn within(mut self, dpkg: &Dpkg) -> Self {
+        self.paths = dpkg.paths.clone();
+        self
+    }
+
+    /// Adds directory to scan. Symlinks inside it are not followed
+    #[inline]
+    #[must_use]
+    pub fn root<P: AsRef<Path>>(mut self, root: P) -> Self {
+        self.roots.push(root.as_ref().to_path_buf());
+        self
+    }
+
+    /// Adds shell pattern of paths to skip, e.g. `*/.DS_Store`.
+    /// Patterns are matched against full paths like [`ownership::Query::Glob`] ones.
+    /// Contents of ignored directories are skipped too
+    #[inline]
+    #[must_use]
+    pub fn ignore(mut self, pattern: &str) -> Self {
+        self.ignored.push(pattern.chars().collect());
+        self
+    }
+
+    /// Walks all roots and checks list entries under them
+    ///
+    /// # Errors
+    /// Returns error if any root couldn't be read
+    pub fn scan(&self) -> Result<Report> {
+        let mut report = Report::default();
+        for root in &self.roots {
+            self.walk(root, &mut report.unowned)?;
+        }
+        report.unowned.sort_by(|lhs, rhs| lhs.path.cmp(&rhs.path));
+        report.unowned.dedup();
+
+        report.missing = self.missing();
+
+        Ok(report)
+    }
+
+    /// Walks `root` without following symlinks and collects unowned entries
+    fn walk(&self, root: &Path, unowned: &mut Vec<Orphan>) -> Result<()> {
+        let fs = self.paths.fs();
+        let host_root = self.paths.resolve(root, true)?;
+        let entries = fs.read_dir(&host_root)?.into_iter();
+        let mut directories = vec![(entries, root.to_path_buf())];
+
+        while let Some((entries, directory)) = directories.last_mut() {
+            let Some(entry) = entries.next() else {
+                directories.pop();
+                continue;
+            };
+
+            // Symlinks aren't followed, so paths inside of root are built component by component
+            let Some(name) = entry.path.file_name() else {
+                continue;
+            };
+            let path = directory.join(name);
+            if self.is_ignored(&path) {
+                continue;
+            }
+
+            let is_dir = entry.metadata.is_dir();
+            if !self.index.contains(&path.to_string_lossy()) {
+                unowned.push(Orphan { path, is_dir });
+                continue;
+            }
+
+            if is_dir {
+                match fs.read_dir(&entry.path) {
+                    Ok(entries) => directories.push((entries.into_iter(), path)),
+                    Err(error) => log::warn!("Skipping {}: {}", path.display(), error),
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Collects list entries under roots that don't exist.
+    /// Packages which files are diverted aren't expected to have them at original paths
+    fn missing(&self) -> Vec<Missing> {
+        self.index
+            .iter()
+            .filter_map(|found| {
+                let path = Path::new(found.path);
+                let packages: Vec<_> = match found.diversion {
+                    Some(diversion) if diversion.from == path => (found.packages.iter())
+                        .filter(|package| !diversion.applies_to(package))
+                        .cloned()
+                        .collect(),
+                    _ => found.packages.to_vec(),
+                };
+                (!packages.is_empty()).then_some((path, packages))
+            })
+            .filter(|(path, _)| self.roots.iter().any(|root| path.starts_with(root)))
+            .filter(|(path, _)| !self.is_ignored(path))
+            .filter(|(path, _)| {
+                let metadata = (self.paths.resolve(path, false))
+                    .and_then(|path| self.paths.fs().symlink_metadata(&path));
+                matches!(metadata, Err(error) if error.kind() == io::ErrorKind::NotFound)
+            })
+            .map(|(path, packages)| Missing {
+                path: path.to_path_buf(),
+                packages,
+            })
+            .collect()
+    }
+
+    fn is_ignored(&self, path: &Path) -> bool {
+        if self.ignored.is_empty() {
+            return false;
+        }
+
+        let path: Vec<_> = path.to_string_lossy().chars().collect();
+        self.ignored
+            .iter()
+            .any(|pattern| ownership::glob_match(pattern, &path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Orphan, Scanner};
+    use crate::{overrides::Diversion, ownership::Index, testing::temp_dir, Result};
+    use std::fs;
+
+    #[test]
+    fn leftovers() -> Result<()> {
+        let root = temp_dir("twackup-orphans")?;
+        fs::create_dir_all(root.join("owned"))?;
+        fs::create_dir_all(root.join("leftover/nested"))?;
+        fs::write(root.join("owned/tweak.dylib"), "")?;
+        fs::write(root.join("owned/removed.dylib"), "")?;
+        fs::write(root.join("owned/.DS_Store"), "")?;
+        fs::write(root.join("leftover/nested/file"), "")?;
+        fs::write(root.join("owned/tweak.conf.distrib"), "")?;
+
+        let path = |name: &str| root.join(name).to_string_lossy().into_owned();
+        let mut index = Index::default();
+        index.insert("tweak", &path("owned"));
+        index.insert("tweak", &path("owned/tweak.dylib"));
+        index.insert("tweak", &path("owned/tweak.plist"));
+        index.insert("tweak", &path("owned/tweak.conf"));
+        // Diverted file is stored at new path only
+        index.divert(Diversion {
+            from: root.join("owned/tweak.conf"),
+            to: root.join("owned/tweak.conf.distrib"),
+            package: None,
+        });
+
+        let report = Scanner::new(&index)
+            .root(&root)
+            .ignore("*/.DS_Store")
+            .scan()?;
+
+        assert_eq!(
+            report.unowned,
+            [
+                Orphan {
+                    path: root.join("leftover"),
+                    is_dir: true
+                },
+                Orphan {
+                    path: root.join("owned/removed.dylib"),
+                    is_dir: false
+                },
+            ]
+        );
+
+        assert_eq!(report.missing.len(), 1);
+        assert_eq!(report.missing[0].path, root.join("owned/tweak.plist"));
+        assert_eq!(report.missing[0].packages, ["tweak"]);
+
+        fs::remove_dir_all(root)?;
+
+        Ok(())
+    }
+}
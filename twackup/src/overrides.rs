@@ -0,0 +1,424 @@
+/*
+ * Copyright 2020 DanP
+ *
+ * This file is part of Twackup
+ *
+ * Twackup is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Twackup is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Twackup. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Typed parsers of dpkg `diversions` and `statoverride` databases.
+//!
+//! Diversions move files of packages to other locations, so a package's `.list`
+//! keeps the original path while its file lives at the diverted one.
+//! Stat overrides replace owner and permissions dpkg sets for installed files.
+//!
+//! ### Example usage
+//!
+//! ```no_run
+//! use twackup::{Dpkg, Result};
+//!
+//! fn main() -> Result<()> {
+//!     let dpkg = Dpkg::new("/var/lib/dpkg", false);
+//!     for diversion in dpkg.diversions()?.iter() {
+//!         println!("{:?} -> {:?}", diversion.from, diversion.to);
+//!     }
+//!
+//!     for stat in dpkg.stat_overrides()?.iter() {
+//!         println!("{:?} {:o}", stat.path, stat.mode);
+//!     }
+//!
+//!     Ok(())
+//! }
+//! ```
+
+use crate::{dpkg::Paths, error::Result};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    path::{Path, PathBuf},
+};
+
+/// Marker dpkg writes instead of package name for diversions made by administrator
+const LOCAL_DIVERSION: &str = ":";
+
+/// Errors of parsing dpkg databases
+#[derive(thiserror::Error, Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// Diversions file doesn't consist of three-line records
+    #[error("Diversion of {0:?} is truncated")]
+    TruncatedDiversion(String),
+
+    /// Statoverride file contains malformed line
+    #[error("Invalid stat override at line {line}: {reason}")]
+    InvalidStatOverride {
+        /// Number of line starting from 1
+        line: usize,
+        /// What is wrong with it
+        reason: String,
+    },
+}
+
+/// Single record of `diversions` file
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct Diversion {
+    /// Path packages install the file to and list in their `.list` files
+    pub from: PathBuf,
+    /// Path the file of other packages is moved to
+    pub to: PathBuf,
+    /// Package that made the diversion. `None` for local ones made by administrator
+    pub package: Option<String>,
+}
+
+/// Diversions indexed by original path
+#[derive(Clone, Debug, Default)]
+pub struct Diversions(BTreeMap<PathBuf, Diversion>);
+
+/// User or group in stat override
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum Owner {
+    /// Name to be resolved on the system
+    Name(String),
+    /// Numeric identifier, written with `#` prefix
+    Id(u32),
+}
+
+/// Single record of `statoverride` file
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct StatOverride {
+    /// Owner of the file
+    pub user: Owner,
+    /// Group of the file
+    pub group: Owner,
+    /// Permission bits including setuid, setgid and sticky ones
+    pub mode: u32,
+    /// Absolute path of the file
+    pub path: PathBuf,
+}
+
+/// Stat overrides indexed by path
+#[derive(Clone, Debug, Default)]
+pub struct StatOverrides(BTreeMap<PathBuf, StatOverride>);
+
+/// Users and groups of the system packages are installed to.
+/// Owner names of stat overrides are resolved with them
+#[derive(Clone, Debug, Default)]
+pub struct Accounts {
+    users: HashMap<String, u32>,
+    groups: HashMap<String, u32>,
+}
+
+impl Diversion {
+    /// Whether file of `package` listed at [`Diversion::from`] is stored at [`Diversion::to`].
+    /// Package that made the diversion keeps its file at the original path
+    #[inline]
+    #[must_use]
+    pub fn applies_to(&self, package: &str) -> bool {
+        self.package.as_deref() != Some(package)
+    }
+}
+
+impl Diversions {
+    /// Parses contents of diversions file
+    ///
+    /// # Errors
+    /// Returns error if contents don't consist of three-line records
+    pub fn parse(contents: &str) -> std::result::Result<Self, Error> {
+        let mut diversions = BTreeMap::new();
+        let mut lines = contents.lines();
+
+        while let Some(from) = lines.next() {
+            let (Some(to), Some(package)) = (lines.next(), lines.next()) else {
+                return Err(Error::TruncatedDiversion(from.to_string()));
+            };
+
+            let diversion = Diversion {
+                from: PathBuf::from(from),
+                to: PathBuf::from(to),
+                package: (package != LOCAL_DIVERSION).then(|| package.to_string()),
+            };
+            diversions.insert(diversion.from.clone(), diversion);
+        }
+
+        Ok(Self(diversions))
+    }
+
+    /// Diversion of the original `path` if there's any
+    #[inline]
+    #[must_use]
+    pub fn get<P: AsRef<Path>>(&self, path: P) -> Option<&Diversion> {
+        self.0.get(path.as_ref())
+    }
+
+    /// Location where file `package` lists at `path` is actually stored
+    #[must_use]
+    pub fn source_of<'a>(&'a self, path: &'a Path, package: &str) -> &'a Path {
+        match self.get(path) {
+            Some(diversion) if diversion.applies_to(package) => &diversion.to,
+            _ => path,
+        }
+    }
+
+    /// Iterates over diversions sorted by original path
+    pub fn iter(&self) -> impl Iterator<Item = &Diversion> {
+        self.0.values()
+    }
+
+    /// Count of diversions
+    #[inline]
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Whether there are no diversions
+    #[inline]
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl Owner {
+    /// Parses owner as written in statoverride file
+    fn parse(value: &str) -> std::result::Result<Self, String> {
+        match value.strip_prefix('#') {
+            Some(id) => id
+                .parse()
+                .map(Self::Id)
+                .map_err(|_| format!("invalid identifier `{value}`")),
+            None if value.is_empty() => Err("empty owner".to_string()),
+            None => Ok(Self::Name(value.to_string())),
+        }
+    }
+
+    /// Name if owner is set by name
+    #[inline]
+    #[must_use]
+    pub fn name(&self) -> Option<&str> {
+        match self {
+            Self::Name(name) => Some(name),
+            Self::Id(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for Owner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Name(name) => f.write_str(name),
+            Self::Id(id) => write!(f, "#{id}"),
+        }
+    }
+}
+
+impl StatOverride {
+    /// Numeric user identifier. Names are looked up in `accounts`
+    #[must_use]
+    pub fn uid(&self, accounts: &Accounts) -> Option<u32> {
+        match &self.user {
+            Owner::Id(id) => Some(*id),
+            Owner::Name(name) => accounts.uid(name),
+        }
+    }
+
+    /// Numeric group identifier. Names are looked up in `accounts`
+    #[must_use]
+    pub fn gid(&self, accounts: &Accounts) -> Option<u32> {
+        match &self.group {
+            Owner::Id(id) => Some(*id),
+            Owner::Name(name) => accounts.gid(name),
+        }
+    }
+}
+
+impl StatOverrides {
+    /// Parses contents of statoverride file with `user group mode path` lines
+    ///
+    /// # Errors
+    /// Returns error if any line is malformed
+    pub fn parse(contents: &str) -> std::result::Result<Self, Error> {
+        let mut overrides = BTreeMap::new();
+
+        for (index, line) in contents.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let invalid = |reason: String| Error::InvalidStatOverride {
+                line: index + 1,
+                reason,
+            };
+
+            // Path is the last field and can contain spaces
+            let mut fields = line.splitn(4, ' ');
+            let (Some(user), Some(group), Some(mode), Some(path)) =
+                (fields.next(), fields.next(), fields.next(), fields.next())
+            else {
+                return Err(invalid("expected `user group mode path`".to_string()));
+            };
+
+            let stat = StatOverride {
+                user: Owner::parse(user).map_err(invalid)?,
+                group: Owner::parse(group).map_err(invalid)?,
+                mode: u32::from_str_radix(mode, 8)
+                    .map_err(|_| invalid(format!("invalid mode `{mode}`")))?,
+                path: PathBuf::from(path),
+            };
+            overrides.insert(stat.path.clone(), stat);
+        }
+
+        Ok(Self(overrides))
+    }
+
+    /// Override of the file at `path` if there's any
+    #[inline]
+    #[must_use]
+    pub fn get<P: AsRef<Path>>(&self, path: P) -> Option<&StatOverride> {
+        self.0.get(path.as_ref())
+    }
+
+    /// Iterates over overrides sorted by path
+    pub fn iter(&self) -> impl Iterator<Item = &StatOverride> {
+        self.0.values()
+    }
+
+    /// Count of overrides
+    #[inline]
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Whether there are no overrides
+    #[inline]
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl Accounts {
+    /// Reads `/etc/passwd` and `/etc/group` inside of root of `paths`.
+    /// Missing files mean there are no accounts
+    ///
+    /// # Errors
+    /// Returns error if any file exists but couldn't be read
+    pub(crate) fn load(paths: &Paths) -> Result<Self> {
+        let read = |path: &str| paths.read_optional(&paths.resolve(Path::new(path), true)?);
+        Ok(Self::parse(&read("/etc/passwd")?, &read("/etc/group")?))
+    }
+
+    /// Parses contents of `passwd` and `group` files. Comments and malformed lines are skipped
+    #[must_use]
+    pub fn parse(passwd: &str, group: &str) -> Self {
+        Self {
+            users: parse_ids(passwd),
+            groups: parse_ids(group),
+        }
+    }
+
+    /// Identifier of user with `name`
+    #[inline]
+    #[must_use]
+    pub fn uid(&self, name: &str) -> Option<u32> {
+        self.users.get(name).copied()
+    }
+
+    /// Identifier of group with `name`
+    #[inline]
+    #[must_use]
+    pub fn gid(&self, name: &str) -> Option<u32> {
+        self.groups.get(name).copied()
+    }
+}
+
+/// Parses `name:password:id:...` lines of passwd or group file skipping malformed ones
+fn parse_ids(contents: &str) -> HashMap<String, u32> {
+    contents
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| {
+            let mut fields = line.split(':');
+            let (Some(name), Some(_), Some(id)) = (fields.next(), fields.next(), fields.next())
+            else {
+                return None;
+            };
+            Some((name.to_string(), id.parse().ok()?))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Accounts, Diversions, Error, Owner, StatOverrides};
+    use std::path::Path;
+
+    #[test]
+    fn diversions() {
+        let contents = "/usr/bin/cat\n/usr/bin/cat.real\nfancy-cat\n/etc/motd\n/etc/motd.orig\n:\n";
+        let diversions = Diversions::parse(contents).unwrap();
+        assert_eq!(diversions.len(), 2);
+
+        let cat = Path::new("/usr/bin/cat");
+        assert_eq!(
+            diversions.source_of(cat, "coreutils"),
+            Path::new("/usr/bin/cat.real")
+        );
+        assert_eq!(diversions.source_of(cat, "fancy-cat"), cat);
+
+        let motd = Path::new("/etc/motd");
+        assert_eq!(diversions.get(motd).unwrap().package, None);
+        assert_eq!(
+            diversions.source_of(motd, "base-files"),
+            Path::new("/etc/motd.orig")
+        );
+
+        let error = Diversions::parse("/usr/bin/cat\n/usr/bin/cat.real\n").err();
+        assert!(matches!(error, Some(Error::TruncatedDiversion(_))));
+    }
+
+    #[test]
+    fn stat_overrides() {
+        let contents = "root mobile 4755 /usr/bin/su helper\n#501 #20 0700 /var/mobile/secret\n";
+        let overrides = StatOverrides::parse(contents).unwrap();
+
+        let accounts = Accounts::parse(
+            "##\n# User Database\nroot:*:0:0:System Administrator:/var/root:/bin/sh\nbroken\n",
+            "wheel:*:0:root\nmobile:*:501:mobile\n",
+        );
+
+        let su = overrides.get("/usr/bin/su helper").unwrap();
+        assert_eq!(su.user, Owner::Name("root".to_string()));
+        assert_eq!(su.mode, 0o4755);
+        assert_eq!((su.uid(&accounts), su.gid(&accounts)), (Some(0), Some(501)));
+        assert_eq!(su.uid(&Accounts::default()), None);
+
+        let secret = overrides.get("/var/mobile/secret").unwrap();
+        let ids = (secret.uid(&accounts), secret.gid(&accounts));
+        assert_eq!(ids, (Some(501), Some(20)));
+
+        let error = StatOverrides::parse("root root 0644 /a\nroot root rwx /b\n").err();
+        assert!(matches!(
+            error,
+            Some(Error::InvalidStatOverride { line: 2, .. })
+        ));
+    }
+}
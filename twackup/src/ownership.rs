@@ -0,0 +1,428 @@
+/*
+ * Copyright 2020 DanP
+ *
+ * This file is part of Twackup
+ *
+ * Twackup is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Twackup is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Twackup. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Index of files installed by packages built from `info/<package>.list` files.
+//! Answers the same question as `dpkg -S` does: which package installed a file.
+//! Diverted files are owned by their packages at both original and new paths.
+//!
+//! ### Example usage
+//!
+//! ```no_run
+//! use twackup::{ownership::{Index, Query}, Dpkg, Result};
+//!
+//! #[tokio::main]
+//! async fn main() -> Result<()> {
+//!     let dpkg = Dpkg::new("/var/lib/dpkg", true);
+//!     let index = Index::build(&dpkg).await?;
+//!
+//!     for found in index.lookup(&Query::detect("/usr/bin/*sh")) {
+//!         println!("{}: {}", found.packages.join(", "), found.path);
+//!     }
+//!
+//!     Ok(())
+//! }
+//! ```
+
+use crate::{error::Result, overrides::Diversion, Dpkg};
+use std::{collections::BTreeMap, ffi::OsStr, ops::Bound};
+
+/// Describes how files are looked up in the index
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Query {
+    /// Path must be equal to this one
+    Exact(String),
+    /// Path must start with this string
+    Prefix(String),
+    /// Path must match shell pattern. `*` and `?` match `/` too, like in `dpkg -S`
+    Glob(String),
+}
+
+/// File found in the index
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[non_exhaustive]
+pub struct Match<'a> {
+    /// Absolute path of the file
+    pub path: &'a str,
+    /// Identifiers of packages that list this file. Directories are usually shared
+    /// by many packages, regular files - by overwriting or diverting ones
+    pub packages: &'a [String],
+    /// Diversion from or to this path if there's any
+    pub diversion: Option<&'a Diversion>,
+}
+
+/// Maps installed files to packages owning them
+#[derive(Clone, Debug, Default)]
+pub struct Index {
+    files: BTreeMap<String, Vec<String>>,
+    /// Diversions indexed by both original and new paths
+    diversions: BTreeMap<String, Diversion>,
+}
+
+impl Query {
+    /// Creates glob query if `query` contains shell pattern characters, exact one otherwise
+    #[must_use]
+    pub fn detect(query: &str) -> Self {
+        if query.contains(['*', '?', '[']) {
+            Self::Glob(query.to_string())
+        } else {
+            Self::Exact(query.to_string())
+        }
+    }
+}
+
+impl Index {
+    /// Reads all `info/*.list` files and diversions of dpkg database
+    ///
+    /// # Errors
+    /// Returns error if info directory, any list file or diversions couldn't be read
+    /// or dpkg directory lock failed
+    pub async fn build(dpkg: &Dpkg) -> Result<Self> {
+        let lock = dpkg.lock().await?;
+
+        let mut index = Self::default();
+        for path in dpkg.info_dir_contents()? {
+            if path.extension() != Some(OsStr::new("list")) {
+                continue;
+            }
+            let Some(package) = path.file_stem().and_then(OsStr::to_str) else {
+                continue;
+            };
+
+            let contents = dpkg.paths.fs().read(&path)?;
+            for file in String::from_utf8_lossy(&contents).lines() {
+                index.insert(package, file);
+            }
+        }
+
+        for diversion in dpkg.diversions()?.iter() {
+            index.divert(diversion.clone());
+        }
+
+        drop(lock);
+
+        Ok(index)
+    }
+
+    /// Records that `package` owns file at `path`
+    pub fn insert(&mut self, package: &str, path: &str) {
+        let path = normalize(path);
+        // Every list file of debian packages starts with `/.` entry
+        if path.is_empty() || path == "/." {
+            return;
+        }
+
+        let owners = self.files.entry(path.to_string()).or_default();
+        if let Err(position) = owners.binary_search_by(|owner| owner.as_str().cmp(package)) {
+            owners.insert(position, package.to_string());
+        }
+    }
+
+    /// Records diversion making packages it applies to own file at [`Diversion::to`]
+    /// instead of [`Diversion::from`]. Must be called after files of all packages are inserted
+    pub fn divert(&mut self, diversion: Diversion) {
+        let from = normalize(&diversion.from.to_string_lossy()).to_string();
+        let to = normalize(&diversion.to.to_string_lossy()).to_string();
+
+        let diverted: Vec<_> = (self.owners(&from).iter())
+            .filter(|package| diversion.applies_to(package))
+            .cloned()
+            .collect();
+        for package in diverted {
+            self.insert(&package, &to);
+        }
+
+        self.diversions.insert(from, diversion.clone());
+        self.diversions.insert(to, diversion);
+    }
+
+    /// Count of indexed files
+    #[inline]
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    /// Whether no files are indexed
+    #[inline]
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    /// Whether any package owns file at `path`
+    #[inline]
+    #[must_use]
+    pub fn contains(&self, path: &str) -> bool {
+        self.files.contains_key(normalize(path))
+    }
+
+    /// Identifiers of packages that own file at `path` sorted alphabetically
+    #[must_use]
+    pub fn owners(&self, path: &str) -> &[String] {
+        self.files.get(normalize(path)).map_or(&[], Vec::as_slice)
+    }
+
+    /// Diversion from or to `path` if there's any
+    #[must_use]
+    pub fn diversion(&self, path: &str) -> Option<&Diversion> {
+        self.diversions.get(normalize(path))
+    }
+
+    /// Iterates over all indexed files sorted by path
+    pub fn iter(&self) -> impl Iterator<Item = Match<'_>> {
+        self.files.iter().map(|entry| self.found(entry))
+    }
+
+    /// Iterates over files which paths start with `prefix` sorted by path
+    pub fn prefix(&self, prefix: &str) -> impl Iterator<Item = Match<'_>> {
+        let owned = prefix.to_string();
+        self.files
+            .range::<str, _>((Bound::Included(prefix), Bound::Unbounded))
+            .take_while(move |(path, _)| path.starts_with(&owned))
+            .map(|entry| self.found(entry))
+    }
+
+    /// Iterates over files which paths match shell `pattern` sorted by path
+    pub fn glob<'a>(&'a self, pattern: &str) -> impl Iterator<Item = Match<'a>> {
+        let pattern: Vec<_> = pattern.chars().collect();
+        self.iter().filter(move |found| {
+            let path: Vec<_> = found.path.chars().collect();
+            glob_match(&pattern, &path)
+        })
+    }
+
+    /// Performs lookup described by `query`
+    #[must_use]
+    pub fn lookup(&self, query: &Query) -> Vec<Match<'_>> {
+        match query {
+            Query::Exact(path) => self
+                .files
+                .get_key_value(normalize(path))
+                .map(|entry| self.found(entry))
+                .into_iter()
+                .collect(),
+            Query::Prefix(prefix) => self.prefix(prefix).collect(),
+            Query::Glob(pattern) => self.glob(pattern).collect(),
+        }
+    }
+
+    fn found<'a>(&'a self, (path, packages): (&'a String, &'a Vec<String>)) -> Match<'a> {
+        Match {
+            path,
+            packages,
+            diversion: self.diversions.get(path),
+        }
+    }
+}
+
+/// Strips trailing slashes dpkg sometimes writes for directories
+fn normalize(path: &str) -> &str {
+    let trimmed = path.trim_end_matches('/');
+    if trimmed.is_empty() && !path.is_empty() {
+        "/"
+    } else {
+        trimmed
+    }
+}
+
+/// Matches `text` against shell pattern with `*`, `?`, `[...]`, `[!...]` and `\` escapes.
+/// Unterminated bracket is matched literally.
+pub(crate) fn glob_match(pattern: &[char], text: &[char]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    // Position after the last `*` and text position it currently covers
+    let mut backtrack = None;
+
+    while t < text.len() {
+        if p < pattern.len() {
+            match pattern[p] {
+                '*' => {
+                    p += 1;
+                    backtrack = Some((p, t));
+                    continue;
+                }
+                '?' => {
+                    p += 1;
+                    t += 1;
+                    continue;
+                }
+                '[' => {
+                    if let Some((matched, next)) = match_class(&pattern[p..], text[t]) {
+                        if matched {
+                            p += next;
+                            t += 1;
+                            continue;
+                        }
+                    } else if text[t] == '[' {
+                        p += 1;
+                        t += 1;
+                        continue;
+                    }
+                }
+                '\\' if p + 1 < pattern.len() => {
+                    if pattern[p + 1] == text[t] {
+                        p += 2;
+                        t += 1;
+                        continue;
+                    }
+                }
+                symbol => {
+                    if symbol == text[t] {
+                        p += 1;
+                        t += 1;
+                        continue;
+                    }
+                }
+            }
+        }
+
+        // Let the last `*` consume one more character
+        match backtrack {
+            Some((star, covered)) => {
+                p = star;
+                t = covered + 1;
+                backtrack = Some((star, t));
+            }
+            None => return false,
+        }
+    }
+
+    pattern[p..].iter().all(|symbol| *symbol == '*')
+}
+
+/// Matches `symbol` against bracket expression at the start of `pattern`.
+/// Returns match result and length of the expression or `None` if bracket is unterminated.
+fn match_class(pattern: &[char], symbol: char) -> Option<(bool, usize)> {
+    let mut index = 1;
+    let negated = matches!(pattern.get(index), Some('!' | '^'));
+    if negated {
+        index += 1;
+    }
+
+    let mut matched = false;
+    let mut first = true;
+    loop {
+        let start = *pattern.get(index)?;
+        if start == ']' && !first {
+            return Some((matched != negated, index + 1));
+        }
+        first = false;
+
+        match (pattern.get(index + 1), pattern.get(index + 2)) {
+            (Some('-'), Some(&end)) if end != ']' => {
+                matched |= (start..=end).contains(&symbol);
+                index += 3;
+            }
+            _ => {
+                matched |= start == symbol;
+                index += 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{glob_match, Index, Query};
+    use crate::{overrides::Diversion, Dpkg, Result};
+    use std::path::PathBuf;
+
+    fn matches(pattern: &str, text: &str) -> bool {
+        let pattern: Vec<_> = pattern.chars().collect();
+        let text: Vec<_> = text.chars().collect();
+        glob_match(&pattern, &text)
+    }
+
+    #[test]
+    fn glob() {
+        assert!(matches("/usr/bin/*", "/usr/bin/bash"));
+        assert!(matches("*bash", "/usr/bin/bash"));
+        assert!(matches("/usr/*/ba?h", "/usr/local/bin/bash"));
+        assert!(matches("/etc/[a-c]*", "/etc/bash.bashrc"));
+        assert!(matches("/etc/[!a-c]*", "/etc/hosts"));
+        assert!(matches("/weird/[name", "/weird/[name"));
+        assert!(matches("/escaped/\\*", "/escaped/*"));
+        assert!(!matches("/escaped/\\*", "/escaped/file"));
+        assert!(!matches("/etc/[!a-c]*", "/etc/bash.bashrc"));
+        assert!(!matches("/usr/bin/?", "/usr/bin/sh"));
+        assert!(!matches("/usr/bin", "/usr/bin/sh"));
+    }
+
+    #[test]
+    fn lookups() {
+        let mut index = Index::default();
+        index.insert("bash", "/.");
+        index.insert("bash", "/usr/bin");
+        index.insert("bash", "/usr/bin/bash");
+        index.insert("coreutils", "/usr/bin/");
+        index.insert("coreutils", "/usr/bin/ls");
+        index.insert("bash-diverter", "/usr/bin/bash");
+
+        assert_eq!(index.len(), 3);
+        assert_eq!(index.owners("/usr/bin/"), ["bash", "coreutils"]);
+        assert_eq!(index.owners("/usr/bin/bash"), ["bash", "bash-diverter"]);
+        assert!(index.owners("/usr/bin/zsh").is_empty());
+
+        let found = index.lookup(&Query::Prefix("/usr/bin/".to_string()));
+        let paths: Vec<_> = found.iter().map(|found| found.path).collect();
+        assert_eq!(paths, ["/usr/bin/bash", "/usr/bin/ls"]);
+
+        let found = index.lookup(&Query::detect("*/l?"));
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].packages, ["coreutils"]);
+    }
+
+    #[test]
+    fn diversions() {
+        let mut index = Index::default();
+        index.insert("bash", "/bin/sh");
+        index.insert("dash", "/bin/sh");
+        index.insert("dash", "/bin/dash");
+        index.divert(Diversion {
+            from: PathBuf::from("/bin/sh"),
+            to: PathBuf::from("/bin/sh.distrib"),
+            package: Some("dash".to_string()),
+        });
+
+        assert_eq!(index.owners("/bin/sh"), ["bash", "dash"]);
+        assert_eq!(index.owners("/bin/sh.distrib"), ["bash"]);
+        assert!(index.contains("/bin/sh.distrib"));
+        assert!(index.diversion("/bin/dash").is_none());
+
+        let found = index.lookup(&Query::detect("/bin/sh*"));
+        assert_eq!(found.len(), 2);
+        for found in found {
+            let diversion = found.diversion.unwrap();
+            assert_eq!(diversion.package.as_deref(), Some("dash"));
+            assert_eq!(diversion.to, PathBuf::from("/bin/sh.distrib"));
+        }
+    }
+
+    #[tokio::test]
+    async fn database() -> Result<()> {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/assets/dpkg_database_dir");
+        let index = Index::build(&Dpkg::new(path, false)).await?;
+
+        assert_eq!(index.owners("/etc/hosts"), ["hosts"]);
+        assert_eq!(index.owners("/var"), ["valid-package"]);
+
+        Ok(())
+    }
+}
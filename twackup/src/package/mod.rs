@@ -24,19 +24,19 @@ mod field;
 mod priority;
 mod section;
 mod status;
+mod version;
 
 pub use self::{
     field::Field,
     priority::Priority,
     section::Section,
     status::{Flags as StatusFlags, SelectionState, State, Status},
+    version::Version,
 };
-use crate::parser::Parsable;
+use crate::{dpkg::Paths, parser::Parsable};
 use std::{
     collections::{HashMap, HashSet},
-    fs::File,
     io::{self, BufRead, BufReader},
-    path::Path,
 };
 
 /// Different errors for package fields
@@ -66,6 +66,10 @@ pub enum Error {
     /// This package is virtual
     #[error("This package is virtual")]
     VirtualPackage,
+
+    /// Version doesn't match Debian format
+    #[error("Invalid package version: `{0}`")]
+    InvalidVersion(String),
 }
 
 /// Wrapper for dpkg database package
@@ -139,12 +143,17 @@ impl Parsable for Package {
 impl Package {
     /// Searches for installed files
     ///
+    /// # Parameters
+    /// - `dpkg_dir` - dpkg directory path or [`Dpkg::paths`](crate::Dpkg::paths)
+    ///   if database is located in another filesystem
+    ///
     /// # Errors
     /// Returns error if dpkg directory couldn't be read or package is not installed
     #[inline]
-    pub fn get_installed_files(&self, dpkg_dir: &Path) -> io::Result<Vec<String>> {
-        let file = File::open(dpkg_dir.join(format!("info/{}.list", self.id)))?;
-        BufReader::new(file).lines().collect()
+    pub fn get_installed_files<P: Into<Paths>>(&self, dpkg_dir: P) -> io::Result<Vec<String>> {
+        let paths = dpkg_dir.into();
+        let list = paths.info_dir().join(format!("{}.list", self.id));
+        BufReader::new(paths.fs().open(&list)?).lines().collect()
     }
 
     /// Creates canonical DEB filename in format of `id_version_arch`
@@ -202,10 +211,12 @@ impl Package {
             control
         };
 
-        // Build header with important fields
+        // Build header with important fields.
+        // Dpkg rejects fields without value, so missing ones are skipped
         let control = String::with_capacity(control_length);
         let control = header_fields
             .iter()
+            .filter(|(_, value)| !value.is_empty())
             .fold(control, |control, (name, value)| push(control, name, value));
 
         // And build other fields
@@ -314,6 +325,23 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn control_skips_empty_fields() -> Result<()> {
+        let mut package_info = HashMap::new();
+        package_info.insert("Package".to_string(), "valid-package".to_string());
+        package_info.insert("Version".to_string(), "1.0.0".to_string());
+        package_info.insert("Status".to_string(), "install ok installed".to_string());
+        package_info.insert("Section".to_string(), "Tweaks".to_string());
+
+        let package = Package::new(package_info)?;
+        let control = package.to_control();
+        assert!(control.starts_with("Package: valid-package\n"));
+        assert!(!control.contains("Depiction"));
+        assert!(!control.lines().any(|line| line.ends_with(": ")));
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn valid_database() -> Result<()> {
         let database = concat!(env!("CARGO_MANIFEST_DIR"), "/assets/databases/valid");
@@ -352,7 +380,7 @@ mod tests {
         let description = package.get(Field::Description)?;
         assert_eq!(description, "First Line\n Second Line\n  Third Line");
 
-        assert!(packages.get("invalid-package-1").is_none());
+        assert!(!packages.contains_key("invalid-package-1"));
 
         Ok(())
     }
@@ -361,7 +389,7 @@ mod tests {
     fn no_permissions_database() -> Result<()> {
         let database = env::temp_dir().join("twackup-no-permissions");
         let mut file = File::create(&database)?;
-        file.write_all("This contents will never be read".as_bytes())?;
+        file.write_all(b"This contents will never be read")?;
         fs::set_permissions(&database, fs::Permissions::from_mode(0o333))?;
 
         let parser = Parser::new(database.as_path());
@@ -0,0 +1,190 @@
+/*
+ * Copyright 2020 DanP
+ *
+ * This file is part of Twackup
+ *
+ * Twackup is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Twackup is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Twackup. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use super::Error;
+use std::{cmp::Ordering, fmt, str::FromStr};
+
+/// Debian package version in `[epoch:]upstream_version[-debian_revision]` format.
+/// Is compared with the same algorithm dpkg uses, so `1.10` is newer than `1.9`
+/// and `1.0~beta1` is older than `1.0`
+#[derive(Clone, Debug)]
+pub struct Version {
+    /// Epoch, zero if omitted
+    pub epoch: u32,
+    /// Main part of the version
+    pub upstream: String,
+    /// Debian revision, empty if omitted
+    pub revision: String,
+}
+
+impl FromStr for Version {
+    type Err = Error;
+
+    fn from_str(version: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::InvalidVersion(version.to_string());
+
+        let (epoch, rest) = match version.split_once(':') {
+            Some((epoch, rest)) => (epoch.parse().map_err(|_| invalid())?, rest),
+            None => (0, version),
+        };
+
+        let (upstream, revision) = rest.rsplit_once('-').unwrap_or((rest, ""));
+        if upstream.is_empty() || upstream.contains(char::is_whitespace) {
+            return Err(invalid());
+        }
+
+        Ok(Self {
+            epoch,
+            upstream: upstream.to_string(),
+            revision: revision.to_string(),
+        })
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.epoch != 0 {
+            write!(f, "{}:", self.epoch)?;
+        }
+        write!(f, "{}", self.upstream)?;
+        if !self.revision.is_empty() {
+            write!(f, "-{}", self.revision)?;
+        }
+        Ok(())
+    }
+}
+
+impl Ord for Version {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.epoch
+            .cmp(&other.epoch)
+            .then_with(|| compare_part(&self.upstream, &other.upstream))
+            .then_with(|| compare_part(&self.revision, &other.revision))
+    }
+}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Version {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Version {}
+
+/// Weight of non-digit character. Tilde sorts before anything, even the end of the part,
+/// letters sort before other characters
+fn order(character: Option<&u8>) -> i32 {
+    match character {
+        None => 0,
+        Some(character) if character.is_ascii_digit() => 0,
+        Some(character) if character.is_ascii_alphabetic() => i32::from(*character),
+        Some(b'~') => -1,
+        Some(character) => i32::from(*character) + 256,
+    }
+}
+
+/// Port of `verrevcmp` from dpkg
+fn compare_part(lhs: &str, rhs: &str) -> Ordering {
+    let (lhs, rhs) = (lhs.as_bytes(), rhs.as_bytes());
+    let is_digit = |part: &[u8], index: usize| part.get(index).is_some_and(u8::is_ascii_digit);
+    let (mut i, mut j) = (0, 0);
+
+    while i < lhs.len() || j < rhs.len() {
+        while (i < lhs.len() && !is_digit(lhs, i)) || (j < rhs.len() && !is_digit(rhs, j)) {
+            let (left, right) = (order(lhs.get(i)), order(rhs.get(j)));
+            if left != right {
+                return left.cmp(&right);
+            }
+            i += 1;
+            j += 1;
+        }
+
+        while lhs.get(i) == Some(&b'0') {
+            i += 1;
+        }
+        while rhs.get(j) == Some(&b'0') {
+            j += 1;
+        }
+
+        let mut first_difference = Ordering::Equal;
+        while is_digit(lhs, i) && is_digit(rhs, j) {
+            if first_difference == Ordering::Equal {
+                first_difference = lhs[i].cmp(&rhs[j]);
+            }
+            i += 1;
+            j += 1;
+        }
+
+        if is_digit(lhs, i) {
+            return Ordering::Greater;
+        }
+        if is_digit(rhs, j) {
+            return Ordering::Less;
+        }
+        if first_difference != Ordering::Equal {
+            return first_difference;
+        }
+    }
+
+    Ordering::Equal
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Version;
+    use std::cmp::Ordering;
+
+    fn compare(lhs: &str, rhs: &str) -> Ordering {
+        let lhs: Version = lhs.parse().unwrap();
+        lhs.cmp(&rhs.parse().unwrap())
+    }
+
+    #[test]
+    fn ordering() {
+        assert_eq!(compare("1.10", "1.9"), Ordering::Greater);
+        assert_eq!(compare("1.0~beta1", "1.0"), Ordering::Less);
+        assert_eq!(compare("1.0", "1.0+b1"), Ordering::Less);
+        assert_eq!(compare("1.0a", "1.0+"), Ordering::Less);
+        assert_eq!(compare("1:0.1", "2.0"), Ordering::Greater);
+        assert_eq!(compare("1.0-2", "1.0-10"), Ordering::Less);
+        assert_eq!(compare("1.0", "1.00"), Ordering::Equal);
+        assert_eq!(compare("2.0-1-1", "2.0-1"), Ordering::Greater);
+        assert_eq!(compare("1a", "1.0"), Ordering::Less);
+        assert_eq!(compare("1.0+b1", "1.0.1"), Ordering::Less);
+        assert_eq!(compare("0:1.0", "1.0-0"), Ordering::Equal);
+    }
+
+    #[test]
+    fn parsing() {
+        let version: Version = "3:1.2-beta-4".parse().unwrap();
+        assert_eq!(version.epoch, 3);
+        assert_eq!(version.upstream, "1.2-beta");
+        assert_eq!(version.revision, "4");
+        assert_eq!(version.to_string(), "3:1.2-beta-4");
+
+        assert!("a:1.0".parse::<Version>().is_err());
+        assert!("".parse::<Version>().is_err());
+    }
+}
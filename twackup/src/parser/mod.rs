@@ -73,8 +73,16 @@ pub trait Parsable: Send + Sized {
 /// }
 /// ```
 pub struct Parser {
-    mmap: Mmap,
+    data: Data,
 }
+
+/// Contents parser works with. Files are mapped, but virtual filesystems
+/// can only provide them read to memory
+enum Data {
+    Mapped(Mmap),
+    Buffer(Vec<u8>),
+}
+
 impl Parser {
     /// Prepares environment and creates parser instance
     ///
@@ -86,14 +94,25 @@ impl Parser {
         let file = File::open(file_path)?;
         let mmap = unsafe { Mmap::map(&file) }?;
 
-        Ok(Self { mmap })
+        Ok(Self {
+            data: Data::Mapped(mmap),
+        })
+    }
+
+    /// Creates parser of contents that are already read to memory
+    #[inline]
+    #[must_use]
+    pub fn from_bytes(contents: Vec<u8>) -> Self {
+        Self {
+            data: Data::Buffer(contents),
+        }
     }
 
     /// This method will parse file with key-value syntax on separate lines.
     pub async fn parse<P: Parsable + 'static>(&self) -> LinkedList<P> {
         let mut workers = LinkedList::new();
 
-        for chunk in UnOwnedLine::double_line(&self.mmap) {
+        for chunk in UnOwnedLine::double_line(self.data.as_ref()) {
             let worker = ChunkWorker::new(ptr::NonNull::from(chunk));
             workers.push_back(tokio::spawn(async move { worker.run::<P>() }));
         }
@@ -111,6 +130,15 @@ impl Parser {
     }
 }
 
+impl AsRef<[u8]> for Data {
+    fn as_ref(&self) -> &[u8] {
+        match self {
+            Self::Mapped(mmap) => mmap,
+            Self::Buffer(buffer) => buffer,
+        }
+    }
+}
+
 struct ChunkWorker {
     chunk: ptr::NonNull<[u8]>,
 }
@@ -131,54 +159,63 @@ impl ChunkWorker {
 
     /// Converts raw chunk bytes to list of lines with multi-line syntax support
     fn parse_chunk(&self) -> HashMap<String, String> {
-        let mut fields: LinkedList<Vec<_>> = LinkedList::new();
-
         // SAFETY: As parser will wait for all workers to continue,
-        // mmap pages will be always exist, so this will not cause UB.
+        // mmap pages or buffer will be always exist, so this will not cause UB.
         let chunk = unsafe { self.chunk.as_ref() };
-        let line_iter = UnOwnedLine::single_line(chunk).flat_map(std::str::from_utf8);
+        parse_fields(chunk)
+    }
+}
 
-        // Now we'll process each line of chunk
-        for line in line_iter {
-            // If line is empty (but it shouldn't) - skip
-            if line.is_empty() {
-                continue;
-            }
+/// Converts raw chunk bytes to key-value fields with multi-line syntax support
+pub(crate) fn parse_fields(chunk: &[u8]) -> HashMap<String, String> {
+    let mut fields: LinkedList<Vec<_>> = LinkedList::new();
 
-            // Keys can have multi-line syntax starting with single space
-            // So we'll process them and concat with previous line in list
-            if line.starts_with(' ') {
-                let mut prev_lines = fields.pop_back().unwrap_or_default();
+    let line_iter = UnOwnedLine::single_line(chunk).flat_map(std::str::from_utf8);
 
-                prev_lines.push(line);
-                fields.push_back(prev_lines);
-            } else {
-                fields.push_back(vec![line]);
-            }
+    // Now we'll process each line of chunk
+    for line in line_iter {
+        // If line is empty (but it shouldn't) - skip
+        if line.is_empty() {
+            continue;
         }
 
-        fields
-            .iter()
-            .filter_map(|field_lines| {
-                // Find delimiter in first line
-                let (key, first_val) = field_lines.first()?.split_once(':')?;
-                let key = key.to_owned();
+        // Keys can have multi-line syntax starting with single space
+        // So we'll process them and concat with previous line in list
+        if line.starts_with(' ') {
+            let mut prev_lines = fields.pop_back().unwrap_or_default();
 
-                // Count total length to effectively allocate space
-                let total_len = field_lines
+            prev_lines.push(line);
+            fields.push_back(prev_lines);
+        } else {
+            fields.push_back(vec![line]);
+        }
+    }
+
+    fields
+        .iter()
+        .filter_map(|field_lines| {
+            // Find delimiter in first line
+            let (key, first_val) = field_lines.first()?.split_once(':')?;
+            let key = key.to_owned();
+
+            // Count total length to effectively allocate space
+            let total_len = field_lines
+                .iter()
+                .skip(1)
+                .fold(0, |sum, line| sum + line.len() + 1);
+            let total_len = total_len + first_val.len();
+
+            // Create copy for the first line
+            let mut value = String::with_capacity(total_len);
+            value.push_str(first_val.trim_start());
+
+            // And for other lines
+            let value =
+                field_lines
                     .iter()
                     .skip(1)
-                    .fold(0, |sum, line| sum + line.len() + 1);
-                let total_len = total_len + first_val.len();
-
-                // Create copy for the first line
-                let mut value = String::with_capacity(total_len);
-                value.push_str(first_val.trim_start());
-
-                // And for other lines
-                let value = field_lines.iter().skip(1).enumerate().fold(
-                    value,
-                    |mut value, (index, line)| {
+                    .enumerate()
+                    .fold(value, |mut value, (index, line)| {
                         value.push('\n');
                         // If this is the last line - trim it from the end
                         if index == field_lines.len() - 1 {
@@ -187,13 +224,11 @@ impl ChunkWorker {
                             value.push_str(line);
                         }
                         value
-                    },
-                );
+                    });
 
-                Some((key, value))
-            })
-            .collect()
-    }
+            Some((key, value))
+        })
+        .collect()
 }
 
 unsafe impl Send for ChunkWorker {}
@@ -20,19 +20,61 @@
 //! This module represents some traits used for allowing
 //! user to see packages build progress.
 
-#![allow(unused_variables)]
-
-use crate::package::Package;
+use crate::{builder::BuildReport, error::Generic, package::Package};
 use std::path::Path;
 
-/// Allow users to see progress
-pub trait Progress {
-    /// Sets current progress message
-    fn started_processing(&self, package: &Package);
+/// Build progress event sent by workers
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Event<'a> {
+    /// Package build started
+    Started {
+        /// Package being built
+        package: &'a Package,
+    },
+
+    /// File was placed to data archive of the deb
+    FileArchived {
+        /// Package being built
+        package: &'a Package,
+        /// Absolute path of the file
+        path: &'a Path,
+        /// Uncompressed size of the file in bytes
+        bytes: u64,
+    },
+
+    /// Data archive compression progress. Is sent after every archived file
+    Compression {
+        /// Package being built
+        package: &'a Package,
+        /// Count of uncompressed bytes passed to compressor
+        processed: u64,
+        /// Count of bytes compressor produced so far
+        compressed: u64,
+    },
 
-    /// Will be called when total progress is incrementing
-    fn finished_processing<P: AsRef<Path>>(&self, package: &Package, deb_path: P);
+    /// Package build failed or was cancelled
+    Failed {
+        /// Package being built
+        package: &'a Package,
+        /// Occurred error
+        error: &'a Generic,
+    },
 
-    /// For cleanup and finish
-    fn finished_all(&self);
+    /// Package is built
+    Finished {
+        /// Package being built
+        package: &'a Package,
+        /// Description of what was placed to the deb
+        report: &'a BuildReport,
+    },
+
+    /// All packages passed to build session are processed
+    AllFinished,
+}
+
+/// Allow users to see progress
+pub trait Progress {
+    /// Is called for every build event
+    fn on_event(&self, event: Event<'_>);
 }
@@ -0,0 +1,586 @@
+/*
+ * Copyright 2020 DanP
+ *
+ * This file is part of Twackup
+ *
+ * Twackup is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Twackup is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Twackup. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Retention policy for the directory debs and archives are built to.
+//!
+//! Manifests stored next to archives as `<archive>.manifest.json` are deleted together with them.
+//!
+//! ### Example usage
+//!
+//! ```no_run
+//! use twackup::{prune::{KeepRule, Pruner}, Result};
+//!
+//! fn main() -> Result<()> {
+//!     let rules = [KeepRule::Versions(2), KeepRule::Days(30)];
+//!     let plan = Pruner::new("/var/mobile/Documents/twackup", rules).plan()?;
+//!     for candidate in &plan.delete {
+//!         println!("{}", candidate.path.display());
+//!     }
+//!     plan.apply()?;
+//!
+//!     Ok(())
+//! }
+//! ```
+
+use crate::{error::Result, package::Version};
+use std::{
+    collections::{HashMap, HashSet},
+    fs, io,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::{Duration, SystemTime},
+};
+
+/// Suffix added to archive name to get name of manifest stored next to it.
+/// Same as `manifest::SIDECAR_SUFFIX`, which is only available with `serde` feature
+const SIDECAR_SUFFIX: &str = ".manifest.json";
+
+/// Different prune errors
+#[derive(thiserror::Error, Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// Keep rule couldn't be parsed
+    #[error("Invalid keep rule `{0}`, expected versions=N (N > 0), days=N or size=N[K|M|G|T]")]
+    InvalidRule(String),
+}
+
+/// Single retention rule
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum KeepRule {
+    /// Keep N latest versions of every package
+    Versions(usize),
+    /// Keep archives modified during last N days
+    Days(u64),
+    /// Keep total size of debs and archives under N bytes
+    Size(u64),
+}
+
+/// Why file is going to be deleted
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Reason {
+    /// There are enough newer versions of the package
+    OldVersion,
+    /// Archive is older than allowed
+    Expired,
+    /// Directory is bigger than allowed
+    SizeLimit,
+    /// Archive this manifest was stored next to is deleted
+    Sidecar,
+}
+
+/// File selected for deletion
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct Candidate {
+    /// Path of the file
+    pub path: PathBuf,
+    /// Size of the file in bytes
+    pub size: u64,
+    /// Rule that selected the file
+    pub reason: Reason,
+}
+
+/// Files selected for deletion
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct Plan {
+    /// Files to be deleted
+    pub delete: Vec<Candidate>,
+    /// Total size of files left in directory after deletion in bytes,
+    /// including manifests of kept archives
+    pub kept_size: u64,
+}
+
+/// Selects files of destination directory to delete according to rules
+#[derive(Debug)]
+pub struct Pruner {
+    directory: PathBuf,
+    rules: Vec<KeepRule>,
+    protected: HashSet<PathBuf>,
+}
+
+/// Deb or archive found in directory
+struct Entry {
+    path: PathBuf,
+    /// Size of the file and its manifest
+    size: u64,
+    modified: SystemTime,
+    kind: Kind,
+    /// Manifest stored next to archive and its size
+    sidecar: Option<(PathBuf, u64)>,
+}
+
+enum Kind {
+    Deb {
+        id: String,
+        version: Version,
+        architecture: String,
+    },
+    Archive,
+}
+
+impl FromStr for KeepRule {
+    type Err = Error;
+
+    fn from_str(rule: &str) -> std::result::Result<Self, Self::Err> {
+        let invalid = || Error::InvalidRule(rule.to_string());
+
+        let (name, value) = rule.split_once('=').ok_or_else(invalid)?;
+        match name.trim() {
+            "versions" => match value.parse() {
+                // Keeping no versions would delete every deb, including the latest ones
+                Ok(0) | Err(_) => Err(invalid()),
+                Ok(count) => Ok(Self::Versions(count)),
+            },
+            "days" => Ok(Self::Days(value.parse().map_err(|_| invalid())?)),
+            "size" => parse_size(value).map(Self::Size).ok_or_else(invalid),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl Pruner {
+    /// Creates pruner for directory
+    #[inline]
+    pub fn new<P: AsRef<Path>, I: IntoIterator<Item = KeepRule>>(directory: P, rules: I) -> Self {
+        Self {
+            directory: directory.as_ref().to_path_buf(),
+            rules: rules.into_iter().collect(),
+            protected: HashSet::new(),
+        }
+    }
+
+    /// Files that are never deleted, e.g. debs that have just been built
+    #[inline]
+    #[must_use]
+    pub fn protect<I: IntoIterator<Item = PathBuf>>(mut self, paths: I) -> Self {
+        self.protected.extend(paths);
+        self
+    }
+
+    /// Selects files to delete without touching them.
+    /// Versions are compared as dpkg does, files which names aren't recognized are left as is.
+    ///
+    /// # Errors
+    /// Returns error if directory couldn't be read
+    pub fn plan(&self) -> Result<Plan> {
+        let mut entries = self.scan()?;
+        let mut plan = Plan::default();
+
+        for rule in &self.rules {
+            let (selected, reason) = match *rule {
+                KeepRule::Versions(count) => {
+                    (Self::old_versions(&entries, count), Reason::OldVersion)
+                }
+                KeepRule::Days(days) => (Self::expired(&entries, days), Reason::Expired),
+                KeepRule::Size(_) => continue,
+            };
+            self.move_to_plan(&mut entries, &mut plan, &selected, reason);
+        }
+
+        let limit = self.rules.iter().find_map(|rule| match rule {
+            KeepRule::Size(size) => Some(*size),
+            _ => None,
+        });
+        if let Some(limit) = limit {
+            let selected = self.over_limit(&entries, limit);
+            self.move_to_plan(&mut entries, &mut plan, &selected, Reason::SizeLimit);
+        }
+
+        plan.kept_size = entries.iter().map(|entry| entry.size).sum();
+        Ok(plan)
+    }
+
+    fn scan(&self) -> Result<Vec<Entry>> {
+        let mut entries = vec![];
+        for entry in fs::read_dir(&self.directory)? {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            if !metadata.is_file() {
+                continue;
+            }
+
+            let path = entry.path();
+            let Some(kind) = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(Kind::parse)
+            else {
+                continue;
+            };
+
+            let sidecar = match kind {
+                Kind::Archive => Self::sidecar(&path)?,
+                Kind::Deb { .. } => None,
+            };
+
+            entries.push(Entry {
+                size: metadata.len() + sidecar.as_ref().map_or(0, |(_, size)| *size),
+                path,
+                modified: metadata.modified()?,
+                kind,
+                sidecar,
+            });
+        }
+
+        Ok(entries)
+    }
+
+    /// Finds manifest stored next to archive at `archive`
+    fn sidecar(archive: &Path) -> Result<Option<(PathBuf, u64)>> {
+        let mut path = archive.as_os_str().to_owned();
+        path.push(SIDECAR_SUFFIX);
+        let path = PathBuf::from(path);
+
+        match fs::metadata(&path) {
+            Ok(metadata) if metadata.is_file() => Ok(Some((path, metadata.len()))),
+            Ok(_) => Ok(None),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    /// Returns indices of debs older than `count` latest versions of the same package
+    fn old_versions(entries: &[Entry], count: usize) -> Vec<usize> {
+        let mut packages: HashMap<(&str, &str), Vec<(usize, &Version)>> = HashMap::new();
+        for (index, entry) in entries.iter().enumerate() {
+            if let Kind::Deb {
+                id,
+                version,
+                architecture,
+            } = &entry.kind
+            {
+                let key = (id.as_str(), architecture.as_str());
+                packages.entry(key).or_default().push((index, version));
+            }
+        }
+
+        packages
+            .into_values()
+            .flat_map(|mut versions| {
+                versions.sort_by(|lhs, rhs| rhs.1.cmp(lhs.1));
+                versions.into_iter().skip(count).map(|(index, _)| index)
+            })
+            .collect()
+    }
+
+    /// Returns indices of archives modified more than `days` days ago
+    fn expired(entries: &[Entry], days: u64) -> Vec<usize> {
+        let max_age = Duration::from_secs(days * 24 * 60 * 60);
+        let now = SystemTime::now();
+
+        (entries.iter().enumerate())
+            .filter(|(_, entry)| matches!(entry.kind, Kind::Archive))
+            .filter(|(_, entry)| now.duration_since(entry.modified).unwrap_or_default() > max_age)
+            .map(|(index, _)| index)
+            .collect()
+    }
+
+    /// Returns indices of files to delete to fit into limit.
+    /// Archives go first, then older versions of packages and then oldest files
+    fn over_limit(&self, entries: &[Entry], limit: u64) -> Vec<usize> {
+        let mut latest: HashMap<(&str, &str), &Version> = HashMap::new();
+        for entry in entries {
+            if let Kind::Deb {
+                id,
+                version,
+                architecture,
+            } = &entry.kind
+            {
+                let current = latest.entry((id, architecture)).or_insert(version);
+                if version > *current {
+                    *current = version;
+                }
+            }
+        }
+
+        let priority = |entry: &Entry| match &entry.kind {
+            Kind::Archive => 0,
+            Kind::Deb {
+                id,
+                version,
+                architecture,
+            } if latest.get(&(id.as_str(), architecture.as_str())) != Some(&version) => 1,
+            Kind::Deb { .. } => 2,
+        };
+
+        let mut order: Vec<_> = (0..entries.len())
+            .filter(|&index| !self.protected.contains(&entries[index].path))
+            .collect();
+        order.sort_by_key(|&index| (priority(&entries[index]), entries[index].modified));
+
+        let mut total: u64 = entries.iter().map(|entry| entry.size).sum();
+        order
+            .into_iter()
+            .take_while(|&index| {
+                let over = total > limit;
+                total -= entries[index].size;
+                over
+            })
+            .collect()
+    }
+
+    /// Moves selected entries except protected ones from the list to the plan
+    /// keeping order of selection
+    fn move_to_plan(
+        &self,
+        entries: &mut Vec<Entry>,
+        plan: &mut Plan,
+        selected: &[usize],
+        reason: Reason,
+    ) {
+        let selected: Vec<_> = (selected.iter())
+            .filter(|&&index| !self.protected.contains(&entries[index].path))
+            .collect();
+
+        for &&index in &selected {
+            let entry = &entries[index];
+            let sidecar_size = entry.sidecar.as_ref().map_or(0, |(_, size)| *size);
+            plan.delete.push(Candidate {
+                path: entry.path.clone(),
+                size: entry.size - sidecar_size,
+                reason,
+            });
+
+            if let Some((path, size)) = &entry.sidecar {
+                plan.delete.push(Candidate {
+                    path: path.clone(),
+                    size: *size,
+                    reason: Reason::Sidecar,
+                });
+            }
+        }
+
+        let mut index = 0;
+        entries.retain(|_| {
+            let keep = !selected.contains(&&index);
+            index += 1;
+            keep
+        });
+    }
+}
+
+impl Plan {
+    /// Deletes selected files
+    ///
+    /// # Errors
+    /// Returns error if any file couldn't be deleted
+    pub fn apply(&self) -> Result<()> {
+        for candidate in &self.delete {
+            fs::remove_file(&candidate.path)?;
+        }
+
+        Ok(())
+    }
+
+    /// Total size of selected files in bytes
+    #[must_use]
+    pub fn deleted_size(&self) -> u64 {
+        self.delete.iter().map(|candidate| candidate.size).sum()
+    }
+}
+
+impl Kind {
+    /// Recognizes `id_version_arch.deb` and `*.tar[.compression][.enc]` names
+    fn parse(name: &str) -> Option<Self> {
+        if let Some(stem) = name.strip_suffix(".deb") {
+            let mut parts = stem.split('_');
+            let (Some(id), Some(version), Some(architecture), None) =
+                (parts.next(), parts.next(), parts.next(), parts.next())
+            else {
+                return None;
+            };
+
+            return Some(Self::Deb {
+                id: id.to_string(),
+                version: version.parse().ok()?,
+                architecture: architecture.to_string(),
+            });
+        }
+
+        let path = Path::new(name.strip_suffix(".enc").unwrap_or(name));
+        let stem = path.file_stem().map(Path::new);
+        let is_tar = |path: &Path| path.extension().is_some_and(|extension| extension == "tar");
+        let is_archive = is_tar(path) || stem.is_some_and(is_tar);
+        is_archive.then_some(Self::Archive)
+    }
+}
+
+/// Parses size like `500M` or `2G`
+fn parse_size(size: &str) -> Option<u64> {
+    let size = size.trim().trim_end_matches(['B', 'b']);
+    let (number, multiplier) = match size.chars().last()?.to_ascii_uppercase() {
+        'K' => (&size[..size.len() - 1], 1 << 10),
+        'M' => (&size[..size.len() - 1], 1 << 20),
+        'G' => (&size[..size.len() - 1], 1 << 30),
+        'T' => (&size[..size.len() - 1], 1 << 40),
+        _ => (size, 1),
+    };
+
+    number.trim().parse::<u64>().ok()?.checked_mul(multiplier)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_size, KeepRule, Kind, Pruner, Reason};
+    use crate::{testing::temp_dir, Result};
+    use std::fs;
+
+    #[test]
+    fn rules() {
+        assert_eq!(
+            "versions=2".parse::<KeepRule>().unwrap(),
+            KeepRule::Versions(2)
+        );
+        assert_eq!("days=30".parse::<KeepRule>().unwrap(), KeepRule::Days(30));
+        assert_eq!(parse_size("2G"), Some(2 << 30));
+        assert_eq!(parse_size("500MB"), Some(500 << 20));
+        assert_eq!(parse_size("100"), Some(100));
+        assert!("size=lots".parse::<KeepRule>().is_err());
+        assert!("count=1".parse::<KeepRule>().is_err());
+        assert!("versions=0".parse::<KeepRule>().is_err());
+    }
+
+    #[test]
+    fn kinds() {
+        for name in [
+            "backup.tar",
+            "backup.tar.gz",
+            "backup.tar.enc",
+            "backup.tar.zst.enc",
+        ] {
+            assert!(matches!(Kind::parse(name), Some(Kind::Archive)), "{name}");
+        }
+        for name in [
+            "notes.txt",
+            "notes.enc",
+            "backup.gz.enc",
+            "backup.tar.gz.manifest.json",
+        ] {
+            assert!(Kind::parse(name).is_none(), "{name}");
+        }
+        assert!(matches!(
+            Kind::parse("pkg_1.0_all.deb"),
+            Some(Kind::Deb { .. })
+        ));
+    }
+
+    #[test]
+    fn keep_versions_and_size() -> Result<()> {
+        let directory = temp_dir("twackup-prune")?;
+
+        let files = [
+            ("pkg_1.9_all.deb", 10),
+            ("pkg_1.10_all.deb", 10),
+            ("pkg_1.10~beta_all.deb", 10),
+            ("other_1.0_iphoneos-arm.deb", 10),
+            ("backup.tar.gz", 100),
+            ("notes.txt", 1000),
+        ];
+        for (name, size) in files {
+            fs::write(directory.join(name), vec![0; size])?;
+        }
+
+        let plan = Pruner::new(&directory, [KeepRule::Versions(1)]).plan()?;
+        let mut deleted: Vec<_> = (plan.delete.iter())
+            .map(|candidate| candidate.path.file_name().unwrap().to_str().unwrap())
+            .collect();
+        deleted.sort_unstable();
+        assert_eq!(deleted, ["pkg_1.10~beta_all.deb", "pkg_1.9_all.deb"]);
+        assert_eq!(plan.kept_size, 120);
+
+        let rules = [KeepRule::Versions(2), KeepRule::Size(20)];
+        let protected = directory.join("other_1.0_iphoneos-arm.deb");
+        let plan = Pruner::new(&directory, rules).protect([protected]).plan()?;
+        let reasons: Vec<_> = (plan.delete.iter())
+            .map(|candidate| {
+                (
+                    candidate.path.file_name().unwrap().to_str().unwrap(),
+                    candidate.reason,
+                )
+            })
+            .collect();
+        assert_eq!(
+            reasons,
+            [
+                ("pkg_1.9_all.deb", Reason::OldVersion),
+                ("backup.tar.gz", Reason::SizeLimit),
+                ("pkg_1.10~beta_all.deb", Reason::SizeLimit),
+            ]
+        );
+
+        plan.apply()?;
+        assert!(!directory.join("backup.tar.gz").exists());
+        assert!(directory.join("notes.txt").exists());
+
+        fs::remove_dir_all(directory)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn sidecars() -> Result<()> {
+        let directory = temp_dir("twackup-prune-sidecars")?;
+
+        let files = [
+            ("pkg_1.0_all.deb", 10),
+            ("backup.tar.gz", 100),
+            ("backup.tar.gz.manifest.json", 5),
+            ("orphan.tar.gz.manifest.json", 1000),
+        ];
+        for (name, size) in files {
+            fs::write(directory.join(name), vec![0; size])?;
+        }
+
+        let plan = Pruner::new(&directory, [KeepRule::Versions(1)]).plan()?;
+        assert!(plan.delete.is_empty());
+        assert_eq!(plan.kept_size, 115);
+
+        // Manifest counts towards limit, so archive has to go even though it fits alone
+        let plan = Pruner::new(&directory, [KeepRule::Size(110)]).plan()?;
+        let deleted: Vec<_> = (plan.delete.iter())
+            .map(|candidate| {
+                (
+                    candidate.path.file_name().unwrap().to_str().unwrap(),
+                    candidate.size,
+                    candidate.reason,
+                )
+            })
+            .collect();
+        assert_eq!(
+            deleted,
+            [
+                ("backup.tar.gz", 100, Reason::SizeLimit),
+                ("backup.tar.gz.manifest.json", 5, Reason::Sidecar),
+            ]
+        );
+        assert_eq!(plan.deleted_size(), 105);
+        assert_eq!(plan.kept_size, 10);
+
+        plan.apply()?;
+        assert!(!directory.join("backup.tar.gz.manifest.json").exists());
+        assert!(directory.join("orphan.tar.gz.manifest.json").exists());
+
+        fs::remove_dir_all(directory)?;
+
+        Ok(())
+    }
+}
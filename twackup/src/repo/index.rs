@@ -0,0 +1,426 @@
+/*
+ * Copyright 2020 DanP
+ *
+ * This file is part of Twackup
+ *
+ * Twackup is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Twackup is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Twackup. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use super::openpgp::SigningKey;
+use crate::{
+    archiver::{Compression, Encoder, Level, Type},
+    deb::{self, Deb},
+    error::Result,
+    package::Field,
+    parser,
+};
+use md5::Md5;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeSet,
+    fmt::Write,
+    fs,
+    io::{self, Read},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+use tokio::io::AsyncWriteExt;
+
+/// Size of chunks debs are read with while hashing
+const HASH_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Compressions `Packages` file is additionally written with
+const PACKAGES_COMPRESSIONS: [Type; 3] = [Type::Gz, Type::Xz, Type::Zst];
+
+/// Detached and cleartext signatures of `Release` written by [`Index::sign`]
+const SIGNATURES: [&str; 2] = ["Release.gpg", "InRelease"];
+
+/// Fields of the `Release` file describing repository
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct ReleaseInfo {
+    /// Origin of the repository
+    pub origin: String,
+    /// Label shown by package managers
+    pub label: String,
+    /// Suite of the repository
+    pub suite: String,
+    /// Codename of the repository
+    pub codename: String,
+    /// Human-readable description of the repository
+    pub description: String,
+}
+
+impl Default for ReleaseInfo {
+    fn default() -> Self {
+        Self {
+            origin: "Twackup".to_string(),
+            label: "Twackup".to_string(),
+            suite: "stable".to_string(),
+            codename: "twackup".to_string(),
+            description: "Packages rebuilt by Twackup".to_string(),
+        }
+    }
+}
+
+/// Single deb of the repository
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct IndexEntry {
+    /// Control file of the deb without trailing newlines
+    pub control: String,
+    /// Path of the deb relative to the repository root
+    pub filename: String,
+    /// Size of the deb in bytes
+    pub size: u64,
+    /// Hex-encoded MD5 checksum of the deb
+    pub md5: String,
+    /// Hex-encoded SHA-256 checksum of the deb
+    pub sha256: String,
+    /// Architecture from the control file
+    pub architecture: Option<String>,
+}
+
+/// Flat repository contents
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct Index {
+    /// Repository root
+    pub root: PathBuf,
+    /// Debs sorted by their file names
+    pub entries: Vec<IndexEntry>,
+}
+
+/// Metadata file written to repository root
+struct MetadataFile {
+    name: String,
+    data: Vec<u8>,
+}
+
+impl Index {
+    /// Reads control of every deb in directory and its subdirectories.
+    /// Files that are not valid debs are skipped with warning.
+    ///
+    /// # Errors
+    /// Returns error if directory couldn't be read
+    pub async fn scan<P: AsRef<Path>>(root: P) -> Result<Self> {
+        let root = root.as_ref().to_path_buf();
+
+        let mut debs = vec![];
+        collect_debs(&root, &mut debs)?;
+        debs.sort();
+
+        let mut entries = Vec::with_capacity(debs.len());
+        for path in debs {
+            match IndexEntry::new(&root, &path).await {
+                Ok(entry) => entries.push(entry),
+                Err(error) => log::warn!("Skipping {}: {}", path.display(), error),
+            }
+        }
+
+        Ok(Self { root, entries })
+    }
+
+    /// Builds contents of `Packages` file
+    #[must_use]
+    pub fn packages(&self) -> String {
+        let mut packages = String::new();
+        for entry in &self.entries {
+            packages.push_str(&entry.control);
+            // Writing to String never fails
+            write!(
+                packages,
+                "\nFilename: {}\nSize: {}\nMD5sum: {}\nSHA256: {}\n\n",
+                entry.filename, entry.size, entry.md5, entry.sha256
+            )
+            .ok();
+        }
+        packages
+    }
+
+    /// Writes `Packages`, its compressed variants and `Release` to repository root.
+    /// Signatures of previous `Release` are removed, so they must be made again with
+    /// [`Index::sign`]. Returns paths of written files
+    ///
+    /// # Errors
+    /// Returns error if compression failed or files couldn't be written or removed
+    pub async fn write(&self, info: &ReleaseInfo) -> Result<Vec<PathBuf>> {
+        let packages = self.packages().into_bytes();
+
+        let mut files = Vec::with_capacity(PACKAGES_COMPRESSIONS.len() + 2);
+        for r#type in PACKAGES_COMPRESSIONS {
+            files.push(MetadataFile {
+                name: format!("Packages.{}", r#type.as_str()),
+                data: compress(&packages, r#type).await?,
+            });
+        }
+        files.insert(
+            0,
+            MetadataFile {
+                name: "Packages".to_string(),
+                data: packages,
+            },
+        );
+
+        let release = self.release(info, &files);
+        files.push(MetadataFile {
+            name: "Release".to_string(),
+            data: release.into_bytes(),
+        });
+
+        let mut written = Vec::with_capacity(files.len());
+        for file in files {
+            let path = self.root.join(file.name);
+            fs::write(&path, file.data)?;
+            written.push(path);
+        }
+
+        for name in SIGNATURES {
+            match fs::remove_file(self.root.join(name)) {
+                Err(error) if error.kind() != io::ErrorKind::NotFound => return Err(error.into()),
+                _ => {}
+            }
+        }
+
+        Ok(written)
+    }
+
+    /// Signs previously written `Release` file with the key.
+    /// Writes detached `Release.gpg` and cleartext signed `InRelease`
+    /// and returns their paths
+    ///
+    /// # Errors
+    /// Returns error if `Release` couldn't be read or signed files couldn't be written
+    pub fn sign(&self, key: &SigningKey) -> Result<Vec<PathBuf>> {
+        let release = fs::read_to_string(self.root.join("Release"))?;
+
+        let [detached, cleartext] = SIGNATURES.map(|name| self.root.join(name));
+        fs::write(&detached, key.sign_detached(release.as_bytes()))?;
+        fs::write(&cleartext, key.sign_cleartext(&release))?;
+
+        Ok(vec![detached, cleartext])
+    }
+
+    fn release(&self, info: &ReleaseInfo, files: &[MetadataFile]) -> String {
+        let architectures: BTreeSet<_> = (self.entries.iter())
+            .filter_map(|entry| entry.architecture.as_deref())
+            .collect();
+        let architectures: Vec<_> = architectures.into_iter().collect();
+
+        let mut release = format!(
+            "Origin: {}\nLabel: {}\nSuite: {}\nCodename: {}\nVersion: 1.0\n\
+             Architectures: {}\nComponents: main\nDescription: {}\nDate: {}\n",
+            info.origin,
+            info.label,
+            info.suite,
+            info.codename,
+            architectures.join(" "),
+            info.description,
+            rfc2822_now(),
+        );
+
+        release.push_str("MD5Sum:\n");
+        for file in files {
+            let hash = format!("{:x}", Md5::digest(&file.data));
+            writeln!(release, " {hash} {} {}", file.data.len(), file.name).ok();
+        }
+
+        release.push_str("SHA256:\n");
+        for file in files {
+            let hash = format!("{:x}", Sha256::digest(&file.data));
+            writeln!(release, " {hash} {} {}", file.data.len(), file.name).ok();
+        }
+
+        release
+    }
+}
+
+impl IndexEntry {
+    async fn new(root: &Path, path: &Path) -> Result<Self> {
+        let deb = Deb::open(path)?;
+        let control = deb
+            .control_file("control")
+            .await?
+            .ok_or(deb::Error::MissingMember("control"))?;
+        let architecture = parser::parse_fields(&control).remove(Field::Architecture.as_str());
+        let control = String::from_utf8_lossy(&control).trim_end().to_string();
+
+        // Debs can be large, so they are hashed without reading to memory
+        let mut file = fs::File::open(path)?;
+        let (mut md5, mut sha256) = (Md5::new(), Sha256::new());
+        let mut buffer = vec![0; HASH_BUFFER_SIZE];
+        let mut size = 0;
+        loop {
+            let read = file.read(&mut buffer)?;
+            if read == 0 {
+                break;
+            }
+            md5.update(&buffer[..read]);
+            sha256.update(&buffer[..read]);
+            size += read as u64;
+        }
+
+        let filename = path.strip_prefix(root).unwrap_or(path);
+
+        Ok(Self {
+            control,
+            filename: format!("./{}", filename.display()),
+            size,
+            md5: format!("{:x}", md5.finalize()),
+            sha256: format!("{:x}", sha256.finalize()),
+            architecture,
+        })
+    }
+}
+
+fn collect_debs(directory: &Path, debs: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in fs::read_dir(directory)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        let path = entry.path();
+
+        if file_type.is_dir() {
+            collect_debs(&path, debs)?;
+        } else if path.extension().is_some_and(|ext| ext == "deb") {
+            debs.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+async fn compress(data: &[u8], r#type: Type) -> Result<Vec<u8>> {
+    let compression = Compression {
+        r#type,
+        level: Level::Best,
+    };
+
+    let mut encoder = Encoder::new(vec![], compression)?;
+    encoder.write_all(data).await?;
+    encoder.shutdown().await?;
+
+    Ok(encoder.into_inner()?)
+}
+
+/// Formats current time as required by `Date` field of `Release`
+fn rfc2822_now() -> String {
+    const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+
+    let seconds = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs());
+
+    let days = seconds / 86400;
+    let (year, month, day) = civil_from_days(days);
+    let time = seconds % 86400;
+
+    #[allow(clippy::cast_possible_truncation)]
+    let weekday = WEEKDAYS[(days % 7) as usize];
+
+    format!(
+        "{weekday}, {day:02} {} {year} {:02}:{:02}:{:02} UTC",
+        MONTHS[month - 1],
+        time / 3600,
+        time % 3600 / 60,
+        time % 60
+    )
+}
+
+/// Converts days since UNIX epoch to (year, month, day).
+/// Algorithm by Howard Hinnant
+pub(crate) fn civil_from_days(days: u64) -> (u64, usize, u64) {
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+
+    #[allow(clippy::cast_possible_truncation)]
+    (year, month as usize, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{civil_from_days, Index, ReleaseInfo, SigningKey};
+    use crate::{
+        builder::{Preferences, Worker},
+        testing::{temp_dir, NoProgress, DPKG_DIR},
+        Dpkg, Result,
+    };
+    use md5::{Digest, Md5};
+    use std::{fs, sync::Arc};
+
+    #[test]
+    fn civil_dates() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(11_016), (2000, 2, 29));
+        assert_eq!(civil_from_days(20_742), (2026, 10, 16));
+    }
+
+    #[tokio::test]
+    async fn index_rebuilt_package() -> Result<()> {
+        let dpkg = Dpkg::new(DPKG_DIR, false);
+        let package = dpkg.unsorted_packages(false).await?.pop_back().unwrap();
+
+        let root = temp_dir("twackup-repo-index")?;
+        fs::create_dir_all(root.join("debs"))?;
+
+        let dpkg_contents = Arc::new(dpkg.info_dir_contents()?);
+        let preferences = Preferences::new(DPKG_DIR, root.join("debs"));
+        let worker = Worker::new(&package, NoProgress, None, preferences, dpkg_contents);
+        let report = worker.run().await?;
+        fs::write(root.join("broken.deb"), b"not a deb")?;
+
+        let index = Index::scan(&root).await?;
+        assert_eq!(index.entries.len(), 1);
+
+        let files = index.write(&ReleaseInfo::default()).await?;
+        assert_eq!(files.len(), 5);
+
+        let packages = fs::read_to_string(root.join("Packages"))?;
+        assert!(packages.starts_with("Package: hosts\n"));
+        assert!(packages.contains("Filename: ./debs/hosts_1.0.0_all.deb\n"));
+        assert!(packages.contains(&format!("SHA256: {}\n", report.deb_sha256)));
+        let deb = fs::read(&report.deb_path)?;
+        assert!(packages.contains(&format!("Size: {}\n", deb.len())));
+        assert!(packages.contains(&format!("MD5sum: {:x}\n", Md5::digest(&deb))));
+
+        let release = fs::read_to_string(root.join("Release"))?;
+        assert!(release.contains("Architectures: all\n"));
+        assert_eq!(release.matches(" Packages.zst\n").count(), 2);
+
+        // Signatures of previous Release don't match new one
+        let key = SigningKey::parse(include_bytes!("../../assets/openpgp/secret.asc"))?;
+        index.sign(&key)?;
+        index.write(&ReleaseInfo::default()).await?;
+        assert!(!root.join("InRelease").exists());
+        assert!(!root.join("Release.gpg").exists());
+
+        fs::remove_dir_all(root)?;
+
+        Ok(())
+    }
+}
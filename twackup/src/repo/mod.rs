@@ -0,0 +1,51 @@
+/*
+ * Copyright 2020 DanP
+ *
+ * This file is part of Twackup
+ *
+ * Twackup is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Twackup is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Twackup. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Generates flat APT repository from a directory with debs,
+//! so it can be added to APT, Sileo or Zebra on another device,
+//! and serves it over HTTP.
+//!
+//! ### Example usage
+//!
+//! ```no_run
+//! use twackup::{
+//!     repo::{openpgp::SigningKey, Index, ReleaseInfo},
+//!     Result,
+//! };
+//!
+//! #[tokio::main]
+//! async fn main() -> Result<()> {
+//!     let index = Index::scan("/var/mobile/Documents/twackup").await?;
+//!     index.write(&ReleaseInfo::default()).await?;
+//!
+//!     let key = std::fs::read("/var/mobile/twackup.key")?;
+//!     index.sign(&SigningKey::parse(&key)?)?;
+//!
+//!     Ok(())
+//! }
+//! ```
+
+mod index;
+pub mod openpgp;
+mod server;
+
+#[cfg(feature = "upload")]
+pub(crate) use index::civil_from_days;
+pub use index::{Index, IndexEntry, ReleaseInfo};
+pub use server::Server;
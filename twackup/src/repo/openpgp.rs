@@ -0,0 +1,1294 @@
+/*
+ * Copyright 2020 DanP
+ *
+ * This file is part of Twackup
+ *
+ * Twackup is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Twackup is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Twackup. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Minimal `OpenPGP` implementation used for signing and verifying repository metadata.
+//!
+//! Only unprotected v4 Ed25519 keys are supported - the ones created with
+//! `gpg --quick-gen-key <user-id> ed25519`. Signatures are made with SHA-256,
+//! while SHA-256 and SHA-512 are accepted on verification.
+//!
+//! Keys of keyring are trusted only if their self-signatures are valid, allow signing
+//! and the keys are neither expired nor revoked. Subkeys must be bound to primary key
+//! and cross-certify it back.
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use ed25519_dalek::{Signer, Verifier};
+use sha1::Sha1;
+use sha2::{Digest, Sha256, Sha512};
+use std::{fmt::Write, time::SystemTime};
+
+/// `EdDSA` algorithm identifier from RFC 4880bis, used by `GnuPG`
+const ALGORITHM_EDDSA_LEGACY: u8 = 22;
+/// Ed25519 algorithm identifier from RFC 9580
+const ALGORITHM_ED25519: u8 = 27;
+/// Curve identifier of Ed25519 used with legacy `EdDSA` algorithm
+const ED25519_OID: &[u8] = &[0x2B, 0x06, 0x01, 0x04, 0x01, 0xDA, 0x47, 0x0F, 0x01];
+
+const HASH_SHA256: u8 = 8;
+const HASH_SHA512: u8 = 10;
+
+/// Names of hash algorithms used in `Hash` armor header
+const HASH_NAMES: [(&str, u8); 7] = [
+    ("MD5", 1),
+    ("SHA1", 2),
+    ("RIPEMD160", 3),
+    ("SHA256", HASH_SHA256),
+    ("SHA384", 9),
+    ("SHA512", HASH_SHA512),
+    ("SHA224", 11),
+];
+
+const TAG_SIGNATURE: u8 = 2;
+const TAG_SECRET_KEY: u8 = 5;
+const TAG_PUBLIC_KEY: u8 = 6;
+const TAG_USER_ID: u8 = 13;
+const TAG_PUBLIC_SUBKEY: u8 = 14;
+
+const SIGNATURE_BINARY: u8 = 0x00;
+const SIGNATURE_TEXT: u8 = 0x01;
+const SIGNATURE_GENERIC_CERTIFICATION: u8 = 0x10;
+const SIGNATURE_POSITIVE_CERTIFICATION: u8 = 0x13;
+const SIGNATURE_SUBKEY_BINDING: u8 = 0x18;
+const SIGNATURE_PRIMARY_KEY_BINDING: u8 = 0x19;
+const SIGNATURE_DIRECT_KEY: u8 = 0x1F;
+const SIGNATURE_KEY_REVOCATION: u8 = 0x20;
+const SIGNATURE_SUBKEY_REVOCATION: u8 = 0x28;
+
+const SUBPACKET_CREATION_TIME: u8 = 2;
+const SUBPACKET_SIGNATURE_EXPIRATION_TIME: u8 = 3;
+const SUBPACKET_KEY_EXPIRATION_TIME: u8 = 9;
+const SUBPACKET_ISSUER: u8 = 16;
+const SUBPACKET_KEY_FLAGS: u8 = 27;
+const SUBPACKET_EMBEDDED_SIGNATURE: u8 = 32;
+const SUBPACKET_ISSUER_FINGERPRINT: u8 = 33;
+
+/// Key flag allowing key to sign data
+const KEY_FLAG_SIGN: u8 = 0x02;
+
+const SIGNED_MESSAGE_HEADER: &str = "-----BEGIN PGP SIGNED MESSAGE-----";
+const SIGNATURE_HEADER: &str = "-----BEGIN PGP SIGNATURE-----";
+
+/// Different `OpenPGP` errors
+#[derive(thiserror::Error, Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// ASCII armor is broken
+    #[error("Invalid armor: {0}")]
+    InvalidArmor(&'static str),
+
+    /// Packet couldn't be parsed
+    #[error("Malformed packet: {0}")]
+    MalformedPacket(&'static str),
+
+    /// Data doesn't contain any supported key
+    #[error("No supported key found")]
+    NoKey,
+
+    /// Key or signature uses algorithm other than Ed25519
+    #[error("Public key algorithm {0} is not supported")]
+    UnsupportedAlgorithm(u8),
+
+    /// Signature uses unsupported hash algorithm
+    #[error("Hash algorithm {0} is not supported")]
+    UnsupportedHash(u8),
+
+    /// Secret key is protected with passphrase
+    #[error("Secret key is protected with passphrase")]
+    EncryptedKey,
+
+    /// Key material is invalid
+    #[error("Key is invalid")]
+    InvalidKey,
+
+    /// Message is not a cleartext signed message
+    #[error("Message is not signed")]
+    NotSigned,
+
+    /// None of signatures is made by key from keyring
+    #[error("No signature made by known key")]
+    UnknownSigner,
+
+    /// Signature doesn't match signed data
+    #[error("Signature is invalid")]
+    BadSignature,
+
+    /// Signature is made with hash algorithm other than declared in `Hash` armor header
+    #[error("Signature hash algorithm doesn't match Hash armor header")]
+    HashMismatch,
+}
+
+/// Ed25519 public key
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PublicKey {
+    algorithm: u8,
+    key: ed25519_dalek::VerifyingKey,
+    fingerprint: [u8; 20],
+    created: u32,
+    /// Public key packet body, self-signatures are made over it
+    body: Vec<u8>,
+}
+
+/// Ed25519 secret key used for signing
+#[derive(Debug)]
+pub struct SigningKey {
+    public: PublicKey,
+    secret: ed25519_dalek::SigningKey,
+}
+
+/// Text of cleartext signed message that passed verification
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct VerifiedMessage {
+    /// Signed text with LF line endings
+    pub text: String,
+    /// Key that made the signature
+    pub signer: PublicKey,
+}
+
+impl PublicKey {
+    /// Parses all supported signing primary keys and subkeys from armored or binary keyring.
+    /// Keys with unsupported algorithms, without valid self-signatures or signing flag,
+    /// expired and revoked ones are skipped.
+    ///
+    /// # Errors
+    /// Returns error if keyring is malformed or no supported key was found
+    pub fn parse_keyring(data: &[u8]) -> Result<Vec<Self>, Error> {
+        let data = dearmor_if_needed(data)?;
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_secs());
+
+        // Every certificate starts with primary key packet
+        let mut certificates: Vec<Vec<Packet<'_>>> = vec![];
+        for packet in packets(&data)? {
+            if packet.tag == TAG_PUBLIC_KEY {
+                certificates.push(vec![]);
+            }
+            if let Some(certificate) = certificates.last_mut() {
+                certificate.push(packet);
+            }
+        }
+
+        let mut keys = vec![];
+        for certificate in certificates {
+            keys.extend(Self::signing_keys(&certificate, now)?);
+        }
+
+        if keys.is_empty() {
+            return Err(Error::NoKey);
+        }
+
+        Ok(keys)
+    }
+
+    /// Returns primary key and subkeys of certificate which are valid for signing at `now`
+    fn signing_keys(certificate: &[Packet<'_>], now: u64) -> Result<Vec<Self>, Error> {
+        let Some((first, packets)) = certificate.split_first() else {
+            return Ok(vec![]);
+        };
+        let primary = match Self::parse(&mut Reader::new(first.body)) {
+            Ok(key) => key,
+            Err(Error::UnsupportedAlgorithm(_)) => return Ok(vec![]),
+            Err(error) => return Err(error),
+        };
+
+        let mut primary_validity = Validity::default();
+        let mut subkeys: Vec<(Option<Self>, Validity<'_>)> = vec![];
+        let mut user_id = None;
+        for packet in packets {
+            match packet.tag {
+                TAG_USER_ID => user_id = Some(packet.body),
+                TAG_PUBLIC_SUBKEY => {
+                    let subkey = match Self::parse(&mut Reader::new(packet.body)) {
+                        Ok(key) => Some(key),
+                        Err(Error::UnsupportedAlgorithm(_)) => None,
+                        Err(error) => return Err(error),
+                    };
+                    subkeys.push((subkey, Validity::default()));
+                }
+                TAG_SIGNATURE => {
+                    // Certifications made by others can use any algorithm
+                    let Ok(signature) = Signature::parse(packet.body) else {
+                        continue;
+                    };
+                    if !signature.is_issued_by(&primary) || signature.is_expired(now) {
+                        continue;
+                    }
+
+                    // Signatures following primary key and user IDs are made over primary key,
+                    // ones following subkey are made over both keys
+                    let (data, validity) = match (signature.kind, subkeys.last_mut(), user_id) {
+                        (SIGNATURE_KEY_REVOCATION | SIGNATURE_DIRECT_KEY, None, _) => {
+                            (primary.hashed(), &mut primary_validity)
+                        }
+                        (
+                            SIGNATURE_GENERIC_CERTIFICATION..=SIGNATURE_POSITIVE_CERTIFICATION,
+                            None,
+                            Some(user_id),
+                        ) => (primary.certified(user_id)?, &mut primary_validity),
+                        (
+                            SIGNATURE_SUBKEY_BINDING | SIGNATURE_SUBKEY_REVOCATION,
+                            Some((Some(subkey), validity)),
+                            _,
+                        ) => {
+                            let data = [primary.hashed(), subkey.hashed()].concat();
+                            let is_binding = signature.kind == SIGNATURE_SUBKEY_BINDING;
+                            if is_binding && !signature.is_cross_certified(subkey, &data) {
+                                continue;
+                            }
+                            (data, validity)
+                        }
+                        _ => continue,
+                    };
+
+                    if !signature.is_valid(&primary, &data) {
+                        continue;
+                    }
+                    match signature.kind {
+                        SIGNATURE_KEY_REVOCATION | SIGNATURE_SUBKEY_REVOCATION => {
+                            validity.revoked = true;
+                        }
+                        _ => validity.certify(signature),
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        // Subkeys are usable only while primary key is
+        if !primary_validity.is_valid(&primary, now, 0) {
+            return Ok(vec![]);
+        }
+
+        let mut keys = vec![];
+        if primary_validity.is_valid(&primary, now, KEY_FLAG_SIGN) {
+            keys.push(primary);
+        }
+        for (subkey, validity) in subkeys {
+            if let Some(subkey) = subkey.filter(|key| validity.is_valid(key, now, KEY_FLAG_SIGN)) {
+                keys.push(subkey);
+            }
+        }
+
+        Ok(keys)
+    }
+
+    /// Returns upper-case hex-encoded v4 fingerprint of the key
+    #[must_use]
+    pub fn fingerprint(&self) -> String {
+        hex_upper(&self.fingerprint)
+    }
+
+    /// Returns upper-case hex-encoded long key ID
+    #[must_use]
+    pub fn key_id(&self) -> String {
+        hex_upper(self.key_id_bytes())
+    }
+
+    fn key_id_bytes(&self) -> &[u8] {
+        &self.fingerprint[12..]
+    }
+
+    /// Key packet as it is hashed by fingerprints and self-signatures
+    fn hashed(&self) -> Vec<u8> {
+        let mut data = vec![0x99];
+        // Length is checked while parsing
+        data.extend_from_slice(&packet_length_u16(self.body.len()).unwrap_or_default());
+        data.extend_from_slice(&self.body);
+        data
+    }
+
+    /// User ID certified by primary key as it is hashed by certification signatures
+    fn certified(&self, user_id: &[u8]) -> Result<Vec<u8>, Error> {
+        let length = u32::try_from(user_id.len())
+            .map_err(|_| Error::MalformedPacket("user ID is too long"))?;
+
+        let mut data = self.hashed();
+        data.push(0xB4);
+        data.extend_from_slice(&length.to_be_bytes());
+        data.extend_from_slice(user_id);
+        Ok(data)
+    }
+
+    fn parse(reader: &mut Reader<'_>) -> Result<Self, Error> {
+        let start = reader.data;
+
+        if reader.u8()? != 4 {
+            return Err(Error::MalformedPacket("only v4 keys are supported"));
+        }
+        let created = reader.u32()?;
+        let algorithm = reader.u8()?;
+
+        let key: [u8; 32] = match algorithm {
+            ALGORITHM_EDDSA_LEGACY => {
+                let oid_length = reader.u8()?;
+                if reader.take(usize::from(oid_length))? != ED25519_OID {
+                    return Err(Error::UnsupportedAlgorithm(algorithm));
+                }
+
+                // Native point format is prefixed with 0x40
+                match reader.mpi()? {
+                    [0x40, point @ ..] => point.try_into().map_err(|_| Error::InvalidKey)?,
+                    _ => return Err(Error::InvalidKey),
+                }
+            }
+            ALGORITHM_ED25519 => reader.take(32)?.try_into().map_err(|_| Error::InvalidKey)?,
+            _ => return Err(Error::UnsupportedAlgorithm(algorithm)),
+        };
+        let key = ed25519_dalek::VerifyingKey::from_bytes(&key).map_err(|_| Error::InvalidKey)?;
+
+        let body = &start[..start.len() - reader.data.len()];
+        let mut hasher = Sha1::new();
+        hasher.update([0x99]);
+        hasher.update(packet_length_u16(body.len())?);
+        hasher.update(body);
+
+        Ok(Self {
+            algorithm,
+            key,
+            fingerprint: hasher.finalize().into(),
+            created,
+            body: body.to_vec(),
+        })
+    }
+}
+
+/// Latest valid self-signature of the key and whether key is revoked
+#[derive(Default)]
+struct Validity<'a> {
+    self_signature: Option<Signature<'a>>,
+    revoked: bool,
+}
+
+impl<'a> Validity<'a> {
+    /// Remembers verified self-signature, the newest one takes precedence
+    fn certify(&mut self, signature: Signature<'a>) {
+        let is_newer = (self.self_signature.as_ref())
+            .map_or(true, |current| signature.created >= current.created);
+        if is_newer {
+            self.self_signature = Some(signature);
+        }
+    }
+
+    /// Checks that key has self-signature with all of the `flags`, isn't revoked or expired
+    fn is_valid(&self, key: &PublicKey, now: u64, flags: u8) -> bool {
+        let Some(signature) = &self.self_signature else {
+            return false;
+        };
+
+        let expires = (signature.key_expiration)
+            .filter(|&expiration| expiration != 0)
+            .map(|expiration| u64::from(key.created) + u64::from(expiration));
+        let has_flags = signature.key_flags.unwrap_or_default() & flags == flags;
+
+        !self.revoked && has_flags && expires.map_or(true, |expires| now < expires)
+    }
+}
+
+impl SigningKey {
+    /// Parses first secret key from armored or binary data,
+    /// e.g. exported with `gpg --export-secret-keys`
+    ///
+    /// # Errors
+    /// Returns error if key is malformed, protected with passphrase or is not Ed25519 one
+    pub fn parse(data: &[u8]) -> Result<Self, Error> {
+        let data = dearmor_if_needed(data)?;
+        let packet = packets(&data)?
+            .into_iter()
+            .find(|packet| packet.tag == TAG_SECRET_KEY)
+            .ok_or(Error::NoKey)?;
+
+        let mut reader = Reader::new(packet.body);
+        let public = PublicKey::parse(&mut reader)?;
+        if reader.u8()? != 0 {
+            return Err(Error::EncryptedKey);
+        }
+
+        let secret = match public.algorithm {
+            ALGORITHM_EDDSA_LEGACY => left_pad::<32>(reader.mpi()?)?,
+            _ => reader.take(32)?.try_into().map_err(|_| Error::InvalidKey)?,
+        };
+        let secret = ed25519_dalek::SigningKey::from_bytes(&secret);
+        if secret.verifying_key() != public.key {
+            return Err(Error::InvalidKey);
+        }
+
+        Ok(Self { public, secret })
+    }
+
+    /// Public part of the key
+    #[must_use]
+    pub fn public_key(&self) -> &PublicKey {
+        &self.public
+    }
+
+    /// Makes armored detached binary signature, e.g. `Release.gpg`
+    #[must_use]
+    pub fn sign_detached(&self, data: &[u8]) -> String {
+        armor("SIGNATURE", &self.signature_packet(SIGNATURE_BINARY, data))
+    }
+
+    /// Makes cleartext signed message, e.g. `InRelease`
+    #[must_use]
+    pub fn sign_cleartext(&self, text: &str) -> String {
+        let signature = self.signature_packet(SIGNATURE_TEXT, canonical_text(text).as_bytes());
+
+        let mut message = format!("{SIGNED_MESSAGE_HEADER}\nHash: SHA256\n\n");
+        for line in text.lines() {
+            if line.starts_with('-') {
+                message.push_str("- ");
+            }
+            message.push_str(line);
+            message.push('\n');
+        }
+        message.push_str(&armor("SIGNATURE", &signature));
+
+        message
+    }
+
+    fn signature_packet(&self, signature_type: u8, data: &[u8]) -> Vec<u8> {
+        write_packet(
+            TAG_SIGNATURE,
+            &self.signature_body(signature_type, data, &[]),
+        )
+    }
+
+    /// Makes body of signature packet with additional hashed `subpackets`
+    fn signature_body(
+        &self,
+        signature_type: u8,
+        data: &[u8],
+        subpackets: &[(u8, &[u8])],
+    ) -> Vec<u8> {
+        let created = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_secs());
+        let created = u32::try_from(created).unwrap_or(u32::MAX);
+
+        let mut hashed = vec![];
+        push_subpacket(&mut hashed, SUBPACKET_CREATION_TIME, &created.to_be_bytes());
+        let mut fingerprint = vec![4];
+        fingerprint.extend_from_slice(&self.public.fingerprint);
+        push_subpacket(&mut hashed, SUBPACKET_ISSUER_FINGERPRINT, &fingerprint);
+        for (kind, body) in subpackets {
+            push_subpacket(&mut hashed, *kind, body);
+        }
+
+        let mut unhashed = vec![];
+        push_subpacket(&mut unhashed, SUBPACKET_ISSUER, self.public.key_id_bytes());
+
+        // Subpackets here are tiny, so their length always fits
+        let mut body = vec![4, signature_type, self.public.algorithm, HASH_SHA256];
+        body.extend_from_slice(&packet_length_u16(hashed.len()).unwrap_or_default());
+        body.extend_from_slice(&hashed);
+
+        let digest = hashed_digest::<Sha256>(data, &body);
+        let signature = self.secret.sign(&digest).to_bytes();
+
+        body.extend_from_slice(&packet_length_u16(unhashed.len()).unwrap_or_default());
+        body.extend_from_slice(&unhashed);
+        body.extend_from_slice(&digest[..2]);
+
+        if self.public.algorithm == ALGORITHM_EDDSA_LEGACY {
+            push_mpi(&mut body, &signature[..32]);
+            push_mpi(&mut body, &signature[32..]);
+        } else {
+            body.extend_from_slice(&signature);
+        }
+
+        body
+    }
+}
+
+/// Verifies cleartext signed message, e.g. `InRelease`, and returns its signed text
+///
+/// # Errors
+/// Returns error if message is not signed, malformed or its signature is not valid
+pub fn verify_cleartext(message: &str, keyring: &[PublicKey]) -> Result<VerifiedMessage, Error> {
+    let mut lines = message.lines();
+    lines
+        .by_ref()
+        .find(|line| line.trim_end() == SIGNED_MESSAGE_HEADER)
+        .ok_or(Error::NotSigned)?;
+
+    // Signature must use one of hash algorithms declared in armor headers if there are any
+    let mut hashes = vec![];
+    for line in lines.by_ref() {
+        let line = line.trim();
+        if line.is_empty() {
+            break;
+        }
+
+        let Some(names) = line.strip_prefix("Hash:") else {
+            continue;
+        };
+        for name in names.split(',').map(str::trim) {
+            let (_, hash) = (HASH_NAMES.iter())
+                .find(|(known, _)| known.eq_ignore_ascii_case(name))
+                .ok_or(Error::InvalidArmor("unknown hash algorithm in Hash header"))?;
+            hashes.push(*hash);
+        }
+    }
+
+    let mut text = vec![];
+    let mut terminated = false;
+    for line in lines.by_ref() {
+        if line.trim_end() == SIGNATURE_HEADER {
+            terminated = true;
+            break;
+        }
+        text.push(line.strip_prefix("- ").unwrap_or(line));
+    }
+    if !terminated {
+        return Err(Error::NotSigned);
+    }
+
+    let mut signature = format!("{SIGNATURE_HEADER}\n");
+    for line in lines {
+        signature.push_str(line);
+        signature.push('\n');
+    }
+    let signature = dearmor(&signature)?;
+
+    let mut text = text.join("\n");
+    let signer = verify(
+        SIGNATURE_TEXT,
+        canonical_text(&text).as_bytes(),
+        &signature,
+        keyring,
+        &hashes,
+    )?;
+    text.push('\n');
+
+    Ok(VerifiedMessage {
+        text,
+        signer: signer.clone(),
+    })
+}
+
+/// Verifies armored or binary detached signature, e.g. `Release.gpg`
+/// and returns key that made it
+///
+/// # Errors
+/// Returns error if signature is malformed or not valid
+pub fn verify_detached<'k>(
+    data: &[u8],
+    signature: &[u8],
+    keyring: &'k [PublicKey],
+) -> Result<&'k PublicKey, Error> {
+    let signature = dearmor_if_needed(signature)?;
+    verify(SIGNATURE_BINARY, data, &signature, keyring, &[])
+}
+
+/// Verifies signature made by key from `keyring` with one of `hashes`, any hash if it's empty
+fn verify<'k>(
+    signature_type: u8,
+    data: &[u8],
+    signature: &[u8],
+    keyring: &'k [PublicKey],
+    hashes: &[u8],
+) -> Result<&'k PublicKey, Error> {
+    for packet in packets(signature)? {
+        if packet.tag != TAG_SIGNATURE {
+            continue;
+        }
+
+        let signature = Signature::parse(packet.body)?;
+        if let Some(key) = keyring.iter().find(|key| signature.is_issued_by(key)) {
+            if signature.kind != signature_type || signature.algorithm != key.algorithm {
+                return Err(Error::BadSignature);
+            }
+            if !hashes.is_empty() && !hashes.contains(&signature.hash) {
+                return Err(Error::HashMismatch);
+            }
+
+            signature.verify(key, data)?;
+            return Ok(key);
+        }
+    }
+
+    Err(Error::UnknownSigner)
+}
+
+/// Parsed v4 signature packet
+struct Signature<'a> {
+    kind: u8,
+    algorithm: u8,
+    hash: u8,
+    /// Hashed part of the packet
+    prefix: &'a [u8],
+    /// Key IDs and fingerprints of issuer
+    issuers: Vec<&'a [u8]>,
+    /// Creation time, zero if not specified
+    created: u32,
+    /// Seconds after creation signature expires in
+    expiration: Option<u32>,
+    /// Seconds after key creation signed key expires in
+    key_expiration: Option<u32>,
+    key_flags: Option<u8>,
+    /// Body of signature packet embedded in this one
+    embedded: Option<&'a [u8]>,
+    digest_start: &'a [u8],
+    value: [u8; 64],
+}
+
+impl<'a> Signature<'a> {
+    fn parse(body: &'a [u8]) -> Result<Self, Error> {
+        let mut reader = Reader::new(body);
+        if reader.u8()? != 4 {
+            return Err(Error::MalformedPacket("only v4 signatures are supported"));
+        }
+
+        let signature_type = reader.u8()?;
+        let algorithm = reader.u8()?;
+        let hash = reader.u8()?;
+
+        let hashed_length = reader.u16()?;
+        let hashed = reader.take(usize::from(hashed_length))?;
+        let prefix = &body[..body.len() - reader.data.len()];
+
+        let unhashed_length = reader.u16()?;
+        let unhashed = reader.take(usize::from(unhashed_length))?;
+
+        let (mut created, mut expiration, mut key_expiration, mut key_flags) =
+            (0, None, None, None);
+        for (kind, data) in subpackets(hashed)? {
+            let time = || Some(u32::from_be_bytes(data.try_into().ok()?));
+            match kind {
+                SUBPACKET_CREATION_TIME => created = time().unwrap_or_default(),
+                SUBPACKET_SIGNATURE_EXPIRATION_TIME => expiration = time(),
+                SUBPACKET_KEY_EXPIRATION_TIME => key_expiration = time(),
+                SUBPACKET_KEY_FLAGS => key_flags = data.first().copied(),
+                _ => {}
+            }
+        }
+
+        // Issuer and embedded signature are verified by themselves,
+        // so they are taken from unhashed area too
+        let mut issuers = vec![];
+        let mut embedded = None;
+        for (kind, data) in subpackets(hashed)?.into_iter().chain(subpackets(unhashed)?) {
+            match (kind, data) {
+                (SUBPACKET_ISSUER, _) => issuers.push(data),
+                (SUBPACKET_ISSUER_FINGERPRINT, [4, fingerprint @ ..]) => issuers.push(fingerprint),
+                (SUBPACKET_EMBEDDED_SIGNATURE, _) => embedded = Some(data),
+                _ => {}
+            }
+        }
+
+        let digest_start = reader.take(2)?;
+
+        let mut value = [0; 64];
+        match algorithm {
+            ALGORITHM_EDDSA_LEGACY => {
+                value[..32].copy_from_slice(&left_pad::<32>(reader.mpi()?)?);
+                value[32..].copy_from_slice(&left_pad::<32>(reader.mpi()?)?);
+            }
+            ALGORITHM_ED25519 => value.copy_from_slice(reader.take(64)?),
+            _ => return Err(Error::UnsupportedAlgorithm(algorithm)),
+        }
+
+        Ok(Self {
+            kind: signature_type,
+            algorithm,
+            hash,
+            prefix,
+            issuers,
+            created,
+            expiration,
+            key_expiration,
+            key_flags,
+            embedded,
+            digest_start,
+            value,
+        })
+    }
+
+    fn is_issued_by(&self, key: &PublicKey) -> bool {
+        (self.issuers.iter()).any(|&id| id == key.fingerprint || id == key.key_id_bytes())
+    }
+
+    fn is_expired(&self, now: u64) -> bool {
+        (self.expiration)
+            .filter(|&expiration| expiration != 0)
+            .is_some_and(|expiration| u64::from(self.created) + u64::from(expiration) <= now)
+    }
+
+    /// Checks that signature is made by `key` over `data`
+    fn is_valid(&self, key: &PublicKey, data: &[u8]) -> bool {
+        self.algorithm == key.algorithm && self.verify(key, data).is_ok()
+    }
+
+    /// Signing subkey must prove it agrees to be bound to primary key with
+    /// embedded primary key binding signature over the same `data`
+    fn is_cross_certified(&self, subkey: &PublicKey, data: &[u8]) -> bool {
+        if self.key_flags.unwrap_or_default() & KEY_FLAG_SIGN == 0 {
+            return true;
+        }
+
+        let Some(embedded) = self.embedded.and_then(|body| Signature::parse(body).ok()) else {
+            return false;
+        };
+        embedded.kind == SIGNATURE_PRIMARY_KEY_BINDING && embedded.is_valid(subkey, data)
+    }
+
+    fn verify(&self, key: &PublicKey, data: &[u8]) -> Result<(), Error> {
+        let digest = match self.hash {
+            HASH_SHA256 => hashed_digest::<Sha256>(data, self.prefix),
+            HASH_SHA512 => hashed_digest::<Sha512>(data, self.prefix),
+            hash => return Err(Error::UnsupportedHash(hash)),
+        };
+
+        if digest[..2] != *self.digest_start {
+            return Err(Error::BadSignature);
+        }
+
+        let signature = ed25519_dalek::Signature::from_bytes(&self.value);
+        key.key
+            .verify(&digest, &signature)
+            .map_err(|_| Error::BadSignature)
+    }
+}
+
+/// Single `OpenPGP` packet
+struct Packet<'a> {
+    tag: u8,
+    body: &'a [u8],
+}
+
+/// Cursor over packet data
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    fn take(&mut self, length: usize) -> Result<&'a [u8], Error> {
+        if self.data.len() < length {
+            return Err(Error::MalformedPacket("unexpected end of data"));
+        }
+
+        let (head, tail) = self.data.split_at(length);
+        self.data = tail;
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> Result<u8, Error> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, Error> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, Error> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn length_u32(&mut self) -> Result<usize, Error> {
+        usize::try_from(self.u32()?).map_err(|_| Error::MalformedPacket("packet is too long"))
+    }
+
+    fn mpi(&mut self) -> Result<&'a [u8], Error> {
+        let bits = usize::from(self.u16()?);
+        self.take((bits + 7) / 8)
+    }
+}
+
+fn packets(data: &[u8]) -> Result<Vec<Packet<'_>>, Error> {
+    let mut reader = Reader::new(data);
+    let mut packets = vec![];
+
+    while !reader.data.is_empty() {
+        let header = reader.u8()?;
+        if header & 0x80 == 0 {
+            return Err(Error::MalformedPacket("invalid packet header"));
+        }
+
+        let (tag, length) = if header & 0x40 == 0 {
+            let length = match header & 0x03 {
+                0 => usize::from(reader.u8()?),
+                1 => usize::from(reader.u16()?),
+                2 => reader.length_u32()?,
+                _ => reader.data.len(),
+            };
+            ((header >> 2) & 0x0F, length)
+        } else {
+            let first = usize::from(reader.u8()?);
+            let length = match first {
+                0..=191 => first,
+                192..=223 => ((first - 192) << 8) + usize::from(reader.u8()?) + 192,
+                255 => reader.length_u32()?,
+                _ => return Err(Error::MalformedPacket("partial lengths are not supported")),
+            };
+            (header & 0x3F, length)
+        };
+
+        packets.push(Packet {
+            tag,
+            body: reader.take(length)?,
+        });
+    }
+
+    Ok(packets)
+}
+
+fn subpackets(data: &[u8]) -> Result<Vec<(u8, &[u8])>, Error> {
+    let mut reader = Reader::new(data);
+    let mut subpackets = vec![];
+
+    while !reader.data.is_empty() {
+        let first = usize::from(reader.u8()?);
+        let length = match first {
+            0..=191 => first,
+            192..=254 => ((first - 192) << 8) + usize::from(reader.u8()?) + 192,
+            _ => reader.length_u32()?,
+        };
+
+        let body = reader.take(length)?;
+        let [kind, data @ ..] = body else {
+            return Err(Error::MalformedPacket("empty subpacket"));
+        };
+        // Highest bit marks critical subpackets
+        subpackets.push((kind & 0x7F, data));
+    }
+
+    Ok(subpackets)
+}
+
+fn write_packet(tag: u8, body: &[u8]) -> Vec<u8> {
+    let mut packet = vec![0xC0 | tag];
+
+    #[allow(clippy::cast_possible_truncation)]
+    match body.len() {
+        length @ 0..=191 => packet.push(length as u8),
+        length @ 192..=8383 => {
+            let length = length - 192;
+            packet.extend_from_slice(&[(length >> 8) as u8 + 192, length as u8]);
+        }
+        length => {
+            packet.push(0xFF);
+            packet.extend_from_slice(&(length as u32).to_be_bytes());
+        }
+    }
+
+    packet.extend_from_slice(body);
+    packet
+}
+
+fn push_subpacket(data: &mut Vec<u8>, kind: u8, body: &[u8]) {
+    // Only short subpackets are written, their length always fits one octet
+    #[allow(clippy::cast_possible_truncation)]
+    data.push(body.len() as u8 + 1);
+    data.push(kind);
+    data.extend_from_slice(body);
+}
+
+fn push_mpi(data: &mut Vec<u8>, value: &[u8]) {
+    let start = value
+        .iter()
+        .position(|&byte| byte != 0)
+        .unwrap_or(value.len());
+    let value = &value[start..];
+
+    let bits = value.first().map_or(0, |first| {
+        (value.len() - 1) * 8 + (8 - first.leading_zeros() as usize)
+    });
+    data.extend_from_slice(&packet_length_u16(bits).unwrap_or_default());
+    data.extend_from_slice(value);
+}
+
+fn packet_length_u16(length: usize) -> Result<[u8; 2], Error> {
+    u16::try_from(length)
+        .map(u16::to_be_bytes)
+        .map_err(|_| Error::MalformedPacket("packet is too long"))
+}
+
+fn left_pad<const N: usize>(value: &[u8]) -> Result<[u8; N], Error> {
+    if value.len() > N {
+        return Err(Error::InvalidKey);
+    }
+
+    let mut padded = [0; N];
+    padded[N - value.len()..].copy_from_slice(value);
+    Ok(padded)
+}
+
+fn hashed_digest<D: Digest>(data: &[u8], prefix: &[u8]) -> Vec<u8> {
+    let mut hasher = D::new();
+    hasher.update(data);
+    hasher.update(prefix);
+    hasher.update([4, 0xFF]);
+    hasher.update(
+        u32::try_from(prefix.len())
+            .unwrap_or(u32::MAX)
+            .to_be_bytes(),
+    );
+    hasher.finalize().to_vec()
+}
+
+/// Text signatures are made over lines without trailing whitespaces joined with CRLF
+fn canonical_text(text: &str) -> String {
+    let lines: Vec<_> = text
+        .lines()
+        .map(|line| line.trim_end_matches([' ', '\t']))
+        .collect();
+    lines.join("\r\n")
+}
+
+fn hex_upper(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut hex, byte| {
+        write!(hex, "{byte:02X}").ok();
+        hex
+    })
+}
+
+fn armor(kind: &str, data: &[u8]) -> String {
+    let encoded = STANDARD.encode(data);
+
+    let mut armored = format!("-----BEGIN PGP {kind}-----\n\n");
+    for line in encoded.as_bytes().chunks(64) {
+        armored.push_str(&String::from_utf8_lossy(line));
+        armored.push('\n');
+    }
+
+    let checksum = crc24(data).to_be_bytes();
+    armored.push('=');
+    armored.push_str(&STANDARD.encode(&checksum[1..]));
+    write!(armored, "\n-----END PGP {kind}-----\n").ok();
+
+    armored
+}
+
+fn dearmor_if_needed(data: &[u8]) -> Result<Vec<u8>, Error> {
+    let start = (data.iter())
+        .position(|byte| !byte.is_ascii_whitespace())
+        .unwrap_or(data.len());
+
+    if data[start..].starts_with(b"-----BEGIN PGP ") {
+        let text = std::str::from_utf8(data).map_err(|_| Error::InvalidArmor("not UTF-8"))?;
+        dearmor(text)
+    } else {
+        Ok(data.to_vec())
+    }
+}
+
+/// Decodes first armored block in the text
+fn dearmor(text: &str) -> Result<Vec<u8>, Error> {
+    let mut lines = text.lines().map(str::trim_end);
+    lines
+        .by_ref()
+        .find(|line| line.starts_with("-----BEGIN PGP ") && line.ends_with("-----"))
+        .ok_or(Error::InvalidArmor("missing header"))?;
+
+    // Skip armor headers
+    lines.by_ref().find(|line| line.is_empty());
+
+    let mut encoded = String::new();
+    let mut checksum = None;
+    let mut finished = false;
+    for line in lines {
+        if line.starts_with("-----END PGP ") {
+            finished = true;
+            break;
+        }
+
+        match line.strip_prefix('=') {
+            Some(line) => checksum = Some(line),
+            None => encoded.push_str(line),
+        }
+    }
+    if !finished {
+        return Err(Error::InvalidArmor("missing footer"));
+    }
+
+    let data = (STANDARD.decode(encoded)).map_err(|_| Error::InvalidArmor("invalid base64"))?;
+    if let Some(checksum) = checksum {
+        let checksum = (STANDARD.decode(checksum))
+            .map_err(|_| Error::InvalidArmor("invalid checksum encoding"))?;
+        if checksum[..] != crc24(&data).to_be_bytes()[1..] {
+            return Err(Error::InvalidArmor("checksum mismatch"));
+        }
+    }
+
+    Ok(data)
+}
+
+fn crc24(data: &[u8]) -> u32 {
+    let mut crc: u32 = 0x00B7_04CE;
+    for &byte in data {
+        crc ^= u32::from(byte) << 16;
+        for _ in 0..8 {
+            crc <<= 1;
+            if crc & 0x0100_0000 != 0 {
+                crc ^= 0x0186_4CFB;
+            }
+        }
+    }
+    crc & 0x00FF_FFFF
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        dearmor_if_needed, packets, push_mpi, verify_cleartext, verify_detached, write_packet,
+        Error, PublicKey, Reader, SigningKey, ALGORITHM_EDDSA_LEGACY, ED25519_OID, KEY_FLAG_SIGN,
+        SIGNATURE_KEY_REVOCATION, SIGNATURE_PRIMARY_KEY_BINDING, SIGNATURE_SUBKEY_BINDING,
+        SIGNATURE_SUBKEY_REVOCATION, SUBPACKET_EMBEDDED_SIGNATURE, SUBPACKET_KEY_EXPIRATION_TIME,
+        SUBPACKET_KEY_FLAGS, TAG_PUBLIC_SUBKEY, TAG_SIGNATURE,
+    };
+    use crate::testing::temp_dir;
+    use std::{fs, path::Path, process::Command, slice};
+
+    const SECRET_KEY: &[u8] = include_bytes!("../../assets/openpgp/secret.asc");
+    const PUBLIC_KEY: &[u8] = include_bytes!("../../assets/openpgp/public.asc");
+    const FINGERPRINT: &str = "23F0CEA245A070D96AE6106274666C555AAA6217";
+
+    #[test]
+    fn parse_keys() -> Result<(), Error> {
+        let secret = SigningKey::parse(SECRET_KEY)?;
+        assert_eq!(secret.public_key().fingerprint(), FINGERPRINT);
+        assert_eq!(secret.public_key().key_id(), "74666C555AAA6217");
+
+        let keyring = PublicKey::parse_keyring(PUBLIC_KEY)?;
+        assert_eq!(keyring, vec![secret.public_key().clone()]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn cleartext_roundtrip() -> Result<(), Error> {
+        let key = SigningKey::parse(SECRET_KEY)?;
+        let keyring = PublicKey::parse_keyring(PUBLIC_KEY)?;
+
+        let text = "Origin: Twackup\n-dashed line\ntrailing spaces  \n";
+        let message = key.sign_cleartext(text);
+        assert!(message.contains("\n- -dashed line\n"));
+
+        let verified = verify_cleartext(&message, &keyring)?;
+        assert_eq!(verified.text, text);
+        assert_eq!(verified.signer.fingerprint(), FINGERPRINT);
+
+        let tampered = message.replace("Origin: Twackup", "Origin: Evil");
+        assert!(matches!(
+            verify_cleartext(&tampered, &keyring),
+            Err(Error::BadSignature)
+        ));
+
+        let tampered = message.replace("Hash: SHA256", "Hash: SHA512");
+        assert!(matches!(
+            verify_cleartext(&tampered, &keyring),
+            Err(Error::HashMismatch)
+        ));
+
+        Ok(())
+    }
+
+    /// Makes legacy `EdDSA` key from `seed` the same way gpg stores them
+    fn legacy_key(seed: u8) -> SigningKey {
+        let secret = ed25519_dalek::SigningKey::from_bytes(&[seed; 32]);
+        let mut body = vec![4, 0x6A, 0xD5, 0xC8, 0x38, ALGORITHM_EDDSA_LEGACY, 9];
+        body.extend_from_slice(ED25519_OID);
+        push_mpi(
+            &mut body,
+            &[&[0x40], secret.verifying_key().as_bytes().as_slice()].concat(),
+        );
+
+        let public = PublicKey::parse(&mut Reader::new(&body)).unwrap();
+        SigningKey { public, secret }
+    }
+
+    /// Test public key followed by `packets`
+    fn keyring(packets: &[&[u8]]) -> Vec<u8> {
+        let mut keyring = dearmor_if_needed(PUBLIC_KEY).unwrap();
+        for packet in packets {
+            keyring.extend_from_slice(packet);
+        }
+        keyring
+    }
+
+    #[test]
+    fn subkeys() -> crate::Result<()> {
+        let directory = temp_dir("twackup-openpgp-subkeys")?;
+        let primary = SigningKey::parse(SECRET_KEY)?;
+        let subkey = legacy_key(7);
+        let message = subkey.sign_cleartext("Origin: Twackup\n");
+
+        let subkey_packet = write_packet(TAG_PUBLIC_SUBKEY, &subkey.public.body);
+        let data = [primary.public.hashed(), subkey.public.hashed()].concat();
+        let back_signature = subkey.signature_body(SIGNATURE_PRIMARY_KEY_BINDING, &data, &[]);
+        let sign = [KEY_FLAG_SIGN];
+        let binding = |subpackets: &[(u8, &[u8])]| {
+            let body = primary.signature_body(SIGNATURE_SUBKEY_BINDING, &data, subpackets);
+            write_packet(TAG_SIGNATURE, &body)
+        };
+
+        let bound = binding(&[
+            (SUBPACKET_KEY_FLAGS, &sign),
+            (SUBPACKET_EMBEDDED_SIGNATURE, &back_signature),
+        ]);
+        let bound_keyring = keyring(&[&subkey_packet, &bound]);
+        let keys = PublicKey::parse_keyring(&bound_keyring)?;
+        assert_eq!(keys, [primary.public.clone(), subkey.public.clone()]);
+        assert_eq!(verify_cleartext(&message, &keys)?.signer, subkey.public);
+
+        // Anyone can append subkey without binding signature to keyring
+        let keys = PublicKey::parse_keyring(&keyring(&[&subkey_packet]))?;
+        assert_eq!(keys, slice::from_ref(&primary.public));
+        assert!(matches!(
+            verify_cleartext(&message, &keys),
+            Err(Error::UnknownSigner)
+        ));
+
+        let expiration = 1_u32.to_be_bytes();
+        let unusable: [&[(u8, &[u8])]; 3] = [
+            // Not cross-certified by subkey
+            &[(SUBPACKET_KEY_FLAGS, &sign)],
+            // Not allowed to sign
+            &[
+                (SUBPACKET_KEY_FLAGS, &[0x0C]),
+                (SUBPACKET_EMBEDDED_SIGNATURE, &back_signature),
+            ],
+            // Expired a second after creation
+            &[
+                (SUBPACKET_KEY_FLAGS, &sign),
+                (SUBPACKET_EMBEDDED_SIGNATURE, &back_signature),
+                (SUBPACKET_KEY_EXPIRATION_TIME, &expiration),
+            ],
+        ];
+        for subpackets in unusable {
+            let keyring = keyring(&[&subkey_packet, &binding(subpackets)]);
+            assert_eq!(
+                PublicKey::parse_keyring(&keyring)?,
+                slice::from_ref(&primary.public)
+            );
+        }
+
+        let body = primary.signature_body(SIGNATURE_SUBKEY_REVOCATION, &data, &[]);
+        let revocation = write_packet(TAG_SIGNATURE, &body);
+        let keyring = keyring(&[&subkey_packet, &bound, &revocation]);
+        assert_eq!(
+            PublicKey::parse_keyring(&keyring)?,
+            slice::from_ref(&primary.public)
+        );
+
+        // Revoked primary key revokes its subkeys too
+        let public = dearmor_if_needed(PUBLIC_KEY)?;
+        let mut packets: Vec<_> = (packets(&public)?.iter())
+            .map(|packet| write_packet(packet.tag, packet.body))
+            .collect();
+        let body = primary.signature_body(SIGNATURE_KEY_REVOCATION, &primary.public.hashed(), &[]);
+        packets.insert(1, write_packet(TAG_SIGNATURE, &body));
+        packets.extend([subkey_packet, bound]);
+        assert!(matches!(
+            PublicKey::parse_keyring(&packets.concat()),
+            Err(Error::NoKey)
+        ));
+
+        // gpg agrees that subkey is bound properly
+        let in_release = directory.join("InRelease");
+        fs::write(&in_release, &message)?;
+        if let Some(valid) = gpgv(&directory, &bound_keyring, &[&in_release]) {
+            assert!(valid);
+        }
+
+        fs::remove_dir_all(directory)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn detached_roundtrip() -> Result<(), Error> {
+        let key = SigningKey::parse(SECRET_KEY)?;
+        let keyring = PublicKey::parse_keyring(PUBLIC_KEY)?;
+
+        let signature = key.sign_detached(b"Release");
+        assert_eq!(
+            verify_detached(b"Release", signature.as_bytes(), &keyring)?.fingerprint(),
+            FINGERPRINT
+        );
+        assert!(matches!(
+            verify_detached(b"Releases", signature.as_bytes(), &keyring),
+            Err(Error::BadSignature)
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn unsigned_message() {
+        let keyring = PublicKey::parse_keyring(PUBLIC_KEY).unwrap();
+        assert!(matches!(
+            verify_cleartext("Origin: Twackup\n", &keyring),
+            Err(Error::NotSigned)
+        ));
+    }
+
+    /// Runs gpgv with binary `keyring`. Returns None if gpgv isn't installed
+    fn gpgv(directory: &Path, keyring: &[u8], args: &[&Path]) -> Option<bool> {
+        let keyring_path = directory.join("keyring.gpg");
+        fs::write(&keyring_path, keyring).unwrap();
+        let keyring = keyring_path;
+
+        let output = Command::new("gpgv")
+            .arg("--homedir")
+            .arg(directory)
+            .arg("--keyring")
+            .arg(&keyring)
+            .args(args)
+            .output()
+            .ok()?;
+        Some(output.status.success())
+    }
+
+    #[test]
+    fn gpgv_interop() -> crate::Result<()> {
+        let directory = temp_dir("twackup-openpgp-gpgv")?;
+        let key = SigningKey::parse(SECRET_KEY)?;
+        let public = dearmor_if_needed(PUBLIC_KEY)?;
+
+        let release = "Origin: Twackup\n-dashed line\nDate: Mon, 19 Oct 2026 00:00:00 UTC\n";
+        let in_release = directory.join("InRelease");
+        fs::write(&in_release, key.sign_cleartext(release))?;
+
+        // gpgv isn't installed, nothing to check against
+        let Some(valid) = gpgv(&directory, &public, &[&in_release]) else {
+            return Ok(());
+        };
+        assert!(valid);
+
+        let data = directory.join("Release");
+        let signature = directory.join("Release.gpg");
+        fs::write(&data, release)?;
+        fs::write(&signature, key.sign_detached(release.as_bytes()))?;
+        assert_eq!(gpgv(&directory, &public, &[&signature, &data]), Some(true));
+
+        fs::write(&data, release.replace("Twackup", "Evil"))?;
+        assert_eq!(gpgv(&directory, &public, &[&signature, &data]), Some(false));
+
+        fs::remove_dir_all(directory)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,465 @@
+/*
+ * Copyright 2020 DanP
+ *
+ * This file is part of Twackup
+ *
+ * Twackup is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Twackup is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Twackup. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use crate::{builder::CancellationToken, error::Result};
+use std::{
+    fmt::Write as _,
+    io::{self, SeekFrom},
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tokio::{
+    fs::File,
+    io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+    time::timeout,
+};
+
+/// Requests with bigger headers are rejected
+const MAX_HEADER_SIZE: usize = 8 * 1024;
+
+/// Client must send request headers during this time
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Connection is closed if client doesn't receive any data during this time
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Static HTTP server sharing repository directory,
+/// so other devices in the network can install debs from it.
+///
+/// Only repository files are served: debs, `Packages` with its compressed variants,
+/// `Release` and its signatures. Everything else, e.g. build fingerprints
+/// or all-packages archives, is reported as missing
+#[derive(Debug)]
+pub struct Server {
+    root: PathBuf,
+    listener: TcpListener,
+}
+
+/// Parsed HTTP request
+struct Request {
+    method: String,
+    path: String,
+    range: Option<String>,
+}
+
+/// Byte range of the file requested by client
+#[derive(Debug, PartialEq, Eq)]
+enum Range {
+    /// Whole file is requested
+    Full,
+    /// Inclusive range of bytes
+    Partial(u64, u64),
+    /// Range is out of file bounds
+    Unsatisfiable,
+}
+
+impl Server {
+    /// Starts listening on the address. Port `0` binds to any free port.
+    ///
+    /// # Errors
+    /// Returns error if root doesn't exist or address couldn't be bound
+    pub async fn bind<P: AsRef<Path>, A: ToSocketAddrs>(root: P, address: A) -> Result<Self> {
+        Ok(Self {
+            root: tokio::fs::canonicalize(root).await?,
+            listener: TcpListener::bind(address).await?,
+        })
+    }
+
+    /// Address server is listening on
+    ///
+    /// # Errors
+    /// Returns error if address couldn't be received from the socket
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// Serves files until token is cancelled.
+    /// Every connection is handled in separate task
+    ///
+    /// # Errors
+    /// Returns error if accepting connections failed
+    pub async fn run(self, cancellation: CancellationToken) -> Result<()> {
+        loop {
+            let (stream, peer) = tokio::select! {
+                () = cancellation.cancelled() => return Ok(()),
+                accepted = self.listener.accept() => accepted?,
+            };
+
+            let root = self.root.clone();
+            tokio::spawn(async move {
+                if let Err(error) = handle(stream, &root).await {
+                    log::debug!("Connection with {peer} failed: {error}");
+                }
+            });
+        }
+    }
+}
+
+async fn handle(mut stream: TcpStream, root: &Path) -> Result<()> {
+    let peer = stream.peer_addr()?;
+
+    let request = match timeout(READ_TIMEOUT, read_request(&mut stream)).await {
+        Ok(request) => request?,
+        Err(_) => return respond_error(&mut stream, 408, "Request Timeout").await,
+    };
+    let Some(request) = request else {
+        return respond_error(&mut stream, 400, "Bad Request").await;
+    };
+
+    let status = serve(&mut stream, root, &request).await?;
+    log::info!("{peer} {} {} {status}", request.method, request.path);
+
+    stream.shutdown().await?;
+    Ok(())
+}
+
+async fn serve(stream: &mut TcpStream, root: &Path, request: &Request) -> Result<u16> {
+    let head = request.method == "HEAD";
+    if !head && request.method != "GET" {
+        respond_error(stream, 405, "Method Not Allowed").await?;
+        return Ok(405);
+    }
+
+    let Some(path) = resolve(root, &request.path).await else {
+        respond_error(stream, 404, "Not Found").await?;
+        return Ok(404);
+    };
+
+    let opened = async {
+        let file = File::open(&path).await?;
+        let length = file.metadata().await?.len();
+        io::Result::Ok((file, length))
+    };
+    let (mut file, length) = match opened.await {
+        Ok(opened) => opened,
+        Err(error) => {
+            log::warn!("Failed to open {}: {error}", path.display());
+            let (status, reason) = match error.kind() {
+                io::ErrorKind::PermissionDenied => (403, "Forbidden"),
+                _ => (500, "Internal Server Error"),
+            };
+            respond_error(stream, status, reason).await?;
+            return Ok(status);
+        }
+    };
+
+    let mut headers = format!(
+        "Content-Type: {}\r\nAccept-Ranges: bytes\r\n",
+        content_type(&path)
+    );
+
+    let (status, start, end) = match parse_range(request.range.as_deref(), length) {
+        Range::Full => (200, 0, length.saturating_sub(1)),
+        Range::Partial(start, end) => {
+            write!(headers, "Content-Range: bytes {start}-{end}/{length}\r\n").ok();
+            (206, start, end)
+        }
+        Range::Unsatisfiable => {
+            write!(headers, "Content-Range: bytes */{length}\r\n").ok();
+            write_head(stream, 416, "Range Not Satisfiable", &headers, 0).await?;
+            return Ok(416);
+        }
+    };
+
+    if let Err(error) = file.seek(SeekFrom::Start(start)).await {
+        log::warn!("Failed to read {}: {error}", path.display());
+        respond_error(stream, 500, "Internal Server Error").await?;
+        return Ok(500);
+    }
+
+    let content_length = if length == 0 { 0 } else { end - start + 1 };
+    let reason = if status == 206 {
+        "Partial Content"
+    } else {
+        "OK"
+    };
+    write_head(stream, status, reason, &headers, content_length).await?;
+
+    if !head {
+        // Status is already sent, so client sees failure only as short body
+        send_body(file.take(content_length), stream).await?;
+    }
+
+    Ok(status)
+}
+
+/// Copies body to client closing connection if it stops receiving data
+async fn send_body<R: AsyncReadExt + Unpin>(mut body: R, stream: &mut TcpStream) -> Result<()> {
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        let read = body.read(&mut buffer).await?;
+        if read == 0 {
+            return Ok(());
+        }
+
+        timeout(IDLE_TIMEOUT, stream.write_all(&buffer[..read]))
+            .await
+            .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))??;
+    }
+}
+
+async fn read_request(stream: &mut TcpStream) -> Result<Option<Request>> {
+    let mut buffer = Vec::with_capacity(1024);
+    let mut chunk = [0; 1024];
+
+    while !buffer.windows(4).any(|window| window == b"\r\n\r\n") {
+        if buffer.len() > MAX_HEADER_SIZE {
+            return Ok(None);
+        }
+
+        let read = stream.read(&mut chunk).await?;
+        if read == 0 {
+            return Ok(None);
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+    }
+
+    let Ok(head) = std::str::from_utf8(&buffer) else {
+        return Ok(None);
+    };
+    let mut lines = head.split("\r\n");
+
+    let mut request_line = lines.next().unwrap_or_default().split(' ');
+    let (Some(method), Some(path)) = (request_line.next(), request_line.next()) else {
+        return Ok(None);
+    };
+
+    let range = lines
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.eq_ignore_ascii_case("range"))
+        .map(|(_, value)| value.trim().to_string());
+
+    Ok(Some(Request {
+        method: method.to_string(),
+        path: path.to_string(),
+        range,
+    }))
+}
+
+async fn write_head(
+    stream: &mut TcpStream,
+    status: u16,
+    reason: &str,
+    headers: &str,
+    content_length: u64,
+) -> Result<()> {
+    let head = format!(
+        "HTTP/1.1 {status} {reason}\r\n{headers}Content-Length: {content_length}\r\n\
+         Connection: close\r\nServer: twackup/{}\r\n\r\n",
+        env!("CARGO_PKG_VERSION")
+    );
+    timeout(IDLE_TIMEOUT, stream.write_all(head.as_bytes()))
+        .await
+        .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))??;
+    Ok(())
+}
+
+async fn respond_error(stream: &mut TcpStream, status: u16, reason: &str) -> Result<()> {
+    let headers = "Content-Type: text/plain; charset=utf-8\r\n";
+    write_head(stream, status, reason, headers, reason.len() as u64).await?;
+    timeout(IDLE_TIMEOUT, stream.write_all(reason.as_bytes()))
+        .await
+        .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))??;
+    Ok(())
+}
+
+/// Maps request path to file inside root.
+/// Returns `None` for directories, missing or not repository files and paths escaping root
+async fn resolve(root: &Path, request_path: &str) -> Option<PathBuf> {
+    let path = request_path.split(['?', '#']).next()?;
+    let path = percent_decode(path)?;
+
+    let mut resolved = root.to_path_buf();
+    for component in path.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => return None,
+            component => resolved.push(component),
+        }
+    }
+
+    let name = resolved.file_name()?.to_str()?;
+    if !is_repository_file(name) {
+        return None;
+    }
+
+    // Symlinks may point outside of the root
+    let resolved = tokio::fs::canonicalize(resolved).await.ok()?;
+    let metadata = tokio::fs::metadata(&resolved).await.ok()?;
+
+    (resolved.starts_with(root) && metadata.is_file()).then_some(resolved)
+}
+
+fn percent_decode(path: &str) -> Option<String> {
+    let mut bytes = path.bytes();
+    let mut decoded = Vec::with_capacity(path.len());
+
+    while let Some(byte) = bytes.next() {
+        if byte == b'%' {
+            let high = char::from(bytes.next()?).to_digit(16)?;
+            let low = char::from(bytes.next()?).to_digit(16)?;
+            decoded.push(u8::try_from(high << 4 | low).ok()?);
+        } else {
+            decoded.push(byte);
+        }
+    }
+
+    String::from_utf8(decoded).ok()
+}
+
+/// Parses single `bytes=` range. Multiple ranges are not supported,
+/// so whole file is served for them as allowed by RFC 9110
+fn parse_range(header: Option<&str>, length: u64) -> Range {
+    let Some(ranges) = header.and_then(|header| header.strip_prefix("bytes=")) else {
+        return Range::Full;
+    };
+    let Some((start, end)) = ranges.trim().split_once('-') else {
+        return Range::Full;
+    };
+    if ranges.contains(',') {
+        return Range::Full;
+    }
+
+    let range = match (start.parse::<u64>(), end.parse::<u64>()) {
+        // bytes=-500 means last 500 bytes
+        (Err(_), Ok(suffix)) if start.is_empty() && suffix > 0 => {
+            Some((length.saturating_sub(suffix), length.saturating_sub(1)))
+        }
+        (Ok(start), Err(_)) if end.is_empty() => Some((start, length.saturating_sub(1))),
+        (Ok(start), Ok(end)) if start <= end => Some((start, end.min(length.saturating_sub(1)))),
+        _ => return Range::Full,
+    };
+
+    match range {
+        Some((start, end)) if start < length && start <= end => Range::Partial(start, end),
+        _ => Range::Unsatisfiable,
+    }
+}
+
+/// Checks that file is a part of APT repository
+fn is_repository_file(name: &str) -> bool {
+    let is_packages = name == "Packages" || name.starts_with("Packages.");
+    let is_release = matches!(name, "Release" | "Release.gpg" | "InRelease");
+    let is_deb = !name.starts_with('.')
+        && Path::new(name)
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("deb"));
+    is_packages || is_release || is_deb
+}
+
+fn content_type(path: &Path) -> &'static str {
+    let name = path.file_name().and_then(|name| name.to_str());
+    if let Some("Packages" | "Release" | "InRelease") = name {
+        return "text/plain; charset=utf-8";
+    }
+
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some("deb") => "application/vnd.debian.binary-package",
+        Some("gpg") => "application/pgp-signature",
+        Some("gz") => "application/gzip",
+        Some("xz") => "application/x-xz",
+        Some("zst") => "application/zstd",
+        Some("bz2") => "application/x-bzip2",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_range, Range, Server};
+    use crate::{builder::CancellationToken, testing::temp_dir, Result};
+    use std::fs;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpStream,
+    };
+
+    async fn request(address: std::net::SocketAddr, request: &str) -> Result<String> {
+        let mut stream = TcpStream::connect(address).await?;
+        stream.write_all(request.as_bytes()).await?;
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await?;
+        Ok(response)
+    }
+
+    #[test]
+    fn ranges() {
+        assert_eq!(parse_range(None, 10), Range::Full);
+        assert_eq!(parse_range(Some("bytes=2-4"), 10), Range::Partial(2, 4));
+        assert_eq!(parse_range(Some("bytes=5-"), 10), Range::Partial(5, 9));
+        assert_eq!(parse_range(Some("bytes=-3"), 10), Range::Partial(7, 9));
+        assert_eq!(parse_range(Some("bytes=8-100"), 10), Range::Partial(8, 9));
+        assert_eq!(parse_range(Some("bytes=10-"), 10), Range::Unsatisfiable);
+        assert_eq!(parse_range(Some("bytes=0-1,4-5"), 10), Range::Full);
+    }
+
+    #[tokio::test]
+    async fn serve_files() -> Result<()> {
+        let root = temp_dir("twackup-repo-server")?;
+        fs::create_dir_all(root.join("debs"))?;
+        fs::write(root.join("Packages"), "Package: hosts\n")?;
+        fs::write(root.join("debs/hosts 1.0.deb"), "0123456789")?;
+
+        let server = Server::bind(&root, "127.0.0.1:0").await?;
+        let address = server.local_addr()?;
+        let cancellation = CancellationToken::new();
+        let task = tokio::spawn(server.run(cancellation.clone()));
+
+        let response = request(address, "GET /Packages HTTP/1.1\r\nHost: x\r\n\r\n").await?;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("Content-Type: text/plain; charset=utf-8\r\n"));
+        assert!(response.ends_with("\r\n\r\nPackage: hosts\n"));
+
+        let get = "GET /debs/hosts%201.0.deb HTTP/1.1\r\nRange: bytes=2-4\r\n\r\n";
+        let response = request(address, get).await?;
+        assert!(response.starts_with("HTTP/1.1 206 Partial Content\r\n"));
+        assert!(response.contains("Content-Range: bytes 2-4/10\r\n"));
+        assert!(response.contains("Content-Type: application/vnd.debian.binary-package\r\n"));
+        assert!(response.ends_with("\r\n\r\n234"));
+
+        let head = "HEAD /debs/hosts%201.0.deb HTTP/1.1\r\n\r\n";
+        let response = request(address, head).await?;
+        assert!(response.contains("Content-Length: 10\r\n"));
+        assert!(response.ends_with("\r\n\r\n"));
+
+        let response = request(address, "GET /../etc/passwd HTTP/1.1\r\n\r\n").await?;
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+
+        let response = request(address, "GET /debs HTTP/1.1\r\n\r\n").await?;
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+
+        for name in [".twackup-fingerprints", "host_2026.tar.gz"] {
+            fs::write(root.join(name), "private")?;
+            let response = request(address, &format!("GET /{name} HTTP/1.1\r\n\r\n")).await?;
+            assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        }
+
+        let response = request(address, "POST /Packages HTTP/1.1\r\n\r\n").await?;
+        assert!(response.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"));
+
+        cancellation.cancel();
+        task.await??;
+        fs::remove_dir_all(root)?;
+
+        Ok(())
+    }
+}
@@ -20,7 +20,10 @@
 //! Package module represents some repository info
 //! that was parsed from dpkg database
 
-use crate::Parsable;
+use crate::{
+    repo::openpgp::{self, PublicKey},
+    Parsable,
+};
 use std::{collections::HashMap, string::ToString};
 use twackup_derive::StrEnumWithDefault;
 
@@ -157,6 +160,38 @@ impl Repository {
             self.components.join(" ").trim_end()
         )
     }
+
+    /// Returns URL of cleartext signed `InRelease` file of the repository.
+    /// Flat repositories keep it next to `Packages`, others - in `dists` directory
+    #[must_use]
+    pub fn in_release_url(&self) -> String {
+        let url = self.url.trim_end_matches('/');
+        match self.distribution.as_str() {
+            "./" | "/" => format!("{url}/InRelease"),
+            flat if flat.ends_with('/') => format!("{url}/{flat}InRelease"),
+            distribution => format!("{url}/dists/{distribution}/InRelease"),
+        }
+    }
+
+    /// Verifies contents of `InRelease` file downloaded from [`Self::in_release_url`]
+    /// against trusted keys and returns signed `Release` text
+    ///
+    /// # Errors
+    /// Returns error if file is not signed by any of the keys or signature is invalid
+    pub fn verify_in_release(
+        &self,
+        in_release: &str,
+        keyring: &[PublicKey],
+    ) -> Result<String, openpgp::Error> {
+        let message = openpgp::verify_cleartext(in_release, keyring)?;
+        log::debug!(
+            "InRelease of {} is signed by {}",
+            self.url,
+            message.signer.fingerprint()
+        );
+
+        Ok(message.text)
+    }
 }
 
 #[cfg(test)]
@@ -189,12 +224,25 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn in_release_url() {
+        let flat = Repository::from_one_line("deb https://repo.example.com/ ./").unwrap();
+        assert_eq!(flat.in_release_url(), "https://repo.example.com/InRelease");
+
+        let line = "deb https://apt.example.com stable main";
+        let regular = Repository::from_one_line(line).unwrap();
+        assert_eq!(
+            regular.in_release_url(),
+            "https://apt.example.com/dists/stable/InRelease"
+        );
+    }
+
     #[test]
     fn classic_repository() -> Result<()> {
         let database = concat!(env!("CARGO_MANIFEST_DIR"), "/assets/sources_db/classic");
         let reader = BufReader::new(File::open(database)?);
 
-        let lines = reader.lines().flatten();
+        let lines = reader.lines().map_while(std::result::Result::ok);
         let repositories: HashMap<String, Repository> = lines
             .map(|line| {
                 Repository::from_one_line(line.as_str())
@@ -0,0 +1,212 @@
+/*
+ * Copyright 2020 DanP
+ *
+ * This file is part of Twackup
+ *
+ * Twackup is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Twackup is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Twackup. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Helpers for installing debs back from the all-packages archive.
+//!
+//! ### Example usage
+//!
+//! ```no_run
+//! use twackup::{deb::Deb, restore, Result};
+//!
+//! #[tokio::main]
+//! async fn main() -> Result<()> {
+//!     let debs = restore::unpack_debs("backup.tar.gz", "/tmp/restore", None).await?;
+//!
+//!     let mut packages = vec![];
+//!     for path in debs {
+//!         packages.push((Deb::open(&path)?.package().await?, path));
+//!     }
+//!
+//!     for (package, path) in restore::install_order(packages) {
+//!         println!("dpkg -i {path:?} # {}", package.id);
+//!     }
+//!
+//!     Ok(())
+//! }
+//! ```
+
+use crate::{archiver, error::Result, package::Package};
+use std::{
+    borrow::Borrow,
+    collections::{BTreeSet, HashMap},
+    fs,
+    path::{Path, PathBuf},
+};
+use tokio_stream::StreamExt;
+
+/// Extracts every deb from the all-packages archive to `destination`.
+/// Compression of archive is detected automatically, encrypted archive
+/// is decrypted with `passphrase`.
+///
+/// # Errors
+/// Returns error if archive couldn't be read or decrypted or debs couldn't be written
+#[inline]
+pub async fn unpack_debs<A: AsRef<Path>, D: AsRef<Path>>(
+    archive: A,
+    destination: D,
+    passphrase: Option<&str>,
+) -> Result<Vec<PathBuf>> {
+    unpack(archive, destination, passphrase, &[]).await
+}
+
+/// Same as [`unpack_debs`], but also extracts files with names listed in `extra`
+/// from the root of archive, e.g. manifest. Archive is read only once.
+/// Returns paths of all extracted files in archive order
+///
+/// # Errors
+/// Returns error if archive couldn't be read or decrypted or files couldn't be written
+#[inline]
+pub async fn unpack<A: AsRef<Path>, D: AsRef<Path>>(
+    archive: A,
+    destination: D,
+    passphrase: Option<&str>,
+    extra: &[&str],
+) -> Result<Vec<PathBuf>> {
+    let destination = destination.as_ref();
+    fs::create_dir_all(destination)?;
+
+    let mut archive = tokio_tar::Archive::new(archiver::open_detected_with(archive, passphrase)?);
+    let mut entries = archive.entries()?;
+
+    let mut files = vec![];
+    while let Some(entry) = entries.next().await {
+        let mut entry = entry?;
+
+        let path = entry.path()?;
+        let is_deb = path.extension().is_some_and(|ext| ext == "deb");
+        let is_extra = extra.iter().any(|name| path == Path::new(name));
+        let Some(file_name) = path.file_name().filter(|_| is_deb || is_extra) else {
+            continue;
+        };
+
+        // Only file name is used so entries can't escape destination
+        let file_path = destination.join(file_name);
+        entry.unpack(&file_path).await?;
+        files.push(file_path);
+    }
+
+    Ok(files)
+}
+
+/// Sorts packages so every package goes after packages it depends on.
+///
+/// Dependencies that are not in the list are ignored. Otherwise original order is preserved.
+/// Dependency cycles are broken in favour of original order.
+#[inline]
+pub fn install_order<T, P>(items: Vec<(P, T)>) -> Vec<(P, T)>
+where
+    P: Borrow<Package>,
+{
+    let positions: HashMap<&str, usize> = items
+        .iter()
+        .enumerate()
+        .map(|(index, (package, _))| (package.borrow().id.as_str(), index))
+        .collect();
+
+    let dependencies: Vec<BTreeSet<usize>> = items
+        .iter()
+        .enumerate()
+        .map(|(index, (package, _))| {
+            (package.borrow().dependencies())
+                .filter_map(|dependency| positions.get(dependency).copied())
+                .filter(|position| *position != index)
+                .collect()
+        })
+        .collect();
+
+    let mut order = Vec::with_capacity(items.len());
+    let mut state = vec![Visit::New; items.len()];
+    for index in 0..items.len() {
+        visit(index, &dependencies, &mut state, &mut order);
+    }
+
+    let mut items: Vec<_> = items.into_iter().map(Some).collect();
+    order
+        .into_iter()
+        .filter_map(|index| items[index].take())
+        .collect()
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Visit {
+    New,
+    InProgress,
+    Done,
+}
+
+fn visit(
+    index: usize,
+    dependencies: &[BTreeSet<usize>],
+    state: &mut [Visit],
+    order: &mut Vec<usize>,
+) {
+    // Package being visited is a part of dependency cycle, so just skip it here
+    if state[index] != Visit::New {
+        return;
+    }
+
+    state[index] = Visit::InProgress;
+    for &dependency in &dependencies[index] {
+        visit(dependency, dependencies, state, order);
+    }
+    state[index] = Visit::Done;
+
+    order.push(index);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::install_order;
+    use crate::{package::Package, parser::parse_fields, Parsable};
+
+    fn package(id: &str, depends: &str) -> Package {
+        let control = format!(
+            "Package: {id}\nVersion: 1.0\nSection: misc\n\
+             Status: install ok installed\nDepends: {depends}\n"
+        );
+        Package::new(parse_fields(control.as_bytes())).unwrap()
+    }
+
+    #[test]
+    fn dependencies_first() {
+        let packages = vec![
+            (package("app", "libfoo (>= 1.0), libbar | libbaz"), ()),
+            (package("libbar", "libfoo"), ()),
+            (package("libfoo", "libc"), ()),
+            (package("unrelated", ""), ()),
+        ];
+
+        let order: Vec<_> = install_order(packages)
+            .into_iter()
+            .map(|(package, ())| package.id)
+            .collect();
+        assert_eq!(order, ["libfoo", "libbar", "app", "unrelated"]);
+    }
+
+    #[test]
+    fn cycle() {
+        let packages = vec![(package("a", "b"), ()), (package("b", "a"), ())];
+
+        let order: Vec<_> = install_order(packages)
+            .into_iter()
+            .map(|(package, ())| package.id)
+            .collect();
+        assert_eq!(order, ["b", "a"]);
+    }
+}
@@ -0,0 +1,47 @@
+/*
+ * Copyright 2020 DanP
+ *
+ * This file is part of Twackup
+ *
+ * Twackup is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Twackup is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Twackup. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Fixtures and helpers shared by unit tests
+
+use crate::progress::{Event, Progress};
+use std::{env, fs, io, path::PathBuf};
+
+/// Dpkg database containing single `hosts` package
+pub(crate) const DPKG_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/assets/dpkg_database_dir");
+
+/// Progress ignoring all events
+#[derive(Clone, Copy)]
+pub(crate) struct NoProgress;
+
+impl Progress for NoProgress {
+    fn on_event(&self, _event: Event<'_>) {}
+}
+
+/// Creates empty directory with `name` in system temp directory.
+/// Leftovers of previous runs are removed
+pub(crate) fn temp_dir(name: &str) -> io::Result<PathBuf> {
+    let path = env::temp_dir().join(name);
+    match fs::remove_dir_all(&path) {
+        Err(error) if error.kind() != io::ErrorKind::NotFound => return Err(error),
+        _ => {}
+    }
+
+    fs::create_dir_all(&path)?;
+    Ok(path)
+}
@@ -0,0 +1,236 @@
+/*
+ * Copyright 2020 DanP
+ *
+ * This file is part of Twackup
+ *
+ * Twackup is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Twackup is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Twackup. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Uploads finished backups to remote storage.
+//!
+//! Targets are described in a config file, usually TOML:
+//!
+//! ```toml
+//! [[target]]
+//! type = "s3"
+//! name = "minio"
+//! endpoint = "http://127.0.0.1:9000"
+//! path_style = true
+//! bucket = "backups"
+//! prefix = "iphone/"
+//! access_key = "minioadmin"
+//! secret_key = "minioadmin"
+//!
+//! [[target]]
+//! type = "webdav"
+//! url = "https://cloud.example.com/remote.php/dav/files/user/backups/"
+//! username = "user"
+//! password = "password"
+//! chunk_size = 8388608
+//! ```
+//!
+//! ### Example usage
+//!
+//! ```no_run
+//! use twackup::{upload::Config, Result};
+//!
+//! #[tokio::main]
+//! async fn main() -> Result<()> {
+//!     let config: Config = serde_json::from_str(r#"{"target": []}"#).unwrap();
+//!
+//!     for target in config.targets()? {
+//!         let receipt = target.upload("backup.tar.gz".as_ref(), "backup.tar.gz").await?;
+//!         println!("{} uploaded to {}", receipt.sha256, receipt.url);
+//!     }
+//!
+//!     Ok(())
+//! }
+//! ```
+
+mod s3;
+#[cfg(test)]
+mod stand_in;
+mod webdav;
+
+pub use s3::{Config as S3Config, S3};
+pub use webdav::{Config as WebDavConfig, WebDav};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{fmt::Write, path::Path};
+use tokio::io::AsyncReadExt;
+
+/// Different upload errors
+#[derive(thiserror::Error, Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// Request couldn't be sent or response couldn't be received
+    #[error("Request failed: {0}")]
+    Http(#[from] reqwest::Error),
+
+    /// Server responded with unexpected status
+    #[error("Server responded with {status}: {message}")]
+    Status {
+        /// HTTP status code
+        status: u16,
+        /// Response body or its part
+        message: String,
+    },
+
+    /// Server response couldn't be understood
+    #[error("Invalid server response: {0}")]
+    InvalidResponse(String),
+
+    /// Target description in config is invalid
+    #[error("Invalid target config: {0}")]
+    InvalidConfig(String),
+
+    /// Uploaded data differs from local one
+    #[error("Checksum mismatch for {name}: expected {expected}, got {actual}")]
+    ChecksumMismatch {
+        /// Name of uploaded file or its part
+        name: String,
+        /// Local checksum
+        expected: String,
+        /// Checksum reported by server
+        actual: String,
+    },
+}
+
+/// List of upload targets
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Config {
+    /// Targets every file is uploaded to
+    #[serde(default, rename = "target")]
+    pub targets: Vec<TargetConfig>,
+}
+
+/// Description of single upload target
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+#[non_exhaustive]
+pub enum TargetConfig {
+    /// Amazon S3 or compatible storage like `MinIO`
+    S3(S3Config),
+    /// `WebDAV` server
+    WebDav(WebDavConfig),
+}
+
+/// Result of successful upload
+#[derive(Clone, Debug)]
+pub struct Receipt {
+    /// Location of uploaded file
+    pub url: String,
+    /// Size of uploaded file in bytes
+    pub size: u64,
+    /// Hex-encoded SHA256 of uploaded file
+    pub sha256: String,
+    /// Whether previously interrupted upload was continued
+    pub resumed: bool,
+}
+
+/// Storage backups can be uploaded to
+#[async_trait::async_trait]
+pub trait Target: Send + Sync {
+    /// Human-readable target name used in logs
+    fn name(&self) -> &str;
+
+    /// Uploads file at `path` with `name` relative to target location.
+    /// Interrupted upload of the same file is continued if possible.
+    ///
+    /// # Errors
+    /// Returns error if file couldn't be read, uploaded or its checksum doesn't match
+    async fn upload(&self, path: &Path, name: &str) -> crate::Result<Receipt>;
+}
+
+impl Config {
+    /// Creates upload targets from their descriptions
+    ///
+    /// # Errors
+    /// Returns error if any target description is invalid
+    pub fn targets(&self) -> crate::Result<Vec<Box<dyn Target>>> {
+        self.targets.iter().map(TargetConfig::target).collect()
+    }
+}
+
+impl TargetConfig {
+    /// Creates upload target from description
+    ///
+    /// # Errors
+    /// Returns error if description is invalid
+    pub fn target(&self) -> crate::Result<Box<dyn Target>> {
+        Ok(match self {
+            Self::S3(config) => Box::new(S3::new(config.clone())?),
+            Self::WebDav(config) => Box::new(WebDav::new(config.clone())?),
+        })
+    }
+}
+
+/// Converts unsuccessful response to error
+async fn check_status(response: reqwest::Response) -> Result<reqwest::Response, Error> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+
+    let mut message = response.text().await.unwrap_or_default();
+    message.truncate(512);
+
+    Err(Error::Status {
+        status: status.as_u16(),
+        message,
+    })
+}
+
+/// Returns hex-encoded SHA256 of the file reading it in chunks
+async fn file_sha256(path: &Path) -> crate::Result<String> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 1024 * 1024];
+
+    loop {
+        let read = file.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut hex, byte| {
+        write!(hex, "{byte:02x}").ok();
+        hex
+    })
+}
+
+/// Percent-encodes everything except unreserved characters and, if set, slashes
+fn percent_encode(value: &str, keep_slash: bool) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(byte as char);
+            }
+            b'/' if keep_slash => encoded.push('/'),
+            _ => {
+                write!(encoded, "%{byte:02X}").ok();
+            }
+        }
+    }
+
+    encoded
+}
@@ -0,0 +1,198 @@
+/*
+ * Copyright 2020 DanP
+ *
+ * This file is part of Twackup
+ *
+ * Twackup is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Twackup is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Twackup. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Minimal in-process HTTP server standing in for remote storages in tests
+
+use std::{
+    fmt::Write,
+    io,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+};
+
+/// Request received by stand-in server
+pub(super) struct Request {
+    pub(super) method: String,
+    pub(super) path: String,
+    pub(super) query: Vec<(String, String)>,
+    headers: Vec<(String, String)>,
+    pub(super) body: Vec<u8>,
+}
+
+/// Response sent by stand-in server. Body isn't sent for `HEAD` requests
+pub(super) struct Response {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+/// Handles requests keeping state between them
+pub(super) type Handler<S> = fn(&mut S, &Request) -> Response;
+
+impl Request {
+    /// Value of header with lowercase `name`
+    pub(super) fn header(&self, name: &str) -> Option<&str> {
+        (self.headers.iter())
+            .find(|(header, _)| header == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Value of query parameter
+    pub(super) fn query(&self, name: &str) -> Option<&str> {
+        (self.query.iter())
+            .find(|(parameter, _)| parameter == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+impl Response {
+    pub(super) fn new(status: u16) -> Self {
+        Self {
+            status,
+            headers: vec![],
+            body: vec![],
+        }
+    }
+
+    pub(super) fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub(super) fn body(mut self, body: impl Into<Vec<u8>>) -> Self {
+        self.body = body.into();
+        self
+    }
+}
+
+/// Starts server on random local port passing every request to `handler`.
+/// Returns server address and state shared with handler
+pub(super) async fn serve<S: Send + 'static>(
+    state: S,
+    handler: Handler<S>,
+) -> io::Result<(SocketAddr, Arc<Mutex<S>>)> {
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let address = listener.local_addr()?;
+    let state = Arc::new(Mutex::new(state));
+
+    let shared = state.clone();
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            let state = shared.clone();
+            tokio::spawn(async move { handle(stream, &state, handler).await });
+        }
+    });
+
+    Ok((address, state))
+}
+
+async fn handle<S>(stream: TcpStream, state: &Mutex<S>, handler: Handler<S>) -> io::Result<()> {
+    let mut stream = BufReader::new(stream);
+
+    // Connections are kept alive until client closes them
+    while let Some(request) = read_request(&mut stream).await? {
+        let response = {
+            let mut state = state.lock().unwrap();
+            handler(&mut state, &request)
+        };
+
+        let mut head = format!("HTTP/1.1 {} Stand-in\r\n", response.status);
+        let has_length =
+            (response.headers.iter()).any(|(name, _)| name.eq_ignore_ascii_case("content-length"));
+        if !has_length {
+            write!(head, "Content-Length: {}\r\n", response.body.len()).ok();
+        }
+        for (name, value) in &response.headers {
+            write!(head, "{name}: {value}\r\n").ok();
+        }
+        head.push_str("\r\n");
+
+        let stream = stream.get_mut();
+        stream.write_all(head.as_bytes()).await?;
+        if request.method != "HEAD" {
+            stream.write_all(&response.body).await?;
+        }
+    }
+
+    Ok(())
+}
+
+async fn read_request(stream: &mut BufReader<TcpStream>) -> io::Result<Option<Request>> {
+    let mut line = String::new();
+    if stream.read_line(&mut line).await? == 0 {
+        return Ok(None);
+    }
+
+    let mut parts = line.split_whitespace();
+    let method = parts.next().unwrap_or_default().to_string();
+    let target = parts.next().unwrap_or_default();
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let path = path.to_string();
+    let query = (query.split('&'))
+        .filter(|parameter| !parameter.is_empty())
+        .map(|parameter| {
+            let (name, value) = parameter.split_once('=').unwrap_or((parameter, ""));
+            (name.to_string(), value.to_string())
+        })
+        .collect();
+
+    let mut headers = vec![];
+    loop {
+        line.clear();
+        stream.read_line(&mut line).await?;
+        let Some((name, value)) = line.trim_end().split_once(':') else {
+            break;
+        };
+        headers.push((name.to_ascii_lowercase(), value.trim().to_string()));
+    }
+
+    let mut request = Request {
+        method,
+        path,
+        query,
+        headers,
+        body: vec![],
+    };
+
+    if request.header("transfer-encoding") == Some("chunked") {
+        loop {
+            line.clear();
+            stream.read_line(&mut line).await?;
+            let size = usize::from_str_radix(line.trim(), 16).unwrap_or_default();
+            let mut chunk = vec![0; size + 2];
+            stream.read_exact(&mut chunk).await?;
+            if size == 0 {
+                break;
+            }
+            request.body.extend_from_slice(&chunk[..size]);
+        }
+    } else {
+        let length = (request.header("content-length"))
+            .and_then(|length| length.parse().ok())
+            .unwrap_or_default();
+        request.body = vec![0; length];
+        stream.read_exact(&mut request.body).await?;
+    }
+
+    Ok(Some(request))
+}
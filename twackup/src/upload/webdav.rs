@@ -0,0 +1,451 @@
+/*
+ * Copyright 2020 DanP
+ *
+ * This file is part of Twackup
+ *
+ * Twackup is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Twackup is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Twackup. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use super::{check_status, file_sha256, percent_encode, Error, Receipt, Target};
+use crate::Result;
+use reqwest::{header, Body, Client, Method, RequestBuilder, Url};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::Path;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, SeekFrom};
+use tokio_util::io::ReaderStream;
+
+/// `WebDAV` target description
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Config {
+    /// Target name used in logs. Defaults to URL host
+    #[serde(default)]
+    pub name: Option<String>,
+
+    /// URL of existing collection files are uploaded to
+    pub url: String,
+
+    /// User name for basic authentication
+    #[serde(default)]
+    pub username: Option<String>,
+
+    /// Password for basic authentication
+    #[serde(default)]
+    pub password: Option<String>,
+
+    /// Uploads files in chunks of this size using `Content-Range` requests,
+    /// so interrupted upload can be continued. Server must support partial PUT,
+    /// e.g. Apache `mod_dav`. Files are uploaded with single request if not set
+    #[serde(default)]
+    pub chunk_size: Option<u64>,
+
+    /// Downloads uploaded file back to compare its checksum
+    #[serde(default = "default_verify")]
+    pub verify: bool,
+}
+
+/// Uploads files to `WebDAV` server.
+///
+/// File is uploaded under temporary `.part` name and is moved to the final one
+/// only after its size and checksum were verified. Chunked upload is continued
+/// only if already uploaded part is downloaded back and equals to the start of the file.
+pub struct WebDav {
+    config: Config,
+    name: String,
+    client: Client,
+    base: Url,
+}
+
+impl WebDav {
+    /// Creates target from description
+    ///
+    /// # Errors
+    /// Returns error if description is invalid or HTTP client couldn't be created
+    pub fn new(config: Config) -> Result<Self> {
+        let mut url = config.url.clone();
+        if !url.ends_with('/') {
+            url.push('/');
+        }
+        let base = Url::parse(&url).map_err(|error| Error::InvalidConfig(error.to_string()))?;
+
+        if config.chunk_size == Some(0) {
+            return Err(Error::InvalidConfig("chunk size can't be zero".to_string()).into());
+        }
+
+        Ok(Self {
+            name: (config.name.clone())
+                .or_else(|| base.host_str().map(ToString::to_string))
+                .unwrap_or_default(),
+            client: Client::builder().build().map_err(Error::from)?,
+            base,
+            config,
+        })
+    }
+
+    fn request(&self, method: Method, url: &str) -> RequestBuilder {
+        let request = self.client.request(method, url);
+        match &self.config.username {
+            Some(username) => request.basic_auth(username, self.config.password.as_ref()),
+            None => request,
+        }
+    }
+
+    /// Returns size of already uploaded file or `None` if it doesn't exist
+    async fn remote_size(&self, url: &str) -> Result<Option<u64>> {
+        let response = self.request(Method::HEAD, url).send().await;
+        let response = response.map_err(Error::from)?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+
+        let response = check_status(response).await?;
+        let size = (response.headers().get(header::CONTENT_LENGTH))
+            .and_then(|value| value.to_str().ok()?.parse().ok());
+        Ok(size)
+    }
+
+    async fn put_whole(&self, path: &Path, url: &str, size: u64) -> Result<()> {
+        let file = tokio::fs::File::open(path).await?;
+        let request = self
+            .request(Method::PUT, url)
+            .header(header::CONTENT_LENGTH, size)
+            .body(Body::wrap_stream(ReaderStream::new(file)));
+
+        check_status(request.send().await.map_err(Error::from)?).await?;
+        Ok(())
+    }
+
+    async fn put_chunks(&self, path: &Path, url: &str, size: u64, chunk_size: u64) -> Result<bool> {
+        let mut offset = match self.remote_size(url).await? {
+            Some(uploaded) if uploaded <= size => uploaded,
+            _ => 0,
+        };
+        // Part could be left by upload of other file with the same name
+        if offset > 0 && !self.is_prefix(path, url, offset).await? {
+            log::warn!(target: &self.name, "uploaded part differs from the file, starting over");
+            self.delete(url).await;
+            offset = 0;
+        }
+        let resumed = offset > 0;
+        if resumed {
+            log::info!(target: &self.name, "continuing upload from {offset} bytes");
+        }
+
+        let mut file = tokio::fs::File::open(path).await?;
+        file.seek(SeekFrom::Start(offset)).await?;
+
+        // Empty files still need to be created
+        while offset < size || (offset == 0 && size == 0) {
+            let length = chunk_size.min(size - offset);
+            let mut data = vec![0; usize::try_from(length).unwrap_or(usize::MAX)];
+            file.read_exact(&mut data).await?;
+
+            let mut request = self.request(Method::PUT, url);
+            if offset > 0 {
+                let range = format!("bytes {offset}-{}/{size}", offset + length - 1);
+                request = request.header(header::CONTENT_RANGE, range);
+            }
+
+            check_status(request.body(data).send().await.map_err(Error::from)?).await?;
+            offset += length;
+            if size == 0 {
+                break;
+            }
+        }
+
+        Ok(resumed)
+    }
+
+    /// Checks that file at `url` equals to the first `length` bytes of local file
+    async fn is_prefix(&self, path: &Path, url: &str, length: u64) -> Result<bool> {
+        let mut file = tokio::fs::File::open(path).await?.take(length);
+        let mut local = Sha256::new();
+        let mut buffer = vec![0; 1024 * 1024];
+        loop {
+            let read = file.read(&mut buffer).await?;
+            if read == 0 {
+                break;
+            }
+            local.update(&buffer[..read]);
+        }
+
+        let response = self.request(Method::GET, url).send().await;
+        let mut response = check_status(response.map_err(Error::from)?).await?;
+        let mut remote = Sha256::new();
+        let mut received = 0;
+        while let Some(chunk) = response.chunk().await.map_err(Error::from)? {
+            received += chunk.len() as u64;
+            remote.update(&chunk);
+        }
+
+        Ok(received == length && local.finalize() == remote.finalize())
+    }
+
+    async fn verify(&self, url: &str, size: u64, sha256: &str) -> Result<()> {
+        let uploaded = self.remote_size(url).await?.unwrap_or_default();
+        if uploaded != size {
+            return Err(Error::ChecksumMismatch {
+                name: url.to_string(),
+                expected: format!("{size} bytes"),
+                actual: format!("{uploaded} bytes"),
+            }
+            .into());
+        }
+
+        if !self.config.verify {
+            return Ok(());
+        }
+
+        let response = self.request(Method::GET, url).send().await;
+        let mut response = check_status(response.map_err(Error::from)?).await?;
+        let mut hasher = Sha256::new();
+        while let Some(chunk) = response.chunk().await.map_err(Error::from)? {
+            hasher.update(&chunk);
+        }
+
+        let actual = format!("{:x}", hasher.finalize());
+        if actual != sha256 {
+            return Err(Error::ChecksumMismatch {
+                name: url.to_string(),
+                expected: sha256.to_string(),
+                actual,
+            }
+            .into());
+        }
+
+        Ok(())
+    }
+
+    async fn delete(&self, url: &str) {
+        if let Err(error) = self.request(Method::DELETE, url).send().await {
+            log::warn!(target: &self.name, "failed to remove {url}: {error}");
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Target for WebDav {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn upload(&self, path: &Path, name: &str) -> Result<Receipt> {
+        let url = self.base.join(&percent_encode(name, true));
+        let url = url.map_err(|error| Error::InvalidConfig(error.to_string()))?;
+        let url = url.to_string();
+        let part_url = format!("{url}.part");
+
+        let size = tokio::fs::metadata(path).await?.len();
+        let sha256 = file_sha256(path).await?;
+
+        let resumed = if let Some(chunk_size) = self.config.chunk_size {
+            self.put_chunks(path, &part_url, size, chunk_size).await?
+        } else {
+            self.put_whole(path, &part_url, size).await?;
+            false
+        };
+
+        if let Err(error) = self.verify(&part_url, size, &sha256).await {
+            // Broken file can't be continued, so next try starts from scratch
+            self.delete(&part_url).await;
+            return Err(error);
+        }
+
+        let method = Method::from_bytes(b"MOVE").expect("MOVE is valid method name");
+        let request = (self.request(method, &part_url))
+            .header("Destination", &url)
+            .header("Overwrite", "T");
+        check_status(request.send().await.map_err(Error::from)?).await?;
+
+        Ok(Receipt {
+            url,
+            size,
+            sha256,
+            resumed,
+        })
+    }
+}
+
+fn default_verify() -> bool {
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Config, WebDav};
+    use crate::{
+        testing::temp_dir,
+        upload::{
+            stand_in::{self, Request, Response},
+            Target,
+        },
+        Result,
+    };
+    use std::{collections::BTreeMap, fs, net::SocketAddr};
+
+    /// State of `WebDAV` stand-in
+    #[derive(Default)]
+    struct Storage {
+        files: BTreeMap<String, Vec<u8>>,
+        /// `Content-Range` headers of partial writes
+        ranges: Vec<String>,
+        /// Stores different data than received
+        corrupt: bool,
+    }
+
+    fn webdav(storage: &mut Storage, request: &Request) -> Response {
+        let path = request.path.clone();
+        match request.method.as_str() {
+            "HEAD" | "GET" => match storage.files.get(&path) {
+                Some(data) => Response::new(200).body(data.clone()),
+                None => Response::new(404),
+            },
+            "PUT" => {
+                let file = storage.files.entry(path).or_default();
+                match request.header("content-range") {
+                    Some(range) => {
+                        let start = range.strip_prefix("bytes ").unwrap();
+                        let (start, _) = start.split_once('-').unwrap();
+                        if start.parse::<usize>().ok() != Some(file.len()) {
+                            return Response::new(416);
+                        }
+                        storage.ranges.push(range.to_string());
+                    }
+                    None => file.clear(),
+                }
+                file.extend_from_slice(&request.body);
+                if storage.corrupt {
+                    file[0] ^= 1;
+                }
+                Response::new(201)
+            }
+            "MOVE" => {
+                let destination = request.header("destination").unwrap();
+                let (_, destination) = destination.split_once("://").unwrap();
+                let (_, destination) = destination.split_once('/').unwrap();
+                match storage.files.remove(&path) {
+                    Some(data) if request.header("overwrite") == Some("T") => {
+                        storage.files.insert(format!("/{destination}"), data);
+                        Response::new(201)
+                    }
+                    _ => Response::new(412),
+                }
+            }
+            "DELETE" => {
+                storage.files.remove(&path);
+                Response::new(204)
+            }
+            _ => Response::new(405),
+        }
+    }
+
+    fn target(address: SocketAddr, chunk_size: Option<u64>) -> Result<WebDav> {
+        WebDav::new(Config {
+            name: None,
+            url: format!("http://{address}/dav"),
+            username: Some("user".to_string()),
+            password: Some("password".to_string()),
+            chunk_size,
+            verify: true,
+        })
+    }
+
+    #[tokio::test]
+    async fn chunked_resume() -> Result<()> {
+        let directory = temp_dir("twackup-upload-webdav-chunked")?;
+        let path = directory.join("backup.tar");
+        let data: Vec<_> = (0..40).collect();
+        fs::write(&path, &data)?;
+
+        let mut storage = Storage::default();
+        let part = "/dav/backup.tar.part".to_string();
+        storage.files.insert(part.clone(), data[..10].to_vec());
+
+        let (address, storage) = stand_in::serve(storage, webdav).await?;
+        let receipt = target(address, Some(16))?
+            .upload(&path, "backup.tar")
+            .await?;
+
+        assert!(receipt.resumed);
+        assert_eq!(receipt.url, format!("http://{address}/dav/backup.tar"));
+
+        let storage = storage.lock().unwrap();
+        assert_eq!(storage.ranges, ["bytes 10-25/40", "bytes 26-39/40"]);
+        assert_eq!(storage.files.get("/dav/backup.tar"), Some(&data));
+        assert!(!storage.files.contains_key(&part));
+
+        fs::remove_dir_all(directory)?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn stale_part_is_replaced() -> Result<()> {
+        let directory = temp_dir("twackup-upload-webdav-stale")?;
+        let path = directory.join("backup.tar");
+        let data: Vec<_> = (0..40).collect();
+        fs::write(&path, &data)?;
+
+        // Part of the same name left by other file
+        let mut storage = Storage::default();
+        storage
+            .files
+            .insert("/dav/backup.tar.part".to_string(), vec![0xff; 10]);
+
+        let (address, storage) = stand_in::serve(storage, webdav).await?;
+        let mut target = target(address, Some(16))?;
+        target.config.verify = false;
+        let receipt = target.upload(&path, "backup.tar").await?;
+
+        assert!(!receipt.resumed);
+        let storage = storage.lock().unwrap();
+        assert_eq!(storage.files.get("/dav/backup.tar"), Some(&data));
+        assert_eq!(storage.ranges, ["bytes 16-31/40", "bytes 32-39/40"]);
+
+        fs::remove_dir_all(directory)?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn part_is_moved_after_verification() -> Result<()> {
+        let directory = temp_dir("twackup-upload-webdav-move")?;
+        let path = directory.join("backup.tar");
+        fs::write(&path, "backup")?;
+
+        let storage = Storage {
+            corrupt: true,
+            ..Storage::default()
+        };
+        let (address, storage) = stand_in::serve(storage, webdav).await?;
+        let target = target(address, None)?;
+
+        // Corrupted part is removed, so next upload starts from scratch
+        assert!(target.upload(&path, "backup.tar").await.is_err());
+        assert!(storage.lock().unwrap().files.is_empty());
+
+        storage.lock().unwrap().corrupt = false;
+        let receipt = target.upload(&path, "backup.tar").await?;
+        assert!(!receipt.resumed);
+
+        let files: Vec<_> = (storage.lock().unwrap().files.iter())
+            .map(|(path, data)| (path.clone(), data.clone()))
+            .collect();
+        assert_eq!(files, [("/dav/backup.tar".to_string(), b"backup".to_vec())]);
+
+        fs::remove_dir_all(directory)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,317 @@
+/*
+ * Copyright 2020 DanP
+ *
+ * This file is part of Twackup
+ *
+ * Twackup is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Twackup is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Twackup. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use super::{DirEntry, FileKind, FileSystem, Metadata};
+use std::{
+    collections::BTreeMap,
+    io::{self, Cursor, Read},
+    ops::Bound,
+    path::{Component, Path, PathBuf},
+    sync::Arc,
+};
+
+/// Contents of filesystem entry
+#[derive(Clone, Debug)]
+pub(super) enum Data {
+    /// Directories, devices and other entries without contents
+    None,
+    /// Contents of regular file kept in memory
+    Bytes(Arc<[u8]>),
+    /// Contents of regular file located in archive at given offset
+    Archive(u64),
+    /// Target of symbolic link
+    Symlink(PathBuf),
+}
+
+#[derive(Clone, Debug)]
+pub(super) struct Node {
+    pub(super) metadata: Metadata,
+    pub(super) data: Data,
+}
+
+/// Filesystem which keeps all entries in memory.
+/// Useful for tests and snapshots assembled in code
+///
+/// ```
+/// use twackup::vfs::{FileSystem, MemoryFs};
+/// use std::path::Path;
+///
+/// let mut fs = MemoryFs::new();
+/// fs.insert_file("/var/jb/usr/bin/tool", "#!/bin/sh", 0o755);
+/// fs.insert_symlink("/usr/bin/tool", "/var/jb/usr/bin/tool");
+///
+/// let contents = fs.read(Path::new("/usr/bin/tool")).unwrap();
+/// assert_eq!(contents, b"#!/bin/sh");
+/// ```
+#[derive(Clone, Debug)]
+pub struct MemoryFs {
+    nodes: BTreeMap<PathBuf, Node>,
+}
+
+impl MemoryFs {
+    /// Creates filesystem with empty root directory
+    #[inline]
+    #[must_use]
+    pub fn new() -> Self {
+        let root = Node {
+            metadata: Metadata::new(FileKind::Directory, 0, 0o755),
+            data: Data::None,
+        };
+
+        Self {
+            nodes: BTreeMap::from([(PathBuf::from("/"), root)]),
+        }
+    }
+
+    /// Adds regular file replacing existing entry. Missing parent directories are created.
+    /// Returns metadata of the file, so owner or modification time can be changed
+    pub fn insert_file<P: AsRef<Path>, C: Into<Vec<u8>>>(
+        &mut self,
+        path: P,
+        contents: C,
+        permissions: u32,
+    ) -> &mut Metadata {
+        let contents: Arc<[u8]> = contents.into().into();
+        let metadata = Metadata::new(FileKind::File, contents.len() as u64, permissions);
+        self.insert(path.as_ref(), metadata, Data::Bytes(contents))
+    }
+
+    /// Adds directory replacing existing entry. Missing parent directories are created
+    pub fn insert_dir<P: AsRef<Path>>(&mut self, path: P, permissions: u32) -> &mut Metadata {
+        let metadata = Metadata::new(FileKind::Directory, 0, permissions);
+        self.insert(path.as_ref(), metadata, Data::None)
+    }
+
+    /// Adds symbolic link replacing existing entry. Missing parent directories are created
+    pub fn insert_symlink<P: AsRef<Path>, T: AsRef<Path>>(
+        &mut self,
+        path: P,
+        target: T,
+    ) -> &mut Metadata {
+        let target = target.as_ref().to_path_buf();
+        let metadata = Metadata::new(FileKind::Symlink, target.as_os_str().len() as u64, 0o777);
+        self.insert(path.as_ref(), metadata, Data::Symlink(target))
+    }
+
+    /// Count of entries including root directory
+    #[inline]
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Checks if filesystem has nothing but root directory
+    #[inline]
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.nodes.len() == 1
+    }
+
+    pub(super) fn insert(&mut self, path: &Path, metadata: Metadata, data: Data) -> &mut Metadata {
+        // Entry is created in the directory symlinked parents point to
+        let path = normalize(path);
+        let path = match (path.parent(), path.file_name()) {
+            (Some(parent), Some(name)) => match self.resolve(parent, true) {
+                Ok(parent) => parent.join(name),
+                Err(_) => path.clone(),
+            },
+            _ => path,
+        };
+        for parent in path.ancestors().skip(1) {
+            if self.nodes.contains_key(parent) {
+                break;
+            }
+            self.nodes.insert(
+                parent.to_path_buf(),
+                Node {
+                    metadata: Metadata::new(FileKind::Directory, 0, 0o755),
+                    data: Data::None,
+                },
+            );
+        }
+
+        let node = Node { metadata, data };
+        let node = match self.nodes.entry(path) {
+            std::collections::btree_map::Entry::Occupied(mut entry) => {
+                entry.insert(node);
+                entry.into_mut()
+            }
+            std::collections::btree_map::Entry::Vacant(entry) => entry.insert(node),
+        };
+        &mut node.metadata
+    }
+
+    /// Resolves symlinks in `path` like [`super::resolve`] does
+    fn resolve(&self, path: &Path, follow: bool) -> io::Result<PathBuf> {
+        super::resolve(Path::new("/"), path, follow, |path| {
+            Ok(self.nodes.get(path).and_then(|node| match &node.data {
+                Data::Symlink(target) => Some(target.clone()),
+                _ => None,
+            }))
+        })
+    }
+
+    /// Finds entry at `path` following symlinks in all components or in all except the last one
+    pub(super) fn node(&self, path: &Path, follow: bool) -> io::Result<(PathBuf, &Node)> {
+        let resolved = self.resolve(path, follow)?;
+        match self.nodes.get(&resolved) {
+            Some(node) => Ok((resolved, node)),
+            None => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} doesn't exist", path.display()),
+            )),
+        }
+    }
+
+    /// Finds regular file at `path` following symlinks
+    pub(super) fn file(&self, path: &Path) -> io::Result<&Data> {
+        let (_, node) = self.node(path, true)?;
+        if node.metadata.is_file() {
+            Ok(&node.data)
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} is not a regular file", path.display()),
+            ))
+        }
+    }
+}
+
+impl Default for MemoryFs {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FileSystem for MemoryFs {
+    fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        Ok(self.node(path, true)?.1.metadata.clone())
+    }
+
+    fn symlink_metadata(&self, path: &Path) -> io::Result<Metadata> {
+        Ok(self.node(path, false)?.1.metadata.clone())
+    }
+
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf> {
+        match &self.node(path, false)?.1.data {
+            Data::Symlink(target) => Ok(target.clone()),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} is not a symbolic link", path.display()),
+            )),
+        }
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<DirEntry>> {
+        let (directory, node) = self.node(path, true)?;
+        if !node.metadata.is_dir() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} is not a directory", path.display()),
+            ));
+        }
+
+        // Paths are ordered by components, so descendants follow the directory itself
+        Ok(self
+            .nodes
+            .range::<Path, _>((Bound::Excluded(directory.as_path()), Bound::Unbounded))
+            .take_while(|(entry, _)| entry.starts_with(&directory))
+            .filter(|(entry, _)| entry.parent() == Some(directory.as_path()))
+            .map(|(entry, node)| {
+                DirEntry::new(
+                    path.join(entry.strip_prefix(&directory).unwrap_or(entry)),
+                    node.metadata.clone(),
+                )
+            })
+            .collect())
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read + Send>> {
+        match self.file(path)? {
+            Data::Bytes(contents) => Ok(Box::new(Cursor::new(contents.clone()))),
+            _ => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("Contents of {} are not in memory", path.display()),
+            )),
+        }
+    }
+}
+
+/// Makes path absolute and removes `.` components. `..` ones are kept as is
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::from("/");
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => normalized.push(name),
+            Component::ParentDir => normalized.push(".."),
+            Component::RootDir | Component::CurDir | Component::Prefix(_) => {}
+        }
+    }
+
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MemoryFs;
+    use crate::vfs::{FileKind, FileSystem};
+    use std::{io, path::Path};
+
+    #[test]
+    fn memory_fs() -> io::Result<()> {
+        let mut fs = MemoryFs::new();
+        fs.insert_file("private/preboot/jb/usr/lib/libtool.dylib", "tool", 0o644)
+            .uid = 501;
+        fs.insert_symlink("/var/jb", "/private/preboot/jb");
+        fs.insert_symlink("/var/jb/usr/lib/libalias.dylib", "libtool.dylib");
+        fs.insert_symlink("/loop", "/loop");
+
+        let lib = Path::new("/var/jb/usr/lib/libalias.dylib");
+        assert_eq!(fs.read(lib)?, b"tool");
+        assert_eq!(fs.metadata(lib)?.uid, 501);
+        assert_eq!(fs.symlink_metadata(lib)?.kind, FileKind::Symlink);
+        assert_eq!(fs.read_link(lib)?, Path::new("libtool.dylib"));
+        assert_eq!(
+            fs.metadata(Path::new("/private/preboot"))?.kind,
+            FileKind::Directory
+        );
+
+        let mut entries: Vec<_> = fs
+            .read_dir(Path::new("/var/jb/usr/lib"))?
+            .into_iter()
+            .map(|entry| entry.path)
+            .collect();
+        entries.sort();
+        assert_eq!(
+            entries,
+            [
+                Path::new("/var/jb/usr/lib/libalias.dylib"),
+                Path::new("/var/jb/usr/lib/libtool.dylib")
+            ]
+        );
+
+        let missing = fs.metadata(Path::new("/var/jb/usr/bin")).unwrap_err();
+        assert_eq!(missing.kind(), io::ErrorKind::NotFound);
+        assert!(fs.read(Path::new("/loop")).is_err());
+        assert!(fs.open(Path::new("/var/jb")).is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,340 @@
+/*
+ * Copyright 2020 DanP
+ *
+ * This file is part of Twackup
+ *
+ * Twackup is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Twackup is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Twackup. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Filesystem abstraction dpkg database and installed files are read through.
+//!
+//! By default twackup works with [`RealFs`], but [`Dpkg`](crate::Dpkg) can be pointed
+//! to a snapshot instead, e.g. to [`TarFs`] with backup of `/var/lib/dpkg` and package
+//! files or to [`MemoryFs`] built in code.
+//!
+//! ### Example usage
+//!
+//! ```no_run
+//! use twackup::{vfs::TarFs, Dpkg, Result};
+//!
+//! #[tokio::main]
+//! async fn main() -> Result<()> {
+//!     let snapshot = TarFs::open("/var/mobile/Documents/snapshot.tar").await?;
+//!     let dpkg = Dpkg::new("/var/lib/dpkg", false).filesystem(snapshot);
+//!
+//!     for package in dpkg.unsorted_packages(false).await? {
+//!         println!("{}", package.id);
+//!     }
+//!
+//!     Ok(())
+//! }
+//! ```
+
+mod memory;
+mod real;
+mod tar;
+
+pub use memory::MemoryFs;
+pub use real::RealFs;
+pub use tar::TarFs;
+
+use std::{
+    collections::VecDeque,
+    ffi::OsString,
+    fmt, fs,
+    io::{self, Read},
+    os::unix::fs::{FileTypeExt, MetadataExt},
+    path::{Component, Path, PathBuf},
+};
+
+/// Maximum count of symlinks followed while resolving single path, the same as Linux has
+const MAX_SYMLINKS: usize = 40;
+
+/// Read-only access to files of some system. All paths are absolute.
+///
+/// Implementations must follow symlinks like an OS does: every component
+/// except the last one is always resolved, the last one depends on method
+pub trait FileSystem: fmt::Debug + Send + Sync {
+    /// Returns metadata of the file following symlinks
+    ///
+    /// # Errors
+    /// Returns error if file doesn't exist or couldn't be accessed
+    fn metadata(&self, path: &Path) -> io::Result<Metadata>;
+
+    /// Returns metadata of the file itself even if it is a symlink
+    ///
+    /// # Errors
+    /// Returns error if file doesn't exist or couldn't be accessed
+    fn symlink_metadata(&self, path: &Path) -> io::Result<Metadata>;
+
+    /// Reads target of symbolic link
+    ///
+    /// # Errors
+    /// Returns error if file doesn't exist or isn't a symlink
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf>;
+
+    /// Lists directory entries without `.` and `..` in no particular order
+    ///
+    /// # Errors
+    /// Returns error if directory doesn't exist or couldn't be read
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<DirEntry>>;
+
+    /// Opens file for reading following symlinks
+    ///
+    /// # Errors
+    /// Returns error if file doesn't exist, isn't a regular file or couldn't be read
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read + Send>>;
+
+    /// Reads the whole file to memory
+    ///
+    /// # Errors
+    /// Returns error if file couldn't be opened or read
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        let mut contents = vec![];
+        self.open(path)?.read_to_end(&mut contents)?;
+        Ok(contents)
+    }
+
+    /// Reads the whole file to string
+    ///
+    /// # Errors
+    /// Returns error if file couldn't be read or isn't valid UTF-8
+    fn read_to_string(&self, path: &Path) -> io::Result<String> {
+        String::from_utf8(self.read(path)?)
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+    }
+
+    /// Location of the file on this system if filesystem is backed by it.
+    /// Such files are mapped, locked and archived natively instead of being read
+    /// through this trait
+    fn host_path<'a>(&self, _path: &'a Path) -> Option<&'a Path> {
+        None
+    }
+}
+
+/// Type of filesystem entry
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum FileKind {
+    /// Regular file
+    File,
+    /// Directory
+    Directory,
+    /// Symbolic link
+    Symlink,
+    /// Named pipe
+    Fifo,
+    /// Unix domain socket
+    Socket,
+    /// Block device
+    BlockDevice,
+    /// Character device
+    CharDevice,
+}
+
+impl FileKind {
+    /// File type bits of `st_mode`, they are the same on every unix
+    #[must_use]
+    pub const fn mode_bits(self) -> u32 {
+        match self {
+            Self::Fifo => 0o010_000,
+            Self::CharDevice => 0o020_000,
+            Self::Directory => 0o040_000,
+            Self::BlockDevice => 0o060_000,
+            Self::File => 0o100_000,
+            Self::Symlink => 0o120_000,
+            Self::Socket => 0o140_000,
+        }
+    }
+}
+
+impl From<fs::FileType> for FileKind {
+    fn from(file_type: fs::FileType) -> Self {
+        if file_type.is_symlink() {
+            Self::Symlink
+        } else if file_type.is_dir() {
+            Self::Directory
+        } else if file_type.is_fifo() {
+            Self::Fifo
+        } else if file_type.is_socket() {
+            Self::Socket
+        } else if file_type.is_block_device() {
+            Self::BlockDevice
+        } else if file_type.is_char_device() {
+            Self::CharDevice
+        } else {
+            Self::File
+        }
+    }
+}
+
+/// Metadata of filesystem entry
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Metadata {
+    /// Type of the entry
+    pub kind: FileKind,
+    /// Size of contents in bytes
+    pub len: u64,
+    /// Type and permission bits as in `st_mode`
+    pub mode: u32,
+    /// Owner user identifier
+    pub uid: u32,
+    /// Owner group identifier
+    pub gid: u32,
+    /// Modification time in seconds since UNIX epoch
+    pub mtime: i64,
+    /// Nanoseconds part of modification time
+    pub mtime_nsec: i64,
+}
+
+impl Metadata {
+    /// Creates metadata of entry owned by root and modified at UNIX epoch
+    #[inline]
+    #[must_use]
+    pub const fn new(kind: FileKind, len: u64, permissions: u32) -> Self {
+        Self {
+            kind,
+            len,
+            mode: kind.mode_bits() | (permissions & 0o7777),
+            uid: 0,
+            gid: 0,
+            mtime: 0,
+            mtime_nsec: 0,
+        }
+    }
+
+    /// Permission bits including setuid, setgid and sticky ones
+    #[inline]
+    #[must_use]
+    pub const fn permissions(&self) -> u32 {
+        self.mode & 0o7777
+    }
+
+    /// Checks if entry is a regular file
+    #[inline]
+    #[must_use]
+    pub fn is_file(&self) -> bool {
+        self.kind == FileKind::File
+    }
+
+    /// Checks if entry is a directory
+    #[inline]
+    #[must_use]
+    pub fn is_dir(&self) -> bool {
+        self.kind == FileKind::Directory
+    }
+
+    /// Checks if entry is a symbolic link
+    #[inline]
+    #[must_use]
+    pub fn is_symlink(&self) -> bool {
+        self.kind == FileKind::Symlink
+    }
+}
+
+impl From<fs::Metadata> for Metadata {
+    fn from(metadata: fs::Metadata) -> Self {
+        Self {
+            kind: metadata.file_type().into(),
+            len: metadata.len(),
+            mode: metadata.mode(),
+            uid: metadata.uid(),
+            gid: metadata.gid(),
+            mtime: metadata.mtime(),
+            mtime_nsec: metadata.mtime_nsec(),
+        }
+    }
+}
+
+/// Entry of directory listing
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct DirEntry {
+    /// Full path of the entry
+    pub path: PathBuf,
+    /// Metadata of the entry itself, symlinks aren't followed
+    pub metadata: Metadata,
+}
+
+impl DirEntry {
+    /// Creates directory entry
+    #[inline]
+    #[must_use]
+    pub const fn new(path: PathBuf, metadata: Metadata) -> Self {
+        Self { path, metadata }
+    }
+}
+
+/// Resolves symlinks in absolute `path` like kernel does for process chrooted to `root`:
+/// absolute targets and `..` never leave it. The last component is resolved only if `follow` is set.
+///
+/// `read_link` returns target of the symlink at given path or `None` if it isn't a symlink
+pub(crate) fn resolve<F>(
+    root: &Path,
+    path: &Path,
+    follow: bool,
+    mut read_link: F,
+) -> io::Result<PathBuf>
+where
+    F: FnMut(&Path) -> io::Result<Option<PathBuf>>,
+{
+    let mut pending: VecDeque<OsString> = components(path).collect();
+    let mut resolved = PathBuf::new();
+    let mut symlinks = 0;
+
+    while let Some(name) = pending.pop_front() {
+        if name == ".." {
+            resolved.pop();
+            continue;
+        }
+
+        resolved.push(&name);
+        if pending.is_empty() && !follow {
+            break;
+        }
+
+        let Some(target) = read_link(&root.join(&resolved))? else {
+            continue;
+        };
+
+        symlinks += 1;
+        if symlinks > MAX_SYMLINKS {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!("Too many levels of symbolic links in {}", path.display()),
+            ));
+        }
+
+        resolved.pop();
+        if target.is_absolute() {
+            resolved.clear();
+        }
+        for name in components(&target).rev() {
+            pending.push_front(name);
+        }
+    }
+
+    Ok(root.join(resolved))
+}
+
+/// Names of path components without root and `.` ones
+fn components(path: &Path) -> impl DoubleEndedIterator<Item = OsString> + '_ {
+    path.components().filter_map(|component| match component {
+        Component::Normal(name) => Some(name.to_os_string()),
+        Component::ParentDir => Some("..".into()),
+        Component::RootDir | Component::CurDir | Component::Prefix(_) => None,
+    })
+}
@@ -0,0 +1,76 @@
+/*
+ * Copyright 2020 DanP
+ *
+ * This file is part of Twackup
+ *
+ * Twackup is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Twackup is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Twackup. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use super::{DirEntry, FileSystem, Metadata};
+use std::{
+    fs,
+    io::{self, Read},
+    path::{Path, PathBuf},
+};
+
+/// Filesystem of this system
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RealFs;
+
+impl FileSystem for RealFs {
+    #[inline]
+    fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        Ok(fs::metadata(path)?.into())
+    }
+
+    #[inline]
+    fn symlink_metadata(&self, path: &Path) -> io::Result<Metadata> {
+        Ok(fs::symlink_metadata(path)?.into())
+    }
+
+    #[inline]
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf> {
+        fs::read_link(path)
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<DirEntry>> {
+        let mut entries = vec![];
+        for entry in fs::read_dir(path)? {
+            let entry = entry?;
+            // Entry could be removed since directory was listed
+            match entry.metadata() {
+                Ok(metadata) => entries.push(DirEntry::new(entry.path(), metadata.into())),
+                Err(error) if error.kind() == io::ErrorKind::NotFound => {}
+                Err(error) => return Err(error),
+            }
+        }
+
+        Ok(entries)
+    }
+
+    #[inline]
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read + Send>> {
+        Ok(Box::new(fs::File::open(path)?))
+    }
+
+    #[inline]
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        fs::read(path)
+    }
+
+    #[inline]
+    fn host_path<'a>(&self, path: &'a Path) -> Option<&'a Path> {
+        Some(path)
+    }
+}
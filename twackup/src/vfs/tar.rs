@@ -0,0 +1,308 @@
+/*
+ * Copyright 2020 DanP
+ *
+ * This file is part of Twackup
+ *
+ * Twackup is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Twackup is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Twackup. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use super::{
+    memory::{Data, MemoryFs},
+    DirEntry, FileKind, FileSystem, Metadata,
+};
+use crate::{
+    archiver::{self, Type},
+    error::Result,
+};
+use std::{
+    fs::File,
+    io::{self, Read, SeekFrom},
+    os::unix::fs::FileExt,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tokio::io::{AsyncRead, AsyncSeekExt, AsyncWriteExt};
+use tokio_stream::StreamExt;
+use tokio_tar::{Archive, EntryType};
+
+/// Filesystem stored in tar archive, e.g. backup of dpkg database and installed files.
+///
+/// Archive is indexed once when opened and files are read from it on demand.
+/// Compressed archives can't be read at arbitrary position, so they are decompressed
+/// to anonymous temporary file first. Temp directory must have enough free space
+/// for the whole uncompressed archive then. Paths in archive are treated as relative to the root
+#[derive(Debug)]
+pub struct TarFs {
+    tree: MemoryFs,
+    archive: Arc<File>,
+}
+
+impl TarFs {
+    /// Opens archive and indexes its entries.
+    /// Compression type is detected from the contents
+    ///
+    /// # Errors
+    /// Returns error if archive couldn't be read or decompressed
+    /// or decompressed archive couldn't be written to temp directory
+    pub async fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+
+        let mut magic = vec![];
+        File::open(path)?.take(8).read_to_end(&mut magic)?;
+        if Type::detect(&magic).is_none() {
+            return Self::from_archive(File::open(path)?).await;
+        }
+
+        // File is removed as soon as it is closed
+        let archive = tempfile::tempfile()?;
+        let mut decompressed = tokio::fs::File::from_std(archive.try_clone()?);
+        tokio::io::copy(&mut archiver::open_detected(path)?, &mut decompressed).await?;
+        decompressed.flush().await?;
+
+        Self::from_archive(archive).await
+    }
+
+    async fn from_archive(archive: File) -> Result<Self> {
+        // Clone shares position with original file, but reads of the latter are positional
+        let mut reader = tokio::fs::File::from_std(archive.try_clone()?);
+        reader.seek(SeekFrom::Start(0)).await?;
+
+        Ok(Self {
+            tree: index(reader).await?,
+            archive: Arc::new(archive),
+        })
+    }
+}
+
+impl FileSystem for TarFs {
+    #[inline]
+    fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        self.tree.metadata(path)
+    }
+
+    #[inline]
+    fn symlink_metadata(&self, path: &Path) -> io::Result<Metadata> {
+        self.tree.symlink_metadata(path)
+    }
+
+    #[inline]
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf> {
+        self.tree.read_link(path)
+    }
+
+    #[inline]
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<DirEntry>> {
+        self.tree.read_dir(path)
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read + Send>> {
+        let Data::Archive(offset) = self.tree.file(path)? else {
+            return self.tree.open(path);
+        };
+
+        Ok(Box::new(ArchiveReader {
+            archive: self.archive.clone(),
+            offset: *offset,
+            remaining: self.tree.metadata(path)?.len,
+        }))
+    }
+}
+
+/// Reads entries of uncompressed tar archive into tree.
+/// Contents of regular files are recorded as offsets in archive
+async fn index<R: AsyncRead + Unpin + Send>(reader: R) -> io::Result<MemoryFs> {
+    let mut tree = MemoryFs::new();
+
+    let mut archive = Archive::new(reader);
+    let mut entries = archive.entries()?;
+    while let Some(entry) = entries.next().await {
+        let entry = entry?;
+        let header = entry.header().clone();
+        let path = entry.path()?.into_owned();
+
+        let (kind, data) = match header.entry_type() {
+            EntryType::Regular | EntryType::Continuous => {
+                (FileKind::File, Data::Archive(entry.raw_file_position()))
+            }
+            EntryType::Directory => (FileKind::Directory, Data::None),
+            EntryType::Symlink => {
+                let target = entry.link_name()?.unwrap_or_default().into_owned();
+                (FileKind::Symlink, Data::Symlink(target))
+            }
+            EntryType::Link => {
+                // Hard links refer to entries that were archived earlier
+                let target = entry.link_name()?.unwrap_or_default();
+                let Ok((_, node)) = tree.node(&Path::new("/").join(target), false) else {
+                    log::warn!("Hard link target of {} is missing", path.display());
+                    continue;
+                };
+                let (metadata, data) = (node.metadata.clone(), node.data.clone());
+                tree.insert(&path, metadata, data);
+                continue;
+            }
+            EntryType::Fifo => (FileKind::Fifo, Data::None),
+            EntryType::Block => (FileKind::BlockDevice, Data::None),
+            EntryType::Char => (FileKind::CharDevice, Data::None),
+            _ => {
+                log::warn!("Skipping unsupported tar entry {}", path.display());
+                continue;
+            }
+        };
+
+        let mut metadata = Metadata::new(kind, header.size()?, header.mode()?);
+        metadata.uid = u32::try_from(header.uid()?).unwrap_or(u32::MAX);
+        metadata.gid = u32::try_from(header.gid()?).unwrap_or(u32::MAX);
+        metadata.mtime = i64::try_from(header.mtime()?).unwrap_or(i64::MAX);
+        if let Data::Symlink(target) = &data {
+            metadata.len = target.as_os_str().len() as u64;
+        }
+        tree.insert(&path, metadata, data);
+    }
+
+    Ok(tree)
+}
+
+/// Reads contents of single file from uncompressed archive.
+/// Reads are positional, so any count of readers can share the archive
+struct ArchiveReader {
+    archive: Arc<File>,
+    offset: u64,
+    remaining: u64,
+}
+
+impl Read for ArchiveReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len =
+            usize::try_from(self.remaining).map_or(buf.len(), |remaining| remaining.min(buf.len()));
+        if len == 0 {
+            return Ok(0);
+        }
+
+        let read = self.archive.read_at(&mut buf[..len], self.offset)?;
+        if read == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+
+        self.offset += read as u64;
+        self.remaining -= read as u64;
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TarFs;
+    use crate::{
+        archiver::{Compression, Encoder, Level, Type},
+        testing::temp_dir,
+        vfs::{FileKind, FileSystem},
+        Result,
+    };
+    use std::{fs, path::Path};
+    use tokio::io::AsyncWriteExt;
+    use tokio_tar::{Builder, EntryType, Header};
+
+    async fn snapshot() -> Result<Vec<u8>> {
+        let mut builder = Builder::new(vec![]);
+
+        let entry = |path: &str, kind, mode, contents: &'static [u8], link: Option<&str>| {
+            let mut header = Header::new_gnu();
+            header.set_entry_type(kind);
+            header.set_mode(mode);
+            header.set_uid(501);
+            header.set_gid(0);
+            header.set_mtime(0);
+            header.set_size(contents.len() as u64);
+            if let Some(link) = link {
+                header.set_link_name(link).unwrap();
+            }
+            header.set_cksum();
+            (header, path.to_string(), contents)
+        };
+
+        let entries = [
+            entry("./", EntryType::Directory, 0o755, b"", None),
+            entry(
+                "./var/lib/dpkg/status",
+                EntryType::Regular,
+                0o644,
+                b"status",
+                None,
+            ),
+            entry(
+                "./usr/bin/tool",
+                EntryType::Regular,
+                0o4755,
+                b"#!/bin/sh\n",
+                None,
+            ),
+            entry(
+                "./usr/bin/alias",
+                EntryType::Symlink,
+                0o777,
+                b"",
+                Some("tool"),
+            ),
+            entry(
+                "./usr/bin/hard",
+                EntryType::Link,
+                0o755,
+                b"",
+                Some("./usr/bin/tool"),
+            ),
+        ];
+        for (mut header, path, contents) in entries {
+            builder.append_data(&mut header, path, contents).await?;
+        }
+
+        Ok(builder.into_inner().await?)
+    }
+
+    #[tokio::test]
+    async fn tar_fs() -> Result<()> {
+        let directory = temp_dir("twackup-tar-fs")?;
+
+        let plain = snapshot().await?;
+        let compression = Compression {
+            r#type: Type::Gz,
+            level: Level::Fast,
+        };
+        let mut encoder = Encoder::new(vec![], compression)?;
+        encoder.write_all(&plain).await?;
+        encoder.shutdown().await?;
+        let compressed = encoder.into_inner()?;
+
+        for (name, contents) in [("plain.tar", plain), ("compressed.tar.gz", compressed)] {
+            let path = directory.join(name);
+            fs::write(&path, contents)?;
+
+            let fs = TarFs::open(&path).await?;
+            assert_eq!(fs.read(Path::new("/var/lib/dpkg/status"))?, b"status");
+            assert_eq!(fs.read(Path::new("/usr/bin/alias"))?, b"#!/bin/sh\n");
+            assert_eq!(fs.read(Path::new("/usr/bin/hard"))?, b"#!/bin/sh\n");
+
+            let metadata = fs.metadata(Path::new("/usr/bin/tool"))?;
+            assert_eq!((metadata.permissions(), metadata.uid), (0o4755, 501));
+            assert_eq!(
+                fs.symlink_metadata(Path::new("/usr/bin/alias"))?.kind,
+                FileKind::Symlink
+            );
+            assert_eq!(fs.read_dir(Path::new("/usr/bin"))?.len(), 3);
+            assert_eq!(fs.host_path(Path::new("/usr/bin/tool")), None);
+        }
+
+        fs::remove_dir_all(directory)?;
+        Ok(())
+    }
+}